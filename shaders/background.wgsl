@@ -0,0 +1,29 @@
+// 全屏的竖直渐变背景，在小球之前绘制，不写入深度
+
+struct Background {
+    top: vec4f,
+    bottom: vec4f,
+}
+@group(0) @binding(0)
+var<uniform> background: Background;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4f,
+    // 0 在屏幕底部，1 在屏幕顶部
+    @location(0) height: f32,
+};
+
+// 一个覆盖整个屏幕的三角形
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2f(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4f(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.height = uv.y;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    return mix(background.bottom, background.top, clamp(in.height, 0.0, 1.0));
+}
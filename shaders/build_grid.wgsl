@@ -1,7 +1,7 @@
 ///#include "header.wgsl"
 
 @group(0) @binding(0)
-var<storage, read_write> params: Parameters;
+var<uniform> params: Parameters;
 
 @group(1) @binding(0)
 var<storage, read_write> instances: array<Instance>;
@@ -18,19 +18,25 @@ fn main(@builtin(global_invocation_id) id: vec3<u32>) {
     }
 
     let inst = instances[idx];
+    // 死亡的实例排在最后，不属于任何网格单元
+    let alive = inst.cell_index != DEAD_CELL;
 
     if(idx == 0u) {
-        cells[inst.cell_index].start = idx;
+        if (alive) {
+            cells[inst.cell_index].start = idx;
+        }
         return;
     }
 
     let p_inst = instances[idx - 1u];
-    if(idx == arrayLength(&instances) - 1u) {
+    if(alive && idx == arrayLength(&instances) - 1u) {
         cells[inst.cell_index].end = idx + 1u;
     }
 
     if (inst.cell_index != p_inst.cell_index) {
-        cells[inst.cell_index].start = idx;
+        if (alive) {
+            cells[inst.cell_index].start = idx;
+        }
         cells[p_inst.cell_index].end = idx;
     }
 }
\ No newline at end of file
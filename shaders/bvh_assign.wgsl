@@ -0,0 +1,27 @@
+///#include "header.wgsl"
+
+@group(0) @binding(0)
+var<uniform> params: Parameters;
+
+@group(1) @binding(0)
+var<storage, read_write> instances: array<Instance>;
+
+// 每个坐标量化成 10 位
+const MORTON_RESOLUTION: f32 = 1024.0;
+
+// 把球心在边界盒子中的 Morton 码写到 cell_index，之后复用双调排序按它排序
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let my_idx = id.x;
+    if (my_idx >= arrayLength(&instances)) {
+        return;
+    }
+    // Morton 码只有 30 位，死亡的实例排在最后
+    if (instances[my_idx].dead != 0u) {
+        instances[my_idx].cell_index = DEAD_CELL;
+        return;
+    }
+    let normalized = (instances[my_idx].position + params.boundary) / (2.0 * params.boundary);
+    let quantized = clamp(normalized * MORTON_RESOLUTION, vec3f(0.0), vec3f(MORTON_RESOLUTION - 1.0));
+    instances[my_idx].cell_index = morton_code(vec3u(quantized));
+}
@@ -0,0 +1,94 @@
+///#include "header.wgsl"
+
+@group(0) @binding(0)
+var<uniform> params: Parameters;
+
+@group(1) @binding(0)
+var<storage, read_write> instances: array<Instance>;
+
+@group(2) @binding(0)
+var<storage, read_write> bvh_nodes: array<BvhNode>;
+
+// 排序后第 i 个和第 j 个小球的 Morton 码的公共前缀长度，j 越界时为 -1；
+// Morton 码相同时接着比较下标，保证所有的键互不相同
+fn common_prefix(i: i32, j: i32) -> i32 {
+    let count = i32(arrayLength(&instances));
+    if (j < 0 || j >= count) {
+        return -1;
+    }
+    let code_i = instances[i].cell_index;
+    let code_j = instances[j].cell_index;
+    if (code_i == code_j) {
+        return 32 + i32(countLeadingZeros(u32(i) ^ u32(j)));
+    }
+    return i32(countLeadingZeros(code_i ^ code_j));
+}
+
+// 按 Karras 2012 的方法并行地建立 LBVH：第 i 个线程确定第 i 个内部节点覆盖的区间和分割位置，
+// 再遍历区间中的小球求包围盒。每层的区间加起来是 n，总的工作量与树的深度成正比
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let count = arrayLength(&instances);
+    if (id.x + 1u >= count) {
+        return;
+    }
+    let i = i32(id.x);
+
+    // 区间朝公共前缀更长的一侧延伸
+    var d = -1;
+    if (common_prefix(i, i + 1) > common_prefix(i, i - 1)) {
+        d = 1;
+    }
+    // 先倍增找到区间长度的上界，再二分得到另一端 j
+    let prefix_min = common_prefix(i, i - d);
+    var length_max = 2;
+    while (common_prefix(i, i + length_max * d) > prefix_min) {
+        length_max = length_max * 2;
+    }
+    var range_length = 0;
+    for (var t = length_max / 2; t >= 1; t = t / 2) {
+        if (common_prefix(i, i + (range_length + t) * d) > prefix_min) {
+            range_length = range_length + t;
+        }
+    }
+    let j = i + range_length * d;
+
+    // 二分找到区间中公共前缀变短的位置，从那里分成左右两棵子树
+    let prefix_node = common_prefix(i, j);
+    var split = 0;
+    var step = range_length;
+    loop {
+        step = (step + 1) / 2;
+        if (common_prefix(i, i + (split + step) * d) > prefix_node) {
+            split = split + step;
+        }
+        if (step <= 1) {
+            break;
+        }
+    }
+    let gamma = i + split * d + min(d, 0);
+    let first = min(i, j);
+    let last = max(i, j);
+
+    var left = u32(gamma);
+    if (first == gamma) {
+        left = left | BVH_LEAF;
+    }
+    var right = u32(gamma + 1);
+    if (last == gamma + 1) {
+        right = right | BVH_LEAF;
+    }
+
+    var box_min = vec3f(3.4e38);
+    var box_max = vec3f(-3.4e38);
+    for (var k = first; k <= last; k = k + 1) {
+        let instance = instances[k];
+        box_min = min(box_min, instance.position - vec3f(instance.radius));
+        box_max = max(box_max, instance.position + vec3f(instance.radius));
+    }
+
+    bvh_nodes[i].min = box_min;
+    bvh_nodes[i].left = left;
+    bvh_nodes[i].max = box_max;
+    bvh_nodes[i].right = right;
+}
@@ -1,57 +1,148 @@
 ///#include "header.wgsl"
 
 @group(0) @binding(0)
-var<storage, read_write> params: Parameters;
+var<uniform> params: Parameters;
     
 @group(1) @binding(0)
 var<storage, read_write> instances: array<Instance>;
 
+// 只在 broad_phase 为 BROAD_PHASE_BVH 时使用
+@group(2) @binding(0)
+var<storage, read_write> bvh_nodes: array<BvhNode>;
+
 @group(3) @binding(0)
 var<storage, read_write> cells: array<CellIndex>;
 
 @group(4) @binding(0)
 var<storage, read_write> results: array<Result>;
 
+@group(5) @binding(0)
+var<storage, read_write> planes: Planes;
+
+@group(6) @binding(0)
+var<storage, read_write> static_mesh: StaticMesh;
+
+// 新的状态写到另一个实例 buffer 中，避免其他线程在同一轮中读到已经更新的位置
+@group(7) @binding(0)
+var<storage, read_write> instances_out: array<Instance>;
+
 
+// 这个线程的小球这一轮接触到的小球数量，在 interact 中累加
+var<private> contact_count: u32 = 0u;
+
+// 与 assign.wgsl 中的单元编号保持一致
 fn get_index_from_grid(grid_index: vec3u) -> u32 {
-    let grid_count_x = u32(ceil(params.boundary * 2.0 / params.grid_size) + 0.5);
-    let grid_count_y = u32(ceil(params.boundary * 2.0 / params.grid_size) + 0.5);
-    let grid_count_z = u32(ceil(params.boundary * 2.0 / params.grid_size) + 0.5);
-    return grid_index.x + grid_index.y * grid_count_x + grid_index.z * grid_count_x * grid_count_y;
+    let grid_count = grid_counts(params.boundary, params.grid_size, params.dimensions);
+    return cell_index_from_grid(grid_index, grid_count, params.cell_indexing, params.dimensions);
 }
 
 fn get_grid_from_index(index: u32) -> vec3u {
-    let grid_count_x = u32(ceil(params.boundary * 2.0 / params.grid_size) + 0.5);
-    let grid_count_y = u32(ceil(params.boundary * 2.0 / params.grid_size) + 0.5);
-    let grid_count_z = u32(ceil(params.boundary * 2.0 / params.grid_size) + 0.5);
-    let z = index / (grid_count_x * grid_count_y);
-    let y = (index - z * grid_count_x * grid_count_y) / grid_count_x;
-    let x = index - z * grid_count_x * grid_count_y - y * grid_count_x;
-    return vec3u(x, y, z);
+    let grid_count = grid_counts(params.boundary, params.grid_size, params.dimensions);
+    return grid_from_cell_index(index, grid_count, params.cell_indexing, params.dimensions);
 }
 
+// 三角形上离 p 最近的点，参考 Real-Time Collision Detection 5.1.5
+fn closest_point_on_triangle(p: vec3f, a: vec3f, b: vec3f, c: vec3f) -> vec3f {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = dot(ab, ap);
+    let d2 = dot(ac, ap);
+    if (d1 <= 0.0 && d2 <= 0.0) {
+        return a;
+    }
 
+    let bp = p - b;
+    let d3 = dot(ab, bp);
+    let d4 = dot(ac, bp);
+    if (d3 >= 0.0 && d4 <= d3) {
+        return b;
+    }
 
-@compute @workgroup_size(64)
-fn main(@builtin(global_invocation_id) id: vec3<u32>) {
-    let grid_count_x = u32(ceil(params.boundary * 2.0 / params.grid_size) + 0.5);
-    let grid_count_y = u32(ceil(params.boundary * 2.0 / params.grid_size) + 0.5);
-    let grid_count_z = u32(ceil(params.boundary * 2.0 / params.grid_size) + 0.5);
+    let vc = d1 * d4 - d3 * d2;
+    if (vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0) {
+        return a + ab * (d1 / (d1 - d3));
+    }
 
-    let boundary = params.boundary;
-    let time_step = params.time_step;
+    let cp = p - c;
+    let d5 = dot(ab, cp);
+    let d6 = dot(ac, cp);
+    if (d6 >= 0.0 && d5 <= d6) {
+        return c;
+    }
 
-    // 暂时不考虑速度，如果距离小于两个物体的半径之和，就认为发生了碰撞，将结果写入输出
-    let my_idx = id.x;
-    let len = arrayLength(&instances);
-    if (my_idx >= len) {
-        return;        
+    let vb = d5 * d2 - d1 * d6;
+    if (vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0) {
+        return a + ac * (d2 / (d2 - d6));
     }
-    let my_instance = instances[my_idx];
 
-    let mass = my_instance.radius * my_instance.radius * my_instance.radius;
-    var total_force = vec3f(0.0, 0.0, 0.0);
+    let va = d3 * d6 - d5 * d4;
+    if (va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0) {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    return a + ab * (vb * denom) + ac * (vc * denom);
+}
+
+// 另一个小球作用在这个小球上的力，rel_pos 从另一个小球指向这个小球，正的分量表示排斥
+fn pair_force(rel_pos: vec3f, distance: f32, radius_sum: f32) -> vec3f {
+    if (distance <= 0.0) {
+        return vec3f(0.0, 0.0, 0.0);
+    }
+    let normal = rel_pos / distance;
+    if (params.force_model == FORCE_MODEL_CONTACT) {
+        return params.stiffness * max(radius_sum - distance, 0.0) * normal;
+    }
 
+    // 吸引力只在相邻格子内能找到的距离上起作用
+    if (distance >= params.grid_size) {
+        return vec3f(0.0, 0.0, 0.0);
+    }
+    var r0 = params.equilibrium_distance;
+    if (r0 <= 0.0) {
+        r0 = radius_sum;
+    }
+    if (params.force_model == FORCE_MODEL_SPRING) {
+        return params.stiffness * (r0 - distance) * normal;
+    }
+
+    // F(r) = 12 * epsilon / r * ((r0 / r)^12 - (r0 / r)^6)，在 r0 处为 0，刚度为 72 * epsilon / r0^2
+    let epsilon = params.stiffness * r0 * r0 / 72.0;
+    let r = max(distance, LJ_MIN_DISTANCE_FACTOR * r0);
+    let s2 = (r0 / r) * (r0 / r);
+    let s6 = s2 * s2 * s2;
+    return 12.0 * epsilon / r * (s6 * s6 - s6) * normal;
+}
+
+// 排序后的第 other_idx 个小球作用在这个小球上的力，接触时按需唤醒它
+fn interact(my_instance: Instance, other_idx: u32, wakes_neighbors: bool) -> vec3f {
+    let other_instance = instances[other_idx];
+    // BVH 和暴力查找时死亡的实例仍然会被遍历到
+    if (other_instance.dead != 0u) {
+        return vec3f(0.0, 0.0, 0.0);
+    }
+    let rel_pos = my_instance.position - other_instance.position;
+    let distance = length(rel_pos);
+    let radius_sum = my_instance.radius + other_instance.radius;
+
+    if (distance < radius_sum) {
+        contact_count = contact_count + 1u;
+        // 唤醒被碰到的小球，它最晚在下一轮中重新参与计算
+        if (wakes_neighbors) {
+            results[other_instance.id].still_time = 0.0;
+        }
+    }
+    return pair_force(rel_pos, distance, radius_sum);
+}
+
+// 在均匀网格中查找相邻的小球，累加它们的力
+fn grid_force(my_idx: u32, my_instance: Instance, wakes_neighbors: bool) -> vec3f {
+    // 二维时网格只有一层
+    let grid_count = grid_counts(params.boundary, params.grid_size, params.dimensions);
+
+    var total_force = vec3f(0.0, 0.0, 0.0);
+    // 只查找相邻的 3x3x3 个网格，要求网格大小不小于最大直径，见 compute.rs 中的 min_grid_size
     for (var dx = -1; dx <= 1; dx = dx + 1) {
         for (var dy = -1; dy <= 1; dy = dy + 1) {
             for (var dz = -1; dz <= 1; dz = dz + 1) {
@@ -64,7 +155,7 @@ fn main(@builtin(global_invocation_id) id: vec3<u32>) {
 
                 let neigh_grid = vec3u(neigh_grid_i);
 
-                if (neigh_grid.x >= grid_count_x || neigh_grid.y >= grid_count_y || neigh_grid.z >= grid_count_z) {
+                if (any(neigh_grid >= grid_count)) {
                     continue;
                 }
 
@@ -76,64 +167,259 @@ fn main(@builtin(global_invocation_id) id: vec3<u32>) {
                     if (i == my_idx) {
                         continue;
                     }
-                    let other_instance = instances[i];
-                    let rel_pos = my_instance.position - other_instance.position;
-                    let distance = length(rel_pos);
-                    let delta = -distance + my_instance.radius + other_instance.radius;
-                    
-                    if (delta > 0.0) {                      // 碰撞
-                        let normal = normalize(rel_pos);    // 碰撞法线
-                        let f = K * delta * normal;         // 碰撞力
-                        total_force = total_force + f;      // 累加所有的力
-                    }
+                    // 累加所有的力
+                    total_force = total_force + interact(my_instance, i, wakes_neighbors);
                 }
             }
         }
     }
+    return total_force;
+}
+
+// 遍历 LBVH 的深度上限，超过时更深的子树被跳过
+const BVH_STACK_SIZE: u32 = 64u;
+
+// 在 LBVH 中查找包围盒与查询盒相交的小球，累加它们的力。
+// 接触只需要查询半径那么大的盒子；其他作用力模型在一个格子的距离内都有力，查询盒也要相应地放大
+fn bvh_force(my_idx: u32, my_instance: Instance, wakes_neighbors: bool) -> vec3f {
+    var total_force = vec3f(0.0, 0.0, 0.0);
+    if (arrayLength(&instances) < 2u) {
+        return total_force;
+    }
+    var extent = my_instance.radius;
+    if (params.force_model != FORCE_MODEL_CONTACT) {
+        extent = max(extent, params.grid_size);
+    }
+    let query_min = my_instance.position - vec3f(extent);
+    let query_max = my_instance.position + vec3f(extent);
+
+    var stack: array<u32, BVH_STACK_SIZE>;
+    stack[0] = 0u;
+    var top = 1u;
+    while (top > 0u) {
+        top = top - 1u;
+        let index = stack[top];
+        if ((index & BVH_LEAF) != 0u) {
+            let other_idx = index & ~BVH_LEAF;
+            if (other_idx != my_idx) {
+                total_force = total_force + interact(my_instance, other_idx, wakes_neighbors);
+            }
+            continue;
+        }
+        let node = bvh_nodes[index];
+        if (any(query_max < node.min) || any(query_min > node.max)) {
+            continue;
+        }
+        if (top + 2u <= BVH_STACK_SIZE) {
+            stack[top] = node.left;
+            stack[top + 1u] = node.right;
+            top = top + 2u;
+        }
+    }
+    return total_force;
+}
+
+// 和其他所有小球比较，累加它们的力
+fn brute_force_force(my_idx: u32, my_instance: Instance, wakes_neighbors: bool) -> vec3f {
+    var total_force = vec3f(0.0, 0.0, 0.0);
+    let len = arrayLength(&instances);
+    for (var i = 0u; i < len; i = i + 1u) {
+        if (i != my_idx) {
+            total_force = total_force + interact(my_instance, i, wakes_neighbors);
+        }
+    }
+    return total_force;
+}
+
+// 把实例标记为死亡：停在原地，不再参与碰撞，结果也带上死亡标记
+fn mark_dead(my_idx: u32, my_instance: Instance) {
+    let inst_id = my_instance.id;
+    results[inst_id].position = my_instance.position;
+    results[inst_id].velocity = vec3f(0.0, 0.0, 0.0);
+    results[inst_id].acceleration = vec3f(0.0, 0.0, 0.0);
+    results[inst_id].still_time = 0.0;
+    results[inst_id].collision_heat = 0.0;
+    results[inst_id].dead = 1u;
+    instances_out[my_idx] = my_instance;
+    instances_out[my_idx].velocity = vec3f(0.0, 0.0, 0.0);
+    instances_out[my_idx].dead = 1u;
+}
+
+// 速度大小超过 max_speed 时按比例缩小，方向不变
+fn clamp_speed(velocity: vec3f) -> vec3f {
+    let speed = length(velocity);
+    if (speed > params.max_speed) {
+        return velocity * (params.max_speed / speed);
+    }
+    return velocity;
+}
+
+// WORKGROUP_SIZE 在创建计算节点时被替换成 ComputeState 的 workgroup_size
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let boundary = params.boundary;
+    let time_step = params.time_step;
+
+    // 暂时不考虑速度，如果距离小于两个物体的半径之和，就认为发生了碰撞，将结果写入输出
+    let my_idx = id.x;
+    let len = arrayLength(&instances);
+    if (my_idx >= len) {
+        return;        
+    }
+    let my_instance = instances[my_idx];
+    let inst_id = my_instance.id;
+
+    // 死亡的实例原样保留，直到 CPU 读回结果之后把它移除
+    if (my_instance.dead != 0u) {
+        mark_dead(my_idx, my_instance);
+        return;
+    }
 
-    let acceleration = total_force + vec3f(0.0, -G, 0.0);        // 加速度
+    // 休眠的小球不积分也不和其他小球求力，原地保持静止，但仍然留在网格中，醒着的小球照常和它碰撞
+    let still_time = results[inst_id].still_time;
+    let heat_decay = exp(-time_step / HEAT_DECAY_TIME);
+    if (params.sleep_velocity > 0.0 && still_time >= params.sleep_time) {
+        results[inst_id].position = my_instance.position;
+        results[inst_id].velocity = vec3f(0.0, 0.0, 0.0);
+        results[inst_id].acceleration = vec3f(0.0, 0.0, 0.0);
+        results[inst_id].collision_heat = results[inst_id].collision_heat * heat_decay;
+        results[inst_id].dead = 0u;
+        instances_out[id.x] = my_instance;
+        instances_out[id.x].velocity = vec3f(0.0, 0.0, 0.0);
+        return;
+    }
+    // 运动明显快于休眠速度时才唤醒接触到的小球，避免堆里的微小抖动让整堆一直醒着
+    let wakes_neighbors = params.sleep_velocity > 0.0
+        && length(my_instance.velocity) > WAKE_FACTOR * params.sleep_velocity;
+
+    // 所有小球的质量都取 1，力直接作为加速度积分
+    var total_force = vec3f(0.0, 0.0, 0.0);
+    if (params.broad_phase == BROAD_PHASE_BVH) {
+        total_force = bvh_force(my_idx, my_instance, wakes_neighbors);
+    } else if (params.broad_phase == BROAD_PHASE_BRUTE_FORCE) {
+        total_force = brute_force_force(my_idx, my_instance, wakes_neighbors);
+    } else {
+        total_force = grid_force(my_idx, my_instance, wakes_neighbors);
+    }
+
+    let acceleration = total_force + params.gravity;        // 加速度
     var velocity = my_instance.velocity + acceleration * time_step;     // 速度
+    if (params.integrator == INTEGRATOR_VERLET) {
+        // 速度 Verlet：补完上一步的速度更新，用上一步和这一步加速度的平均值
+        let previous_acceleration = results[my_instance.id].acceleration;
+        velocity = my_instance.velocity + (previous_acceleration + acceleration) * 0.5 * time_step;
+    }
+    // 严重重叠的小球受到的力极大，限制速度之后 Verlet 的位置更新也不会一步跳得太远
+    velocity = clamp_speed(velocity);
 
     // 和边界的碰撞
     // x 方向
-    let delta_x_pos = my_instance.position.x + my_instance.radius - boundary;
+    let delta_x_pos = my_instance.position.x + my_instance.radius - boundary.x;
     if(delta_x_pos > 0.0) {     // 正方向
         velocity.x = - abs(velocity.x);
     }
-    let delta_x_neg = my_instance.position.x - my_instance.radius + boundary;
+    let delta_x_neg = my_instance.position.x - my_instance.radius + boundary.x;
     if(delta_x_neg < 0.0) {     // 负方向
         velocity.x = abs(velocity.x);
     }
     // y 方向
-    let delta_y_pos = my_instance.position.y + my_instance.radius - boundary;
+    let delta_y_pos = my_instance.position.y + my_instance.radius - boundary.y;
     if(delta_y_pos > 0.0) {     // 正方向
         velocity.y = - abs(velocity.y);
     }
-    let delta_y_neg = my_instance.position.y - my_instance.radius + boundary;
+    let delta_y_neg = my_instance.position.y - my_instance.radius + boundary.y;
     if(delta_y_neg < 0.0) {     // 负方向
         velocity.y = abs(velocity.y);
     }
     // z 方向
-    let delta_z_pos = my_instance.position.z + my_instance.radius - boundary;
+    let delta_z_pos = my_instance.position.z + my_instance.radius - boundary.z;
     if(delta_z_pos > 0.0) {     // 正方向
         velocity.z = - abs(velocity.z);
     }
-    let delta_z_neg = my_instance.position.z - my_instance.radius + boundary;
+    let delta_z_neg = my_instance.position.z - my_instance.radius + boundary.z;
     if(delta_z_neg < 0.0) {     // 负方向
         velocity.z = abs(velocity.z);
     }    
     
     
-    // 计算位置
-    let position = my_instance.position + my_instance.velocity * time_step + acceleration * time_step * time_step * 0.5;
+    // 计算位置，Verlet 使用这一步开始时的速度（已经处理过边界反弹）
+    var start_velocity = my_instance.velocity;
+    if (params.integrator == INTEGRATOR_VERLET) {
+        start_velocity = velocity;
+    }
+    var position = my_instance.position + start_velocity * time_step + acceleration * time_step * time_step * 0.5;
 
-    let inst_id = instances[my_idx].id;
+    // 和静态平面的碰撞：把穿透的小球推回平面外侧，并按恢复系数反弹法向速度；球心越过吸收平面的小球死亡
+    for (var i = 0u; i < min(planes.count, MAX_PLANES); i = i + 1u) {
+        let plane = planes.planes[i];
+        if (plane.kind == PLANE_KILL) {
+            if (dot(position - plane.point, plane.normal) < 0.0) {
+                var dead_instance = my_instance;
+                dead_instance.position = position;
+                mark_dead(my_idx, dead_instance);
+                return;
+            }
+            continue;
+        }
+        let penetration = my_instance.radius - dot(position - plane.point, plane.normal);
+        if (penetration > 0.0) {
+            position = position + penetration * plane.normal;
+            let v_n = dot(velocity, plane.normal);
+            if (v_n < 0.0) {
+                velocity = velocity - (1.0 + params.restitution) * v_n * plane.normal;
+            }
+        }
+    }
+
+    // 和静态网格的碰撞：暴力遍历所有三角形，沿着朝向球心一侧的法线把小球推出去
+    let triangle_count = min(static_mesh.count, arrayLength(&static_mesh.triangles));
+    for (var i = 0u; i < triangle_count; i = i + 1u) {
+        let triangle = static_mesh.triangles[i];
+        let closest = closest_point_on_triangle(position, triangle.a, triangle.b, triangle.c);
+        let to_center = position - closest;
+        if (dot(to_center, to_center) >= my_instance.radius * my_instance.radius) {
+            continue;
+        }
+        var normal = triangle.normal;
+        if (dot(position - triangle.a, normal) < 0.0) {
+            normal = -normal;
+        }
+        let penetration = my_instance.radius - dot(to_center, normal);
+        if (penetration > 0.0) {
+            position = position + penetration * normal;
+            let v_n = dot(velocity, normal);
+            if (v_n < 0.0) {
+                velocity = velocity - (1.0 + params.restitution) * v_n * normal;
+            }
+        }
+    }
+
+    // 与速度成正比的阻尼，有重力时小球会趋于一个终端速度
+    velocity = velocity * max(1.0 - params.drag * time_step, 0.0);
+
+    // 二维时去掉 z 方向的分量，小球始终留在 z = 0 的平面内
+    if (params.dimensions == DIMENSIONS_2D) {
+        position.z = 0.0;
+        velocity.z = 0.0;
+    }
 
     // 将结果写入输出
     results[inst_id].position = position;
     let v_len = length(velocity);
-    results[inst_id].velocity = velocity * (1.0 - AR * v_len * v_len * v_len * time_step);
-    // 也写回到输入，为了连续模拟
-    instances[id.x].position = results[inst_id].position;
-    instances[id.x].velocity = results[inst_id].velocity;
+    // 空气阻力在速度很大时会让速度反向放大，写回之前再限制一次
+    results[inst_id].velocity = clamp_speed(velocity * (1.0 - AR * v_len * v_len * v_len * time_step));
+    results[inst_id].acceleration = acceleration;
+    results[inst_id].collision_heat =
+        results[inst_id].collision_heat * heat_decay + f32(contact_count) * time_step;
+    results[inst_id].dead = 0u;
+    // 累计低速的时间，速度一旦超过阈值就重新计时
+    if (length(results[inst_id].velocity) < params.sleep_velocity) {
+        results[inst_id].still_time = still_time + time_step;
+    } else {
+        results[inst_id].still_time = 0.0;
+    }
+    // 也写到输出的实例 buffer，为了连续模拟
+    instances_out[id.x] = my_instance;
+    instances_out[id.x].position = results[inst_id].position;
+    instances_out[id.x].velocity = results[inst_id].velocity;
 }
\ No newline at end of file
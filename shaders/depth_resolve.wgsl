@@ -0,0 +1,17 @@
+// 把多重采样的深度解析成单采样的深度：每个像素取第 0 个采样，写入单采样深度附件
+
+@group(0) @binding(0)
+var t_depth: texture_depth_multisampled_2d;
+
+// 覆盖整个屏幕的三角形，不需要顶点缓冲区
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4f {
+    let uv = vec2f(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4f(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) frag_coord: vec4f) -> @builtin(frag_depth) f32 {
+    // 两个深度纹理同样大小，按像素读取；深度不能取平均，边缘处取平均会得到两个表面之间的位置
+    return textureLoad(t_depth, vec2i(frag_coord.xy), 0);
+}
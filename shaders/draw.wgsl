@@ -3,6 +3,7 @@
 struct Camera {
     view_pos: vec4f,
     view_proj: mat4x4f,
+    view: mat4x4f,
 }
 @group(1) @binding(0)
 var<uniform> camera: Camera;
@@ -10,9 +11,25 @@ var<uniform> camera: Camera;
 struct Light {
     position: vec3f,
     color: vec3f,
+    // 光源空间的视图投影矩阵
+    view_proj: mat4x4f,
 }
 @group(2) @binding(0)
-var<uniform> light: Light;
+var<storage, read> lights: array<Light>;
+
+// 与单个光源无关的光照参数
+struct LightGlobals {
+    count: u32,
+    exposure: f32,
+    ambient: vec3f,
+}
+@group(2) @binding(1)
+var<uniform> light_globals: LightGlobals;
+
+// 按曝光把线性的颜色压缩到 [0, 1)，避免多个光源叠加之后过曝
+fn tonemap(color: vec3f) -> vec3f {
+    return 1.0 - exp(-color * light_globals.exposure);
+}
 
 struct VertexInput {
     @location(0) position: vec3f,
@@ -29,14 +46,17 @@ struct InstanceInput {
     @location(9) normal_matrix_0: vec3f,
     @location(10) normal_matrix_1: vec3f,
     @location(11) normal_matrix_2: vec3f,
+    @location(12) color: vec4f,
 }
 
 struct VertexOutput {
-    @builtin(position) clip_position: vec4f,
+    @invariant @builtin(position) clip_position: vec4f,
     @location(0) tex_coords: vec2f,
-    @location(1) tangent_position: vec3f,
-    @location(2) tangent_light_position: vec3f,
-    @location(3) tangent_view_position: vec3f,
+    @location(1) world_position: vec3f,
+    @location(2) world_normal: vec3f,
+    @location(3) world_tangent: vec3f,
+    @location(4) world_bitangent: vec3f,
+    @location(5) color: vec4f,
 }
 
 @vertex
@@ -56,24 +76,18 @@ fn vs_main(
         instance.normal_matrix_2,
     );
 
-    // Construct the tangent matrix
-    let world_normal = normalize(normal_matrix * model.normal);
-    let world_tangent = normalize(normal_matrix * model.tangent);
-    let world_bitangent = normalize(normal_matrix * model.bitangent);
-    let tangent_matrix = transpose(mat3x3f(
-        world_tangent,
-        world_bitangent,
-        world_normal,
-    ));
-
     let world_position = model_matrix * vec4f(model.position, 1.0);
 
+    // 光源数量不固定，所以在世界空间中计算光照，把切线空间的基向量传给片元着色器
     var out: VertexOutput;
     out.clip_position = camera.view_proj * world_position;
     out.tex_coords = model.tex_coords;
-    out.tangent_position = tangent_matrix * world_position.xyz;
-    out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
-    out.tangent_light_position = tangent_matrix * light.position;
+    out.world_position = world_position.xyz;
+    // 模型矩阵带有按半径的均匀缩放，法线矩阵因此带有 1 / radius 的缩放，方向不变，归一化即可
+    out.world_normal = normalize(normal_matrix * model.normal);
+    out.world_tangent = normalize(normal_matrix * model.tangent);
+    out.world_bitangent = normalize(normal_matrix * model.bitangent);
+    out.color = instance.color;
     return out;
 }
 
@@ -88,28 +102,113 @@ var t_normal: texture_2d<f32>;
 @group(0) @binding(3)
 var s_normal: sampler;
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4f {
-    let object_color: vec4f = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+// 材质的高光参数，来自 MTL 的 Ns 和 Ks
+struct Material {
+    shininess: f32,
+    specular_strength: f32,
+}
+@group(0) @binding(4)
+var<uniform> material: Material;
+
+@group(3) @binding(0)
+var t_shadow: texture_depth_2d;
+@group(3) @binding(1)
+var s_shadow: sampler_comparison;
+// 屏幕空间环境光遮蔽，与表面同样大小，没有开启时为 1
+@group(3) @binding(2)
+var t_ao: texture_2d<f32>;
+
+// 采样阴影贴图，返回被照亮的比例，1.0 表示完全不在阴影中
+// 只有第一个光源投射阴影
+fn fetch_shadow(world_position: vec3f) -> f32 {
+    let light_space = lights[0].view_proj * vec4f(world_position, 1.0);
+    let ndc = light_space.xyz / light_space.w;
+    // NDC 的 y 轴朝上，纹理坐标的 y 轴朝下
+    let uv = ndc.xy * vec2f(0.5, -0.5) + vec2f(0.5, 0.5);
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0 || ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    // 3x3 PCF
+    let texel = 1.0 / vec2f(textureDimensions(t_shadow));
+    var visibility = 0.0;
+    for (var x = -1; x <= 1; x = x + 1) {
+        for (var y = -1; y <= 1; y = y + 1) {
+            let offset = vec2f(f32(x), f32(y)) * texel;
+            visibility = visibility + textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, ndc.z);
+        }
+    }
+    return visibility / 9.0;
+}
+
+struct Shading {
+    color: vec4f,
+    // 世界空间中的着色法线，包含法线贴图的扰动
+    normal: vec3f,
+}
+
+fn shade(in: VertexOutput) -> Shading {
+    // 漫反射纹理乘上实例颜色
+    let object_color: vec4f = textureSample(t_diffuse, s_diffuse, in.tex_coords) * in.color;
     let object_normal: vec4f = textureSample(t_normal, s_normal, in.tex_coords);
-    
-    // We don't need (or want) much ambient light, so 0.1 is fine
-    let ambient_strength = 0.1;
-    let ambient_color = light.color * ambient_strength;
 
-    // Create the lighting vectors
+    // 把法线贴图中的切线空间法线变换到世界空间
     let tangent_normal = object_normal.xyz * 2.0 - 1.0;
-    let light_dir = normalize(in.tangent_light_position - in.tangent_position);
-    let view_dir = normalize(in.tangent_view_position - in.tangent_position);
-    let half_dir = normalize(view_dir + light_dir);
+    let tangent_to_world = mat3x3f(
+        normalize(in.world_tangent),
+        normalize(in.world_bitangent),
+        normalize(in.world_normal),
+    );
+    let normal = normalize(tangent_to_world * tangent_normal);
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
 
-    let diffuse_strength = max(dot(tangent_normal, light_dir), 0.0);
-    let diffuse_color = light.color * diffuse_strength;
+    var lit_color = vec3f(0.0, 0.0, 0.0);
+    for (var i = 0u; i < light_globals.count; i = i + 1u) {
+        let light = lights[i];
 
-    let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), 32.0);
-    let specular_color = specular_strength * light.color;
+        // Create the lighting vectors
+        let light_dir = normalize(light.position - in.world_position);
+        let half_dir = normalize(view_dir + light_dir);
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+        let diffuse_strength = max(dot(normal, light_dir), 0.0);
+        let diffuse_color = light.color * diffuse_strength;
 
-    return vec4f(result, object_color.a);
-}
\ No newline at end of file
+        let specular_strength = material.specular_strength * pow(max(dot(normal, half_dir), 0.0), material.shininess);
+        let specular_color = specular_strength * light.color;
+
+        var shadow = 1.0;
+        if (i == 0u) {
+            shadow = fetch_shadow(in.world_position);
+        }
+        lit_color = lit_color + shadow * (diffuse_color + specular_color);
+    }
+
+    // 环境光遮蔽只影响环境光
+    let ao = textureLoad(t_ao, vec2i(in.clip_position.xy), 0).r;
+    let result = tonemap((light_globals.ambient * ao + lit_color) * object_color.xyz);
+
+    return Shading(vec4f(result, object_color.a), normal);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    return shade(in).color;
+}
+
+struct NormalsOutput {
+    @location(0) color: vec4f,
+    // 视图空间的法线，w 为 1 表示这个像素上有小球，背景清空为 0
+    @location(1) view_normal: vec4f,
+}
+
+// 同时把视图空间的法线写入第二个渲染目标，供后处理使用
+@fragment
+fn fs_normals(in: VertexOutput) -> NormalsOutput {
+    let shading = shade(in);
+    // 视图矩阵只有旋转和平移，左上角的 3x3 就能变换法线
+    let view_rotation = mat3x3f(camera.view[0].xyz, camera.view[1].xyz, camera.view[2].xyz);
+    var out: NormalsOutput;
+    out.color = shading.color;
+    out.view_normal = vec4f(normalize(view_rotation * shading.normal), 1.0);
+    return out;
+}
@@ -0,0 +1,66 @@
+// 地面网格：以相机为中心的一大块水平平面，片元着色器按世界坐标画出网格线，越远越透明
+
+struct Camera {
+    view_pos: vec4f,
+    view_proj: mat4x4f,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Floor {
+    // 地面的高度
+    height: f32,
+    // 相邻网格线的间距
+    spacing: f32,
+    // 网格完全消失的水平距离，平面的半边长也取这个值
+    fade_distance: f32,
+    _padding: f32,
+}
+@group(1) @binding(0)
+var<uniform> floor_params: Floor;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4f,
+    @location(0) world_position: vec3f,
+};
+
+// 两个三角形组成的正方形，跟着相机在水平方向上移动，看起来像无限大的平面
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var corners = array<vec2f, 6>(
+        vec2f(-1.0, -1.0), vec2f(1.0, -1.0), vec2f(1.0, 1.0),
+        vec2f(-1.0, -1.0), vec2f(1.0, 1.0), vec2f(-1.0, 1.0),
+    );
+    let corner = corners[vertex_index] * floor_params.fade_distance;
+    let world_position = vec3f(
+        camera.view_pos.x + corner.x,
+        floor_params.height,
+        camera.view_pos.z + corner.y,
+    );
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4f(world_position, 1.0);
+    out.world_position = world_position;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    let coord = in.world_position.xz / floor_params.spacing;
+    // 按屏幕空间的导数抗锯齿，线宽始终约为一个像素
+    let derivative = fwidth(coord);
+    let distance_to_line = abs(fract(coord - 0.5) - 0.5) / derivative;
+    let line = 1.0 - min(min(distance_to_line.x, distance_to_line.y), 1.0);
+
+    let distance = length(in.world_position.xz - camera.view_pos.xz);
+    let fade = 1.0 - smoothstep(0.5 * floor_params.fade_distance, floor_params.fade_distance, distance);
+
+    // 穿过原点的两条轴线稍亮一些
+    let axis = abs(in.world_position.xz) / derivative / floor_params.spacing;
+    let on_axis = min(axis.x, axis.y) < 1.0;
+    let color = select(vec3f(0.6), vec3f(0.9), on_axis);
+    let alpha = line * fade * 0.5;
+    if alpha <= 0.0 {
+        discard;
+    }
+    return vec4f(color, alpha);
+}
@@ -0,0 +1,73 @@
+///#include "header.wgsl"
+
+// 网格调试视图：每个实例对应一个网格单元，画出它的 12 条棱，空的单元被裁剪掉，
+// 非空的单元按其中小球的数量着色
+
+struct Camera {
+    view_pos: vec4f,
+    view_proj: mat4x4f,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// 模拟参数和 CellIndex 定义在 header.wgsl 中，创建管线时拼在这个文件前面
+@group(1) @binding(0)
+var<uniform> params: Parameters;
+
+@group(1) @binding(1)
+var<storage, read> cells: array<CellIndex>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4f,
+    @location(0) color: vec4f,
+}
+
+// 颜色达到最红时单元中的小球数量
+const FULL_COUNT: f32 = 8.0;
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) cell: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    let count = cells[cell].end - cells[cell].start;
+    if (count == 0u || params.grid_size <= 0.0) {
+        // 放到裁剪空间之外，整条线都会被裁剪
+        out.clip_position = vec4f(2.0, 2.0, 2.0, 1.0);
+        out.color = vec4f(0.0);
+        return out;
+    }
+
+    // 单元的下标与 collision.wgsl 中的 get_grid_from_index 一致
+    let grid_count = grid_counts(params.boundary, params.grid_size, params.dimensions);
+    let grid = grid_from_cell_index(cell, grid_count, params.cell_indexing, params.dimensions);
+
+    // 12 条棱按平行的轴分成 3 组，每组 4 条；棱的两个端点只在这条轴上不同
+    let edge = vertex_index / 2u;
+    let axis = edge / 4u;
+    let a = f32(edge & 1u);
+    let b = f32((edge >> 1u) & 1u);
+    let t = f32(vertex_index & 1u);
+    var corner = vec3f(t, a, b);
+    if (axis == 1u) {
+        corner = vec3f(a, t, b);
+    } else if (axis == 2u) {
+        corner = vec3f(a, b, t);
+    }
+
+    var position = -params.boundary + (vec3f(grid) + corner) * params.grid_size;
+    // 二维时只有一层单元，画成以 z = 0 为中心、厚度为一个格子的盒子
+    if (params.dimensions == DIMENSIONS_2D) {
+        position.z = (corner.z - 0.5) * params.grid_size;
+    }
+    out.clip_position = camera.view_proj * vec4f(position, 1.0);
+    let occupancy = clamp(f32(count) / FULL_COUNT, 0.0, 1.0);
+    out.color = vec4f(mix(vec3f(0.2, 0.6, 1.0), vec3f(1.0, 0.2, 0.1), occupancy), 0.35);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    return in.color;
+}
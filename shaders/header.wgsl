@@ -5,27 +5,88 @@ struct Instance {
     id: u32,
     radius: f32,
     cell_index: u32,
-    // padding 4 bytes
+    // 非 0 表示这个位置上没有小球：被吸收平面移除的小球，或者实例少于容量时的占位
+    dead: u32,
     position: vec3f,
     velocity: vec3f,
 }
 
 struct Result {
     position: vec3f,
-    // padding 4 bytes
+    // 速度连续低于 sleep_velocity 的时间，单位是秒，达到 sleep_time 之后小球进入休眠
+    still_time: f32,
     velocity: vec3f,
-    // padding 4 bytes
+    // 非 0 表示小球已经死亡，CPU 读回之后把它移除
+    dead: u32,
+    // 这一步的加速度，Verlet 积分在下一步中读取
+    acceleration: vec3f,
+    // 按 HEAT_DECAY_TIME 衰减的接触次数，每一轮加上接触的小球数量乘以时间步长，只用于着色
+    collision_heat: f32,
 }
 
+// 模拟参数，以 uniform buffer 绑定在 group 0
 struct Parameters {
+    // 边界盒子在三个轴上的半边长，范围是 [-boundary, boundary]，如果距离 boundary 的平面小于半径，就认为发生了碰撞
+    boundary: vec3f,
+    // padding 4 bytes
+    _padding_boundary: u32,
+    // 重力加速度，CPU 每一步按重力的大小和方向重新写入
+    gravity: vec3f,
+    // padding 4 bytes
+    _padding_gravity: u32,
     // 时间步长，单位是秒
     time_step: f32,
-    // 边界，[-boundary, boundary]，如果距离 boundary 的平面小于半径，就认为发生了碰撞
-    boundary: f32,
     // 从 -boundary 到 boundary 的格子大小，注意总共有三维
     grid_size: f32, 
+    // 与平面、网格碰撞时的恢复系数
+    restitution: f32,
+    // 积分方法，取值见 INTEGRATOR_*
+    integrator: u32,
+    // 速度低于 sleep_velocity 持续 sleep_time 秒的小球进入休眠，sleep_velocity 为 0 时不休眠
+    sleep_velocity: f32,
+    sleep_time: f32,
+    // 与速度成正比的阻尼系数，单位是 1/秒，0 表示没有阻尼
+    drag: f32,
+    // 模拟的维数，取值见 DIMENSIONS_*
+    dimensions: u32,
+    // 小球之间的作用力模型，取值见 FORCE_MODEL_*
+    force_model: u32,
+    // 作用力的刚度，Lennard-Jones 势按平衡位置附近的刚度换算势阱深度
+    stiffness: f32,
+    // 平衡距离，0 表示取两个小球的半径之和
+    equilibrium_distance: f32,
+    // 碰撞阶段查找相邻小球的方法，取值见 BROAD_PHASE_*
+    broad_phase: u32,
+    // 网格单元的编号方式，取值见 CELL_INDEXING_*
+    cell_indexing: u32,
+    // 速度大小的上限，防止初始重叠的小球被弹出极大的速度
+    max_speed: f32,
+    // 作为 uniform 时大小按 16 字节对齐
+    _padding3: vec2u,
 }
 
+// 积分方法，与 Rust 中的 Integrator 一致
+const INTEGRATOR_EULER: u32 = 0u;
+const INTEGRATOR_VERLET: u32 = 1u;
+
+// 模拟的维数，与 Rust 中的 Dimensions 一致；二维时所有小球都在 z = 0 的平面内，网格只有一层
+const DIMENSIONS_2D: u32 = 2u;
+const DIMENSIONS_3D: u32 = 3u;
+
+// 作用力模型，与 Rust 中的 ForceModel 一致
+const FORCE_MODEL_CONTACT: u32 = 0u;
+const FORCE_MODEL_SPRING: u32 = 1u;
+const FORCE_MODEL_LENNARD_JONES: u32 = 2u;
+
+// 查找相邻小球的方法，与 Rust 中的 BroadPhase 一致
+const BROAD_PHASE_GRID: u32 = 0u;
+const BROAD_PHASE_BVH: u32 = 1u;
+const BROAD_PHASE_BRUTE_FORCE: u32 = 2u;
+
+// 网格单元的编号方式，与 Rust 中的 CellIndexing 一致
+const CELL_INDEXING_LINEAR: u32 = 0u;
+const CELL_INDEXING_MORTON: u32 = 1u;
+
 // 双调排序的参数
 struct SortParams {
     j: u32, 
@@ -37,16 +98,173 @@ struct CellIndex {
     end: u32,
 }
 
+// 死亡的实例使用的单元编号，排序之后排在所有存活的实例之后，不属于任何网格单元
+const DEAD_CELL: u32 = 0xffffffffu;
+
+// LBVH 的内部节点，n 个小球有 n - 1 个，根是 0 号节点。包围盒包含子树中所有的小球（算上半径）；
+// 子节点带有 BVH_LEAF 标记时，去掉标记之后是排序后的实例下标，否则是内部节点的下标
+struct BvhNode {
+    min: vec3f,
+    left: u32,
+    max: vec3f,
+    right: u32,
+}
+
+const BVH_LEAF: u32 = 0x80000000u;
+
+// 把 10 位整数的每一位之间插入两个 0
+fn expand_bits(v: u32) -> u32 {
+    var x = v & 0x3ffu;
+    x = (x * 0x00010001u) & 0xff0000ffu;
+    x = (x * 0x00000101u) & 0x0f00f00fu;
+    x = (x * 0x00000011u) & 0xc30c30c3u;
+    x = (x * 0x00000005u) & 0x49249249u;
+    return x;
+}
+
+// 三个 10 位整数交错成 30 位的 Morton 码，x 在最低位
+fn morton_code(v: vec3u) -> u32 {
+    return expand_bits(v.x) | (expand_bits(v.y) << 1u) | (expand_bits(v.z) << 2u);
+}
+
+// expand_bits 的逆运算，取出每三位中的最低位
+fn compact_bits(v: u32) -> u32 {
+    var x = v & 0x09249249u;
+    x = (x ^ (x >> 2u)) & 0x030c30c3u;
+    x = (x ^ (x >> 4u)) & 0x0300f00fu;
+    x = (x ^ (x >> 8u)) & 0xff0000ffu;
+    x = (x ^ (x >> 16u)) & 0x000003ffu;
+    return x;
+}
+
+fn morton_decode(code: u32) -> vec3u {
+    return vec3u(compact_bits(code), compact_bits(code >> 1u), compact_bits(code >> 2u));
+}
+
+// 把 16 位整数的每一位之间插入一个 0，用于二维的 Morton 码
+fn expand_bits_2d(v: u32) -> u32 {
+    var x = v & 0x0000ffffu;
+    x = (x ^ (x << 8u)) & 0x00ff00ffu;
+    x = (x ^ (x << 4u)) & 0x0f0f0f0fu;
+    x = (x ^ (x << 2u)) & 0x33333333u;
+    x = (x ^ (x << 1u)) & 0x55555555u;
+    return x;
+}
+
+fn compact_bits_2d(v: u32) -> u32 {
+    var x = v & 0x55555555u;
+    x = (x ^ (x >> 1u)) & 0x33333333u;
+    x = (x ^ (x >> 2u)) & 0x0f0f0f0fu;
+    x = (x ^ (x >> 4u)) & 0x00ff00ffu;
+    x = (x ^ (x >> 8u)) & 0x0000ffffu;
+    return x;
+}
+
+// 二维的 Morton 码只交错 x 和 y，这样单元的编号不超过边长的平方
+fn morton_code_2d(v: vec2u) -> u32 {
+    return expand_bits_2d(v.x) | (expand_bits_2d(v.y) << 1u);
+}
+
+fn morton_decode_2d(code: u32) -> vec2u {
+    return vec2u(compact_bits_2d(code), compact_bits_2d(code >> 1u));
+}
+
+// 每个轴上的网格数量，与 compute.rs 中的 grid_count 一致；二维时网格只有 z = 0 这一层
+fn grid_counts(boundary: vec3f, grid_size: f32, dimensions: u32) -> vec3u {
+    var counts = vec3u(ceil(boundary * 2.0 / grid_size) + 0.5);
+    if (dimensions == DIMENSIONS_2D) {
+        counts.z = 1u;
+    }
+    return counts;
+}
+
+// 网格坐标到单元下标。线性编号是 x + y * nx + z * nx * ny；Morton 编号让空间上相邻的单元在下标上也尽量接近，
+// 排序之后相邻单元中的小球在 buffer 中靠得更近
+fn cell_index_from_grid(grid_index: vec3u, grid_count: vec3u, cell_indexing: u32, dimensions: u32) -> u32 {
+    if (cell_indexing == CELL_INDEXING_MORTON) {
+        if (dimensions == DIMENSIONS_2D) {
+            return morton_code_2d(grid_index.xy);
+        }
+        return morton_code(grid_index);
+    }
+    return grid_index.x + grid_index.y * grid_count.x + grid_index.z * grid_count.x * grid_count.y;
+}
+
+// cell_index_from_grid 的逆运算
+fn grid_from_cell_index(index: u32, grid_count: vec3u, cell_indexing: u32, dimensions: u32) -> vec3u {
+    if (cell_indexing == CELL_INDEXING_MORTON) {
+        if (dimensions == DIMENSIONS_2D) {
+            return vec3u(morton_decode_2d(index), 0u);
+        }
+        return morton_decode(index);
+    }
+    let layer = grid_count.x * grid_count.y;
+    let z = index / layer;
+    let y = (index - z * layer) / grid_count.x;
+    let x = index - z * layer - y * grid_count.x;
+    return vec3u(x, y, z);
+}
+
+// 鼠标拾取的射线，direction 已经归一化
+struct PickRay {
+    origin: vec3f,
+    direction: vec3f,
+}
+
+// 拾取和最近邻查询的结果，没有命中时 id 为 0xffffffff
+struct PickResult {
+    id: u32,
+    distance: f32,
+}
+
+// 最近邻查询，use_grid 不为 0 时先利用网格查找
+struct NearestQuery {
+    point: vec3f,
+    use_grid: u32,
+}
 
+// 静态平面，小球在 normal 指向的一侧
+struct Plane {
+    point: vec3f,
+    // 取值见 PLANE_*
+    kind: u32,
+    normal: vec3f,
+}
 
-// 力的常数 K
-const K: f32 = 1000.0;
+// 平面的种类，与 compute.rs 中的 PLANE_* 一致：普通平面把小球弹回，吸收平面移除球心越过它的小球
+const PLANE_SOLID: u32 = 0u;
+const PLANE_KILL: u32 = 1u;
+
+const MAX_PLANES: u32 = 16u;
+
+struct Planes {
+    count: u32,
+    planes: array<Plane, MAX_PLANES>,
+}
 
-// 重力加速度
-const G: f32 = 9.8;
 
-// 碰撞能量损失
-const E: f32 = 0.85;
+// 静态网格中的三角形，normal 是按 a、b、c 逆时针方向求出的单位法线
+struct Triangle {
+    a: vec3f,
+    b: vec3f,
+    c: vec3f,
+    normal: vec3f,
+}
+
+struct StaticMesh {
+    count: u32,
+    triangles: array<Triangle>,
+}
+
+// 间接绘制的参数，布局与 draw_indexed_indirect 读取的一致
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
 
-// 空气阻力
-const AR: f32 = 0.01;
\ No newline at end of file
+// 空气阻力系数 AR、collision_heat 衰减的时间 HEAT_DECAY_TIME 等 CPU 求解器也用到的常量
+// 由 compute.rs 中的 shader_header 根据 Rust 中的定义生成，放在这个文件之前
\ No newline at end of file
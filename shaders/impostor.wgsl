@@ -0,0 +1,161 @@
+// 球体替身（impostor）：每个实例画一个朝向相机的方块，在片元着色器中求视线与球的交点，
+// 得到法线和正确的深度，看起来和网格绘制的球体一样，但每个实例只有两个三角形
+
+struct Camera {
+    view_pos: vec4f,
+    view_proj: mat4x4f,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Light {
+    position: vec3f,
+    color: vec3f,
+    // 光源空间的视图投影矩阵
+    view_proj: mat4x4f,
+}
+@group(1) @binding(0)
+var<storage, read> lights: array<Light>;
+
+// 与单个光源无关的光照参数
+struct LightGlobals {
+    count: u32,
+    exposure: f32,
+    ambient: vec3f,
+}
+@group(1) @binding(1)
+var<uniform> light_globals: LightGlobals;
+
+// 按曝光把线性的颜色压缩到 [0, 1)，避免多个光源叠加之后过曝
+fn tonemap(color: vec3f) -> vec3f {
+    return 1.0 - exp(-color * light_globals.exposure);
+}
+
+@group(2) @binding(0)
+var t_shadow: texture_depth_2d;
+@group(2) @binding(1)
+var s_shadow: sampler_comparison;
+
+// 与网格绘制共用同一个实例 buffer，只用到模型矩阵和颜色
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4f,
+    @location(6) model_matrix_1: vec4f,
+    @location(7) model_matrix_2: vec4f,
+    @location(8) model_matrix_3: vec4f,
+    @location(12) color: vec4f,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4f,
+    @location(0) world_position: vec3f,
+    @location(1) center: vec3f,
+    @location(2) radius: f32,
+    @location(3) color: vec4f,
+}
+
+// 两个三角形组成的方块，逆时针
+var<private> quad_corners: array<vec2f, 6> = array<vec2f, 6>(
+    vec2f(-1.0, -1.0),
+    vec2f(1.0, -1.0),
+    vec2f(1.0, 1.0),
+    vec2f(-1.0, -1.0),
+    vec2f(1.0, 1.0),
+    vec2f(-1.0, 1.0),
+);
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    // 模型矩阵是均匀缩放加平移，缩放就是半径
+    let center = instance.model_matrix_3.xyz;
+    let radius = length(instance.model_matrix_0.xyz);
+
+    // 方块过球心并垂直于视线
+    let to_camera = camera.view_pos.xyz - center;
+    let distance = length(to_camera);
+    let forward = to_camera / distance;
+    var world_up = vec3f(0.0, 1.0, 0.0);
+    if (abs(forward.y) > 0.999) {
+        world_up = vec3f(1.0, 0.0, 0.0);
+    }
+    let right = normalize(cross(world_up, forward));
+    let up = cross(forward, right);
+
+    // 透视下球的轮廓比半径大，方块的半边长取 r * d / sqrt(d^2 - r^2) 才能完整覆盖
+    let half_size = radius * distance / sqrt(max(distance * distance - radius * radius, 1e-6));
+    let corner = quad_corners[vertex_index];
+    let world_position = center + (right * corner.x + up * corner.y) * half_size;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4f(world_position, 1.0);
+    out.world_position = world_position;
+    out.center = center;
+    out.radius = radius;
+    out.color = instance.color;
+    return out;
+}
+
+// 片元着色器
+
+struct FragmentOutput {
+    @location(0) color: vec4f,
+    @builtin(frag_depth) depth: f32,
+}
+
+// 采样阴影贴图，返回被照亮的比例，1.0 表示完全不在阴影中
+// 只有第一个光源投射阴影
+fn fetch_shadow(world_position: vec3f) -> f32 {
+    let light_space = lights[0].view_proj * vec4f(world_position, 1.0);
+    let ndc = light_space.xyz / light_space.w;
+    // NDC 的 y 轴朝上，纹理坐标的 y 轴朝下
+    let uv = ndc.xy * vec2f(0.5, -0.5) + vec2f(0.5, 0.5);
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0 || ndc.z > 1.0) {
+        return 1.0;
+    }
+    return textureSampleCompareLevel(t_shadow, s_shadow, uv, ndc.z);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    // 从相机出发经过当前片元的视线与球求交，取较近的交点
+    let origin = camera.view_pos.xyz;
+    let direction = normalize(in.world_position - origin);
+    let oc = origin - in.center;
+    let b = dot(oc, direction);
+    let c = dot(oc, oc) - in.radius * in.radius;
+    let discriminant = b * b - c;
+    if (discriminant < 0.0) {
+        discard;
+    }
+    let hit = origin + direction * (-b - sqrt(discriminant));
+    let normal = normalize(hit - in.center);
+
+    let view_dir = -direction;
+
+    var lit_color = vec3f(0.0, 0.0, 0.0);
+    for (var i = 0u; i < light_globals.count; i = i + 1u) {
+        let light = lights[i];
+
+        let light_dir = normalize(light.position - hit);
+        let half_dir = normalize(view_dir + light_dir);
+
+        let diffuse_color = light.color * max(dot(normal, light_dir), 0.0);
+        let specular_color = light.color * pow(max(dot(normal, half_dir), 0.0), 32.0);
+
+        var shadow = 1.0;
+        if (i == 0u) {
+            shadow = fetch_shadow(hit);
+        }
+        lit_color = lit_color + shadow * (diffuse_color + specular_color);
+    }
+
+    // 写入交点的深度，替身之间以及和网格之间的遮挡关系才正确
+    let clip = camera.view_proj * vec4f(hit, 1.0);
+
+    var out: FragmentOutput;
+    out.color = vec4f(tonemap((light_globals.ambient + lit_color) * in.color.xyz), in.color.a);
+    out.depth = clip.z / clip.w;
+    return out;
+}
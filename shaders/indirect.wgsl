@@ -0,0 +1,33 @@
+///#include "header.wgsl"
+
+@group(0) @binding(0)
+var<storage, read_write> instances: array<Instance>;
+
+@group(1) @binding(0)
+var<storage, read_write> draw_args: array<DrawIndexedIndirect>;
+
+var<workgroup> live_counts: array<u32, 64>;
+
+// 只启动一个工作组。实例 buffer 的长度是容量，其中还有占位和已经死亡的实例，
+// 所以先由每个线程数出一段中存活的实例，在工作组内求和，再填写所有网格的实例数量，其余参数在 CPU 上创建时已经写好
+@compute @workgroup_size(64)
+fn main(@builtin(local_invocation_index) local_idx: u32) {
+    var count = 0u;
+    for (var i = local_idx; i < arrayLength(&instances); i = i + 64u) {
+        if (instances[i].dead == 0u) {
+            count = count + 1u;
+        }
+    }
+    live_counts[local_idx] = count;
+    workgroupBarrier();
+    for (var stride = 32u; stride > 0u; stride = stride >> 1u) {
+        if (local_idx < stride) {
+            live_counts[local_idx] = live_counts[local_idx] + live_counts[local_idx + stride];
+        }
+        workgroupBarrier();
+    }
+
+    for (var mesh = local_idx; mesh < arrayLength(&draw_args); mesh = mesh + 64u) {
+        draw_args[mesh].instance_count = live_counts[0];
+    }
+}
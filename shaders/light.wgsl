@@ -10,9 +10,10 @@ var<uniform> camera: Camera;
 struct Light {
     position: vec3f,
     color: vec3f,
+    view_proj: mat4x4f,
 }
 @group(1) @binding(0)
-var<uniform> light: Light;
+var<storage, read> lights: array<Light>;
 
 struct VertexInput {
     @location(0) position: vec3f, 
@@ -26,8 +27,11 @@ struct VertexOutput {
 @vertex
 fn vs_main(
     model: VertexInput,
+    @builtin(instance_index) light_index: u32,
 ) -> VertexOutput {
-    let scale = 0.25;
+    // 每个实例对应一个光源
+    let light = lights[light_index];
+    let scale = 0.05;
     var out: VertexOutput;
     out.clip_position = camera.view_proj * vec4f(model.position * scale + light.position, 1.0);
     out.color = light.color;
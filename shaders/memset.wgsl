@@ -1,7 +1,7 @@
 ///#include "header.wgsl"
 
 @group(0) @binding(0)
-var<storage, read_write> params: Parameters;
+var<uniform> params: Parameters;
 
 @group(3) @binding(0)
 var<storage, read_write> cells: array<CellIndex>;
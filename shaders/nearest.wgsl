@@ -0,0 +1,116 @@
+///#include "header.wgsl"
+
+@group(0) @binding(0)
+var<uniform> params: Parameters;
+
+@group(1) @binding(0)
+var<storage, read_write> instances: array<Instance>;
+
+@group(2) @binding(0)
+var<storage, read_write> query: NearestQuery;
+
+@group(3) @binding(0)
+var<storage, read_write> cells: array<CellIndex>;
+
+@group(4) @binding(0)
+var<storage, read_write> nearest_result: PickResult;
+
+const NO_HIT: u32 = 0xffffffffu;
+const FAR: f32 = 3.4e38;
+
+var<workgroup> best_distance: array<f32, 64>;
+var<workgroup> best_id: array<u32, 64>;
+
+// 与 assign.wgsl 中的网格划分保持一致
+fn calculate_grid(position: vec3f) -> vec3u{
+    let offset = position - params.boundary;
+    var grid_index = vec3u(
+        u32(offset.x / params.grid_size),
+        u32(offset.y / params.grid_size),
+        u32(offset.z / params.grid_size)
+    );
+    // 二维时网格只有一层
+    if (params.dimensions == DIMENSIONS_2D) {
+        grid_index.z = 0u;
+    }
+    return grid_index;
+}
+
+fn get_grid_count() -> vec3u {
+    return grid_counts(params.boundary, params.grid_size, params.dimensions);
+}
+
+fn get_index_from_grid(grid_index: vec3u) -> u32 {
+    return cell_index_from_grid(grid_index, get_grid_count(), params.cell_indexing, params.dimensions);
+}
+
+// 在工作组内归约出最近的实例，结果在下标 0 处
+fn reduce(local_idx: u32) {
+    workgroupBarrier();
+    for (var stride = 32u; stride > 0u; stride = stride >> 1u) {
+        if (local_idx < stride && best_distance[local_idx + stride] < best_distance[local_idx]) {
+            best_distance[local_idx] = best_distance[local_idx + stride];
+            best_id[local_idx] = best_id[local_idx + stride];
+        }
+        workgroupBarrier();
+    }
+}
+
+// 只启动一个工作组。先在查询点所在格子及其 26 个邻居中查找（每个线程负责一个格子），
+// 如果没有找到、或者最近的距离超过一个格子的大小（此时邻居之外可能有更近的实例），再退回到暴力查找
+@compute @workgroup_size(64)
+fn main(@builtin(local_invocation_index) local_idx: u32) {
+    let len = arrayLength(&instances);
+    let point = query.point;
+
+    var my_distance = FAR;
+    var my_id = NO_HIT;
+
+    let in_bounds = all(abs(point) <= params.boundary);
+    if (query.use_grid != 0u && in_bounds && local_idx < 27u) {
+        // 二维时网格只有 z = 0 这一层
+        let grid_count = vec3i(get_grid_count());
+        let neigh = vec3i(calculate_grid(point)) + vec3i(
+            i32(local_idx % 3u) - 1,
+            i32((local_idx / 3u) % 3u) - 1,
+            i32(local_idx / 9u) - 1,
+        );
+        if (all(neigh >= vec3i(0)) && all(neigh < grid_count)) {
+            let cell = cells[get_index_from_grid(vec3u(neigh))];
+            for (var i = cell.start; i < cell.end; i = i + 1u) {
+                let d = distance(point, instances[i].position);
+                if (d < my_distance) {
+                    my_distance = d;
+                    my_id = instances[i].id;
+                }
+            }
+        }
+    }
+    best_distance[local_idx] = my_distance;
+    best_id[local_idx] = my_id;
+    reduce(local_idx);
+
+    let need_brute_force = best_id[0] == NO_HIT || best_distance[0] > params.grid_size;
+    // 保证所有线程都读完了下标 0 再覆盖
+    workgroupBarrier();
+
+    for (var i = local_idx; need_brute_force && i < len; i = i + 64u) {
+        // 死亡的实例不在网格中，但暴力查找时会遍历到
+        if (instances[i].dead != 0u) {
+            continue;
+        }
+        let d = distance(point, instances[i].position);
+        if (d < my_distance) {
+            my_distance = d;
+            my_id = instances[i].id;
+        }
+    }
+    best_distance[local_idx] = my_distance;
+    best_id[local_idx] = my_id;
+    reduce(local_idx);
+
+    if (local_idx == 0u) {
+        nearest_result.id = best_id[0];
+        nearest_result.distance = best_distance[0];
+    }
+}
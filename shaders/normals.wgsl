@@ -0,0 +1,18 @@
+// 法线调试视图：把视图空间的法线从 [-1, 1] 映射到 [0, 1] 显示成颜色，没有写入法线的像素显示为黑色
+
+@group(0) @binding(0)
+var t_normal: texture_2d<f32>;
+
+// 覆盖整个屏幕的三角形，不需要顶点缓冲区
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4f {
+    let uv = vec2f(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4f(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) frag_coord: vec4f) -> @location(0) vec4f {
+    // 法线纹理与表面同样大小，按像素读取
+    let normal = textureLoad(t_normal, vec2i(frag_coord.xy), 0);
+    return vec4f((normal.xyz * 0.5 + 0.5) * normal.w, 1.0);
+}
@@ -0,0 +1,75 @@
+///#include "header.wgsl"
+
+@group(0) @binding(0)
+var<uniform> params: Parameters;
+
+@group(1) @binding(0)
+var<storage, read_write> instances: array<Instance>;
+
+@group(2) @binding(0)
+var<storage, read_write> ray: PickRay;
+
+@group(3) @binding(0)
+var<storage, read_write> pick_result: PickResult;
+
+const NO_HIT: u32 = 0xffffffffu;
+const FAR: f32 = 3.4e38;
+
+var<workgroup> best_distance: array<f32, 64>;
+var<workgroup> best_id: array<u32, 64>;
+
+// 射线与球求交，返回沿射线的距离，没有交点时返回 FAR
+fn intersect(inst: Instance) -> f32 {
+    let oc = ray.origin - inst.position;
+    let b = dot(oc, ray.direction);
+    let c = dot(oc, oc) - inst.radius * inst.radius;
+    let discriminant = b * b - c;
+    if (discriminant < 0.0) {
+        return FAR;
+    }
+    let s = sqrt(discriminant);
+    var t = -b - s;
+    // 射线起点在球内时取远处的交点
+    if (t < 0.0) {
+        t = -b + s;
+    }
+    if (t < 0.0) {
+        return FAR;
+    }
+    return t;
+}
+
+// 只启动一个工作组：每个线程跨步遍历所有实例，再在工作组内归约出最近的交点
+@compute @workgroup_size(64)
+fn main(@builtin(local_invocation_index) local_idx: u32) {
+    let len = arrayLength(&instances);
+
+    var my_distance = FAR;
+    var my_id = NO_HIT;
+    for (var i = local_idx; i < len; i = i + 64u) {
+        if (instances[i].dead != 0u) {
+            continue;
+        }
+        let t = intersect(instances[i]);
+        if (t < my_distance) {
+            my_distance = t;
+            my_id = instances[i].id;
+        }
+    }
+    best_distance[local_idx] = my_distance;
+    best_id[local_idx] = my_id;
+    workgroupBarrier();
+
+    for (var stride = 32u; stride > 0u; stride = stride >> 1u) {
+        if (local_idx < stride && best_distance[local_idx + stride] < best_distance[local_idx]) {
+            best_distance[local_idx] = best_distance[local_idx + stride];
+            best_id[local_idx] = best_id[local_idx + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if (local_idx == 0u) {
+        pick_result.id = best_id[0];
+        pick_result.distance = best_distance[0];
+    }
+}
@@ -0,0 +1,34 @@
+// 阴影贴图的顶点着色器，只输出光源空间下的深度
+
+struct Light {
+    position: vec3f,
+    color: vec3f,
+    view_proj: mat4x4f,
+}
+@group(0) @binding(0)
+var<storage, read> lights: array<Light>;
+
+struct VertexInput {
+    @location(0) position: vec3f,
+}
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4f,
+    @location(6) model_matrix_1: vec4f,
+    @location(7) model_matrix_2: vec4f,
+    @location(8) model_matrix_3: vec4f,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4f {
+    let model_matrix = mat4x4f(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    // 只有第一个光源投射阴影
+    return lights[0].view_proj * model_matrix * vec4f(model.position, 1.0);
+}
@@ -2,7 +2,7 @@
 
 
 @group(0) @binding(0)
-var<storage, read_write> params: Parameters;
+var<uniform> params: Parameters;
 
 @group(1) @binding(0)
 var<storage, read_write> instances: array<Instance>;
@@ -22,23 +22,24 @@ fn agentlt(idx1 : u32, idx2 : u32) -> bool {
     return instances[idx1].cell_index < instances[idx2].cell_index;
 }
 
-fn agentgt(idx1 : u32, idx2 : u32) -> bool {
-    return instances[idx1].cell_index > instances[idx2].cell_index;
-}
-
-
-
+// 一遍双调排序。每个归并阶段的第一遍（j == k / 2）比较块内的镜像位置，之后按跨度 j 比较，
+// 较小的键总是放在前面。排序长度补齐到 2 的幂，越界的位置看作键为 DEAD_CELL 的哨兵，
+// 和它们比较时较小的键已经在前面，所以跳过这些比较就等于在补齐的数组上排序
 @compute @workgroup_size(64)
 fn main(@builtin(global_invocation_id) id : vec3<u32>) {
-    var global_tid = id.x;
+    let global_tid = id.x;
     let j = sort_params.j;
     let k = sort_params.k;
+    let count = arrayLength(&instances);
+    if (global_tid >= count) {
+        return;
+    }
 
-
-    var l = global_tid ^ j; 
-    if (l > global_tid) {
-        if (  ((global_tid & k) == 0u && agentgt(global_tid, l)) || ((global_tid & k) != 0u && agentlt(global_tid, l))){
-            swap(global_tid, l);
-        }
+    var l = global_tid ^ j;
+    if (j == k >> 1u) {
+        l = global_tid ^ (k - 1u);
+    }
+    if (l > global_tid && l < count && agentlt(l, global_tid)) {
+        swap(global_tid, l);
     }
 }
@@ -0,0 +1,150 @@
+// 屏幕空间环境光遮蔽（SSAO）
+// vs_gbuffer / fs_gbuffer 把小球的视图空间位置和法线写入 G-buffer，
+// vs_fullscreen / fs_ao 根据 G-buffer 在法线方向的半球内采样，估计每个像素被遮挡的程度
+
+// 与 ssao.rs 中的 KERNEL_SIZE 一致
+const KERNEL_SIZE: u32 = 16u;
+
+struct Ssao {
+    view: mat4x4f,
+    proj: mat4x4f,
+    // 切线空间中 +z 半球内的采样点，越靠近中心越密
+    kernel: array<vec4f, 16>,
+    radius: f32,
+    bias: f32,
+    // 遮蔽程度的指数，越大越暗
+    power: f32,
+    _padding: f32,
+    // G-buffer 所在的视口 [x, y, 宽, 高]，锁定宽高比时不是整个纹理
+    viewport: vec4f,
+}
+@group(0) @binding(0)
+var<uniform> ssao: Ssao;
+
+struct VertexInput {
+    @location(0) position: vec3f,
+    @location(2) normal: vec3f,
+}
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4f,
+    @location(6) model_matrix_1: vec4f,
+    @location(7) model_matrix_2: vec4f,
+    @location(8) model_matrix_3: vec4f,
+    @location(9) normal_matrix_0: vec3f,
+    @location(10) normal_matrix_1: vec3f,
+    @location(11) normal_matrix_2: vec3f,
+}
+
+struct GBufferVertexOutput {
+    @builtin(position) clip_position: vec4f,
+    @location(0) view_position: vec3f,
+    @location(1) view_normal: vec3f,
+}
+
+@vertex
+fn vs_gbuffer(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> GBufferVertexOutput {
+    let model_matrix = mat4x4f(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3f(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    // 视图矩阵只有旋转和平移，左上角的 3x3 就能变换法线
+    let view_rotation = mat3x3f(ssao.view[0].xyz, ssao.view[1].xyz, ssao.view[2].xyz);
+
+    let view_position = ssao.view * model_matrix * vec4f(model.position, 1.0);
+    var out: GBufferVertexOutput;
+    out.clip_position = ssao.proj * view_position;
+    out.view_position = view_position.xyz;
+    out.view_normal = view_rotation * normal_matrix * model.normal;
+    return out;
+}
+
+struct GBufferOutput {
+    // w 为 1 表示这个像素上有小球，背景清空为 0
+    @location(0) position: vec4f,
+    @location(1) normal: vec4f,
+}
+
+@fragment
+fn fs_gbuffer(in: GBufferVertexOutput) -> GBufferOutput {
+    var out: GBufferOutput;
+    out.position = vec4f(in.view_position, 1.0);
+    out.normal = vec4f(normalize(in.view_normal), 0.0);
+    return out;
+}
+
+// 覆盖整个屏幕的三角形，不需要顶点缓冲区
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4f {
+    let uv = vec2f(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4f(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@group(1) @binding(0)
+var t_position: texture_2d<f32>;
+@group(1) @binding(1)
+var t_normal: texture_2d<f32>;
+
+// 按像素在 4x4 的块中的位置给出一个伪随机角度，模糊阶段用 4x4 的均值消除由此产生的噪声
+fn noise_angle(coord: vec2i) -> f32 {
+    let index = f32((coord.x & 3) + (coord.y & 3) * 4);
+    return fract(sin(index * 12.9898) * 43758.5453) * 6.2831853;
+}
+
+@fragment
+fn fs_ao(@builtin(position) frag_coord: vec4f) -> @location(0) vec4f {
+    let coord = vec2i(frag_coord.xy);
+    let position = textureLoad(t_position, coord, 0);
+    // 背景没有遮蔽
+    if position.w == 0.0 {
+        return vec4f(1.0);
+    }
+    let normal = normalize(textureLoad(t_normal, coord, 0).xyz);
+
+    // 绕法线随机旋转采样核，用少量采样点覆盖更多方向
+    let angle = noise_angle(coord);
+    let random_direction = vec3f(cos(angle), sin(angle), 0.0);
+    var tangent = random_direction - normal * dot(random_direction, normal);
+    // 随机方向恰好与法线平行时换一个方向
+    if dot(tangent, tangent) < 1e-6 {
+        tangent = vec3f(0.0, 1.0, 0.0) - normal * normal.y;
+    }
+    tangent = normalize(tangent);
+    let bitangent = cross(normal, tangent);
+    let tbn = mat3x3f(tangent, bitangent, normal);
+
+    let size = vec2i(textureDimensions(t_position));
+    var occlusion = 0.0;
+    for (var i = 0u; i < KERNEL_SIZE; i++) {
+        let sample_position = position.xyz + tbn * ssao.kernel[i].xyz * ssao.radius;
+        // 把采样点投影到屏幕上，读取那里最近的表面
+        let clip = ssao.proj * vec4f(sample_position, 1.0);
+        let ndc = clip.xy / clip.w;
+        let uv = vec2f(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        let sample_coord = vec2i(ssao.viewport.xy + uv * ssao.viewport.zw);
+        if any(sample_coord < vec2i(0)) || any(sample_coord >= size) {
+            continue;
+        }
+        let surface = textureLoad(t_position, sample_coord, 0);
+        if surface.w == 0.0 {
+            continue;
+        }
+        // 视图空间中相机看向 -z，表面的 z 更大说明它挡在采样点前面；
+        // 距离远超采样半径的表面不算遮挡，避免前景的小球在背景上留下暗边
+        let range_check = smoothstep(0.0, 1.0, ssao.radius / abs(position.z - surface.z));
+        if surface.z >= sample_position.z + ssao.bias {
+            occlusion += range_check;
+        }
+    }
+    let ao = 1.0 - occlusion / f32(KERNEL_SIZE);
+    return vec4f(pow(ao, ssao.power));
+}
@@ -0,0 +1,24 @@
+// 对环境光遮蔽做 4x4 的均值模糊，消除采样核随机旋转带来的噪声
+
+@group(0) @binding(0)
+var t_ao: texture_2d<f32>;
+
+// 覆盖整个屏幕的三角形，不需要顶点缓冲区
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4f {
+    let uv = vec2f(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4f(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) frag_coord: vec4f) -> @location(0) vec4f {
+    let coord = vec2i(frag_coord.xy);
+    let max_coord = vec2i(textureDimensions(t_ao)) - 1;
+    var sum = 0.0;
+    for (var y = -2; y < 2; y++) {
+        for (var x = -2; x < 2; x++) {
+            sum += textureLoad(t_ao, clamp(coord + vec2i(x, y), vec2i(0), max_coord), 0).r;
+        }
+    }
+    return vec4f(sum / 16.0);
+}
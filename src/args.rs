@@ -0,0 +1,151 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+
+use crate::config::{Boundary, SceneConfig};
+use crate::record::{self, RecordConfig};
+
+/// `SceneArgs` 是命令行参数，设置了的字段会覆盖场景文件中的值。
+///
+/// Properties:
+///
+/// * `config`: 场景文件的路径，不设置时读取当前目录下的 `scene.toml`（如果存在）。
+/// * `points`: 小球的数量。
+/// * `boundary`: 边界立方体的半边长，小球在 `[-boundary, boundary]` 内运动。
+/// * `radius`: 小球的半径。
+/// * `seed`: 生成初始位置和速度的随机种子。
+/// * `samples`: MSAA 的采样数。
+/// * `bench`: 设置时不打开窗口，只运行给定步数的模拟并输出性能数据。
+/// * `record`: 设置时不进入交互，离屏录制给定帧数的动画，见 [`RecordConfig`]。
+/// * `record_dt`: 录制时每帧推进的秒数。
+/// * `record_dir`: 录制时保存帧的目录。
+#[derive(Debug, Clone, Default)]
+pub struct SceneArgs {
+    pub config: Option<PathBuf>,
+    pub points: Option<u32>,
+    pub boundary: Option<f32>,
+    pub radius: Option<f32>,
+    pub seed: Option<u64>,
+    pub samples: Option<u32>,
+    pub bench: Option<u32>,
+    pub record: Option<u32>,
+    pub record_dt: Option<f32>,
+    pub record_dir: Option<PathBuf>,
+}
+
+pub const USAGE: &str = "用法: my-collision-detect [--config FILE] [--points N] [--boundary B] [--radius R] [--seed S] [--samples N] [--bench STEPS] [--record FRAMES [--record-dt SECONDS] [--record-dir DIR]]";
+
+impl SceneArgs {
+    /// 解析命令行参数，支持 `--config`、`--points`、`--boundary`、`--radius`、`--seed`、`--samples`、`--bench`、
+    /// `--record`、`--record-dt` 和 `--record-dir`，既可以写成 `--points 1000`，也可以写成 `--points=1000`。
+    ///
+    /// Arguments:
+    ///
+    /// * `args`: 不包含程序名的参数列表。
+    ///
+    /// Returns:
+    ///
+    /// 参数无法识别或者数值无法解析时返回错误。
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> anyhow::Result<Self> {
+        let mut scene = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None => {
+                    let value = args
+                        .next()
+                        .with_context(|| format!("参数 {arg} 缺少取值"))?;
+                    (arg, value)
+                }
+            };
+
+            match name.as_str() {
+                "--config" => scene.config = Some(PathBuf::from(value)),
+                "--points" => scene.points = Some(parse_value(&name, &value)?),
+                "--boundary" => scene.boundary = Some(parse_value(&name, &value)?),
+                "--radius" => scene.radius = Some(parse_value(&name, &value)?),
+                "--seed" => scene.seed = Some(parse_value(&name, &value)?),
+                "--samples" => scene.samples = Some(parse_value(&name, &value)?),
+                "--bench" => scene.bench = Some(parse_value(&name, &value)?),
+                "--record" => scene.record = Some(parse_value(&name, &value)?),
+                "--record-dt" => scene.record_dt = Some(parse_value(&name, &value)?),
+                "--record-dir" => scene.record_dir = Some(PathBuf::from(value)),
+                _ => bail!("无法识别的参数 {name}"),
+            }
+        }
+
+        if scene.bench == Some(0) {
+            bail!("--bench 的步数必须大于 0");
+        }
+        if scene.record.is_none() && (scene.record_dt.is_some() || scene.record_dir.is_some()) {
+            bail!("--record-dt 和 --record-dir 需要和 --record 一起使用");
+        }
+        if scene.bench.is_some() && scene.record.is_some() {
+            bail!("--bench 和 --record 不能同时使用");
+        }
+        Ok(scene)
+    }
+
+    /// 离屏录制的配置：设置了 `--record` 时使用命令行参数，否则读取 `RECORD_*` 环境变量。
+    ///
+    /// Returns:
+    ///
+    /// 不需要录制时返回 `None`；每帧的时间不合法，或者环境变量无法解析时返回错误。
+    pub fn record_config(&self) -> anyhow::Result<Option<RecordConfig>> {
+        let Some(frames) = self.record else {
+            return RecordConfig::from_env();
+        };
+        let output_dir = self
+            .record_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(record::DEFAULT_RECORD_DIR));
+        RecordConfig::new(
+            frames,
+            self.record_dt.unwrap_or(record::DEFAULT_RECORD_DT),
+            output_dir,
+        )
+        .map(Some)
+    }
+
+    /// 读取场景文件，用命令行参数覆盖其中的值，并检查结果是否合法。
+    ///
+    /// Returns:
+    ///
+    /// 场景文件无法读取、解析，或者最终的参数组合不合法时返回错误。
+    pub fn scene_config(&self) -> anyhow::Result<SceneConfig> {
+        let mut config = match &self.config {
+            Some(path) => SceneConfig::load(path)?,
+            None => SceneConfig::load_default()?,
+        };
+
+        if let Some(points) = self.points {
+            config.points = points;
+        }
+        if let Some(boundary) = self.boundary {
+            config.boundary = Boundary::Cube(boundary);
+        }
+        if let Some(radius) = self.radius {
+            config.radius = radius;
+        }
+        if let Some(seed) = self.seed {
+            config.seed = seed;
+        }
+        if let Some(samples) = self.samples {
+            config.sample_count = samples;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: &str) -> anyhow::Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    value
+        .parse()
+        .with_context(|| format!("参数 {name} 的取值 {value} 无法解析"))
+}
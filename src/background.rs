@@ -0,0 +1,230 @@
+use app_surface::AppSurface;
+use wgpu::util::DeviceExt;
+
+use crate::{shaders, utils};
+
+/// 背景着色器使用的 uniform，与 background.wgsl 中的 `Background` 结构体布局一致，颜色都是线性空间的。
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BackgroundUniform {
+    top: [f32; 4],
+    bottom: [f32; 4],
+}
+
+fn color_to_array(color: wgpu::Color) -> [f32; 4] {
+    [
+        color.r as f32,
+        color.g as f32,
+        color.b as f32,
+        color.a as f32,
+    ]
+}
+
+/// `BackgroundState` 在小球之前绘制一个从上到下的全屏渐变，代替单一的清屏颜色。
+///
+/// 锁定宽高比时背景只画在视口之内，视口之外清空为黑色；没有渐变时用清屏颜色画一个纯色的背景。
+///
+/// Properties:
+///
+/// * `gradient`: 顶部和底部的颜色，`None` 时不绘制，只使用清屏颜色。
+/// * `clear_color`: 清屏颜色，锁定宽高比并且没有渐变时用它填充视口。
+/// * `uniform_buffer`: 存放两种颜色的缓冲区。
+/// * `bind_group`: `uniform_buffer` 的绑定组。
+/// * `pipeline_layout`: 渲染管线的布局，重新加载着色器时复用。
+/// * `pipeline`: 绘制全屏三角形的管线，不进行深度测试。
+pub struct BackgroundState {
+    gradient: Option<(wgpu::Color, wgpu::Color)>,
+    clear_color: wgpu::Color,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl BackgroundState {
+    /// 创建背景的渲染管线，默认不绘制渐变。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和表面格式。
+    /// * `clear_color`: 清屏颜色（线性空间）。
+    /// * `sample_count`: MSAA 采样数。
+    pub fn new(app: &AppSurface, clear_color: wgpu::Color, sample_count: u32) -> Self {
+        let uniform_buffer = app
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Background Uniform Buffer"),
+                contents: bytemuck::bytes_of(&BackgroundUniform {
+                    top: color_to_array(clear_color),
+                    bottom: color_to_array(clear_color),
+                }),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+        let bind_group_layout =
+            app.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    }],
+                    label: Some("background_bind_group_layout"),
+                });
+        let bind_group = app.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("background_bind_group"),
+        });
+        let pipeline_layout = app
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Background Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = Self::create_pipeline(app, &pipeline_layout, sample_count);
+
+        Self {
+            gradient: None,
+            clear_color,
+            uniform_buffer,
+            bind_group,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    fn create_pipeline(
+        app: &AppSurface,
+        layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let shader = app
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Background Shader"),
+                source: wgpu::ShaderSource::Wgsl(shaders::shader_source!("background.wgsl")),
+            });
+        app.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Background Pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: app.config.format.add_srgb_suffix(),
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+            })
+    }
+
+    /// 重新读取着色器并重建渲染管线，着色器有错误时保留原来的管线。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和表面格式。
+    /// * `sample_count`: MSAA 采样数，需要和当前的渲染附件一致，采样数改变之后用新的值重建。
+    pub fn reload_shader(&mut self, app: &AppSurface, sample_count: u32) {
+        if let Some(pipeline) = shaders::try_rebuild(&app.device, "background.wgsl", || {
+            Self::create_pipeline(app, &self.pipeline_layout, sample_count)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    /// 顶部和底部的颜色，没有设置渐变时为 `None`。
+    pub fn gradient(&self) -> Option<(wgpu::Color, wgpu::Color)> {
+        self.gradient
+    }
+
+    /// 设置渐变的顶部和底部颜色（线性空间），`None` 时不绘制渐变。
+    pub fn set_gradient(&mut self, app: &AppSurface, gradient: Option<(wgpu::Color, wgpu::Color)>) {
+        self.gradient = gradient;
+        self.write_colors(app);
+    }
+
+    /// 设置清屏颜色（线性空间），只在锁定宽高比并且没有渐变时用来填充视口。
+    pub fn set_clear_color(&mut self, app: &AppSurface, clear_color: wgpu::Color) {
+        self.clear_color = clear_color;
+        self.write_colors(app);
+    }
+
+    // 有渐变时写入渐变的两种颜色，否则两种颜色都是清屏颜色
+    fn write_colors(&self, app: &AppSurface) {
+        let (top, bottom) = self
+            .gradient
+            .unwrap_or((self.clear_color, self.clear_color));
+        app.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&BackgroundUniform {
+                top: color_to_array(top),
+                bottom: color_to_array(bottom),
+            }),
+        );
+    }
+
+    /// 设置了渐变或者锁定了宽高比时在单独的渲染通道中画出背景，之后的渲染通道应该加载而不是清空颜色。
+    ///
+    /// Arguments:
+    ///
+    /// * `encoder`: 命令编码器。
+    /// * `color_view`: 颜色目标，开启 MSAA 时是多重采样纹理，由之后的渲染通道解析。
+    /// * `letterbox`: 锁定宽高比时的视口，视口之外是黑边；`None` 时铺满整个颜色目标。
+    ///
+    /// Returns:
+    ///
+    /// 是否绘制了背景。
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        color_view: &wgpu::TextureView,
+        letterbox: Option<[f32; 4]>,
+    ) -> bool {
+        if self.gradient.is_none() && letterbox.is_none() {
+            return false;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Background Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: color_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        if let Some(viewport) = letterbox {
+            utils::set_viewport(&mut render_pass, viewport);
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+        true
+    }
+}
@@ -0,0 +1,231 @@
+use std::iter;
+
+use winit::{event_loop::EventLoop, window::WindowBuilder};
+
+use crate::{compute, config::SceneConfig, create_compute_state, framework, requirements};
+
+// GPU 时间戳：计算通道的开始和结束各写一个
+struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    period: f32,
+}
+
+impl GpuTimer {
+    fn new(app: &app_surface::AppSurface) -> Option<Self> {
+        if !app
+            .device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+        {
+            return None;
+        }
+
+        let query_set = app.device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Bench Timestamp Query Set"),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2,
+        });
+        let resolve_buffer = app.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Bench Timestamp Resolve Buffer"),
+            size: 2 * std::mem::size_of::<u64>() as u64,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            period: app.queue.get_timestamp_period(),
+        })
+    }
+
+    fn timestamp_writes(&self) -> wgpu::ComputePassTimestampWrites {
+        wgpu::ComputePassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: Some(1),
+        }
+    }
+
+    // 读回上一次计算通道的耗时，单位是毫秒
+    fn elapsed_ms(&self, app: &app_surface::AppSurface) -> f64 {
+        let mut encoder = app
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Bench Timestamp Encoder"),
+            });
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve_buffer, 0);
+        app.queue.submit(iter::once(encoder.finish()));
+
+        let bytes = compute::read_back(&app.device, &app.queue, &self.resolve_buffer);
+        let timestamps: Vec<u64> = bytemuck::pod_collect_to_vec(&bytes);
+        let ticks = timestamps[1].saturating_sub(timestamps[0]);
+        ticks as f64 * self.period as f64 / 1.0e6
+    }
+}
+
+// 一种单元编号和工作组大小组合的测试结果
+struct BenchResult {
+    cell_indexing: compute::CellIndexing,
+    workgroup_size: u32,
+    cpu_total: std::time::Duration,
+    gpu_total_ms: Option<f64>,
+    sleeping: usize,
+}
+
+impl BenchResult {
+    // 每一步的耗时，有 GPU 计时的时候以 GPU 计时为准
+    fn ms_per_step(&self, steps: u32) -> f64 {
+        match self.gpu_total_ms {
+            Some(gpu_total_ms) => gpu_total_ms / steps as f64,
+            None => self.cpu_total.as_secs_f64() * 1000.0 / steps as f64,
+        }
+    }
+}
+
+/// 不显示窗口，以固定的时间步长运行 `steps` 步模拟，并以 JSON 输出性能数据。
+///
+/// 每一步都会运行完整的计算管线（`substeps` 轮分配网格、排序、建网格和碰撞检测），
+/// CPU 计时包含提交和等待 GPU 完成的时间；设备支持 `TIMESTAMP_QUERY` 时还会输出 GPU 计时。
+/// 结束时还会输出进入休眠的小球数量，开启休眠时可以用来比较堆积稳定之后的性能。
+///
+/// 网格单元的线性编号和 Morton 编号各测试一遍，每种编号下碰撞阶段的工作组大小依次尝试
+/// `compute::WORKGROUP_SIZES` 中设备支持的每一个。每个组合都从相同的初始状态开始，各输出一行；
+/// 之后一行比较两种编号各自最快的耗时，最后一行给出最快的组合，可以写到场景文件的 `cell_indexing`
+/// 和 `workgroup_size` 中。
+///
+/// Arguments:
+///
+/// * `scene`: 场景参数，使用其中的随机种子生成初始状态，保证多次运行的结果可比较。
+/// * `steps`: 每个工作组大小模拟的步数。
+pub fn run(scene: &SceneConfig, steps: u32) -> anyhow::Result<()> {
+    // AppSurface 需要一个窗口来创建设备，这里使用不可见的窗口，不会显示出来
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_visible(false)
+        .build(&event_loop)?;
+    let app = pollster::block_on(app_surface::AppSurface::new(window));
+    requirements::check(&app, scene.capacity())?;
+
+    let gpu_timer = GpuTimer::new(&app);
+    let limits = app.device.limits();
+    let max_size = limits
+        .max_compute_workgroup_size_x
+        .min(limits.max_compute_invocations_per_workgroup);
+
+    let mut results = Vec::new();
+    for (cell_indexing, workgroup_size) in
+        [compute::CellIndexing::Linear, compute::CellIndexing::Morton]
+            .into_iter()
+            .flat_map(|cell_indexing| compute::WORKGROUP_SIZES.map(|size| (cell_indexing, size)))
+    {
+        if workgroup_size > max_size {
+            continue;
+        }
+        let scene = SceneConfig {
+            cell_indexing,
+            ..scene.clone()
+        };
+        let result = run_steps(&app, &scene, steps, workgroup_size, gpu_timer.as_ref())?;
+
+        let total_s = result.cpu_total.as_secs_f64();
+        let gpu_ms_per_step = match result.gpu_total_ms {
+            Some(gpu_total_ms) => format!("{:.4}", gpu_total_ms / steps as f64),
+            None => "null".to_string(),
+        };
+        println!(
+            "{{\"adapter\":{:?},\"particles\":{},\"steps\":{},\"rounds_per_step\":{},\"cell_indexing\":\"{:?}\",\"workgroup_size\":{},\"total_s\":{:.6},\"steps_per_sec\":{:.3},\"ms_per_step\":{:.4},\"gpu_ms_per_step\":{},\"sleeping\":{}}}",
+            app.adapter.get_info().name,
+            scene.points,
+            steps,
+            scene.substeps,
+            result.cell_indexing,
+            result.workgroup_size,
+            total_s,
+            steps as f64 / total_s,
+            total_s * 1000.0 / steps as f64,
+            gpu_ms_per_step,
+            result.sleeping,
+        );
+
+        results.push(result);
+    }
+
+    // 每种编号取最快的工作组大小来比较
+    let best_ms_per_step = |cell_indexing| {
+        results
+            .iter()
+            .filter(|result| result.cell_indexing == cell_indexing)
+            .map(|result| result.ms_per_step(steps))
+            .fold(f64::INFINITY, f64::min)
+    };
+    let linear_ms = best_ms_per_step(compute::CellIndexing::Linear);
+    let morton_ms = best_ms_per_step(compute::CellIndexing::Morton);
+    if linear_ms.is_finite() && morton_ms.is_finite() {
+        println!(
+            "{{\"linear_ms_per_step\":{:.4},\"morton_ms_per_step\":{:.4},\"morton_speedup\":{:.3}}}",
+            linear_ms,
+            morton_ms,
+            linear_ms / morton_ms,
+        );
+    }
+
+    let fastest = results
+        .iter()
+        .min_by(|a, b| a.ms_per_step(steps).total_cmp(&b.ms_per_step(steps)));
+    if let Some(fastest) = fastest {
+        println!(
+            "{{\"fastest_cell_indexing\":\"{:?}\",\"fastest_workgroup_size\":{},\"ms_per_step\":{:.4}}}",
+            fastest.cell_indexing,
+            fastest.workgroup_size,
+            fastest.ms_per_step(steps),
+        );
+    }
+
+    Ok(())
+}
+
+// 以给定的工作组大小从初始状态开始模拟 steps 步
+fn run_steps(
+    app: &app_surface::AppSurface,
+    scene: &SceneConfig,
+    steps: u32,
+    workgroup_size: u32,
+    gpu_timer: Option<&GpuTimer>,
+) -> anyhow::Result<BenchResult> {
+    let mut compute_state = create_compute_state(app, scene);
+    compute_state.set_workgroup_size(&app.device, workgroup_size)?;
+    compute_state.write_instances_buffer(&app.queue);
+    compute_state.write_params(&app.queue, framework::FIXED_DT, scene.substeps);
+
+    let mut cpu_total = std::time::Duration::ZERO;
+    let mut gpu_total_ms = 0.0;
+
+    for _ in 0..steps {
+        let start = std::time::Instant::now();
+        compute_state.do_compute_timed(
+            &app.device,
+            &app.queue,
+            scene.substeps,
+            gpu_timer.map(GpuTimer::timestamp_writes),
+        );
+        app.device.poll(wgpu::MaintainBase::Wait);
+        cpu_total += start.elapsed();
+
+        if let Some(timer) = gpu_timer {
+            gpu_total_ms += timer.elapsed_ms(app);
+        }
+    }
+
+    // 读回最终状态，统计静止下来进入休眠的小球
+    compute_state.read_results(&app.device);
+
+    Ok(BenchResult {
+        cell_indexing: scene.cell_indexing,
+        workgroup_size,
+        cpu_total,
+        gpu_total_ms: gpu_timer.map(|_| gpu_total_ms),
+        sleeping: compute_state.sleeping,
+    })
+}
@@ -0,0 +1,205 @@
+use app_surface::AppSurface;
+use wgpu::util::DeviceExt;
+
+use crate::{model, shaders, texture};
+
+/// `BoundaryVertex` 是边界线框的顶点，只有位置。
+///
+/// Properties:
+///
+/// * `position`: 顶点在世界空间中的位置。
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BoundaryVertex {
+    position: [f32; 3],
+}
+
+impl model::Vertex for BoundaryVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<BoundaryVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[wgpu::VertexAttribute {
+                offset: 0,
+                shader_location: 0,
+                format: wgpu::VertexFormat::Float32x3,
+            }],
+        }
+    }
+}
+
+/// 生成范围是 `[-boundary, boundary]` 的长方体的 12 条棱，每条棱两个顶点。
+///
+/// Arguments:
+///
+/// * `boundary`: 模拟的边界在三个轴上的半边长。
+///
+/// Returns:
+///
+/// 按 LineList 排列的 24 个顶点。
+fn box_edges(boundary: glam::Vec3) -> Vec<BoundaryVertex> {
+    let corner = |i: u32| BoundaryVertex {
+        position: [
+            if i & 1 == 0 { -boundary.x } else { boundary.x },
+            if i & 2 == 0 { -boundary.y } else { boundary.y },
+            if i & 4 == 0 { -boundary.z } else { boundary.z },
+        ],
+    };
+    let mut vertices = Vec::with_capacity(24);
+    for i in 0..8u32 {
+        // 只和某一维坐标不同、且编号更大的角相连，保证每条棱只出现一次
+        for bit in [1, 2, 4] {
+            if i & bit == 0 {
+                vertices.push(corner(i));
+                vertices.push(corner(i | bit));
+            }
+        }
+    }
+    vertices
+}
+
+/// `BoundaryState` 负责绘制模拟边界的线框长方体。
+///
+/// Properties:
+///
+/// * `vertex_buffer`: 存储长方体棱的顶点缓冲区。
+/// * `vertex_count`: 顶点数量。
+/// * `pipeline_layout`: 渲染管线的布局，重新加载着色器时复用。
+/// * `pipeline`: 使用 LineList 拓扑的渲染管线，会进行深度测试，因此会被小球遮挡。
+/// * `visible`: 是否绘制边界。
+pub struct BoundaryState {
+    pub vertex_buffer: wgpu::Buffer,
+    pub vertex_count: u32,
+    pub pipeline_layout: wgpu::PipelineLayout,
+    pub pipeline: wgpu::RenderPipeline,
+    pub visible: bool,
+}
+
+impl BoundaryState {
+    /// 创建边界线框的顶点缓冲区和渲染管线。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和表面格式。
+    /// * `camera_bind_group_layout`: 相机的绑定组布局，位于 group 0。
+    /// * `boundary`: 模拟的边界，和 `Parameters::boundary` 一致。
+    /// * `sample_count`: MSAA 采样数。
+    ///
+    /// Returns:
+    ///
+    /// `BoundaryState` 的一个实例。
+    pub fn new(
+        app: &AppSurface,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        boundary: glam::Vec3,
+        sample_count: u32,
+    ) -> Self {
+        let vertices = box_edges(boundary);
+        let vertex_buffer = app
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Boundary Vertex Buffer"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+
+        let layout = app
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Boundary Pipeline Layout"),
+                bind_group_layouts: &[camera_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = Self::create_pipeline(app, &layout, sample_count);
+
+        Self {
+            vertex_buffer,
+            vertex_count: vertices.len() as u32,
+            pipeline_layout: layout,
+            pipeline,
+            visible: true,
+        }
+    }
+
+    fn create_pipeline(
+        app: &AppSurface,
+        layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let shader = app
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Boundary Shader"),
+                source: wgpu::ShaderSource::Wgsl(shaders::shader_source!("boundary.wgsl")),
+            });
+        app.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Boundary Pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[<BoundaryVertex as model::Vertex>::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: app.config.format.add_srgb_suffix(),
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::LineList,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+            })
+    }
+
+    /// 重新读取着色器并重建渲染管线，着色器有错误时保留原来的管线。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和表面格式。
+    /// * `sample_count`: MSAA 采样数，需要和当前的渲染附件一致，采样数改变之后用新的值重建。
+    pub fn reload_shader(&mut self, app: &AppSurface, sample_count: u32) {
+        if let Some(pipeline) = shaders::try_rebuild(&app.device, "boundary.wgsl", || {
+            Self::create_pipeline(app, &self.pipeline_layout, sample_count)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    /// 在给定的渲染通道中绘制边界线框。
+    ///
+    /// Arguments:
+    ///
+    /// * `render_pass`: 当前的渲染通道。
+    /// * `camera_bind_group`: 相机的绑定组。
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if !self.visible {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
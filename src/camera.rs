@@ -1,3 +1,4 @@
+use crate::compute::ComputeInstance;
 use app_surface::AppSurface;
 use std::f32::consts::FRAC_PI_2;
 use std::time::Duration;
@@ -7,6 +8,31 @@ use winit::event::*;
 
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
 
+// 手柄摇杆的默认死区和视角灵敏度（摇杆推到底时每秒转过的弧度）
+pub const DEFAULT_GAMEPAD_DEADZONE: f32 = 0.15;
+pub const DEFAULT_GAMEPAD_SENSITIVITY: f32 = 2.0;
+
+// 跟随小球时相机相对于小球的默认偏移，以及趋近目标位置的速率（1/秒）
+pub const DEFAULT_FOLLOW_OFFSET: glam::Vec3 = glam::Vec3::new(0.0, 1.0, 4.0);
+pub const DEFAULT_FOLLOW_SMOOTHING: f32 = 8.0;
+
+// 跟随时相机与小球的最小距离，防止向前移动穿过小球之后视角翻转
+const MIN_FOLLOW_DISTANCE: f32 = 0.1;
+
+// 对准包围盒时在包围球外留出的比例
+const FRAME_MARGIN: f32 = 1.1;
+
+// 默认的近平面和远平面距离，按场景大小调整时远平面不会比默认值更近
+pub const DEFAULT_ZNEAR: f32 = 0.1;
+pub const DEFAULT_ZFAR: f32 = 100.0;
+
+// 按场景大小调整远平面时留出的余量
+const CLIP_MARGIN: f32 = 1.1;
+
+// 正交视图在边界外留出的比例，以及相机离开边界的距离
+const SNAP_VIEW_MARGIN: f32 = 1.1;
+const SNAP_VIEW_DISTANCE: f32 = 1.0;
+
 /// “Camera”结构代表 3D 空间中的相机，具有位置、偏航和俯仰。
 ///
 /// Properties:
@@ -62,11 +88,13 @@ impl Camera {
 /// * `fovy`: fovy 属性表示垂直方向的视野角。它指定场景的垂直可见程度。
 /// * `znear`: `znear` 属性表示到投影的近裁剪平面的距离。它决定了物体在开始被剪裁或从视图中消失之前与相机的距离有多近。
 /// * `zfar`: “Projection”结构中的“zfar”属性表示从观看者到远裁剪平面的距离。它定义了场景中对象可见的最大距离。任何超出此距离的对象都将被剪裁并且不会渲染。
+/// * `ortho_half_height`: 设置时使用正交投影，值是视野在世界空间中的半高，此时忽略 `fovy`。
 pub struct Projection {
     aspect: f32,
     fovy: f32,
     znear: f32,
     zfar: f32,
+    ortho_half_height: Option<f32>,
 }
 
 impl Projection {
@@ -89,6 +117,7 @@ impl Projection {
             fovy: fovy.to_radians(),
             znear,
             zfar,
+            ortho_half_height: None,
         }
     }
 
@@ -102,9 +131,48 @@ impl Projection {
         self.aspect = width as f32 / height as f32;
     }
 
-    /// `calc_matrix` 函数使用给定的视场、纵横比、近平面距离和远平面距离返回透视投影矩阵。
+    /// 设置近平面和远平面的距离，`zfar` 需要大于 `znear`。
+    pub fn set_clip(&mut self, znear: f32, zfar: f32) {
+        self.znear = znear;
+        self.zfar = zfar;
+    }
+
+    /// 近平面的距离。
+    pub fn znear(&self) -> f32 {
+        self.znear
+    }
+
+    /// 远平面的距离。
+    pub fn zfar(&self) -> f32 {
+        self.zfar
+    }
+
+    /// 切换正交投影和透视投影。
+    ///
+    /// Arguments:
+    ///
+    /// * `half_height`: 正交投影时视野在世界空间中的半高，宽度按纵横比计算；`None` 表示透视投影。
+    pub fn set_orthographic(&mut self, half_height: Option<f32>) {
+        self.ortho_half_height = half_height;
+    }
+
+    /// `calc_matrix` 函数返回投影矩阵：设置了正交投影时返回正交投影矩阵，否则使用给定的视场、纵横比、
+    /// 近平面距离和远平面距离返回透视投影矩阵。
     pub fn calc_matrix(&self) -> glam::Mat4 {
-        glam::Mat4::perspective_rh(self.fovy, self.aspect, self.znear, self.zfar)
+        match self.ortho_half_height {
+            Some(half_height) => {
+                let half_width = half_height * self.aspect;
+                glam::Mat4::orthographic_rh(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.znear,
+                    self.zfar,
+                )
+            }
+            None => glam::Mat4::perspective_rh(self.fovy, self.aspect, self.znear, self.zfar),
+        }
     }
 }
 
@@ -123,6 +191,14 @@ impl Projection {
 /// * `scroll`: `scroll` 属性表示接收到的滚动输入的数量。它的类型为“f32”，这意味着它是一个浮点数。
 /// * `speed`: speed 属性决定相机在场景中移动的速度。
 /// * `sensitivity`: 灵敏度属性决定相机控制器对用户输入的敏感程度。它会影响相机响应用户操作而旋转或移动的程度。
+/// * `gamepad`: 最近一次读到的手柄状态，没有手柄时全为 0。
+/// * `gamepad_deadzone`: 摇杆和扳机的死区，避免摇杆回中不准时相机慢慢漂移。
+/// * `gamepad_sensitivity`: 右摇杆推到底时每秒转过的弧度。
+/// * `key_bindings`: 移动使用的按键。
+/// * `invert_y`: 反转鼠标和右摇杆的上下方向，向上移动时视角向下。
+/// * `smoothing`: 平滑的时间常数（秒），移动和转动在这段时间内趋近输入，0 表示立即响应。
+/// * `movement`: 平滑之后的前后、左右、上下的移动量。
+/// * `pending_rotation`: 还没有转过的偏航角和俯仰角（弧度）。
 #[derive(Debug)]
 pub struct CameraController {
     amount_left: f32,
@@ -136,6 +212,14 @@ pub struct CameraController {
     scroll: f32,
     speed: f32,
     sensitivity: f32,
+    gamepad: GamepadInput,
+    gamepad_deadzone: f32,
+    gamepad_sensitivity: f32,
+    key_bindings: KeyBindings,
+    invert_y: bool,
+    smoothing: f32,
+    movement: glam::Vec3,
+    pending_rotation: glam::Vec2,
 }
 
 impl CameraController {
@@ -162,9 +246,75 @@ impl CameraController {
             scroll: 0.0,
             speed,
             sensitivity,
+            gamepad: GamepadInput::default(),
+            gamepad_deadzone: DEFAULT_GAMEPAD_DEADZONE,
+            gamepad_sensitivity: DEFAULT_GAMEPAD_SENSITIVITY,
+            key_bindings: KeyBindings::default(),
+            invert_y: false,
+            smoothing: 0.0,
+            movement: glam::Vec3::ZERO,
+            pending_rotation: glam::Vec2::ZERO,
         }
     }
 
+    /// 设置移动和转动的平滑时间常数（秒），0 表示立即响应，负数按 0 处理。
+    pub fn set_smoothing(&mut self, smoothing: f32) {
+        self.smoothing = smoothing.max(0.0);
+    }
+
+    /// 替换移动使用的按键，已经按下的按键对应的移动会被清除。
+    pub fn set_key_bindings(&mut self, key_bindings: KeyBindings) {
+        self.key_bindings = key_bindings;
+        self.reset_movement();
+    }
+
+    /// 清除按住的移动键和还没有完成的平滑移动，相机立即停下。
+    ///
+    /// 窗口失去焦点时收不到松开按键的事件，需要调用这个方法，否则回到窗口时相机还会继续移动。
+    pub fn reset_movement(&mut self) {
+        self.amount_left = 0.0;
+        self.amount_right = 0.0;
+        self.amount_forward = 0.0;
+        self.amount_backward = 0.0;
+        self.amount_up = 0.0;
+        self.amount_down = 0.0;
+        self.movement = glam::Vec3::ZERO;
+    }
+
+    pub fn key_bindings(&self) -> &KeyBindings {
+        &self.key_bindings
+    }
+
+    /// 设置是否反转鼠标和右摇杆的上下方向。
+    pub fn set_invert_y(&mut self, invert_y: bool) {
+        self.invert_y = invert_y;
+    }
+
+    pub fn invert_y(&self) -> bool {
+        self.invert_y
+    }
+
+    /// 设置手柄的死区和视角灵敏度。
+    ///
+    /// Arguments:
+    ///
+    /// * `deadzone`: 死区，范围 [0, 1)。
+    /// * `sensitivity`: 右摇杆推到底时每秒转过的弧度。
+    pub fn set_gamepad_settings(&mut self, deadzone: f32, sensitivity: f32) {
+        self.gamepad_deadzone = deadzone.clamp(0.0, 0.99);
+        self.gamepad_sensitivity = sensitivity;
+    }
+
+    /// 记录手柄的当前状态。与键盘和鼠标的事件不同，摇杆是持续的输入，在每次 `update_camera` 中都会生效，
+    /// 直到下一次调用为止；手柄断开时应传入 `GamepadInput::default()`。
+    ///
+    /// Arguments:
+    ///
+    /// * `input`: 手柄的摇杆和扳机的状态。
+    pub fn process_gamepad(&mut self, input: GamepadInput) {
+        self.gamepad = input;
+    }
+
     /// 函数“process_keyboard”将按键及其状态作为输入，并根据按下的按键更新相应的移动变量。
     ///
     /// Arguments:
@@ -174,40 +324,31 @@ impl CameraController {
     ///
     /// Returns:
     ///
-    /// 一个布尔值，表示按键是否被处理。
+    /// 一个布尔值，表示按键是否被处理。没有绑定到任何移动的按键不会被处理。
     pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
         let amount = if state == ElementState::Pressed {
             1.0
         } else {
             0.0
         };
-        match key {
-            VirtualKeyCode::W | VirtualKeyCode::Up => {
-                self.amount_forward = amount;
-                true
-            }
-            VirtualKeyCode::S | VirtualKeyCode::Down => {
-                self.amount_backward = amount;
-                true
-            }
-            VirtualKeyCode::A | VirtualKeyCode::Left => {
-                self.amount_left = amount;
-                true
-            }
-            VirtualKeyCode::D | VirtualKeyCode::Right => {
-                self.amount_right = amount;
-                true
-            }
-            VirtualKeyCode::Space => {
-                self.amount_up = amount;
-                true
-            }
-            VirtualKeyCode::LShift => {
-                self.amount_down = amount;
-                true
-            }
-            _ => false,
-        }
+        let bindings = &self.key_bindings;
+        let target = if bindings.forward.contains(&key) {
+            &mut self.amount_forward
+        } else if bindings.backward.contains(&key) {
+            &mut self.amount_backward
+        } else if bindings.left.contains(&key) {
+            &mut self.amount_left
+        } else if bindings.right.contains(&key) {
+            &mut self.amount_right
+        } else if bindings.up.contains(&key) {
+            &mut self.amount_up
+        } else if bindings.down.contains(&key) {
+            &mut self.amount_down
+        } else {
+            return false;
+        };
+        *target = amount;
+        true
     }
 
     /// 函数“process_mouse”根据鼠标移动更新水平和垂直旋转值。
@@ -218,7 +359,11 @@ impl CameraController {
     /// * `mouse_dy`: `mouse_dy` 参数表示鼠标垂直位置的变化。它是一个“f64”（64位浮点）值，这意味着它可以存储十进制数。
     pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
         self.rotate_horizontal = mouse_dx as f32;
-        self.rotate_vertical = mouse_dy as f32;
+        self.rotate_vertical = if self.invert_y {
+            -mouse_dy as f32
+        } else {
+            mouse_dy as f32
+        };
     }
 
     /// 函数“process_scroll”接受“MouseScrollDelta”并根据滚动增量的类型更新“scroll”变量。
@@ -237,11 +382,36 @@ impl CameraController {
     pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
         let dt = dt.as_secs_f32();
 
+        // 手柄和键盘同时使用时叠加，但不超过单独使用时的最大速度
+        let stick = apply_deadzone(self.gamepad.left_stick, self.gamepad_deadzone);
+        let look = apply_deadzone(self.gamepad.right_stick, self.gamepad_deadzone);
+        let up = apply_trigger_deadzone(self.gamepad.right_trigger, self.gamepad_deadzone)
+            - apply_trigger_deadzone(self.gamepad.left_trigger, self.gamepad_deadzone);
+        let amount_forward =
+            (self.amount_forward - self.amount_backward + stick.y).clamp(-1.0, 1.0);
+        let amount_right = (self.amount_right - self.amount_left + stick.x).clamp(-1.0, 1.0);
+        let amount_up = (self.amount_up - self.amount_down + up).clamp(-1.0, 1.0);
+
+        // 按指数衰减趋近输入，衰减只取决于经过的时间，帧率不同时手感一致；smoothing 为 0 时 t 为 1
+        let t = if self.smoothing > 0.0 {
+            1.0 - (-dt / self.smoothing).exp()
+        } else {
+            1.0
+        };
+        self.movement = self
+            .movement
+            .lerp(glam::Vec3::new(amount_right, amount_up, amount_forward), t);
+        let glam::Vec3 {
+            x: amount_right,
+            y: amount_up,
+            z: amount_forward,
+        } = self.movement;
+
         let (yaw_sin, yaw_cos) = camera.yaw.sin_cos();
         let forward = glam::Vec3::new(yaw_cos, 0.0, yaw_sin).normalize();
         let right = glam::Vec3::new(-yaw_sin, 0.0, yaw_cos).normalize();
-        camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
-        camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
+        camera.position += forward * amount_forward * self.speed * dt;
+        camera.position += right * amount_right * self.speed * dt;
 
         let (pitch_sin, pitch_cos) = camera.pitch.sin_cos();
         let scrollward =
@@ -249,10 +419,21 @@ impl CameraController {
         camera.position += scrollward * self.scroll * self.speed * self.sensitivity * dt;
         self.scroll = 0.0;
 
-        camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
+        camera.position.y += amount_up * self.speed * dt;
 
-        camera.yaw += self.rotate_horizontal * self.sensitivity * dt;
-        camera.pitch += -self.rotate_vertical * self.sensitivity * dt;
+        // 右摇杆换算成与鼠标移动相同的增量，向上推摇杆对应向上移动鼠标；鼠标的反转已经在 process_mouse 中处理
+        let look_y = if self.invert_y { -look.y } else { look.y };
+        let rotate_horizontal =
+            self.rotate_horizontal + look.x * self.gamepad_sensitivity / self.sensitivity;
+        let rotate_vertical =
+            self.rotate_vertical - look_y * self.gamepad_sensitivity / self.sensitivity;
+        // 输入的转动先累积起来，每帧转过其中的一部分
+        self.pending_rotation +=
+            glam::Vec2::new(rotate_horizontal, -rotate_vertical) * self.sensitivity * dt;
+        let rotation = self.pending_rotation * t;
+        self.pending_rotation -= rotation;
+        camera.yaw += rotation.x;
+        camera.pitch += rotation.y;
 
         self.rotate_horizontal = 0.0;
         self.rotate_vertical = 0.0;
@@ -265,17 +446,86 @@ impl CameraController {
     }
 }
 
+/// `KeyBindings` 是相机移动使用的按键，每个方向可以绑定多个按键。
+///
+/// Properties:
+///
+/// * `forward`: 向前移动，默认为 W 和上方向键。
+/// * `backward`: 向后移动，默认为 S 和下方向键。
+/// * `left`: 向左移动，默认为 A 和左方向键。
+/// * `right`: 向右移动，默认为 D 和右方向键。
+/// * `up`: 上升，默认为空格。
+/// * `down`: 下降，默认为左 Shift。
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBindings {
+    pub forward: Vec<VirtualKeyCode>,
+    pub backward: Vec<VirtualKeyCode>,
+    pub left: Vec<VirtualKeyCode>,
+    pub right: Vec<VirtualKeyCode>,
+    pub up: Vec<VirtualKeyCode>,
+    pub down: Vec<VirtualKeyCode>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            forward: vec![VirtualKeyCode::W, VirtualKeyCode::Up],
+            backward: vec![VirtualKeyCode::S, VirtualKeyCode::Down],
+            left: vec![VirtualKeyCode::A, VirtualKeyCode::Left],
+            right: vec![VirtualKeyCode::D, VirtualKeyCode::Right],
+            up: vec![VirtualKeyCode::Space],
+            down: vec![VirtualKeyCode::LShift],
+        }
+    }
+}
+
+/// `GamepadInput` 是某一时刻手柄的摇杆和扳机的状态，与具体的手柄库无关。
+///
+/// Properties:
+///
+/// * `left_stick`: 左摇杆，x 向右、y 向前为正，范围 [-1, 1]，控制移动。
+/// * `right_stick`: 右摇杆，x 向右、y 向上为正，范围 [-1, 1]，控制视角。
+/// * `left_trigger`: 左扳机，范围 [0, 1]，控制下降。
+/// * `right_trigger`: 右扳机，范围 [0, 1]，控制上升。
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct GamepadInput {
+    pub left_stick: glam::Vec2,
+    pub right_stick: glam::Vec2,
+    pub left_trigger: f32,
+    pub right_trigger: f32,
+}
+
+// 径向死区：长度小于 deadzone 的输入视为 0，之外的部分重新映射到 [0, 1]，推到底时仍然是全速
+fn apply_deadzone(stick: glam::Vec2, deadzone: f32) -> glam::Vec2 {
+    let length = stick.length();
+    if length <= deadzone {
+        return glam::Vec2::ZERO;
+    }
+    let scaled = ((length - deadzone) / (1.0 - deadzone)).min(1.0);
+    stick * (scaled / length)
+}
+
+fn apply_trigger_deadzone(value: f32, deadzone: f32) -> f32 {
+    if value <= deadzone {
+        0.0
+    } else {
+        ((value - deadzone) / (1.0 - deadzone)).min(1.0)
+    }
+}
+
 /// “CameraUniform”类型表示图形应用程序中相机的统一数据。
 ///
 /// Properties:
 ///
 /// * `view_position`: f32 值的 4 元素数组，表示相机在视图空间中的位置。这些元素对应于位置的 x、y、z 和 w 坐标。
 /// * `view_proj`: “view_proj”属性是一个 4x4 矩阵，表示相机的组合视图和投影矩阵。它用于在 3D 渲染管道中将世界坐标转换为屏幕坐标。
+/// * `view`: 视图矩阵，用于把法线变换到视图空间。放在最后，只声明前两个字段的着色器不受影响。
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
     view_position: [f32; 4],
     view_proj: [[f32; 4]; 4],
+    view: [[f32; 4]; 4],
 }
 
 impl CameraUniform {
@@ -283,10 +533,11 @@ impl CameraUniform {
         Self {
             view_position: [0.0; 4],
             view_proj: glam::Mat4::IDENTITY.to_cols_array_2d(),
+            view: glam::Mat4::IDENTITY.to_cols_array_2d(),
         }
     }
 
-    /// 函数 update_view_proj 根据相机和投影参数更新视图位置和视图投影矩阵。
+    /// 函数 update_view_proj 根据相机和投影参数更新视图位置、视图投影矩阵和视图矩阵。
     ///
     /// Arguments:
     ///
@@ -295,10 +546,85 @@ impl CameraUniform {
     /// “Projection”结构的“calc_matrix()”方法以“Matrix4”类型返回投影矩阵。
     pub fn update_view_proj(&mut self, camera: &Camera, projection: &Projection) {
         self.view_position = camera.position.extend(1.0).into();
-        self.view_proj = (projection.calc_matrix() * camera.calc_matrix()).to_cols_array_2d()
+        let view = camera.calc_matrix();
+        self.view_proj = (projection.calc_matrix() * view).to_cols_array_2d();
+        self.view = view.to_cols_array_2d();
+    }
+
+    /// 由视图投影矩阵求出视锥体的六个平面。
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_proj(&glam::Mat4::from_cols_array_2d(&self.view_proj))
     }
 }
 
+/// `Frustum` 是相机的视锥体，由六个法线朝内的平面组成。
+///
+/// Properties:
+///
+/// * `planes`: 平面 `(n, d)`，点 `p` 在平面内侧当且仅当 `dot(n, p) + d >= 0`，`n` 已归一化。
+pub struct Frustum {
+    planes: [glam::Vec4; 6],
+}
+
+impl Frustum {
+    /// 从视图投影矩阵中提取视锥体平面（Gribb-Hartmann 方法）。wgpu 的裁剪空间深度范围是 [0, 1]，
+    /// 所以近平面就是第三行本身。
+    ///
+    /// Arguments:
+    ///
+    /// * `view_proj`: 相机的视图投影矩阵。
+    pub fn from_view_proj(view_proj: &glam::Mat4) -> Self {
+        let [row0, row1, row2, row3] = [0, 1, 2, 3].map(|i| view_proj.row(i));
+        let planes = [
+            row3 + row0, // left
+            row3 - row0, // right
+            row3 + row1, // bottom
+            row3 - row1, // top
+            row2,        // near
+            row3 - row2, // far
+        ]
+        .map(|plane| plane / plane.truncate().length());
+        Self { planes }
+    }
+
+    /// 判断球体是否与视锥体相交，完全在某个平面外侧的球体返回 `false`。
+    ///
+    /// Arguments:
+    ///
+    /// * `center`: 球心。
+    /// * `radius`: 半径。
+    pub fn intersects_sphere(&self, center: glam::Vec3, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(center) + plane.w >= -radius)
+    }
+}
+
+/// `View` 是可以一键切换到的视图。
+///
+/// Variants:
+///
+/// * `Top`: 从 +Y 方向向下看的正交视图，屏幕上方是 -Z。
+/// * `Front`: 从 +Z 方向看的正交视图。
+/// * `Side`: 从 +X 方向看的正交视图。
+/// * `Default`: 回到第一次切换之前的相机位姿和投影。
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum View {
+    Top,
+    Front,
+    Side,
+    Default,
+}
+
+// 切换到正交视图之前的相机位姿和投影
+#[derive(Debug, Copy, Clone)]
+struct SavedView {
+    position: glam::Vec3,
+    yaw: f32,
+    pitch: f32,
+    ortho_half_height: Option<f32>,
+}
+
 pub struct CameraState {
     pub camera: Camera,
     pub projection: Projection,
@@ -308,6 +634,16 @@ pub struct CameraState {
     pub camera_bind_group_layout: wgpu::BindGroupLayout,
     pub camera_bind_group: wgpu::BindGroup,
     pub mouse_pressed: bool,
+    // 跟随的小球，None 时自由移动
+    follow: Option<u32>,
+    // 跟随时相机相对于小球的偏移，移动按键调整的是它
+    follow_offset: glam::Vec3,
+    // 趋近目标位置的速率（1/秒），0 表示直接放到目标位置
+    follow_smoothing: f32,
+    // 切换到正交视图之前的状态，View::Default 时恢复
+    saved_view: Option<SavedView>,
+    // 以原点为中心的场景的半边长，设置时每次更新按相机到场景的距离调整远平面
+    scene_bounds: Option<glam::Vec3>,
 }
 
 impl CameraState {
@@ -316,13 +652,19 @@ impl CameraState {
     /// Arguments:
     ///
     /// * `app`: “app”参数的类型为“&AppSurface”，它可能是对应用程序表面或窗口的引用。用于访问设备并创建与相机相关的各种资源。
+    /// * `camera`: 相机的初始位姿。
     ///
     /// Returns:
     ///
     /// “new”函数返回定义它的结构的实例。
-    pub fn new(app: &AppSurface) -> Self {
-        let camera = Camera::new((0.0, 0.0, 15.0), -90.0, -20.0);
-        let projection = Projection::new(app.config.width, app.config.height, 45.0, 0.1, 100.0);
+    pub fn new(app: &AppSurface, camera: Camera) -> Self {
+        let projection = Projection::new(
+            app.config.width,
+            app.config.height,
+            45.0,
+            DEFAULT_ZNEAR,
+            DEFAULT_ZFAR,
+        );
         let camera_controller = CameraController::new(4.0, 0.4);
         let mut camera_uniform = CameraUniform::new();
         camera_uniform.update_view_proj(&camera, &projection);
@@ -367,6 +709,11 @@ impl CameraState {
             camera_bind_group_layout,
             camera_bind_group,
             mouse_pressed,
+            follow: None,
+            follow_offset: DEFAULT_FOLLOW_OFFSET,
+            follow_smoothing: DEFAULT_FOLLOW_SMOOTHING,
+            saved_view: None,
+            scene_bounds: None,
         }
     }
     /// 此函数更新 Rust 应用程序中的相机和相机制服。
@@ -375,8 +722,27 @@ impl CameraState {
     ///
     /// * `app`: `app` 参数的类型为 `AppSurface`。它代表将在其上渲染图形的应用程序表面或窗口。
     /// * `dt`: `dt` 是一个 `std::time::Duration` 参数，表示当前帧和前一帧之间的时间差。它用于根据经过的时间更新相机的位置和方向。
-    pub fn update(&mut self, app: &AppSurface, dt: std::time::Duration) {
-        self.camera_controller.update_camera(&mut self.camera, dt);
+    /// * `instances`: 最近一次读回的小球，跟随小球时从中查找它的位置；跟随的小球已经被移除时停止跟随。
+    pub fn update(
+        &mut self,
+        app: &AppSurface,
+        dt: std::time::Duration,
+        instances: &[ComputeInstance],
+    ) {
+        let target = self.follow.and_then(|id| {
+            instances
+                .iter()
+                .find(|instance| instance.id == id)
+                .map(|instance| instance.position)
+        });
+        match target {
+            Some(target) => self.update_follow(target, dt),
+            None => {
+                self.follow = None;
+                self.camera_controller.update_camera(&mut self.camera, dt);
+            }
+        }
+        self.update_clip();
         self.camera_uniform
             .update_view_proj(&self.camera, &self.projection);
         app.queue.write_buffer(
@@ -386,6 +752,221 @@ impl CameraState {
         );
     }
 
+    /// 设置以原点为中心的场景的半边长，之后每次更新都把远平面放到场景中离相机最远的点之外，
+    /// 场景很大或者相机拉得很远时小球不会被裁掉；`None` 时保持当前的近平面和远平面。
+    pub fn set_scene_bounds(&mut self, half_extents: Option<glam::Vec3>) {
+        self.scene_bounds = half_extents;
+        self.update_clip();
+    }
+
+    // 场景中任意一点到相机的距离不超过相机到原点的距离加上包围盒的半对角线
+    fn update_clip(&mut self) {
+        if let Some(half_extents) = self.scene_bounds {
+            let zfar = (self.camera.position.length() + half_extents.length()) * CLIP_MARGIN;
+            self.projection
+                .set_clip(DEFAULT_ZNEAR, zfar.max(DEFAULT_ZFAR));
+        }
+    }
+
+    /// 开始跟随小球 `id`，之后每次更新都把相机放到小球加上偏移的位置并看向小球；`None` 恢复自由移动。
+    ///
+    /// 跟随时移动按键调整的是相对于小球的偏移：向前靠近小球，左右绕着小球移动，鼠标不再转动视角。
+    pub fn follow(&mut self, id: Option<u32>) {
+        self.follow = id;
+    }
+
+    /// 正在跟随的小球。
+    pub fn following(&self) -> Option<u32> {
+        self.follow
+    }
+
+    /// 设置跟随时相机相对于小球的偏移，长度不能小于最小距离。
+    pub fn set_follow_offset(&mut self, offset: glam::Vec3) {
+        if offset.length() >= MIN_FOLLOW_DISTANCE {
+            self.follow_offset = offset;
+        }
+    }
+
+    /// 设置相机趋近目标位置的速率（1/秒），越大跟得越紧，0 表示不做平滑。
+    pub fn set_follow_smoothing(&mut self, smoothing: f32) {
+        self.follow_smoothing = smoothing.max(0.0);
+    }
+
+    // 跟随时更新相机：移动按键作用在偏移上，相机平滑地趋近目标位置，然后看向小球
+    fn update_follow(&mut self, target: glam::Vec3, dt: std::time::Duration) {
+        let mut offset_camera = Camera {
+            position: self.follow_offset,
+            yaw: self.camera.yaw,
+            pitch: self.camera.pitch,
+        };
+        self.camera_controller.update_camera(&mut offset_camera, dt);
+        self.set_follow_offset(offset_camera.position);
+
+        let desired = target + self.follow_offset;
+        // 按指数衰减趋近，帧率不同时跟随的快慢一致
+        let t = if self.follow_smoothing > 0.0 {
+            1.0 - (-self.follow_smoothing * dt.as_secs_f32()).exp()
+        } else {
+            1.0
+        };
+        self.camera.position = self.camera.position.lerp(desired, t);
+
+        if let Some(direction) = (target - self.camera.position).try_normalize() {
+            self.camera.yaw = direction.z.atan2(direction.x);
+            self.camera.pitch = direction.y.asin().clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2);
+        }
+    }
+
+    /// 立即切换到正对边界盒子的正交视图，视野刚好放下整个边界；`View::Default` 回到切换之前的相机。
+    ///
+    /// 切换时停止跟随小球，并丢弃还没有完成的平滑移动和转动。正交视图中仍然可以移动相机。
+    ///
+    /// Arguments:
+    ///
+    /// * `view`: 要切换到的视图。
+    /// * `boundary`: 边界在三个轴上的半边长。
+    pub fn snap_view(&mut self, view: View, boundary: glam::Vec3) {
+        self.follow = None;
+        self.camera_controller.movement = glam::Vec3::ZERO;
+        self.camera_controller.pending_rotation = glam::Vec2::ZERO;
+
+        // 屏幕上水平和竖直方向的半边长，以及相机的位姿
+        let (half_width, half_height, position, yaw, pitch) = match view {
+            View::Top => (
+                boundary.x,
+                boundary.z,
+                glam::Vec3::new(0.0, boundary.y + SNAP_VIEW_DISTANCE, 0.0),
+                -FRAC_PI_2,
+                -SAFE_FRAC_PI_2,
+            ),
+            View::Front => (
+                boundary.x,
+                boundary.y,
+                glam::Vec3::new(0.0, 0.0, boundary.z + SNAP_VIEW_DISTANCE),
+                -FRAC_PI_2,
+                0.0,
+            ),
+            View::Side => (
+                boundary.z,
+                boundary.y,
+                glam::Vec3::new(boundary.x + SNAP_VIEW_DISTANCE, 0.0, 0.0),
+                std::f32::consts::PI,
+                0.0,
+            ),
+            View::Default => {
+                if let Some(saved) = self.saved_view.take() {
+                    self.camera.position = saved.position;
+                    self.camera.yaw = saved.yaw;
+                    self.camera.pitch = saved.pitch;
+                    self.projection.set_orthographic(saved.ortho_half_height);
+                }
+                return;
+            }
+        };
+
+        // 只在第一次切换时保存，连续切换几个正交视图之后仍然回到原来的相机
+        if self.saved_view.is_none() {
+            self.saved_view = Some(SavedView {
+                position: self.camera.position,
+                yaw: self.camera.yaw,
+                pitch: self.camera.pitch,
+                ortho_half_height: self.projection.ortho_half_height,
+            });
+        }
+        self.camera.position = position;
+        self.camera.yaw = yaw;
+        self.camera.pitch = pitch;
+        let half_height = half_height.max(half_width / self.projection.aspect) * SNAP_VIEW_MARGIN;
+        self.projection.set_orthographic(Some(half_height));
+    }
+
+    /// 保持相机的朝向，把相机沿视线方向移动到刚好能看到整个包围盒的位置。
+    ///
+    /// 透视投影时按竖直和水平视野中较小的一个计算距离，正交投影时同时调整视野的大小。
+    /// 对准时停止跟随小球，并丢弃还没有完成的平滑移动和转动。
+    ///
+    /// Arguments:
+    ///
+    /// * `min`: 包围盒在世界空间中的最小角。
+    /// * `max`: 包围盒在世界空间中的最大角。
+    pub fn frame_bounds(&mut self, min: glam::Vec3, max: glam::Vec3) {
+        self.follow = None;
+        self.camera_controller.movement = glam::Vec3::ZERO;
+        self.camera_controller.pending_rotation = glam::Vec2::ZERO;
+
+        let center = (min + max) * 0.5;
+        // 用包围球代替包围盒，无论从哪个方向看都不会超出视野
+        let radius = ((max - min) * 0.5).length().max(MIN_FOLLOW_DISTANCE) * FRAME_MARGIN;
+        let (sin_pitch, cos_pitch) = self.camera.pitch.sin_cos();
+        let (sin_yaw, cos_yaw) = self.camera.yaw.sin_cos();
+        let forward = glam::Vec3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw);
+
+        let distance = match self.projection.ortho_half_height {
+            Some(_) => {
+                let half_height = radius.max(radius / self.projection.aspect);
+                self.projection.set_orthographic(Some(half_height));
+                radius + SNAP_VIEW_DISTANCE
+            }
+            None => {
+                let half_fovy = self.projection.fovy * 0.5;
+                let half_fovx = (half_fovy.tan() * self.projection.aspect).atan();
+                radius / half_fovy.min(half_fovx).sin()
+            }
+        };
+        self.camera.position = center - forward * distance;
+    }
+
+    /// 根据窗口中的光标位置计算世界空间中的拾取射线。
+    ///
+    /// Arguments:
+    ///
+    /// * `cursor`: 光标在窗口中的物理像素位置，原点在左上角。
+    /// * `width`: 窗口的宽度（像素）。
+    /// * `height`: 窗口的高度（像素）。
+    ///
+    /// Returns:
+    ///
+    /// 射线的起点（位于近裁剪平面上）和归一化的方向。
+    pub fn screen_ray(
+        &self,
+        cursor: PhysicalPosition<f64>,
+        width: u32,
+        height: u32,
+    ) -> (glam::Vec3, glam::Vec3) {
+        let ndc_x = 2.0 * cursor.x as f32 / width as f32 - 1.0;
+        let ndc_y = 1.0 - 2.0 * cursor.y as f32 / height as f32;
+        let inv_view_proj = (self.projection.calc_matrix() * self.camera.calc_matrix()).inverse();
+        // wgpu 的 NDC 深度范围是 [0, 1]
+        let near = inv_view_proj.project_point3(glam::Vec3::new(ndc_x, ndc_y, 0.0));
+        let far = inv_view_proj.project_point3(glam::Vec3::new(ndc_x, ndc_y, 1.0));
+        (near, (far - near).normalize())
+    }
+
+    /// 把窗口中的像素和它的深度还原成世界空间中的位置，深度可以用 [`Texture::read_depth_at`](crate::texture::Texture::read_depth_at) 读回。
+    ///
+    /// Arguments:
+    ///
+    /// * `cursor`: 像素在窗口中的物理像素位置，原点在左上角。
+    /// * `width`: 窗口的宽度（像素）。
+    /// * `height`: 窗口的高度（像素）。
+    /// * `depth`: 这个像素在深度纹理中的值，范围 [0, 1]。
+    ///
+    /// Returns:
+    ///
+    /// 世界空间中的位置。深度为 1 时没有绘制任何东西，得到的是远平面上的点。
+    pub fn unproject(
+        &self,
+        cursor: PhysicalPosition<f64>,
+        width: u32,
+        height: u32,
+        depth: f32,
+    ) -> glam::Vec3 {
+        let ndc_x = 2.0 * cursor.x as f32 / width as f32 - 1.0;
+        let ndc_y = 1.0 - 2.0 * cursor.y as f32 / height as f32;
+        let inv_view_proj = (self.projection.calc_matrix() * self.camera.calc_matrix()).inverse();
+        inv_view_proj.project_point3(glam::Vec3::new(ndc_x, ndc_y, depth))
+    }
+
     /// 该函数处理各种输入事件，例如键盘输入、鼠标滚轮滚动和鼠标按钮单击。
     ///
     /// Arguments:
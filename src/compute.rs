@@ -1,8 +1,10 @@
-use std::{iter, sync::Arc};
+use std::{collections::HashMap, iter, sync::Arc};
 
 use app_surface::AppSurface;
+use rand::{Rng, SeedableRng};
+use wgpu::util::DeviceExt;
 
-use crate::utils;
+use crate::{model, readback::Readback, shaders, utils};
 
 #[derive(Debug, Copy, Clone)]
 pub struct ComputeInstance {
@@ -10,6 +12,10 @@ pub struct ComputeInstance {
     pub position: glam::Vec3,
     pub radius: f32,
     pub velocity: glam::Vec3,
+    // 仅用于渲染，不参与碰撞计算
+    pub color: glam::Vec4,
+    // 按 HEAT_DECAY_TIME 衰减的接触次数，由碰撞阶段累加、读回时更新，仅用于渲染
+    pub collision_heat: f32,
 }
 
 #[repr(C)]
@@ -19,7 +25,8 @@ pub struct ComputeInstanceRaw {
     id: u32,
     radius: f32,
     cell_index: u32,
-    _padding_radius: u32,
+    // 非 0 表示这个位置上没有小球，见 ComputeInstanceRaw::dead
+    dead: u32,
     position: [f32; 3],
     _padding_position: u32,
     velocity: [f32; 3],
@@ -35,7 +42,23 @@ impl ComputeInstance {
             radius: self.radius,
             velocity: self.velocity.to_array(),
             _padding_position: 0,
-            _padding_radius: 0,
+            dead: 0,
+            _padding_velocity: 0,
+        }
+    }
+}
+
+impl ComputeInstanceRaw {
+    // 实例少于 buffer 的容量时，空出的位置用死亡的占位实例填满，它们不参与碰撞，结果也会被标记为死亡
+    fn dead(id: u32) -> Self {
+        Self {
+            id,
+            radius: 0.0,
+            cell_index: 0,
+            dead: 1,
+            position: [0.0; 3],
+            _padding_position: 0,
+            velocity: [0.0; 3],
             _padding_velocity: 0,
         }
     }
@@ -44,13 +67,33 @@ impl ComputeInstance {
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Parameters {
+    // 边界盒子在三个轴上的半边长
+    pub boundary: [f32; 3],
+    _padding_boundary: u32,
+    // 重力加速度，即 ComputeState 的 gravity_direction * gravity
+    pub gravity: [f32; 3],
+    _padding_gravity: u32,
     pub time_step: f32,
-    pub boundary: f32,
     pub grid_size: f32,
+    pub restitution: f32,
+    pub integrator: u32,
+    pub sleep_velocity: f32,
+    pub sleep_time: f32,
+    pub drag: f32,
+    pub dimensions: u32,
+    pub force_model: u32,
+    pub stiffness: f32,
+    pub equilibrium_distance: f32,
+    pub broad_phase: u32,
+    pub cell_indexing: u32,
+    pub max_speed: f32,
+    // uniform buffer 的大小按 16 字节对齐
+    _padding: [u32; 2],
 }
 
+// 双调排序中的一遍：k 是这一遍所在的归并阶段的块大小，j 是比较的跨度，j == k / 2 时比较块内的镜像位置
 #[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct SortParams {
     pub j: u32,
     pub k: u32,
@@ -63,18 +106,587 @@ pub struct CellIndex {
     pub end: u32,
 }
 
+// LBVH 的内部节点，子节点的最高位是 BVH_LEAF 标记，见 header.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BvhNode {
+    pub min: [f32; 3],
+    pub left: u32,
+    pub max: [f32; 3],
+    pub right: u32,
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Result {
     pub position: [f32; 3],
-    _padding: u32,
+    // 速度连续低于休眠速度的时间，只在 GPU 上跨步保留
+    pub still_time: f32,
     pub velocity: [f32; 3],
+    // 非 0 表示小球已经死亡，例如越过了吸收平面，读回时会被移除
+    pub dead: u32,
+    // 这一步的加速度，Verlet 积分在下一步中用到
+    pub acceleration: [f32; 3],
+    // 按 HEAT_DECAY_TIME 衰减的接触次数，只在 GPU 上跨步累加
+    pub collision_heat: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PickRay {
+    pub origin: [f32; 3],
+    _padding: u32,
+    pub direction: [f32; 3],
+    _padding2: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PickResult {
+    pub id: u32,
+    pub distance: f32,
+}
+
+// 默认的边界，半边长为 10 的立方体
+pub const DEFAULT_BOUNDARY: f32 = 10.0;
+
+// 每次 update 中碰撞检测的默认轮数
+pub const SIMULATION_ROUNDS: u32 = 10;
+
+// 自适应子步的默认值：每一轮中小球最多移动半径的一半
+pub const DEFAULT_MAX_MOVE_FRACTION: f32 = 0.5;
+
+// 自适应子步时一次 update 的轮数上限，速度极大时宁可穿透，也不让一帧无限地变慢
+pub const MAX_ADAPTIVE_ROUNDS: u32 = 256;
+
+// 默认的时间缩放，1 表示按真实时间推进
+pub const DEFAULT_TIME_SCALE: f32 = 1.0;
+
+// 时间缩放的上限，快进时每次 update 的轮数按比例增加
+pub const MAX_TIME_SCALE: f32 = 16.0;
+
+// 默认的速度上限，远大于正常运动的速度，只用来防止初始重叠的小球被弹出极大的速度、穿过其他小球
+pub const DEFAULT_MAX_SPEED: f32 = 100.0;
+
+// 默认的重力加速度
+pub const DEFAULT_GRAVITY: f32 = 9.8;
+
+// 默认的碰撞恢复系数
+pub const DEFAULT_RESTITUTION: f32 = 0.85;
+
+// 默认的阻尼系数，0 表示没有阻尼
+pub const DEFAULT_DRAG: f32 = 0.0;
+
+// 小球之间作用力默认的刚度，每一步通过 Parameters::stiffness 传给着色器
+pub const DEFAULT_STIFFNESS: f32 = 1000.0;
+
+// 默认的平衡距离，0 表示取两个小球的半径之和
+pub const DEFAULT_EQUILIBRIUM_DISTANCE: f32 = 0.0;
+
+// 碰撞阶段默认的工作组大小
+pub const DEFAULT_WORKGROUP_SIZE: u32 = 64;
+
+// 分配网格、排序、建网格和间接绘制的着色器中写死的工作组大小
+pub const SHADER_WORKGROUP_SIZE: u32 = 64;
+
+// 结果 buffer 和拾取结果 buffer 既是 storage buffer 又可以映射读取，创建设备时必须请求这个特性
+pub const REQUIRED_FEATURES: wgpu::Features = wgpu::Features::MAPPABLE_PRIMARY_BUFFERS;
+
+// 基准测试中依次尝试的碰撞阶段工作组大小
+pub const WORKGROUP_SIZES: [u32; 4] = [32, 64, 128, 256];
+
+// 初始状态中相互重叠的小球对超过实例数量的这个比例时打印警告
+pub const OVERLAP_WARNING_FRACTION: f32 = 0.01;
+
+// 默认的休眠速度，0 表示不休眠
+pub const DEFAULT_SLEEP_VELOCITY: f32 = 0.0;
+
+// 默认的休眠等待时间，单位是秒
+pub const DEFAULT_SLEEP_TIME: f32 = 0.5;
+
+// collision_heat 衰减到 1/e 的时间，单位是秒，着色器中的 HEAT_DECAY_TIME 由它生成。
+// 一直保持 n 个接触的小球，collision_heat 趋于 n * HEAT_DECAY_TIME
+pub const HEAT_DECAY_TIME: f32 = 0.5;
+
+// 与速度三次方成正比的空气阻力系数，着色器中的 AR 由它生成
+pub const AR: f32 = 0.01;
+
+// 速度超过休眠速度的这个倍数时，小球会唤醒它接触到的休眠小球，着色器中的 WAKE_FACTOR 由它生成
+pub const WAKE_FACTOR: f32 = 2.0;
+
+// Lennard-Jones 势中距离的下限与平衡距离之比，更近时力增长得太快，显式积分会发散。
+// 着色器中的 LJ_MIN_DISTANCE_FACTOR 由它生成
+pub const LJ_MIN_DISTANCE_FACTOR: f32 = 0.8;
+
+/// 计算着色器和网格视图共用的开头：由上面的 Rust 常量生成的 WGSL 常量，接着是 header.wgsl。
+///
+/// CPU 求解器与着色器因此使用同一份常量，修改时只需要改这里的定义。
+pub fn shader_header() -> String {
+    format!(
+        "const AR: f32 = {AR:?};\n\
+         const HEAT_DECAY_TIME: f32 = {HEAT_DECAY_TIME:?};\n\
+         const WAKE_FACTOR: f32 = {WAKE_FACTOR:?};\n\
+         const LJ_MIN_DISTANCE_FACTOR: f32 = {LJ_MIN_DISTANCE_FACTOR:?};\n\
+         {}",
+        shaders::shader_source!("header.wgsl")
+    )
+}
+
+/// `Integrator` 是碰撞阶段使用的数值积分方法，取值与 header.wgsl 中的 `INTEGRATOR_*` 常量一致。
+///
+/// Variants:
+///
+/// * `Euler`: 用这一步的加速度更新速度，位置按泰勒展开前进，长时间运行时能量会漂移。
+/// * `Verlet`: 速度 Verlet，速度用上一步和这一步加速度的平均值更新，能量漂移更小。
+///   上一步的加速度按实例 ID 保存在 `result_buffer` 的 `Result::acceleration` 中，它只在 GPU 上
+///   跨步保留，CPU 每次写回实例时不会覆盖它；第一步时 buffer 被清零，相当于上一步的加速度为 0。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Integrator {
+    #[default]
+    Euler = 0,
+    Verlet = 1,
+}
+
+impl Integrator {
+    // 从状态文件中保存的取值恢复，未知的取值返回 None
+    fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Integrator::Euler),
+            1 => Some(Integrator::Verlet),
+            _ => None,
+        }
+    }
+}
+
+/// `ForceModel` 是小球之间的作用力模型，取值与 header.wgsl 中的 `FORCE_MODEL_*` 常量一致。
+///
+/// 所有模型都在碰撞阶段遍历相邻格子时计算，所以只有距离小于一个格子的小球对之间有力；
+/// 平衡距离 `r0` 是 `equilibrium_distance`，为 0 时取两个小球的半径之和。
+///
+/// Variants:
+///
+/// * `Contact`: 只在重叠时有排斥力，大小是 `stiffness` 乘以重叠的深度。
+/// * `Spring`: 线性弹簧，距离小于 `r0` 时排斥、大于 `r0` 时吸引，刚度是 `stiffness`。
+/// * `LennardJones`: Lennard-Jones 势，势能最低点在 `r0`，势阱深度取 `stiffness * r0^2 / 72`，
+///   使平衡位置附近的刚度也是 `stiffness`；距离小于 `0.8 * r0` 时按 `0.8 * r0` 计算，避免力过大。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForceModel {
+    #[default]
+    Contact = 0,
+    Spring = 1,
+    LennardJones = 2,
+}
+
+impl ForceModel {
+    // 从状态文件中保存的取值恢复，未知的取值返回 None
+    fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(ForceModel::Contact),
+            1 => Some(ForceModel::Spring),
+            2 => Some(ForceModel::LennardJones),
+            _ => None,
+        }
+    }
+}
+
+/// `BroadPhase` 是碰撞阶段查找相邻小球的方法，取值与 header.wgsl 中的 `BROAD_PHASE_*` 常量一致。
+///
+/// Variants:
+///
+/// * `Grid`: 均匀网格，网格大小不能小于最大直径，半径相差很大时小球会在同一个格子中堆积很多。
+/// * `Bvh`: 每一步按球心的 Morton 码排序（复用网格的双调排序），并行地建立 LBVH，碰撞阶段遍历它。
+///   包围盒按每个小球自己的半径计算，不受最大半径的影响，适合半径相差很大的场景。
+/// * `BruteForce`: 不排序也不建立任何结构，每个小球和其他所有小球比较，复杂度是 O(n^2)。
+///   不会漏掉任何一对小球，用作检查其他方法是否正确的基准。
+///
+/// 宽相位可以在模拟过程中随时切换：CPU 每一步都会写回所有实例，按 ID 保存在 GPU 上的状态也与宽相位无关，
+/// 切换前后小球的位置和速度是连续的。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BroadPhase {
+    #[default]
+    Grid = 0,
+    Bvh = 1,
+    BruteForce = 2,
+}
+
+impl BroadPhase {
+    // 从状态文件中保存的取值恢复，未知的取值返回 None
+    fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(BroadPhase::Grid),
+            1 => Some(BroadPhase::Bvh),
+            2 => Some(BroadPhase::BruteForce),
+            _ => None,
+        }
+    }
+
+    /// 返回循环切换时的下一个宽相位。
+    pub fn next(self) -> Self {
+        match self {
+            BroadPhase::Grid => BroadPhase::Bvh,
+            BroadPhase::Bvh => BroadPhase::BruteForce,
+            BroadPhase::BruteForce => BroadPhase::Grid,
+        }
+    }
+}
+
+/// `CellIndexing` 是网格单元的编号方式，取值与 header.wgsl 中的 `CELL_INDEXING_*` 常量一致。
+///
+/// 小球按所在单元的编号排序，编号方式决定了排序之后哪些小球在 buffer 中相邻。
+///
+/// Variants:
+///
+/// * `Linear`: `x + y * n + z * n * n`，只有 x 方向上相邻的单元在编号上相邻。
+/// * `Morton`: Z 序曲线，空间上相邻的单元在编号上也尽量接近，碰撞阶段读取相邻单元时的缓存命中率更高。
+///   编号要求边长是 2 的幂，单元 buffer 按向上取整之后的边长分配，多出的单元始终是空的；
+///   三维时每个轴最多 1024 个单元。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CellIndexing {
+    #[default]
+    Linear = 0,
+    Morton = 1,
+}
+
+impl CellIndexing {
+    // 从状态文件中保存的取值恢复，未知的取值返回 None
+    fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(CellIndexing::Linear),
+            1 => Some(CellIndexing::Morton),
+            _ => None,
+        }
+    }
+}
+
+/// `Dimensions` 是模拟的维数，取值与 header.wgsl 中的 `DIMENSIONS_*` 常量一致。
+///
+/// Variants:
+///
+/// * `D2`: 小球只在 z = 0 的平面内运动，碰撞阶段每一步都去掉位置和速度的 z 分量，网格只有一层。
+///   实例的布局与三维相同，只是 z 固定为 0。
+/// * `D3`: 三维模拟。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+pub enum Dimensions {
+    #[serde(rename = "2d")]
+    D2 = 2,
+    #[default]
+    #[serde(rename = "3d")]
+    D3 = 3,
+}
+
+impl Dimensions {
+    // 从状态文件中保存的取值恢复，未知的取值返回 None
+    fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            2 => Some(Dimensions::D2),
+            3 => Some(Dimensions::D3),
+            _ => None,
+        }
+    }
+}
+
+/// `Diagnostics` 是所有实例的守恒量之和，用来检查碰撞是否守恒。质量与碰撞着色器一致，每个小球都取 1。
+///
+/// 同时记录相互重叠的小球对的数量，长时间运行时可以和动能一起用来判断是否已经收敛。
+///
+/// Properties:
+///
+/// * `kinetic_energy`: 总动能，`sum(|v|^2 / 2)`。
+/// * `momentum`: 总线动量，`sum(v)`。
+/// * `overlaps`: 相互重叠的小球对的数量。
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Diagnostics {
+    pub kinetic_energy: f32,
+    pub momentum: glam::Vec3,
+    pub overlaps: usize,
+}
+
+/// `Emitter` 以固定的速率在一个点上发射新的小球，初速度的方向在以 `direction` 为轴的圆锥内随机选取。
+///
+/// 新的小球占用被移除的小球空出来的位置，`instances` 达到容量时不再发射，和吸收平面一起可以得到稳定的喷泉。
+///
+/// Properties:
+///
+/// * `position`: 发射点。
+/// * `direction`: 圆锥的轴，不需要归一化。
+/// * `spread`: 圆锥的半角，单位是弧度，0 表示总是沿 `direction` 发射。
+/// * `rate`: 每秒（模拟时间）发射的小球数量。
+/// * `speed`: 初速度的大小。
+/// * `radius`: 新的小球的半径，直径不能超过网格大小。
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Emitter {
+    pub position: glam::Vec3,
+    pub direction: glam::Vec3,
+    pub spread: f32,
+    pub rate: f32,
+    pub speed: f32,
+    pub radius: f32,
+}
+
+impl Emitter {
+    /// 检查发射器的参数，返回方向归一化之后的发射器。
+    ///
+    /// Arguments:
+    ///
+    /// * `grid_size`: 碰撞检测的网格大小，新的小球的直径不能超过它。
+    ///
+    /// Returns:
+    ///
+    /// 方向为零、速率或速度为负、半角不在 [0, π] 之内，或者半径不是正数、直径超过网格大小时返回错误。
+    pub fn validated(self, grid_size: f32) -> anyhow::Result<Self> {
+        let Some(direction) = self.direction.try_normalize() else {
+            anyhow::bail!("emitter direction must be non-zero");
+        };
+        if !(self.rate >= 0.0 && self.rate.is_finite()) {
+            anyhow::bail!("emission rate must be non-negative, got {}", self.rate);
+        }
+        if !(self.speed >= 0.0 && self.speed.is_finite()) {
+            anyhow::bail!("emission speed must be non-negative, got {}", self.speed);
+        }
+        if !(0.0..=std::f32::consts::PI).contains(&self.spread) {
+            anyhow::bail!(
+                "emission spread must be between 0 and pi, got {}",
+                self.spread
+            );
+        }
+        if !(self.radius > 0.0 && min_grid_size(self.radius) <= grid_size) {
+            anyhow::bail!(
+                "emitted radius must be positive and its diameter at most the grid size {}, got {}",
+                grid_size,
+                self.radius
+            );
+        }
+        Ok(Self { direction, ..self })
+    }
+}
+
+// 按 instances 重新标记 used 中被占用的 id，used 的长度变为 len，没有小球的 id 为 false
+fn mark_used_ids(used: &mut Vec<bool>, instances: &[ComputeInstance], len: usize) {
+    used.clear();
+    used.resize(len, false);
+    for instance in instances {
+        used[instance.id as usize] = true;
+    }
+}
+
+// 状态文件的开头，用来识别文件格式
+const STATE_MAGIC: u32 = u32::from_le_bytes(*b"CDGS");
+const STATE_VERSION: u32 = 4;
+
+// 状态文件的文件头，后面紧跟着 count 个 SavedInstance
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct StateHeader {
+    magic: u32,
+    version: u32,
+    // 存活的小球数量；capacity 是 buffer 的容量，所有 id 都小于它，被移除的小球空出的 id 可以不连续
+    count: u32,
+    capacity: u32,
+    boundary: [f32; 3],
+    grid_size: f32,
+    gravity: f32,
+    gravity_direction: [f32; 3],
+    restitution: f32,
+    substeps: u32,
+    // Dimensions 和 CellIndexing 的取值
+    dimensions: u32,
+    cell_indexing: u32,
+    drag: f32,
+    max_speed: f32,
+    // Integrator、ForceModel 和 BroadPhase 的取值
+    integrator: u32,
+    force_model: u32,
+    stiffness: f32,
+    equilibrium_distance: f32,
+    broad_phase: u32,
+    sleep_velocity: f32,
+    sleep_time: f32,
+}
+
+// ComputeInstance 在文件中的布局
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SavedInstance {
+    id: u32,
+    radius: f32,
+    position: [f32; 3],
+    velocity: [f32; 3],
+    color: [f32; 4],
+    collision_heat: f32,
+}
+
+// 把单位向量 current 朝 target 转过不超过 max_angle 弧度，结果仍然是单位向量；target 为零向量时不旋转
+fn turn_toward(current: glam::Vec3, target: glam::Vec3, max_angle: f32) -> glam::Vec3 {
+    let Some(target) = target.try_normalize() else {
+        return current;
+    };
+    if current.angle_between(target) <= max_angle {
+        return target;
+    }
+    let axis = current
+        .cross(target)
+        .try_normalize()
+        .unwrap_or_else(|| current.any_orthonormal_vector());
+    // 旋转之后再归一化，多次旋转累积的误差不会改变重力的大小
+    (glam::Quat::from_axis_angle(axis, max_angle) * current).normalize()
+}
+
+// 所有实例的总动能和总动量；碰撞着色器直接把力当作加速度，所以每个小球的质量都是 1，
+// 否则半径不同的小球之间的碰撞在这里看起来不守恒
+fn conserved_totals(instances: &[ComputeInstance]) -> (f64, glam::DVec3) {
+    // 用 f64 累加，避免实例很多时的舍入误差
+    instances.iter().fold(
+        (0.0f64, glam::DVec3::ZERO),
+        |(kinetic_energy, momentum), instance| {
+            let velocity = instance.velocity.as_dvec3();
+            (
+                kinetic_energy + 0.5 * velocity.length_squared(),
+                momentum + velocity,
+            )
+        },
+    )
+}
+
+// 把文件头和实例编码成状态文件的内容
+fn encode_state(header: &StateHeader, instances: &[ComputeInstance]) -> Vec<u8> {
+    let instances = instances
+        .iter()
+        .map(|instance| SavedInstance {
+            id: instance.id,
+            radius: instance.radius,
+            position: instance.position.to_array(),
+            velocity: instance.velocity.to_array(),
+            color: instance.color.to_array(),
+            collision_heat: instance.collision_heat,
+        })
+        .collect::<Vec<_>>();
+    let mut bytes = bytemuck::bytes_of(header).to_vec();
+    bytes.extend_from_slice(bytemuck::cast_slice(&instances));
+    bytes
+}
+
+// 解码状态文件的内容，文件头和每个实例都检查过之后才交给 GPU
+fn decode_state(bytes: &[u8]) -> anyhow::Result<(StateHeader, Vec<ComputeInstance>)> {
+    let header_size = std::mem::size_of::<StateHeader>();
+    if bytes.len() < header_size {
+        anyhow::bail!("state file is too short");
+    }
+    let header: StateHeader = bytemuck::pod_read_unaligned(&bytes[..header_size]);
+    if header.magic != STATE_MAGIC || header.version != STATE_VERSION {
+        anyhow::bail!("not a state file of version {}", STATE_VERSION);
+    }
+    if Dimensions::from_u32(header.dimensions).is_none() {
+        anyhow::bail!("invalid dimensions {} in state file", header.dimensions);
+    }
+    if CellIndexing::from_u32(header.cell_indexing).is_none() {
+        anyhow::bail!(
+            "invalid cell indexing {} in state file",
+            header.cell_indexing
+        );
+    }
+    if Integrator::from_u32(header.integrator).is_none() {
+        anyhow::bail!("invalid integrator {} in state file", header.integrator);
+    }
+    if ForceModel::from_u32(header.force_model).is_none() {
+        anyhow::bail!("invalid force model {} in state file", header.force_model);
+    }
+    if BroadPhase::from_u32(header.broad_phase).is_none() {
+        anyhow::bail!("invalid broad phase {} in state file", header.broad_phase);
+    }
+    if header.count > header.capacity {
+        anyhow::bail!(
+            "state file holds {} instances, more than its capacity {}",
+            header.count,
+            header.capacity
+        );
+    }
+    let saved: Vec<SavedInstance> = utils::bytes_to_vec(&bytes[header_size..])?;
+    if saved.len() != header.count as usize {
+        anyhow::bail!(
+            "state file holds {} instances, header says {}",
+            saved.len(),
+            header.count
+        );
+    }
+    // id 是结果 buffer 的下标，超出范围或者重复都会让着色器越界或者互相覆盖
+    let mut used = vec![false; header.capacity as usize];
+    for instance in &saved {
+        match used.get_mut(instance.id as usize) {
+            Some(slot) if !*slot => *slot = true,
+            Some(_) => anyhow::bail!("duplicate instance id {} in state file", instance.id),
+            None => anyhow::bail!(
+                "instance id {} in state file is out of range 0..{}",
+                instance.id,
+                header.capacity
+            ),
+        }
+    }
+    let instances = saved
+        .iter()
+        .map(|instance| ComputeInstance {
+            id: instance.id,
+            position: glam::Vec3::from_array(instance.position),
+            radius: instance.radius,
+            velocity: glam::Vec3::from_array(instance.velocity),
+            color: glam::Vec4::from_array(instance.color),
+            collision_heat: instance.collision_heat,
+        })
+        .collect();
+    Ok((header, instances))
+}
+
+// 静态平面的数量上限，要和 header.wgsl 中的 MAX_PLANES 保持一致
+pub const MAX_PLANES: usize = 16;
+
+// 静态平面的种类，要和 header.wgsl 中的 PLANE_* 保持一致：普通平面把小球弹回，吸收平面移除越过它的小球
+const PLANE_SOLID: u32 = 0;
+const PLANE_KILL: u32 = 1;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct StaticPlane {
+    pub point: [f32; 3],
+    // PLANE_SOLID 或者 PLANE_KILL
+    kind: u32,
+    pub normal: [f32; 3],
     _padding2: u32,
 }
 
+impl StaticPlane {
+    /// 是否是吸收平面，越过它的小球被移除，而不是被弹回。
+    pub fn is_kill(&self) -> bool {
+        self.kind == PLANE_KILL
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct StaticTriangle {
+    pub a: [f32; 3],
+    _padding_a: u32,
+    pub b: [f32; 3],
+    _padding_b: u32,
+    pub c: [f32; 3],
+    _padding_c: u32,
+    pub normal: [f32; 3],
+    _padding_normal: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct NearestQuery {
+    pub point: [f32; 3],
+    pub use_grid: u32,
+}
+
 // 这里面不存 Buffer，负责逻辑部分
 pub struct ComputeNode {
     pub bind_group_layout: wgpu::BindGroupLayout,
+    pub uniform_bind_group_layout: wgpu::BindGroupLayout,
     pub bind_groups: Vec<wgpu::BindGroup>,
     pub pipeline_layout: wgpu::PipelineLayout,
     pub pipeline: wgpu::ComputePipeline,
@@ -93,6 +705,19 @@ pub fn new_layout_entry(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutE
     }
 }
 
+pub fn new_uniform_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
 pub fn new_group_entry(binding: u32, buffer: &wgpu::Buffer) -> wgpu::BindGroupEntry {
     wgpu::BindGroupEntry {
         binding,
@@ -106,65 +731,88 @@ pub fn new_group_entry(binding: u32, buffer: &wgpu::Buffer) -> wgpu::BindGroupEn
 
 impl ComputeNode {
     pub fn new(
-        app: &AppSurface,
+        device: &wgpu::Device,
+        shader_source: &str,
+        buffers: &[Arc<wgpu::Buffer>],
+        label: &str,
+    ) -> Self {
+        Self::with_workgroup_size(
+            device,
+            shader_source,
+            buffers,
+            label,
+            DEFAULT_WORKGROUP_SIZE,
+        )
+    }
+
+    /// 与 `new` 相同，但会把着色器中的 `WORKGROUP_SIZE` 替换成 `workgroup_size`，
+    /// 用于工作组大小可以配置的着色器。
+    pub fn with_workgroup_size(
+        device: &wgpu::Device,
         shader_source: &str,
         buffers: &[Arc<wgpu::Buffer>],
         label: &str,
+        workgroup_size: u32,
     ) -> Self {
-        let header = include_str!("../shaders/header.wgsl");
+        let header = shader_header();
+        let shader_source = shader_source.replace("WORKGROUP_SIZE", &workgroup_size.to_string());
 
         let full_shader_source =
             wgpu::ShaderSource::Wgsl(format!("{}\n{}", header, shader_source).into());
 
-        let shader_module = app
-            .device
-            .create_shader_module(wgpu::ShaderModuleDescriptor {
-                label: Some(format!("{} Shader", label).as_str()),
-                source: full_shader_source,
-            });
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(format!("{} Shader", label).as_str()),
+            source: full_shader_source,
+        });
 
-        // layout 都是统一的
-        let bind_group_layout =
-            app.device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    label: Some(format!("{} Bind Group Layout", label).as_str()),
-                    entries: &[new_layout_entry(0, false)],
-                });
-        let pipeline_layout = app
-            .device
-            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some(format!("{} Pipeline Layout", label).as_str()),
-                bind_group_layouts: &[
-                    &bind_group_layout,
-                    &bind_group_layout,
-                    &bind_group_layout,
-                    &bind_group_layout,
-                    &bind_group_layout,
-                ],
-                push_constant_ranges: &[],
-            });
-        let pipeline = app
-            .device
-            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: Some(format!("{} Pipeline", label).as_str()),
-                layout: Some(&pipeline_layout),
-                module: &shader_module,
-                entry_point: "main",
+        // 带有 UNIFORM 用途的 buffer 绑定为 uniform，其余都绑定为 storage
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(format!("{} Bind Group Layout", label).as_str()),
+            entries: &[new_layout_entry(0, false)],
+        });
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(format!("{} Uniform Bind Group Layout", label).as_str()),
+                entries: &[new_uniform_layout_entry(0)],
             });
+        let layout_for = |buffer: &wgpu::Buffer| {
+            if buffer.usage().contains(wgpu::BufferUsages::UNIFORM) {
+                &uniform_bind_group_layout
+            } else {
+                &bind_group_layout
+            }
+        };
+        // 每个 buffer 占一个 group
+        let bind_group_layouts = buffers
+            .iter()
+            .map(|buffer| layout_for(buffer))
+            .collect::<Vec<_>>();
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(format!("{} Pipeline Layout", label).as_str()),
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(format!("{} Pipeline", label).as_str()),
+            layout: Some(&pipeline_layout),
+            module: &shader_module,
+            entry_point: "main",
+        });
 
         let mut bind_groups = Vec::new();
 
         for (i, buffer) in buffers.iter().enumerate() {
-            let bind_group = app.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
                 label: Some(format!("{} Bind Group {}", label, i).as_str()),
-                layout: &bind_group_layout,
-                entries: &[new_group_entry(0, &buffer)],
+                layout: layout_for(buffer),
+                entries: &[new_group_entry(0, buffer)],
             });
             bind_groups.push(bind_group);
         }
 
         Self {
             bind_group_layout,
+            uniform_bind_group_layout,
             bind_groups,
             pipeline_layout,
             pipeline,
@@ -179,261 +827,2903 @@ impl ComputeNode {
         }
         cpass.dispatch_workgroups(workgroup_count, 1, 1);
     }
+
+    /// 与 `dispatch` 相同，但把第 `group` 个 bind group 替换成 `bind_group`，
+    /// 用于同一个通道中每次 dispatch 需要不同参数的节点。
+    pub fn dispatch_with_group<'a, 'b: 'a>(
+        &'b self,
+        cpass: &mut wgpu::ComputePass<'a>,
+        workgroup_count: u32,
+        group: u32,
+        bind_group: &'b wgpu::BindGroup,
+    ) {
+        cpass.set_pipeline(&self.pipeline);
+        for (i, bind_group) in self.bind_groups.iter().enumerate() {
+            cpass.set_bind_group(i as u32, bind_group, &[]);
+        }
+        cpass.set_bind_group(group, bind_group, &[]);
+        cpass.dispatch_workgroups(workgroup_count, 1, 1);
+    }
 }
 
-pub fn read_buffer_bytes(app: &AppSurface, buffer: Arc<wgpu::Buffer>) -> Vec<u8> {
-    buffer
-        .clone()
-        .slice(..)
-        .map_async(wgpu::MapMode::Read, move |result| {
-            result.expect("failed to map storage buffer");
-        });
+/// 半径不超过 `max_radius` 的小球所需的最小网格大小，也就是最大直径。
+///
+/// 碰撞阶段只在小球所在的网格及其相邻的 3x3x3 个网格中查找其他小球。网格不小于最大直径时，
+/// 两个相互重叠的小球的球心相距不超过一个网格，一定落在相邻的网格中；网格更小时可能相隔两个网格，
+/// 这次碰撞就会被漏掉。
+pub fn min_grid_size(max_radius: f32) -> f32 {
+    2.0 * max_radius
+}
 
-    while !app.device.poll(wgpu::MaintainBase::Wait) {
-        // println!("waiting for the map to complete");
+/// 检查规则点阵的参数：相邻的小球在扰动之后也不会重叠，并且整个点阵放得进边界。
+///
+/// Arguments:
+///
+/// * `spacing`: 相邻格点的间距。
+/// * `counts`: 三个方向上的格点数量。
+/// * `jitter`: 每个坐标的随机扰动的最大值。
+/// * `max_radius`: 最大的半径。
+/// * `boundary`: 模拟的边界在三个轴上的半边长。
+///
+/// Returns:
+///
+/// 参数不合法、小球可能重叠或者点阵超出边界时返回错误。
+pub fn check_lattice(
+    spacing: f32,
+    counts: [u32; 3],
+    jitter: f32,
+    max_radius: f32,
+    boundary: glam::Vec3,
+) -> anyhow::Result<()> {
+    if !(spacing > 0.0 && spacing.is_finite()) {
+        anyhow::bail!("lattice spacing must be positive, got {}", spacing);
     }
+    if !(jitter >= 0.0 && jitter.is_finite()) {
+        anyhow::bail!("lattice jitter must be non-negative, got {}", jitter);
+    }
+    if counts.contains(&0) {
+        anyhow::bail!("lattice counts must be positive, got {:?}", counts);
+    }
+    // 扰动让相邻的两个小球在同一个轴上最多靠近 2 * jitter
+    let min_spacing = 2.0 * max_radius + 2.0 * jitter;
+    if spacing < min_spacing {
+        anyhow::bail!(
+            "lattice spacing {} is smaller than {}, neighboring spheres could overlap",
+            spacing,
+            min_spacing
+        );
+    }
+    let half_extent = (glam::UVec3::from_array(counts) - 1).as_vec3() * spacing / 2.0
+        + glam::Vec3::splat(max_radius + jitter);
+    if half_extent.cmpgt(boundary).any() {
+        anyhow::bail!(
+            "lattice of {:?} with spacing {} does not fit in boundary {}",
+            counts,
+            spacing,
+            boundary
+        );
+    }
+    Ok(())
+}
 
-    let mut results: Vec<u8> = Vec::new();
+// 按 ComputeState::seed_lattice 的规则生成点阵上的小球，不检查数量是否超过 buffer 的容量
+#[allow(clippy::too_many_arguments)]
+fn lattice_instances(
+    spacing: f32,
+    counts: [u32; 3],
+    jitter: f32,
+    speed: f32,
+    dimensions: Dimensions,
+    boundary: glam::Vec3,
+    radius_of: impl Fn(u32) -> f32,
+    rng: &mut impl rand::Rng,
+) -> anyhow::Result<Vec<ComputeInstance>> {
+    let count = counts.iter().product::<u32>();
+    if !(speed >= 0.0 && speed.is_finite()) {
+        anyhow::bail!("initial speed must be non-negative, got {}", speed);
+    }
+    if dimensions == Dimensions::D2 && counts[2] != 1 {
+        anyhow::bail!(
+            "a 2D lattice must have a single layer along z, got {:?}",
+            counts
+        );
+    }
+    let max_radius = (0..count).map(&radius_of).fold(0.0, f32::max);
+    check_lattice(spacing, counts, jitter, max_radius, boundary)?;
 
-    {
-        // map the buffer and read results
-        let bufferview = buffer.slice(..).get_mapped_range();
-        let readback = bufferview.to_vec();
-        // copy readback to results
-        results.extend_from_slice(&readback);
+    let [nx, ny, nz] = counts;
+    let origin = -(glam::UVec3::from_array(counts) - 1).as_vec3() * spacing / 2.0;
+    let mut random_in = |range: f32| {
+        if range > 0.0 {
+            rng.gen_range(-range..range)
+        } else {
+            0.0
+        }
+    };
+    let mut instances = Vec::with_capacity(count as usize);
+    for z in 0..nz {
+        for y in 0..ny {
+            for x in 0..nx {
+                let id = instances.len() as u32;
+                let lattice_point = glam::UVec3::new(x, y, z).as_vec3() * spacing;
+                let mut offset =
+                    glam::Vec3::new(random_in(jitter), random_in(jitter), random_in(jitter));
+                let mut velocity =
+                    glam::Vec3::new(random_in(speed), random_in(speed), random_in(speed));
+                if dimensions == Dimensions::D2 {
+                    offset.z = 0.0;
+                    velocity.z = 0.0;
+                }
+                instances.push(ComputeInstance {
+                    id,
+                    position: origin + lattice_point + offset,
+                    radius: radius_of(id),
+                    velocity,
+                    color: glam::Vec4::ONE,
+                    collision_heat: 0.0,
+                });
+            }
+        }
     }
+    Ok(instances)
+}
 
-    // unmap the buffer
-    buffer.clone().unmap();
+/// 边界盒子每个轴上的网格数量，与着色器中的 `grid_counts` 一致，二维时 z 方向上的数量不使用。
+pub fn grid_count(boundary: glam::Vec3, grid_size: f32) -> [u64; 3] {
+    boundary
+        .to_array()
+        .map(|half_extent| ((half_extent * 2.0 / grid_size).ceil() + 0.3) as u64)
+}
 
-    results
+// 单元 buffer 中 CellIndex 的数量。线性编号是三个轴上网格数量的乘积；
+// Morton 编号的最大值由最长的轴向上取整到 2 的幂的边长决定；二维时网格只有 z = 0 这一层
+fn cell_count(
+    boundary: glam::Vec3,
+    grid_size: f32,
+    dimensions: Dimensions,
+    cell_indexing: CellIndexing,
+) -> u64 {
+    let [x, y, z] = grid_count(boundary, grid_size);
+    let z = match dimensions {
+        Dimensions::D2 => 1,
+        Dimensions::D3 => z,
+    };
+    match cell_indexing {
+        CellIndexing::Linear => x * y * z,
+        CellIndexing::Morton => {
+            let side = x.max(y).max(z).next_power_of_two();
+            match dimensions {
+                Dimensions::D2 => side * side,
+                Dimensions::D3 => side * side * side,
+            }
+        }
+    }
 }
 
-pub struct ComputeState {
-    pub instances: Vec<ComputeInstance>,
-    buffer_len: u32,                           // the number of instances
-    boundary: f32,                             // the boundary of the simulation
-    grid_size: f32,                            // the size of the grid
-    pub params_buffer: Arc<wgpu::Buffer>,      // group 0
-    pub instances_buffer: Arc<wgpu::Buffer>,   // group 1
-    pub sort_params_buffer: Arc<wgpu::Buffer>, // group 2
-    pub cell_index_buffer: Arc<wgpu::Buffer>,  // group 3
-    pub result_buffer: Arc<wgpu::Buffer>,      // group 4
-
-    pub assign_cell_node: ComputeNode, // stage 1
-    pub sort_node: ComputeNode,        // stage 2
-    pub memset_node: ComputeNode,      // stage 3
-    pub build_grid_node: ComputeNode,  // stage 4
-    pub collision_node: ComputeNode,   // stage 5
+/// Morton 编号时每个轴上最多的网格数量，三维的编号用 30 位，二维用 32 位。
+pub fn max_morton_grid_count(dimensions: Dimensions) -> u64 {
+    match dimensions {
+        Dimensions::D2 => 1 << 16,
+        Dimensions::D3 => 1 << 10,
+    }
 }
 
-impl ComputeState {
-    pub fn new(app: &AppSurface, buffer_len: u32, boundary: f32, grid_size: f32) -> Self {
-        let grid_count = ((boundary * 2.0 / grid_size).ceil() + 0.3) as u64;
+/// 覆盖 `count` 个线程所需的最少工作组数量，多出的线程由着色器中的边界检查跳过。
+pub fn workgroup_count(count: u32, workgroup_size: u32) -> u32 {
+    count.div_ceil(workgroup_size)
+}
 
-        // 创建 buffer
-        let params_buffer = Arc::new(app.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Params Buffer"),
-            size: std::mem::size_of::<Parameters>() as u64,
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        }));
+/// 对 `len` 个元素做双调排序需要的每一遍，按执行的顺序排列。
+///
+/// 排序的长度补齐到 2 的幂，补齐的位置看作键为 `DEAD_CELL` 的哨兵。每个归并阶段的第一遍比较块内的镜像位置，
+/// 之后的各遍按跨度 `j` 比较，所有比较都把较小的键放在前面，所以与越界的哨兵比较时总是不需要交换，
+/// 着色器直接跳过这些比较，不需要真的为哨兵分配空间。
+pub fn sort_passes(len: u32) -> Vec<SortParams> {
+    let mut passes = Vec::new();
+    let mut k = 2;
+    while k <= len.next_power_of_two() {
+        let mut j = k >> 1;
+        while j > 0 {
+            passes.push(SortParams { j, k });
+            j >>= 1;
+        }
+        k <<= 1;
+    }
+    passes
+}
 
-        let instances_buffer = Arc::new(app.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Instances Buffer"),
-            size: std::mem::size_of::<ComputeInstanceRaw>() as u64 * buffer_len as u64,
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        }));
+// sort_params_buffer 中相邻两遍参数的间隔，每一遍单独绑定，偏移要满足 storage buffer 的对齐要求
+fn sort_params_stride(device: &wgpu::Device) -> u64 {
+    (device.limits().min_storage_buffer_offset_alignment as u64)
+        .max(std::mem::size_of::<SortParams>() as u64)
+}
 
-        let sort_params_buffer = Arc::new(app.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Sort Params Buffer"),
-            size: std::mem::size_of::<SortParams>() as u64,
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        }));
+// 阻塞地读回一个可以映射的 buffer
+pub fn read_buffer_bytes(device: &wgpu::Device, buffer: Arc<wgpu::Buffer>) -> Vec<u8> {
+    Readback::new(buffer).wait(device)
+}
 
-        let cell_index_buffer = Arc::new(app.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Cell Index Buffer"),
-            size: std::mem::size_of::<CellIndex>() as u64 * grid_count * grid_count * grid_count,
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        }));
+// 异步地读回一个可以映射的 buffer，等待期间 CPU 可以处理其他工作
+pub async fn read_buffer_bytes_async(device: &wgpu::Device, buffer: Arc<wgpu::Buffer>) -> Vec<u8> {
+    Readback::new(buffer).into_future(device).await
+}
 
-        let result_buffer = Arc::new(app.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Output Position Buffer"),
-            size: std::mem::size_of::<Result>() as u64 * buffer_len as u64,
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::MAP_READ,
-            mapped_at_creation: false,
-        }));
+// 把一个不能直接映射的 buffer 复制到临时的 buffer 中再读回来
+pub fn read_back(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer) -> Vec<u8> {
+    read_back_range(device, queue, buffer, 0, buffer.size())
+}
 
-        let buffers = vec![
-            params_buffer.clone(),
-            instances_buffer.clone(),
-            sort_params_buffer.clone(),
-            cell_index_buffer.clone(),
-            result_buffer.clone(),
-        ];
-
-        // 创建 compute node
-
-        let assign_cell_node = ComputeNode::new(
-            app,
-            include_str!("../shaders/assign.wgsl"),
-            &buffers,
-            "Assign Cell",
-        );
-        let sort_node =
-            ComputeNode::new(app, include_str!("../shaders/sort.wgsl"), &buffers, "Sort");
-        let memset_node = ComputeNode::new(
-            app,
-            include_str!("../shaders/memset.wgsl"),
-            &buffers,
-            "Memset",
-        );
-        let build_grid_node = ComputeNode::new(
-            app,
-            include_str!("../shaders/build_grid.wgsl"),
-            &buffers,
-            "Build Grid",
-        );
-        let collision_node = ComputeNode::new(
-            app,
-            include_str!("../shaders/collision.wgsl"),
-            &buffers,
-            "Collision",
-        );
+// 只把 buffer 中从 offset 开始的 size 个字节复制到临时的 buffer 中再读回来，offset 和 size 都要按 4 字节对齐
+pub fn read_back_range(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+    offset: u64,
+    size: u64,
+) -> Vec<u8> {
+    let staging_buffer = Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Staging Buffer"),
+        size,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    }));
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, offset, &staging_buffer, 0, size);
+    queue.submit(iter::once(encoder.finish()));
+
+    read_buffer_bytes(device, staging_buffer)
+}
+
+/// `ComputeStateBuilder` 用链式调用配置 [`ComputeState`]，没有设置的参数使用默认值，最后用 `build` 创建。
+///
+/// Properties:
+///
+/// * `buffer_len`: 小球的数量，决定所有实例 buffer 的大小。
+/// * `grid_size`: 碰撞检测的网格大小，不能小于最大直径，见 [`min_grid_size`]。
+/// * `boundary`: 边界盒子在三个轴上的半边长，默认是半边长为 10 的立方体。
+/// * `dimensions`: 模拟的维数。
+/// * `cell_indexing`: 网格单元的编号方式。
+/// * `broad_phase`: 碰撞阶段查找相邻小球的方法。
+/// * `force_model`: 小球之间的作用力模型。
+/// * `stiffness`: 作用力的刚度。
+/// * `equilibrium_distance`: 平衡距离，0 表示取两个小球的半径之和。
+/// * `gravity`: 重力加速度的大小，方向开始时沿 -Y，见 [`ComputeState::set_gravity_direction`]。
+/// * `restitution`: 与平面、网格碰撞时的恢复系数。
+/// * `drag`: 与速度成正比的阻尼系数。
+/// * `max_speed`: 小球速度大小的上限。
+/// * `integrator`: 碰撞阶段的数值积分方法。
+/// * `sleep_velocity`: 休眠速度，0 表示不休眠。
+/// * `sleep_time`: 速度低于 `sleep_velocity` 持续多少秒之后进入休眠。
+/// * `substeps`: 每次 `update` 的碰撞检测轮数。
+/// * `max_move_fraction`: 自适应子步时每一轮中小球最多移动的距离，以半径为单位，0 表示轮数固定。
+/// * `time_scale`: 时间缩放。
+/// * `workgroup_size`: 碰撞阶段的工作组大小。
+/// * `readback_interval`: 每隔多少次 `update` 读回一次结果。
+#[derive(Debug, Clone, Copy)]
+pub struct ComputeStateBuilder {
+    buffer_len: u32,
+    grid_size: f32,
+    boundary: glam::Vec3,
+    dimensions: Dimensions,
+    cell_indexing: CellIndexing,
+    broad_phase: BroadPhase,
+    force_model: ForceModel,
+    stiffness: f32,
+    equilibrium_distance: f32,
+    gravity: f32,
+    restitution: f32,
+    drag: f32,
+    max_speed: f32,
+    integrator: Integrator,
+    sleep_velocity: f32,
+    sleep_time: f32,
+    substeps: u32,
+    max_move_fraction: f32,
+    time_scale: f32,
+    workgroup_size: u32,
+    readback_interval: u32,
+}
 
+impl ComputeStateBuilder {
+    /// 用 `buffer_len` 个小球和给定的网格大小开始配置，其他参数都是默认值。
+    pub fn new(buffer_len: u32, grid_size: f32) -> Self {
         Self {
-            instances: Vec::new(),
             buffer_len,
-            boundary,
             grid_size,
-            params_buffer,
-            instances_buffer,
+            boundary: glam::Vec3::splat(DEFAULT_BOUNDARY),
+            dimensions: Dimensions::default(),
+            cell_indexing: CellIndexing::default(),
+            broad_phase: BroadPhase::default(),
+            force_model: ForceModel::default(),
+            stiffness: DEFAULT_STIFFNESS,
+            equilibrium_distance: DEFAULT_EQUILIBRIUM_DISTANCE,
+            gravity: DEFAULT_GRAVITY,
+            restitution: DEFAULT_RESTITUTION,
+            drag: DEFAULT_DRAG,
+            max_speed: DEFAULT_MAX_SPEED,
+            integrator: Integrator::default(),
+            sleep_velocity: DEFAULT_SLEEP_VELOCITY,
+            sleep_time: DEFAULT_SLEEP_TIME,
+            substeps: SIMULATION_ROUNDS,
+            max_move_fraction: DEFAULT_MAX_MOVE_FRACTION,
+            time_scale: DEFAULT_TIME_SCALE,
+            workgroup_size: DEFAULT_WORKGROUP_SIZE,
+            readback_interval: 1,
+        }
+    }
+
+    /// 边界盒子在三个轴上的半边长。
+    pub fn boundary(mut self, boundary: glam::Vec3) -> Self {
+        self.boundary = boundary;
+        self
+    }
+
+    /// 半边长为 `boundary` 的立方体边界。
+    pub fn cubic_boundary(self, boundary: f32) -> Self {
+        self.boundary(glam::Vec3::splat(boundary))
+    }
+
+    pub fn dimensions(mut self, dimensions: Dimensions) -> Self {
+        self.dimensions = dimensions;
+        self
+    }
+
+    pub fn cell_indexing(mut self, cell_indexing: CellIndexing) -> Self {
+        self.cell_indexing = cell_indexing;
+        self
+    }
+
+    pub fn broad_phase(mut self, broad_phase: BroadPhase) -> Self {
+        self.broad_phase = broad_phase;
+        self
+    }
+
+    pub fn force_model(mut self, force_model: ForceModel) -> Self {
+        self.force_model = force_model;
+        self
+    }
+
+    pub fn stiffness(mut self, stiffness: f32) -> Self {
+        self.stiffness = stiffness;
+        self
+    }
+
+    pub fn equilibrium_distance(mut self, equilibrium_distance: f32) -> Self {
+        self.equilibrium_distance = equilibrium_distance;
+        self
+    }
+
+    pub fn gravity(mut self, gravity: f32) -> Self {
+        self.gravity = gravity;
+        self
+    }
+
+    pub fn restitution(mut self, restitution: f32) -> Self {
+        self.restitution = restitution;
+        self
+    }
+
+    /// 阻尼系数，`build` 时按 [`ComputeState::set_drag`] 检查。
+    pub fn drag(mut self, drag: f32) -> Self {
+        self.drag = drag;
+        self
+    }
+
+    /// 速度上限，`build` 时按 [`ComputeState::set_max_speed`] 检查。
+    pub fn max_speed(mut self, max_speed: f32) -> Self {
+        self.max_speed = max_speed;
+        self
+    }
+
+    pub fn integrator(mut self, integrator: Integrator) -> Self {
+        self.integrator = integrator;
+        self
+    }
+
+    /// 速度持续 `sleep_time` 秒低于 `sleep_velocity` 的小球进入休眠，`sleep_velocity` 为 0 时不休眠。
+    pub fn sleep(mut self, sleep_velocity: f32, sleep_time: f32) -> Self {
+        self.sleep_velocity = sleep_velocity;
+        self.sleep_time = sleep_time;
+        self
+    }
+
+    pub fn substeps(mut self, substeps: u32) -> Self {
+        self.substeps = substeps;
+        self
+    }
+
+    /// 自适应子步的移动上限，`build` 时按 [`ComputeState::set_max_move_fraction`] 检查。
+    pub fn max_move_fraction(mut self, max_move_fraction: f32) -> Self {
+        self.max_move_fraction = max_move_fraction;
+        self
+    }
+
+    /// 时间缩放，`build` 时按 [`ComputeState::set_time_scale`] 检查。
+    pub fn time_scale(mut self, time_scale: f32) -> Self {
+        self.time_scale = time_scale;
+        self
+    }
+
+    /// 碰撞阶段的工作组大小，`build` 时按 [`ComputeState::set_workgroup_size`] 检查。
+    pub fn workgroup_size(mut self, workgroup_size: u32) -> Self {
+        self.workgroup_size = workgroup_size;
+        self
+    }
+
+    /// 读回结果的间隔，`build` 时按 [`ComputeState::set_readback_interval`] 检查。
+    pub fn readback_interval(mut self, readback_interval: u32) -> Self {
+        self.readback_interval = readback_interval;
+        self
+    }
+
+    /// 创建所有的 buffer 和计算节点。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 用于创建 buffer 和管线的设备。
+    ///
+    /// Returns:
+    ///
+    /// 阻尼系数、速度上限、自适应子步的移动上限、时间缩放或者工作组大小不合法时返回错误，与对应的 setter 的检查相同。
+    pub fn build(&self, device: &wgpu::Device) -> anyhow::Result<ComputeState> {
+        // 工作组大小在创建碰撞阶段之前检查，其他参数交给运行时也会用到的 setter 检查
+        check_workgroup_size(device, self.workgroup_size)?;
+        let mut state = ComputeState::from_builder(device, self);
+        state.set_drag(self.drag)?;
+        state.set_max_speed(self.max_speed)?;
+        state.set_max_move_fraction(self.max_move_fraction)?;
+        state.set_time_scale(self.time_scale)?;
+        state.set_readback_interval(self.readback_interval)?;
+        Ok(state)
+    }
+}
+
+// 检查碰撞阶段的工作组大小是否在设备的限制之内
+fn check_workgroup_size(device: &wgpu::Device, workgroup_size: u32) -> anyhow::Result<()> {
+    let limits = device.limits();
+    let max_size = limits
+        .max_compute_workgroup_size_x
+        .min(limits.max_compute_invocations_per_workgroup);
+    if workgroup_size == 0 || workgroup_size > max_size {
+        anyhow::bail!(
+            "workgroup size must be between 1 and {}, got {}",
+            max_size,
+            workgroup_size
+        );
+    }
+    Ok(())
+}
+
+pub struct ComputeState {
+    pub instances: Vec<ComputeInstance>,
+    buffer_len: u32,                               // the number of instances
+    boundary: glam::Vec3,                          // half extents of the simulation box
+    grid_size: f32,                                // the size of the grid
+    dimensions: Dimensions,                        // 2D keeps every instance on z = 0
+    cell_indexing: CellIndexing,                   // numbering of the grid cells
+    pub gravity: f32,                              // gravity acceleration along gravity_direction
+    gravity_direction: glam::Vec3,                 // unit "down" direction, -Y by default
+    pub restitution: f32,                          // restitution of plane and mesh contacts
+    pub force_model: ForceModel,                   // pairwise force between instances
+    pub stiffness: f32,                            // stiffness of the pairwise force
+    pub equilibrium_distance: f32,                 // rest separation, 0 for the sum of radii
+    pub broad_phase: BroadPhase,                   // neighbor search of the collision stage
+    drag: f32,                                     // velocity-proportional damping, 1/s
+    max_speed: f32,                                // upper bound of every instance's speed
+    pub integrator: Integrator,                    // numerical integration of the collision stage
+    pub sleep_velocity: f32,                       // speed below which instances may fall asleep
+    pub sleep_time: f32,                           // seconds below sleep_velocity before sleeping
+    pub sleeping: usize,                           // sleeping instances in the latest readback
+    initial_instances: Vec<ComputeInstance>,       // snapshot restored by reset
+    pub substeps: u32,                             // collision rounds per update
+    max_move_fraction: f32,                        // per-round travel limit in radii, 0 for fixed
+    last_rounds: u32,                              // collision rounds of the latest update
+    time_scale: f32,                               // simulated seconds per real second
+    workgroup_size: u32,                           // workgroup size of the collision stage
+    readback_interval: u32,                        // updates between two readbacks of the results
+    unsynced_updates: u32,                         // updates since instances was last read back
+    instances_raw: Vec<ComputeInstanceRaw>,        // scratch of write_instances_buffer
+    used_ids: Vec<bool>,                           // scratch of write_instances_buffer and emit
+    pub params_buffer: Arc<wgpu::Buffer>,          // group 0
+    pub instances_buffers: [Arc<wgpu::Buffer>; 2], // group 1, A/B pair
+    current: usize,                                // index of the buffer holding the latest state
+    pub sort_params_buffer: Arc<wgpu::Buffer>,     // group 2, one slot per sort pass
+    sort_pass_groups: [Vec<wgpu::BindGroup>; 2],   // group 2 of sort_node, one per sort pass
+    pub cell_index_buffer: Arc<wgpu::Buffer>,      // group 3
+    pub bvh_nodes_buffer: Arc<wgpu::Buffer>,       // bvh group 2, collision group 2
+    pub result_buffer: Arc<wgpu::Buffer>,          // group 4
+    pub pick_ray_buffer: Arc<wgpu::Buffer>,        // pick group 2
+    pub pick_result_buffer: Arc<wgpu::Buffer>,     // pick group 3, nearest group 4
+    pub nearest_query_buffer: Arc<wgpu::Buffer>,   // nearest group 2
+    grid_ready: bool,                              // whether cell_index_buffer has been built
+    pub planes_buffer: Arc<wgpu::Buffer>,          // group 5
+    planes: Vec<StaticPlane>,                      // static planes, mirrored in planes_buffer
+    emitter: Option<Emitter>,                      // spawns new instances in update
+    emit_budget: f32,                              // fractional instance left from the last update
+    emit_seed: u64,                                // seed of emit_rng, restored by reset
+    emit_rng: rand::rngs::StdRng,                  // draws the emission directions
+    pub static_mesh_buffer: Arc<wgpu::Buffer>,     // collision group 6
+    #[cfg(target_arch = "wasm32")]
+    pending_readback: Option<Readback>, // readback of result_buffer still in flight
+
+    // 每个节点都有两份，第 i 份从 instances_buffers[i] 读取；碰撞阶段把结果写到另一个 buffer 中
+    pub assign_cell_node: [ComputeNode; 2], // stage 1
+    pub sort_node: [ComputeNode; 2],        // stage 2
+    pub memset_node: [ComputeNode; 2],      // stage 3
+    pub build_grid_node: [ComputeNode; 2],  // stage 4
+    pub collision_node: [ComputeNode; 2],   // stage 5
+    pub bvh_assign_node: [ComputeNode; 2],  // stage 1 of the BVH broad phase
+    pub bvh_build_node: [ComputeNode; 2],   // replaces stages 3 and 4 with the BVH broad phase
+    pub pick_node: [ComputeNode; 2],        // ray pick, not part of the simulation
+    pub nearest_node: [ComputeNode; 2],     // nearest-neighbor query, not part of the simulation
+}
+
+impl ComputeState {
+    /// 开始用 [`ComputeStateBuilder`] 配置 `buffer_len` 个小球、网格大小为 `grid_size` 的模拟。
+    pub fn builder(buffer_len: u32, grid_size: f32) -> ComputeStateBuilder {
+        ComputeStateBuilder::new(buffer_len, grid_size)
+    }
+
+    // 边界是立方体、其他参数都取默认值时的便捷写法，boundary 是立方体的半边长
+    pub fn new(
+        device: &wgpu::Device,
+        buffer_len: u32,
+        boundary: f32,
+        grid_size: f32,
+        dimensions: Dimensions,
+        cell_indexing: CellIndexing,
+    ) -> Self {
+        Self::builder(buffer_len, grid_size)
+            .cubic_boundary(boundary)
+            .dimensions(dimensions)
+            .cell_indexing(cell_indexing)
+            .build(device)
+            .expect("default parameters are valid")
+    }
+
+    // 按照 builder 创建所有的 buffer 和计算节点，参数已经在 ComputeStateBuilder::build 中检查过
+    fn from_builder(device: &wgpu::Device, builder: &ComputeStateBuilder) -> Self {
+        let ComputeStateBuilder {
+            buffer_len,
+            grid_size,
+            boundary,
+            dimensions,
+            cell_indexing,
+            workgroup_size,
+            ..
+        } = *builder;
+
+        // 创建 buffer
+        let params_buffer = Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Params Buffer"),
+            size: std::mem::size_of::<Parameters>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+
+        let instances_buffers = ["Instances Buffer A", "Instances Buffer B"].map(|label| {
+            Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: std::mem::size_of::<ComputeInstanceRaw>() as u64 * buffer_len as u64,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }))
+        });
+
+        // 同一个计算通道中的 write_buffer 都在提交时才生效，所以排序的每一遍都在 buffer 中占一段，
+        // 只和小球数量有关，创建时一次写好
+        let stride = sort_params_stride(device) as usize;
+        let passes = sort_passes(buffer_len);
+        let mut sort_params = vec![0; stride * passes.len().max(1)];
+        for (i, pass) in passes.iter().enumerate() {
+            sort_params[i * stride..][..std::mem::size_of::<SortParams>()]
+                .copy_from_slice(bytemuck::bytes_of(pass));
+        }
+        let sort_params_buffer = Arc::new(device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Sort Params Buffer"),
+                contents: &sort_params,
+                usage: wgpu::BufferUsages::STORAGE,
+            },
+        ));
+
+        let cell_index_buffer = Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cell Index Buffer"),
+            size: std::mem::size_of::<CellIndex>() as u64
+                * cell_count(boundary, grid_size, dimensions, cell_indexing),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+
+        // n 个小球的 LBVH 有 n - 1 个内部节点
+        let bvh_nodes_buffer = Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("BVH Nodes Buffer"),
+            size: std::mem::size_of::<BvhNode>() as u64
+                * buffer_len.saturating_sub(1).max(1) as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        }));
+
+        let result_buffer = Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Output Position Buffer"),
+            size: std::mem::size_of::<Result>() as u64 * buffer_len as u64,
+            // COPY_SRC 用于只读回单个实例，见 read_instance；COPY_DST 用于在 reset 时清空
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::MAP_READ
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+
+        let pick_ray_buffer = Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Pick Ray Buffer"),
+            size: std::mem::size_of::<PickRay>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+
+        let pick_result_buffer = Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Pick Result Buffer"),
+            size: std::mem::size_of::<PickResult>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        }));
+
+        let nearest_query_buffer = Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Nearest Query Buffer"),
+            size: std::mem::size_of::<NearestQuery>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+
+        // 前 16 字节是平面数量，之后是平面数组
+        let planes_buffer = Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Static Planes Buffer"),
+            size: 16 + (std::mem::size_of::<StaticPlane>() * MAX_PLANES) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+
+        // 前 16 字节是三角形数量，之后是三角形数组；还没有设置网格时只有一个空位
+        let static_mesh_buffer = Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Static Mesh Buffer"),
+            size: 16 + std::mem::size_of::<StaticTriangle>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+
+        let [assign_cell_node, sort_node, memset_node, build_grid_node] =
+            Self::create_simulation_nodes(
+                device,
+                &params_buffer,
+                &instances_buffers,
+                &sort_params_buffer,
+                &cell_index_buffer,
+                &result_buffer,
+                &planes_buffer,
+            );
+        let sort_pass_groups =
+            Self::create_sort_pass_groups(device, &sort_node, &sort_params_buffer, buffer_len);
+        let collision_node = Self::create_collision_nodes(
+            device,
+            &params_buffer,
+            &instances_buffers,
+            &bvh_nodes_buffer,
+            &cell_index_buffer,
+            &result_buffer,
+            &planes_buffer,
+            &static_mesh_buffer,
+            workgroup_size,
+        );
+        let [bvh_assign_node, bvh_build_node] = Self::create_bvh_nodes(
+            device,
+            &params_buffer,
+            &instances_buffers,
+            &bvh_nodes_buffer,
+        );
+        let [pick_node, nearest_node] = Self::create_query_nodes(
+            device,
+            &params_buffer,
+            &instances_buffers,
+            &cell_index_buffer,
+            &pick_ray_buffer,
+            &pick_result_buffer,
+            &nearest_query_buffer,
+        );
+
+        Self {
+            instances: Vec::new(),
+            initial_instances: Vec::new(),
+            buffer_len,
+            boundary,
+            grid_size,
+            dimensions,
+            cell_indexing,
+            gravity: builder.gravity,
+            gravity_direction: glam::Vec3::NEG_Y,
+            restitution: builder.restitution,
+            broad_phase: builder.broad_phase,
+            force_model: builder.force_model,
+            stiffness: builder.stiffness,
+            equilibrium_distance: builder.equilibrium_distance,
+            drag: builder.drag,
+            max_speed: builder.max_speed,
+            integrator: builder.integrator,
+            sleep_velocity: builder.sleep_velocity,
+            sleep_time: builder.sleep_time,
+            sleeping: 0,
+            substeps: builder.substeps,
+            max_move_fraction: builder.max_move_fraction,
+            last_rounds: 0,
+            time_scale: builder.time_scale,
+            workgroup_size,
+            readback_interval: 1,
+            unsynced_updates: 0,
+            instances_raw: Vec::with_capacity(buffer_len as usize),
+            used_ids: Vec::new(),
+            params_buffer,
+            instances_buffers,
+            current: 0,
             sort_params_buffer,
+            sort_pass_groups,
             cell_index_buffer,
+            bvh_nodes_buffer,
             result_buffer,
+            pick_ray_buffer,
+            pick_result_buffer,
+            nearest_query_buffer,
+            grid_ready: false,
+            #[cfg(target_arch = "wasm32")]
+            pending_readback: None,
+            planes_buffer,
+            planes: Vec::new(),
+            emitter: None,
+            emit_budget: 0.0,
+            emit_seed: 0,
+            emit_rng: rand::rngs::StdRng::seed_from_u64(0),
+            static_mesh_buffer,
             assign_cell_node,
             sort_node,
             memset_node,
             build_grid_node,
             collision_node,
+            bvh_assign_node,
+            bvh_build_node,
+            pick_node,
+            nearest_node,
+        }
+    }
+
+    // 创建模拟的前四个阶段，每个节点按照读取的实例 buffer 各创建一份
+    fn create_simulation_nodes(
+        device: &wgpu::Device,
+        params_buffer: &Arc<wgpu::Buffer>,
+        instances_buffers: &[Arc<wgpu::Buffer>; 2],
+        sort_params_buffer: &Arc<wgpu::Buffer>,
+        cell_index_buffer: &Arc<wgpu::Buffer>,
+        result_buffer: &Arc<wgpu::Buffer>,
+        planes_buffer: &Arc<wgpu::Buffer>,
+    ) -> [[ComputeNode; 2]; 4] {
+        let simulation_nodes = |source: &str, label: &str| {
+            [0, 1].map(|i| {
+                ComputeNode::new(
+                    device,
+                    source,
+                    &[
+                        params_buffer.clone(),
+                        instances_buffers[i].clone(),
+                        sort_params_buffer.clone(),
+                        cell_index_buffer.clone(),
+                        result_buffer.clone(),
+                        planes_buffer.clone(),
+                    ],
+                    label,
+                )
+            })
+        };
+
+        [
+            simulation_nodes(&shaders::shader_source!("assign.wgsl"), "Assign Cell"),
+            simulation_nodes(&shaders::shader_source!("sort.wgsl"), "Sort"),
+            simulation_nodes(&shaders::shader_source!("memset.wgsl"), "Memset"),
+            simulation_nodes(&shaders::shader_source!("build_grid.wgsl"), "Build Grid"),
+        ]
+    }
+
+    // 排序节点的每一遍各自的 group 2，只绑定 sort_params_buffer 中这一遍的参数
+    fn create_sort_pass_groups(
+        device: &wgpu::Device,
+        sort_node: &[ComputeNode; 2],
+        sort_params_buffer: &wgpu::Buffer,
+        buffer_len: u32,
+    ) -> [Vec<wgpu::BindGroup>; 2] {
+        let stride = sort_params_stride(device);
+        sort_node.each_ref().map(|node| {
+            (0..sort_passes(buffer_len).len() as u64)
+                .map(|i| {
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some(format!("Sort Pass Bind Group {}", i).as_str()),
+                        layout: &node.bind_group_layout,
+                        entries: &[wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                                buffer: sort_params_buffer,
+                                offset: i * stride,
+                                size: wgpu::BufferSize::new(
+                                    std::mem::size_of::<SortParams>() as u64
+                                ),
+                            }),
+                        }],
+                    })
+                })
+                .collect()
+        })
+    }
+
+    // 碰撞阶段从 instances_buffers[i] 读取，写入 instances_buffers[1 - i]；
+    // 碰撞着色器不使用排序参数，group 2 绑定 LBVH 的节点
+    #[allow(clippy::too_many_arguments)]
+    fn create_collision_nodes(
+        device: &wgpu::Device,
+        params_buffer: &Arc<wgpu::Buffer>,
+        instances_buffers: &[Arc<wgpu::Buffer>; 2],
+        bvh_nodes_buffer: &Arc<wgpu::Buffer>,
+        cell_index_buffer: &Arc<wgpu::Buffer>,
+        result_buffer: &Arc<wgpu::Buffer>,
+        planes_buffer: &Arc<wgpu::Buffer>,
+        static_mesh_buffer: &Arc<wgpu::Buffer>,
+        workgroup_size: u32,
+    ) -> [ComputeNode; 2] {
+        let source = shaders::shader_source!("collision.wgsl");
+        [0, 1].map(|i| {
+            ComputeNode::with_workgroup_size(
+                device,
+                &source,
+                &[
+                    params_buffer.clone(),
+                    instances_buffers[i].clone(),
+                    bvh_nodes_buffer.clone(),
+                    cell_index_buffer.clone(),
+                    result_buffer.clone(),
+                    planes_buffer.clone(),
+                    static_mesh_buffer.clone(),
+                    instances_buffers[1 - i].clone(),
+                ],
+                "Collision",
+                workgroup_size,
+            )
+        })
+    }
+
+    // BVH 宽相位的两个阶段：计算 Morton 码，以及在排序之后建立 LBVH
+    fn create_bvh_nodes(
+        device: &wgpu::Device,
+        params_buffer: &Arc<wgpu::Buffer>,
+        instances_buffers: &[Arc<wgpu::Buffer>; 2],
+        bvh_nodes_buffer: &Arc<wgpu::Buffer>,
+    ) -> [[ComputeNode; 2]; 2] {
+        let assign_source = shaders::shader_source!("bvh_assign.wgsl");
+        let build_source = shaders::shader_source!("bvh_build.wgsl");
+        let bvh_assign_node = [0, 1].map(|i| {
+            ComputeNode::new(
+                device,
+                &assign_source,
+                &[params_buffer.clone(), instances_buffers[i].clone()],
+                "BVH Assign",
+            )
+        });
+        let bvh_build_node = [0, 1].map(|i| {
+            ComputeNode::new(
+                device,
+                &build_source,
+                &[
+                    params_buffer.clone(),
+                    instances_buffers[i].clone(),
+                    bvh_nodes_buffer.clone(),
+                ],
+                "BVH Build",
+            )
+        });
+        [bvh_assign_node, bvh_build_node]
+    }
+
+    // 拾取和最近邻查询使用单独的 buffer 组合，不参与模拟
+    fn create_query_nodes(
+        device: &wgpu::Device,
+        params_buffer: &Arc<wgpu::Buffer>,
+        instances_buffers: &[Arc<wgpu::Buffer>; 2],
+        cell_index_buffer: &Arc<wgpu::Buffer>,
+        pick_ray_buffer: &Arc<wgpu::Buffer>,
+        pick_result_buffer: &Arc<wgpu::Buffer>,
+        nearest_query_buffer: &Arc<wgpu::Buffer>,
+    ) -> [[ComputeNode; 2]; 2] {
+        let pick_source = shaders::shader_source!("pick.wgsl");
+        let nearest_source = shaders::shader_source!("nearest.wgsl");
+        let pick_node = [0, 1].map(|i| {
+            ComputeNode::new(
+                device,
+                &pick_source,
+                &[
+                    params_buffer.clone(),
+                    instances_buffers[i].clone(),
+                    pick_ray_buffer.clone(),
+                    pick_result_buffer.clone(),
+                ],
+                "Pick",
+            )
+        });
+        let nearest_node = [0, 1].map(|i| {
+            ComputeNode::new(
+                device,
+                &nearest_source,
+                &[
+                    params_buffer.clone(),
+                    instances_buffers[i].clone(),
+                    nearest_query_buffer.clone(),
+                    cell_index_buffer.clone(),
+                    pick_result_buffer.clone(),
+                ],
+                "Nearest",
+            )
+        });
+        [pick_node, nearest_node]
+    }
+
+    /// 重新读取着色器并重建全部计算节点，buffer 和实例数据保持不变。着色器有错误时保留原来的节点。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 用于创建管线的设备。
+    pub fn reload_shaders(&mut self, device: &wgpu::Device) {
+        let nodes = shaders::try_rebuild(device, "compute shaders", || {
+            (
+                Self::create_simulation_nodes(
+                    device,
+                    &self.params_buffer,
+                    &self.instances_buffers,
+                    &self.sort_params_buffer,
+                    &self.cell_index_buffer,
+                    &self.result_buffer,
+                    &self.planes_buffer,
+                ),
+                Self::create_collision_nodes(
+                    device,
+                    &self.params_buffer,
+                    &self.instances_buffers,
+                    &self.bvh_nodes_buffer,
+                    &self.cell_index_buffer,
+                    &self.result_buffer,
+                    &self.planes_buffer,
+                    &self.static_mesh_buffer,
+                    self.workgroup_size,
+                ),
+                Self::create_bvh_nodes(
+                    device,
+                    &self.params_buffer,
+                    &self.instances_buffers,
+                    &self.bvh_nodes_buffer,
+                ),
+                Self::create_query_nodes(
+                    device,
+                    &self.params_buffer,
+                    &self.instances_buffers,
+                    &self.cell_index_buffer,
+                    &self.pick_ray_buffer,
+                    &self.pick_result_buffer,
+                    &self.nearest_query_buffer,
+                ),
+            )
+        });
+        if let Some((
+            simulation_nodes,
+            collision_node,
+            [bvh_assign_node, bvh_build_node],
+            [pick_node, nearest_node],
+        )) = nodes
+        {
+            [
+                self.assign_cell_node,
+                self.sort_node,
+                self.memset_node,
+                self.build_grid_node,
+            ] = simulation_nodes;
+            // 新的节点有新的 bind group layout，每一遍的 bind group 也要重新创建
+            self.sort_pass_groups = Self::create_sort_pass_groups(
+                device,
+                &self.sort_node,
+                &self.sort_params_buffer,
+                self.buffer_len,
+            );
+            self.collision_node = collision_node;
+            self.bvh_assign_node = bvh_assign_node;
+            self.bvh_build_node = bvh_build_node;
+            self.pick_node = pick_node;
+            self.nearest_node = nearest_node;
+        }
+    }
+
+    /// 把小球排成间距为 `spacing` 的规则点阵，点阵的中心在边界的中心，替换现有的所有实例。
+    ///
+    /// 小球的 ID 按 x、y、z 的顺序递增。每个坐标加上 `[-jitter, jitter]` 内的随机扰动，
+    /// 速度的每个分量在 `[-speed, speed]` 内随机取值，`speed` 为 0 时所有小球静止。
+    /// 参数通过 [`check_lattice`] 的检查时，初始状态中没有相互重叠的小球。
+    ///
+    /// Arguments:
+    ///
+    /// * `spacing`: 相邻格点的间距。
+    /// * `counts`: 三个方向上的格点数量，乘积不能超过创建时的容量，多出的容量留给之后加入的小球。
+    /// * `jitter`: 每个坐标的随机扰动的最大值，0 表示不扰动。
+    /// * `speed`: 初始速度每个分量的最大值，0 表示静止。
+    /// * `radius_of`: 第 `i` 个小球的半径。
+    /// * `rng`: 生成扰动和速度的随机数生成器。
+    ///
+    /// Returns:
+    ///
+    /// 数量超过容量或者点阵不合法时返回错误，实例保持不变。
+    pub fn seed_lattice(
+        &mut self,
+        spacing: f32,
+        counts: [u32; 3],
+        jitter: f32,
+        speed: f32,
+        radius_of: impl Fn(u32) -> f32,
+        rng: &mut impl rand::Rng,
+    ) -> anyhow::Result<()> {
+        let count = counts.iter().map(|&count| count as u64).product::<u64>();
+        if count > self.buffer_len as u64 {
+            anyhow::bail!(
+                "lattice of {:?} holds {} instances, more than the capacity {}",
+                counts,
+                count,
+                self.buffer_len
+            );
+        }
+        self.instances = lattice_instances(
+            spacing,
+            counts,
+            jitter,
+            speed,
+            self.dimensions,
+            self.boundary,
+            radius_of,
+            rng,
+        )?;
+        Ok(())
+    }
+
+    /// 把当前的实例记为初始状态，之后 [`reset`](Self::reset) 会恢复到这里。通常在放好小球之后、第一步之前调用。
+    pub fn save_initial_state(&mut self) {
+        self.initial_instances = self.instances.clone();
+    }
+
+    /// 恢复到 [`save_initial_state`](Self::save_initial_state) 记录的初始状态，并重新上传实例。
+    ///
+    /// 不重新创建 buffer 和管线，只清空结果 buffer 中跨步保留的加速度和休眠计时，
+    /// 因此即使小球很多也几乎是立即完成的。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 用于创建命令和等待 GPU 的设备。
+    /// * `queue`: 用于写入 buffer 和提交命令的队列。
+    ///
+    /// Returns:
+    ///
+    /// 还没有记录初始状态时返回错误，实例保持不变。
+    pub fn reset(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+        if self.initial_instances.is_empty() {
+            anyhow::bail!("no initial state has been saved");
+        }
+        // 还在读回的是原来状态的结果，取消映射并丢掉它，避免覆盖刚恢复的实例；映射中的 buffer 也不能被清空
+        #[cfg(target_arch = "wasm32")]
+        if self.pending_readback.take().is_some() {
+            self.result_buffer.unmap();
+        }
+        self.instances.clone_from(&self.initial_instances);
+        self.write_instances_buffer(queue);
+        self.unsynced_updates = 0;
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Reset Encoder"),
+        });
+        encoder.clear_buffer(&self.result_buffer, 0, None);
+        queue.submit(iter::once(encoder.finish()));
+
+        self.sleeping = 0;
+        // 网格是按原来的位置建立的，下一步之前最近邻查询只能暴力查找
+        self.grid_ready = false;
+        // 发射器也从头开始，重新发射同样的小球
+        self.emit_budget = 0.0;
+        self.emit_rng = rand::rngs::StdRng::seed_from_u64(self.emit_seed);
+        Ok(())
+    }
+
+    /// 设置或者移除发射器，之后每次 `update` 都会按照经过的模拟时间发射新的小球。
+    ///
+    /// Arguments:
+    ///
+    /// * `emitter`: 新的发射器，`None` 表示停止发射。
+    /// * `seed`: 选取发射方向的随机数种子，相同的种子总是得到相同的小球。
+    ///
+    /// Returns:
+    ///
+    /// 发射器的参数不合法时返回错误（见 [`Emitter::validated`]），发射器保持不变。
+    pub fn set_emitter(&mut self, emitter: Option<Emitter>, seed: u64) -> anyhow::Result<()> {
+        self.emitter = emitter
+            .map(|emitter| emitter.validated(self.grid_size))
+            .transpose()?;
+        self.emit_budget = 0.0;
+        self.emit_seed = seed;
+        self.emit_rng = rand::rngs::StdRng::seed_from_u64(seed);
+        Ok(())
+    }
+
+    // 按这一步的模拟时间发射新的小球，使用空出来的 id；超出容量的部分直接丢弃，不会积攒到之后
+    fn emit(&mut self, dt: std::time::Duration) {
+        let Some(emitter) = self.emitter else {
+            return;
+        };
+        if emitter.rate <= 0.0 {
+            return;
+        }
+        self.emit_budget += emitter.rate * dt.as_secs_f32();
+        let count = self.emit_budget.floor();
+        self.emit_budget -= count;
+        let count = (count as usize).min(self.buffer_len as usize - self.instances.len());
+        if count == 0 {
+            return;
+        }
+
+        // 复用 used_ids，每一步发射时不再重新分配
+        mark_used_ids(
+            &mut self.used_ids,
+            &self.instances,
+            self.buffer_len as usize,
+        );
+        let used = &self.used_ids;
+        let free_ids = (0..self.buffer_len).filter(|&id| !used[id as usize]);
+        let (axis_a, axis_b) = emitter.direction.any_orthonormal_pair();
+        let cos_spread = emitter.spread.cos();
+        let interval = 1.0 / emitter.rate;
+        for (i, id) in free_ids.take(count).enumerate() {
+            // 在圆锥对应的球冠上均匀地选取方向
+            let cos_theta = 1.0 - self.emit_rng.gen::<f32>() * (1.0 - cos_spread);
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = self.emit_rng.gen::<f32>() * std::f32::consts::TAU;
+            let mut velocity = (emitter.direction * cos_theta
+                + (axis_a * phi.cos() + axis_b * phi.sin()) * sin_theta)
+                * emitter.speed;
+            // 同一步中的小球按发射时刻错开，先发射的已经飞出一段距离，而不是全部重叠在发射点上
+            let age = (count - 1 - i) as f32 * interval;
+            let mut position = emitter.position + velocity * age;
+            if self.dimensions == Dimensions::D2 {
+                position.z = 0.0;
+                velocity.z = 0.0;
+            }
+            self.instances.push(ComputeInstance {
+                id,
+                position,
+                radius: emitter.radius,
+                velocity,
+                color: glam::Vec4::ONE,
+                collision_heat: 0.0,
+            });
+        }
+    }
+
+    /// 在 CPU 上统计当前实例中相互重叠的小球对的数量，通常在第一步之前调用。
+    ///
+    /// 随机放置的小球可能一开始就相互重叠，第一步碰撞时会被很大的力猛烈地弹开。
+    pub fn count_initial_overlaps(&self) -> usize {
+        self.overlapping_pairs().len()
+    }
+
+    /// 在模拟开始之前把相互重叠的小球沿球心连线各推开一半的穿透深度，重复 `iterations` 次，
+    /// 或者直到没有重叠为止。推开之后的小球会被限制在边界之内，速度保持不变。
+    ///
+    /// Arguments:
+    ///
+    /// * `iterations`: 最多的迭代次数。
+    ///
+    /// Returns:
+    ///
+    /// 结束时仍然相互重叠的小球对的数量。
+    pub fn relax_overlaps(&mut self, iterations: u32) -> usize {
+        let boundary = self.boundary;
+        for _ in 0..iterations {
+            let pairs = self.overlapping_pairs();
+            if pairs.is_empty() {
+                break;
+            }
+            for (i, j) in pairs {
+                let delta = self.instances[i].position - self.instances[j].position;
+                let penetration =
+                    self.instances[i].radius + self.instances[j].radius - delta.length();
+                // 球心重合时没有方向，沿 x 轴分开
+                let normal = delta.try_normalize().unwrap_or(glam::Vec3::X);
+                self.instances[i].position += normal * penetration * 0.5;
+                self.instances[j].position -= normal * penetration * 0.5;
+            }
+            for instance in &mut self.instances {
+                let limit = boundary - instance.radius;
+                instance.position = instance.position.clamp(-limit, limit);
+            }
+        }
+        self.count_initial_overlaps()
+    }
+
+    // 用大小为 grid_size 的哈希网格找出所有相互重叠的小球对，返回两个小球的下标，前一个更小
+    fn overlapping_pairs(&self) -> Vec<(usize, usize)> {
+        let cell_of = |position: glam::Vec3| (position / self.grid_size).floor().as_ivec3();
+        let mut cells: HashMap<glam::IVec3, Vec<usize>> = HashMap::new();
+        for (i, instance) in self.instances.iter().enumerate() {
+            cells.entry(cell_of(instance.position)).or_default().push(i);
+        }
+
+        // 网格不小于最大直径，重叠的小球一定在相邻的网格中
+        let mut pairs = Vec::new();
+        for (i, instance) in self.instances.iter().enumerate() {
+            let cell = cell_of(instance.position);
+            for dz in -1..=1 {
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        let neighbor = cell + glam::IVec3::new(dx, dy, dz);
+                        let Some(others) = cells.get(&neighbor) else {
+                            continue;
+                        };
+                        for &j in others.iter().filter(|&&j| j > i) {
+                            let other = &self.instances[j];
+                            let distance = instance.position.distance(other.position);
+                            if distance < instance.radius + other.radius {
+                                pairs.push((i, j));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        pairs
+    }
+
+    /// 检查网格大小是否不小于当前实例中最大的直径，见 [`min_grid_size`]。
+    ///
+    /// Returns:
+    ///
+    /// 网格太小、会漏掉碰撞时返回错误。
+    pub fn check_grid_size(&self) -> anyhow::Result<()> {
+        let max_radius = self
+            .instances
+            .iter()
+            .map(|instance| instance.radius)
+            .fold(0.0, f32::max);
+        let min_grid_size = min_grid_size(max_radius);
+        if self.grid_size < min_grid_size {
+            anyhow::bail!(
+                "grid size {} is smaller than the largest diameter {}, collisions between neighbors more than one cell apart would be missed",
+                self.grid_size,
+                min_grid_size
+            );
+        }
+        Ok(())
+    }
+
+    /// 小球的数量，决定所有实例 buffer 的大小，创建之后不能改变。
+    pub fn buffer_len(&self) -> u32 {
+        self.buffer_len
+    }
+
+    /// 仍然存活的小球的数量，即 `instances` 的长度。被吸收平面移除的小球读回之后就不再计入，最多为 `buffer_len`。
+    pub fn live_count(&self) -> usize {
+        self.instances.len()
+    }
+
+    /// 模拟的维数，创建之后不能改变。
+    pub fn dimensions(&self) -> Dimensions {
+        self.dimensions
+    }
+
+    /// 网格单元的编号方式，单元 buffer 的大小取决于它，创建之后不能改变。
+    pub fn cell_indexing(&self) -> CellIndexing {
+        self.cell_indexing
+    }
+
+    /// 边界盒子在三个轴上的半边长。
+    pub fn boundary(&self) -> glam::Vec3 {
+        self.boundary
+    }
+
+    /// 网格的大小，也是弹簧和 Lennard-Jones 力的截断距离。
+    pub fn grid_size(&self) -> f32 {
+        self.grid_size
+    }
+
+    /// 当前的静态平面和吸收平面，顺序与 `planes_buffer` 中一致。
+    pub fn planes(&self) -> &[StaticPlane] {
+        &self.planes
+    }
+
+    /// 改变边界盒子的大小，下一次写入模拟参数时生效。已有的实例不会被移动，边界之外的小球会被边界弹回。
+    ///
+    /// 单元 buffer 在创建时按照当时的边界分配，新的边界需要的网格单元不能比它多。
+    ///
+    /// Arguments:
+    ///
+    /// * `boundary`: 新的边界在三个轴上的半边长。
+    ///
+    /// Returns:
+    ///
+    /// 半边长不是正数，或者需要的网格单元超过单元 buffer 的容量时返回错误，边界保持不变。
+    pub fn set_boundary(&mut self, boundary: glam::Vec3) -> anyhow::Result<()> {
+        if !(boundary.cmpgt(glam::Vec3::ZERO).all() && boundary.is_finite()) {
+            anyhow::bail!("boundary half extents must be positive, got {}", boundary);
+        }
+        let max_grid_count = max_morton_grid_count(self.dimensions);
+        if self.cell_indexing == CellIndexing::Morton
+            && grid_count(boundary, self.grid_size)
+                .into_iter()
+                .any(|count| count > max_grid_count)
+        {
+            anyhow::bail!(
+                "boundary {} needs more than {} cells per axis, the limit of Morton indexing",
+                boundary,
+                max_grid_count
+            );
+        }
+        let cells = cell_count(
+            boundary,
+            self.grid_size,
+            self.dimensions,
+            self.cell_indexing,
+        );
+        let capacity = self.cell_index_buffer.size() / std::mem::size_of::<CellIndex>() as u64;
+        if cells > capacity {
+            anyhow::bail!(
+                "boundary {} needs {} grid cells, the cell buffer only holds {}",
+                boundary,
+                cells,
+                capacity
+            );
+        }
+        self.boundary = boundary;
+        Ok(())
+    }
+
+    /// 与速度成正比的阻尼系数。
+    pub fn drag(&self) -> f32 {
+        self.drag
+    }
+
+    /// 设置与速度成正比的阻尼系数，每个时间步速度乘以 `max(1 - drag * time_step, 0)`，
+    /// 下一次 `update` 时生效。有重力时，下落的小球会趋于终端速度 `gravity / drag` 并最终静止下来。
+    ///
+    /// Arguments:
+    ///
+    /// * `drag`: 阻尼系数，单位是 1/秒，0 表示没有阻尼。
+    ///
+    /// Returns:
+    ///
+    /// `drag` 为负数或者不是有限的数时返回错误，保持原来的值。
+    pub fn set_drag(&mut self, drag: f32) -> anyhow::Result<()> {
+        if !(drag >= 0.0 && drag.is_finite()) {
+            anyhow::bail!("drag must be non-negative, got {}", drag);
+        }
+        self.drag = drag;
+        Ok(())
+    }
+
+    /// 小球速度大小的上限。
+    pub fn max_speed(&self) -> f32 {
+        self.max_speed
+    }
+
+    /// 设置小球速度大小的上限，下一次 `update` 时生效。碰撞阶段在积分之后把超过上限的速度按比例缩小，
+    /// 方向保持不变。
+    ///
+    /// Arguments:
+    ///
+    /// * `max_speed`: 速度上限，必须是正数。
+    ///
+    /// Returns:
+    ///
+    /// `max_speed` 不是正数或者不是有限的数时返回错误，保持原来的值。
+    pub fn set_max_speed(&mut self, max_speed: f32) -> anyhow::Result<()> {
+        if !(max_speed > 0.0 && max_speed.is_finite()) {
+            anyhow::bail!("max speed must be positive, got {}", max_speed);
+        }
+        self.max_speed = max_speed;
+        Ok(())
+    }
+
+    /// 重力的方向，是一个单位向量，默认是 -Y。
+    pub fn gravity_direction(&self) -> glam::Vec3 {
+        self.gravity_direction
+    }
+
+    /// 设置重力的方向，大小仍然是 `gravity`，下一次写入模拟参数时生效。二维时 z 分量不起作用。
+    ///
+    /// Returns:
+    ///
+    /// `direction` 为零向量或者不是有限值时返回错误，方向保持不变。
+    pub fn set_gravity_direction(&mut self, direction: glam::Vec3) -> anyhow::Result<()> {
+        let Some(direction) = direction.try_normalize() else {
+            anyhow::bail!("gravity direction must be non-zero, got {}", direction);
+        };
+        self.gravity_direction = direction;
+        Ok(())
+    }
+
+    /// 把重力的方向朝 `target` 转过不超过 `max_angle` 弧度，每一步调用一次就能平滑地转到新的方向。
+    ///
+    /// 旋转不改变重力的大小；`target` 与当前方向相反时绕任意一个垂直的轴旋转，`target` 为零向量时不旋转。
+    ///
+    /// Arguments:
+    ///
+    /// * `target`: 目标方向，不需要归一化。
+    /// * `max_angle`: 这一次最多转过的角度，单位是弧度。
+    pub fn turn_gravity_toward(&mut self, target: glam::Vec3, max_angle: f32) {
+        self.gravity_direction = turn_toward(self.gravity_direction, target, max_angle);
+    }
+
+    /// 重力加速度，即 `gravity_direction * gravity`。
+    pub fn gravity_vector(&self) -> glam::Vec3 {
+        self.gravity_direction * self.gravity
+    }
+
+    /// 时间缩放，每次 `update` 推进的模拟时间是 `dt * time_scale`。
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    /// 设置时间缩放，小于 1 时是慢动作，大于 1 时快进，0 表示不推进模拟但照常渲染。
+    ///
+    /// 每次 `update` 的轮数随时间缩放增减，使每一轮的时间步长保持在 `dt / substeps` 左右，
+    /// 快进不会让积分变得不稳定。
+    ///
+    /// Arguments:
+    ///
+    /// * `time_scale`: 时间缩放，在 `[0, MAX_TIME_SCALE]` 之间。
+    ///
+    /// Returns:
+    ///
+    /// `time_scale` 超出范围或者不是有限的数时返回错误，保持原来的值。
+    pub fn set_time_scale(&mut self, time_scale: f32) -> anyhow::Result<()> {
+        if !(0.0..=MAX_TIME_SCALE).contains(&time_scale) {
+            anyhow::bail!(
+                "time scale must be in [0, {}], got {}",
+                MAX_TIME_SCALE,
+                time_scale
+            );
+        }
+        self.time_scale = time_scale;
+        Ok(())
+    }
+
+    /// 每隔多少次 `update` 读回一次结果，1 表示每次都读回。
+    pub fn readback_interval(&self) -> u32 {
+        self.readback_interval
+    }
+
+    /// 设置读回结果的间隔。等待读回会让 CPU 停下来等 GPU，只是偶尔需要位置（例如记录日志）时可以加大间隔，
+    /// 其余的 `update` 只提交计算、不等待，GPU 上的模拟从自己的实例 buffer 连续地推进。
+    ///
+    /// 两次读回之间 `instances` 是过时的：位置、速度、`diagnostics` 和自适应子步的估计都停留在上一次读回时，
+    /// 这期间对 `instances` 的修改也会被丢弃。需要最新的状态或者修改实例之前先调用 `read_results` 同步，
+    /// 见 [`is_synced`](Self::is_synced)。有发射器时需要知道哪些位置空了出来，仍然每次都读回。
+    ///
+    /// Returns:
+    ///
+    /// `readback_interval` 为 0 时返回错误。
+    pub fn set_readback_interval(&mut self, readback_interval: u32) -> anyhow::Result<()> {
+        if readback_interval == 0 {
+            anyhow::bail!("readback interval must be at least 1");
+        }
+        self.readback_interval = readback_interval;
+        Ok(())
+    }
+
+    /// `instances` 是否是最新的，即最近一次 `update` 的结果已经读回。
+    pub fn is_synced(&self) -> bool {
+        self.unsynced_updates == 0
+    }
+
+    // instances 已经是权威的状态，例如 CPU 参考求解器直接写入了结果，下一次 update 会把它上传
+    pub(crate) fn mark_synced(&mut self) {
+        self.unsynced_updates = 0;
+    }
+
+    /// 自适应子步时每一轮中小球最多移动的距离，以半径为单位，0 表示轮数固定。
+    pub fn max_move_fraction(&self) -> f32 {
+        self.max_move_fraction
+    }
+
+    /// 设置自适应子步的移动上限。每次 `update` 按最快的小球（相对于它的半径）估计需要的轮数，
+    /// 使每一轮中没有小球移动超过 `max_move_fraction` 个半径，轮数不少于按 `substeps` 得到的轮数，
+    /// 也不超过 [`MAX_ADAPTIVE_ROUNDS`]。速度突然变大时（例如重叠的小球被弹开）自动加密，
+    /// 运动缓慢时不必为很小的时间步付出代价。
+    ///
+    /// Arguments:
+    ///
+    /// * `max_move_fraction`: 以半径为单位的移动上限，0 表示只使用 `substeps`。
+    ///
+    /// Returns:
+    ///
+    /// `max_move_fraction` 为负数或者不是有限的数时返回错误，保持原来的值。
+    pub fn set_max_move_fraction(&mut self, max_move_fraction: f32) -> anyhow::Result<()> {
+        if !(max_move_fraction >= 0.0 && max_move_fraction.is_finite()) {
+            anyhow::bail!(
+                "max move fraction must be non-negative, got {}",
+                max_move_fraction
+            );
+        }
+        self.max_move_fraction = max_move_fraction;
+        Ok(())
+    }
+
+    /// 最近一次 `update` 实际使用的碰撞检测轮数，还没有推进过时为 0。
+    pub fn last_rounds(&self) -> u32 {
+        self.last_rounds
+    }
+
+    // 计算这一次 update 的轮数并记录下来，CPU 参考求解器也用它，两条路径的时间步长相同
+    pub(crate) fn begin_rounds(&mut self, dt: std::time::Duration) -> u32 {
+        let rounds = self.simulation_rounds(dt);
+        self.last_rounds = rounds;
+        rounds
+    }
+
+    // 一次 update 中的碰撞检测轮数，按时间缩放调整，每一轮的时间步长不超过 dt / substeps；
+    // 开启自适应子步时再按最快的小球增加轮数，dt 是已经乘过时间缩放的模拟时间
+    fn simulation_rounds(&self, dt: std::time::Duration) -> u32 {
+        let rounds = ((self.substeps as f32 * self.time_scale).ceil() as u32).max(1);
+        if self.max_move_fraction <= 0.0 {
+            return rounds;
+        }
+        let dt = dt.as_secs_f32();
+        // 读回的速度是上一步结束时的，这一步中重力最多再增加 gravity * dt，速度上限同样限制了移动
+        let speed_gain = self.gravity.abs() * dt;
+        let max_radii_per_second = self
+            .instances
+            .iter()
+            .map(|instance| {
+                (instance.velocity.length() + speed_gain).min(self.max_speed) / instance.radius
+            })
+            .fold(0.0, f32::max);
+        let required = (max_radii_per_second * dt / self.max_move_fraction).ceil();
+        // 乘积溢出成无穷大时 as 转换会饱和，min 之后仍然是上限
+        rounds.max((required as u32).min(MAX_ADAPTIVE_ROUNDS))
+    }
+
+    /// 碰撞阶段的工作组大小。
+    pub fn workgroup_size(&self) -> u32 {
+        self.workgroup_size
+    }
+
+    /// 设置碰撞阶段的工作组大小并重建碰撞阶段，不同的 GPU 适合的大小不同，常见的是 32 到 256。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 用于创建管线的设备。
+    /// * `workgroup_size`: 新的工作组大小。
+    ///
+    /// Returns:
+    ///
+    /// 大小为 0 或者超过设备的限制时返回错误，保持原来的大小。
+    pub fn set_workgroup_size(
+        &mut self,
+        device: &wgpu::Device,
+        workgroup_size: u32,
+    ) -> anyhow::Result<()> {
+        check_workgroup_size(device, workgroup_size)?;
+        if workgroup_size == self.workgroup_size {
+            return Ok(());
+        }
+
+        self.collision_node = Self::create_collision_nodes(
+            device,
+            &self.params_buffer,
+            &self.instances_buffers,
+            &self.bvh_nodes_buffer,
+            &self.cell_index_buffer,
+            &self.result_buffer,
+            &self.planes_buffer,
+            &self.static_mesh_buffer,
+            workgroup_size,
+        );
+        self.workgroup_size = workgroup_size;
+        Ok(())
+    }
+
+    /// 把所有实例、容量以及所有模拟参数（边界、网格大小、重力、恢复系数、轮数、维数、单元编号、阻尼、速度上限、
+    /// 积分方法、作用力、宽相位和休眠）写到文件中，格式为本机字节序的二进制。
+    ///
+    /// 静态平面和静态网格不会被保存。保存的是最近一次读回的实例，读回间隔大于 1 时应该先同步。
+    ///
+    /// Arguments:
+    ///
+    /// * `path`: 保存的文件路径。
+    pub fn save_state(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let header = StateHeader {
+            magic: STATE_MAGIC,
+            version: STATE_VERSION,
+            count: self.instances.len() as u32,
+            capacity: self.buffer_len,
+            boundary: self.boundary.to_array(),
+            grid_size: self.grid_size,
+            gravity: self.gravity,
+            gravity_direction: self.gravity_direction.to_array(),
+            restitution: self.restitution,
+            substeps: self.substeps,
+            dimensions: self.dimensions as u32,
+            cell_indexing: self.cell_indexing as u32,
+            drag: self.drag,
+            max_speed: self.max_speed,
+            integrator: self.integrator as u32,
+            force_model: self.force_model as u32,
+            stiffness: self.stiffness,
+            equilibrium_distance: self.equilibrium_distance,
+            broad_phase: self.broad_phase as u32,
+            sleep_velocity: self.sleep_velocity,
+            sleep_time: self.sleep_time,
+        };
+        std::fs::write(path, encode_state(&header, &self.instances))?;
+        Ok(())
+    }
+
+    /// 从 `save_state` 写出的文件中恢复模拟状态，按保存的容量重新创建所有的 buffer。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 用于创建 buffer 和管线的设备。
+    /// * `path`: 状态文件的路径。
+    ///
+    /// Returns:
+    ///
+    /// 文件无法读取、格式或版本不对、长度与实例数量不一致、实例的 `id` 超出范围或者重复时返回错误。
+    pub fn load_state(
+        device: &wgpu::Device,
+        path: impl AsRef<std::path::Path>,
+    ) -> anyhow::Result<Self> {
+        let (header, instances) = decode_state(&std::fs::read(path)?)?;
+        let dimensions = Dimensions::from_u32(header.dimensions).unwrap_or_default();
+        let cell_indexing = CellIndexing::from_u32(header.cell_indexing).unwrap_or_default();
+        let integrator = Integrator::from_u32(header.integrator).unwrap_or_default();
+        let force_model = ForceModel::from_u32(header.force_model).unwrap_or_default();
+        let broad_phase = BroadPhase::from_u32(header.broad_phase).unwrap_or_default();
+        let mut state = Self::builder(header.capacity, header.grid_size)
+            .boundary(glam::Vec3::from_array(header.boundary))
+            .dimensions(dimensions)
+            .cell_indexing(cell_indexing)
+            .gravity(header.gravity)
+            .restitution(header.restitution)
+            .substeps(header.substeps)
+            .drag(header.drag)
+            .max_speed(header.max_speed)
+            .integrator(integrator)
+            .force_model(force_model)
+            .stiffness(header.stiffness)
+            .equilibrium_distance(header.equilibrium_distance)
+            .broad_phase(broad_phase)
+            .sleep(header.sleep_velocity, header.sleep_time)
+            .build(device)?;
+        state.set_gravity_direction(glam::Vec3::from_array(header.gravity_direction))?;
+        state.instances = instances;
+        state.check_grid_size()?;
+        Ok(state)
+    }
+
+    /// 保存最新实例状态的 buffer，模拟结束之后所有读取实例的节点都应该绑定它。
+    pub fn instances_buffer(&self) -> &Arc<wgpu::Buffer> {
+        &self.instances_buffers[self.current]
+    }
+
+    /// `instances_buffer()` 在 `instances_buffers` 中的下标，为 A/B 两个 buffer 各建一个节点时用来选择节点。
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// 在 CPU 上对最近一次读回的实例求总动能和总动量，并统计相互重叠的小球对，不需要额外的 GPU 通道。
+    pub fn diagnostics(&self) -> Diagnostics {
+        let (kinetic_energy, momentum) = conserved_totals(&self.instances);
+        Diagnostics {
+            kinetic_energy: kinetic_energy as f32,
+            momentum: momentum.as_vec3(),
+            overlaps: self.overlapping_pairs().len(),
+        }
+    }
+
+    /// 按 `id` 查找 CPU 中的实例，它是最近一次读回的状态。
+    ///
+    /// 实例按 `id` 顺序存放，结果也是按 `id` 读回的，所以先直接按下标查找，不一致时再退回到遍历。
+    ///
+    /// Arguments:
+    ///
+    /// * `id`: 实例的 `id`。
+    ///
+    /// Returns:
+    ///
+    /// 没有这个 `id` 的实例时返回 `None`。
+    pub fn instance(&self, id: u32) -> Option<ComputeInstance> {
+        self.instances
+            .get(id as usize)
+            .filter(|instance| instance.id == id)
+            .or_else(|| self.instances.iter().find(|instance| instance.id == id))
+            .copied()
+    }
+
+    /// 从 GPU 读回单个实例的最新状态，只复制 `result_buffer` 中这个实例的一个 `Result`，不读回整个 buffer。
+    ///
+    /// 会等待已经提交的模拟完成，但不会更新 CPU 中的实例；半径和颜色不在 GPU 上变化，取自 CPU。
+    /// 第一步模拟之前 `result_buffer` 中还没有结果，此时应该使用 [`Self::instance`]。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 用于创建命令和等待 GPU 的设备。
+    /// * `queue`: 用于写入 buffer 和提交命令的队列。
+    /// * `id`: 实例的 `id`。
+    ///
+    /// Returns:
+    ///
+    /// 没有这个 `id` 的实例时返回 `None`。
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_instance(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        id: u32,
+    ) -> Option<ComputeInstance> {
+        let instance = self.instance(id)?;
+        let size = std::mem::size_of::<Result>() as u64;
+        let bytes = read_back_range(device, queue, &self.result_buffer, id as u64 * size, size);
+        let result: Result = bytemuck::pod_read_unaligned(&bytes);
+        Some(ComputeInstance {
+            position: glam::Vec3::from_array(result.position),
+            velocity: glam::Vec3::from_array(result.velocity),
+            collision_heat: result.collision_heat,
+            ..instance
+        })
+    }
+
+    /// 从 GPU 读回所有小球的最新位置，适合只把本模块当作物理后端、自己负责渲染的情况。
+    ///
+    /// 顺序与 `instances` 一致，即第 `i` 个位置属于 `instances[i]`。会等待已经提交的模拟完成，
+    /// 但不会更新 CPU 中的实例。第一步模拟之前 `result_buffer` 中还没有结果，此时应该直接使用 `instances`。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 用于等待读回的设备。
+    ///
+    /// Returns:
+    ///
+    /// 每个小球的位置。
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn positions(&self, device: &wgpu::Device) -> Vec<glam::Vec3> {
+        self.read_result_vectors(device, |result| result.position)
+    }
+
+    /// 从 GPU 读回所有小球的最新速度，顺序和注意事项与 [`Self::positions`] 相同。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 用于等待读回的设备。
+    ///
+    /// Returns:
+    ///
+    /// 每个小球的速度。
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn velocities(&self, device: &wgpu::Device) -> Vec<glam::Vec3> {
+        self.read_result_vectors(device, |result| result.velocity)
+    }
+
+    // 读回整个 result_buffer，按 id 取出每个实例的一个向量
+    #[cfg(not(target_arch = "wasm32"))]
+    fn read_result_vectors(
+        &self,
+        device: &wgpu::Device,
+        field: impl Fn(&Result) -> [f32; 3],
+    ) -> Vec<glam::Vec3> {
+        let mapped_result = read_buffer_bytes(device, self.result_buffer.clone());
+        let results = utils::bytes_to_results(&mapped_result)
+            .expect("result buffer holds whole Result values");
+        self.instances
+            .iter()
+            .map(|instance| glam::Vec3::from_array(field(&results[instance.id as usize])))
+            .collect()
+    }
+
+    /// 在 GPU 上求射线与所有小球的最近交点，用于鼠标拾取。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 用于创建命令和等待 GPU 的设备。
+    /// * `queue`: 用于写入 buffer 和提交命令的队列。
+    /// * `ray_origin`: 世界空间中射线的起点。
+    /// * `ray_dir`: 世界空间中射线的方向，不需要归一化。
+    ///
+    /// Returns:
+    ///
+    /// 命中时返回最近小球的 `id` 和沿射线的距离，否则返回 `None`。
+    pub fn pick(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        ray_origin: glam::Vec3,
+        ray_dir: glam::Vec3,
+    ) -> Option<(u32, f32)> {
+        let direction = ray_dir.try_normalize()?;
+        if self.instances.is_empty() {
+            return None;
+        }
+
+        let ray = PickRay {
+            origin: ray_origin.to_array(),
+            _padding: 0,
+            direction: direction.to_array(),
+            _padding2: 0,
+        };
+        queue.write_buffer(&self.pick_ray_buffer, 0, bytemuck::cast_slice(&[ray]));
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Pick Encoder"),
+        });
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Pick pass"),
+                ..Default::default()
+            });
+            // 一个工作组内做归约，得到全局最近的交点
+            self.pick_node[self.current].dispatch(&mut cpass, 1);
+        }
+        queue.submit(iter::once(encoder.finish()));
+
+        let mapped_result = read_buffer_bytes(device, self.pick_result_buffer.clone());
+        let result: PickResult = bytemuck::pod_read_unaligned(&mapped_result);
+        (result.id != u32::MAX).then_some((result.id, result.distance))
+    }
+
+    /// 在 GPU 上查找离任意一点最近的小球。
+    ///
+    /// 模拟跑过至少一步之后，先只查找该点所在的网格及其相邻网格，找不到足够近的结果时再退回到暴力查找。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 用于创建命令和等待 GPU 的设备。
+    /// * `queue`: 用于写入 buffer 和提交命令的队列。
+    /// * `point`: 世界空间中的查询点，可以在边界之外。
+    ///
+    /// Returns:
+    ///
+    /// 返回最近小球的 `id` 和球心到该点的距离，场景为空时返回 `None`。
+    pub fn nearest(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        point: glam::Vec3,
+    ) -> Option<(u32, f32)> {
+        if self.instances.is_empty() {
+            return None;
+        }
+
+        let query = NearestQuery {
+            point: point.to_array(),
+            use_grid: self.grid_ready as u32,
+        };
+        queue.write_buffer(
+            &self.nearest_query_buffer,
+            0,
+            bytemuck::cast_slice(&[query]),
+        );
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Nearest Encoder"),
+        });
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Nearest pass"),
+                ..Default::default()
+            });
+            self.nearest_node[self.current].dispatch(&mut cpass, 1);
         }
+        queue.submit(iter::once(encoder.finish()));
+
+        let mapped_result = read_buffer_bytes(device, self.pick_result_buffer.clone());
+        let result: PickResult = bytemuck::pod_read_unaligned(&mapped_result);
+        (result.id != u32::MAX).then_some((result.id, result.distance))
+    }
+
+    /// 添加一个静态平面，小球会在平面的法线一侧与其碰撞。
+    ///
+    /// Arguments:
+    ///
+    /// * `queue`: 用于写入 buffer 的队列。
+    /// * `point`: 平面上的任意一点。
+    /// * `normal`: 平面的法线，指向小球所在的一侧，不需要归一化。
+    pub fn add_plane(
+        &mut self,
+        queue: &wgpu::Queue,
+        point: glam::Vec3,
+        normal: glam::Vec3,
+    ) -> anyhow::Result<()> {
+        self.push_plane(queue, point, normal, PLANE_SOLID)
+    }
+
+    /// 添加一个吸收平面：球心越过平面、到达法线的另一侧的小球会被移除，而不是被弹回。
+    ///
+    /// 小球在着色器中被标记为死亡，此后不再参与碰撞，读回结果时从 `instances` 中移除，
+    /// 它的 `id` 空出来，可以留给之后新加入的小球。与静态平面共用 [`MAX_PLANES`] 的数量上限。
+    ///
+    /// Arguments:
+    ///
+    /// * `queue`: 用于写入 buffer 的队列。
+    /// * `point`: 平面上的任意一点。
+    /// * `normal`: 平面的法线，指向小球保留的一侧，不需要归一化。
+    pub fn add_kill_plane(
+        &mut self,
+        queue: &wgpu::Queue,
+        point: glam::Vec3,
+        normal: glam::Vec3,
+    ) -> anyhow::Result<()> {
+        self.push_plane(queue, point, normal, PLANE_KILL)
+    }
+
+    // 把一个平面追加到 planes_buffer 中，kind 是 PLANE_SOLID 或者 PLANE_KILL
+    fn push_plane(
+        &mut self,
+        queue: &wgpu::Queue,
+        point: glam::Vec3,
+        normal: glam::Vec3,
+        kind: u32,
+    ) -> anyhow::Result<()> {
+        let Some(normal) = normal.try_normalize() else {
+            anyhow::bail!("plane normal must be non-zero");
+        };
+        if self.planes.len() >= MAX_PLANES {
+            anyhow::bail!("at most {} static planes are supported", MAX_PLANES);
+        }
+
+        let index = self.planes.len();
+        let plane = StaticPlane {
+            point: point.to_array(),
+            kind,
+            normal: normal.to_array(),
+            _padding2: 0,
+        };
+        self.planes.push(plane);
+
+        queue.write_buffer(
+            &self.planes_buffer,
+            16 + (std::mem::size_of::<StaticPlane>() * index) as u64,
+            bytemuck::cast_slice(&[plane]),
+        );
+        queue.write_buffer(
+            &self.planes_buffer,
+            0,
+            bytemuck::cast_slice(&[self.planes.len() as u32]),
+        );
+        Ok(())
+    }
+
+    /// 设置小球与之碰撞的静态三角形网格，替换之前设置的网格。
+    ///
+    /// 网格的顶点和索引会从 GPU 读回来整理成三角形再上传，顶点坐标直接当作世界坐标使用。
+    /// 目前碰撞阶段会对每个小球遍历所有三角形，适合面数不多的场景几何。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 用于创建命令和等待 GPU 的设备。
+    /// * `queue`: 用于写入 buffer 和提交命令的队列。
+    /// * `mesh`: 静态网格，顶点和索引 buffer 需要带有 `COPY_SRC`。
+    pub fn set_static_mesh(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mesh: &model::Mesh,
+    ) {
+        let vertex_bytes = read_back(device, queue, &mesh.vertex_buffer);
+        let index_bytes = read_back(device, queue, &mesh.index_buffer);
+        let vertices: Vec<model::ModelVertex> = bytemuck::pod_collect_to_vec(&vertex_bytes);
+        let indices: Vec<u32> = bytemuck::pod_collect_to_vec(&index_bytes);
+
+        let triangles: Vec<StaticTriangle> = indices[..mesh.num_elements as usize]
+            .chunks_exact(3)
+            .filter_map(|face| {
+                let [a, b, c] = [face[0], face[1], face[2]]
+                    .map(|i| glam::Vec3::from_array(vertices[i as usize].position));
+                // 退化的三角形没有法线，直接跳过
+                let normal = (b - a).cross(c - a).try_normalize()?;
+                Some(StaticTriangle {
+                    a: a.to_array(),
+                    _padding_a: 0,
+                    b: b.to_array(),
+                    _padding_b: 0,
+                    c: c.to_array(),
+                    _padding_c: 0,
+                    normal: normal.to_array(),
+                    _padding_normal: 0,
+                })
+            })
+            .collect();
+
+        let static_mesh_buffer = Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Static Mesh Buffer"),
+            size: 16 + (std::mem::size_of::<StaticTriangle>() * triangles.len().max(1)) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+        queue.write_buffer(
+            &static_mesh_buffer,
+            0,
+            bytemuck::cast_slice(&[triangles.len() as u32]),
+        );
+        if !triangles.is_empty() {
+            queue.write_buffer(&static_mesh_buffer, 16, bytemuck::cast_slice(&triangles));
+        }
+
+        // bind group 在创建时就固定了 buffer，所以要重建碰撞阶段
+        self.collision_node = Self::create_collision_nodes(
+            device,
+            &self.params_buffer,
+            &self.instances_buffers,
+            &self.bvh_nodes_buffer,
+            &self.cell_index_buffer,
+            &self.result_buffer,
+            &self.planes_buffer,
+            &static_mesh_buffer,
+            self.workgroup_size,
+        );
+        self.static_mesh_buffer = static_mesh_buffer;
     }
 
     // 将 CPU 中的 Instance 数据写到 GPU 的 Instance Buffer 中
-    pub fn write_instances_buffer(&self, app: &AppSurface, instances: &[ComputeInstance]) {
-        app.queue.write_buffer(
-            &self.instances_buffer,
+    // 实例少于容量时，剩下的位置写入死亡的占位实例，使用没有被占用的 id，每个 id 的结果仍然只有一个线程写入
+    // 每次更新都会调用，所以在 instances_raw 和 used_ids 中原地填充，不再每帧分配新的数组
+    pub fn write_instances_buffer(&mut self, queue: &wgpu::Queue) {
+        let raw = &mut self.instances_raw;
+        raw.clear();
+        raw.extend(self.instances.iter().map(ComputeInstance::to_raw));
+        if raw.len() < self.buffer_len as usize {
+            mark_used_ids(
+                &mut self.used_ids,
+                &self.instances,
+                self.buffer_len as usize,
+            );
+            let used = &self.used_ids;
+            raw.extend(
+                (0..self.buffer_len)
+                    .filter(|&id| !used[id as usize])
+                    .map(ComputeInstanceRaw::dead),
+            );
+        }
+        queue.write_buffer(
+            &self.instances_buffers[self.current],
             0,
-            bytemuck::cast_slice(
-                &instances
-                    .iter()
-                    .map(ComputeInstance::to_raw)
-                    .collect::<Vec<_>>(),
-            ),
+            bytemuck::cast_slice(raw),
         );
     }
 
-    pub fn do_compute(&self, app: &AppSurface, simulation_rounds: u32) {
-        let mut encoder = app
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Compute Encoder"),
-            });
+    pub fn do_compute(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        simulation_rounds: u32,
+    ) {
+        self.do_compute_timed(device, queue, simulation_rounds, None);
+    }
+
+    /// 与 `do_compute` 相同，但可以在整个计算通道的开始和结束处写入 GPU 时间戳。
+    ///
+    /// 每一轮中，分配网格、排序和建网格都在当前的实例 buffer 上原地进行，碰撞阶段从当前 buffer 读取、
+    /// 写入另一个 buffer，这样一个线程写回的新状态不会被同一轮中其他线程读到。同一个计算通道中的
+    /// dispatch 按顺序执行，前一个 dispatch 的写入对后一个可见，所以每一轮结束后交换两个 buffer 即可。
+    /// 返回时 `instances_buffer()` 指向最新的状态。
+    pub fn do_compute_timed(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        simulation_rounds: u32,
+        timestamp_writes: Option<wgpu::ComputePassTimestampWrites>,
+    ) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Compute Encoder"),
+        });
+        let mut current = self.current;
+        // 实例数量不是工作组大小的整数倍时，最后一个工作组中多出的线程在着色器中直接返回
+        let instance_groups = workgroup_count(self.buffer_len, SHADER_WORKGROUP_SIZE);
+        let collision_groups = workgroup_count(self.buffer_len, self.workgroup_size);
         {
             let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                 label: Some("Compute pass"),
-                ..Default::default()
+                timestamp_writes,
             });
             for _ in 0..simulation_rounds {
                 // 以下是一次完整的碰撞检测,我们会切碎时间块之后再进行碰撞检测
-                // assign cell
-                self.assign_cell_node.dispatch(&mut cpass, self.buffer_len as u32 / 64 + 1);
-
-                // bitonic sort
-                // adapted from Wikipedia's non-recursive example of bitonic sort:
-                // https://en.wikipedia.org/wiki/Bitonic_sorter
-                let mut k = 2;
-                while k <= self.buffer_len {
-                    // k is doubled every iteration
-                    let mut j = k >> 1;
-                    while j > 0 {
-                        // j is halved at every iteration, with truncation of fractional parts
-                        let sort_params = SortParams { j, k };
-                        app.queue.write_buffer(
-                            &self.sort_params_buffer,
-                            0,
-                            bytemuck::cast_slice(&[sort_params]),
-                        );
-
-                        self.sort_node.dispatch(&mut cpass, self.buffer_len as u32 / 64 + 1);
-
-                        j >>= 1;
-                    }
-                    k <<= 1;
-                }
+                self.dispatch_sort_stage(&mut cpass, current);
 
-                // memset index
-                self.memset_node.dispatch(&mut cpass, 128);
+                match self.broad_phase {
+                    BroadPhase::Grid => {
+                        // memset index
+                        self.memset_node[current].dispatch(&mut cpass, 128);
 
-                // build grid
-                self.build_grid_node.dispatch(&mut cpass, self.buffer_len as u32 / 64 + 1);
+                        // build grid
+                        self.build_grid_node[current].dispatch(&mut cpass, instance_groups);
+                    }
+                    // 排序之后的 Morton 码直接决定了 LBVH 的结构
+                    BroadPhase::Bvh => {
+                        self.bvh_build_node[current].dispatch(&mut cpass, instance_groups)
+                    }
+                    BroadPhase::BruteForce => {}
+                }
 
                 // collision detection
-                self.collision_node
-                    .dispatch(&mut cpass, self.buffer_len as u32 / 64 + 1);
+                self.collision_node[current].dispatch(&mut cpass, collision_groups);
+
+                // 碰撞阶段的输出作为下一轮的输入
+                current = 1 - current;
             }
         }
+        self.current = current;
 
-        app.queue.submit(iter::once(encoder.finish()));
-        app.device.poll(wgpu::MaintainBase::Wait);
+        // 不在这里等待 GPU 完成，读回结果时才会等待
+        queue.submit(iter::once(encoder.finish()));
     }
 
-    pub fn update(&mut self, app: &AppSurface, dt: std::time::Duration) {
-        let simulation_rounds = 10;
+    // 前两个阶段：为 instances_buffers[current] 中的每个实例写入网格编号或者 Morton 码，再按它原地排序
+    fn dispatch_sort_stage<'a>(&'a self, cpass: &mut wgpu::ComputePass<'a>, current: usize) {
+        // 实例数量不是工作组大小的整数倍时，最后一个工作组中多出的线程在着色器中直接返回
+        let instance_groups = workgroup_count(self.buffer_len, SHADER_WORKGROUP_SIZE);
+        // assign cell, BVH 宽相位时是 Morton 码
+        match self.broad_phase {
+            BroadPhase::Grid => self.assign_cell_node[current].dispatch(cpass, instance_groups),
+            BroadPhase::Bvh => self.bvh_assign_node[current].dispatch(cpass, instance_groups),
+            // 暴力查找不需要排序
+            BroadPhase::BruteForce => return,
+        }
 
-        // 首先把 instance buffer 写入 GPU
-        self.write_instances_buffer(app, &self.instances);
+        // bitonic sort, 每一遍的参数见 sort_passes
+        for bind_group in &self.sort_pass_groups[current] {
+            self.sort_node[current].dispatch_with_group(cpass, instance_groups, 2, bind_group);
+        }
+    }
 
-        // 其次, params 也是每次不变的, 写入
+    // 写入模拟参数，一次 update 被切成 simulation_rounds 个小的时间步
+    pub fn write_params(
+        &self,
+        queue: &wgpu::Queue,
+        dt: std::time::Duration,
+        simulation_rounds: u32,
+    ) {
         let params = Parameters {
+            boundary: self.boundary.to_array(),
+            _padding_boundary: 0,
+            gravity: self.gravity_vector().to_array(),
+            _padding_gravity: 0,
             time_step: dt.as_secs_f32() / simulation_rounds as f32,
-            boundary: self.boundary,
             grid_size: self.grid_size, // to be modified
+            restitution: self.restitution,
+            integrator: self.integrator as u32,
+            sleep_velocity: self.sleep_velocity,
+            sleep_time: self.sleep_time,
+            drag: self.drag,
+            dimensions: self.dimensions as u32,
+            force_model: self.force_model as u32,
+            stiffness: self.stiffness,
+            equilibrium_distance: self.equilibrium_distance,
+            broad_phase: self.broad_phase as u32,
+            cell_indexing: self.cell_indexing as u32,
+            max_speed: self.max_speed,
+            _padding: [0; 2],
         };
 
-        app.queue.write_buffer(
+        queue.write_buffer(
             &self.params_buffer,
             0,
             bytemuck::cast_slice(&[params.clone()]),
         );
+    }
+
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, dt: std::time::Duration) {
+        // 浏览器中不能阻塞等待读回：上一次的结果还没有读回时跳过这一帧，读回之后再开始下一次模拟
+        #[cfg(target_arch = "wasm32")]
+        if let Some(readback) = self.pending_readback.as_mut() {
+            match readback.try_take(device) {
+                Some(mapped_result) => {
+                    self.pending_readback = None;
+                    self.apply_results(&mapped_result);
+                }
+                None => return,
+            }
+        }
+
+        // 时间缩放为 0 时不推进模拟，实例保持不变，渲染照常进行
+        if self.time_scale == 0.0 {
+            return;
+        }
+        let dt = dt.mul_f32(self.time_scale);
+
+        // 有发射器时先加入这一步新发射的小球，发射之前需要知道哪些位置空了出来
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.emitter.is_some() && !self.is_synced() {
+            self.read_results(device);
+        }
+        self.emit(dt);
+        let simulation_rounds = self.begin_rounds(dt);
+
+        // 首先把 instance buffer 写入 GPU；instances 过时的时候 GPU 上的实例才是最新的，不能覆盖
+        if self.is_synced() {
+            self.write_instances_buffer(queue);
+        }
+
+        // 其次, params 也是每次不变的, 写入
+        self.write_params(queue, dt, simulation_rounds);
 
         // 执行计算
-        self.do_compute(app, simulation_rounds);
+        self.do_compute(device, queue, simulation_rounds);
+        // 只有网格宽相位会建立网格，其他宽相位时最近邻查询只能暴力查找
+        self.grid_ready = self.broad_phase == BroadPhase::Grid;
+
+        // 没到读回的时候就不等待 GPU，instances 保持上一次读回的状态
+        self.unsynced_updates += 1;
+        if self.emitter.is_none() && self.unsynced_updates < self.readback_interval {
+            return;
+        }
 
         // 从 result 中把结果 readback 回来, 更新 instance, 注意 compute instance 在 CPU 里面是有序的
-        let mapped_result = read_buffer_bytes(app, self.result_buffer.clone());
-
-        let results: Vec<f32> = utils::bytes_to_f32(&mapped_result);
-
-        for i in 0..self.buffer_len {
-            // 一个 result 有 8 个 f32, 只有六个是有用的
-            let pos = [
-                results[i as usize * 8],
-                results[i as usize * 8 + 1],
-                results[i as usize * 8 + 2],
-            ];
-            self.instances[i as usize].position = glam::Vec3::from_array(pos);
-
-            let vel = [
-                results[i as usize * 8 + 4],
-                results[i as usize * 8 + 5],
-                results[i as usize * 8 + 6],
-            ];
-
-            self.instances[i as usize].velocity = glam::Vec3::from_array(vel);
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.pending_readback = Some(Readback::new(self.result_buffer.clone()));
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        self.read_results(device);
+    }
+
+    /// 阻塞地读回最近一次模拟的结果，更新 CPU 中的实例和休眠数量。
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_results(&mut self, device: &wgpu::Device) {
+        let mapped_result = read_buffer_bytes(device, self.result_buffer.clone());
+        self.apply_results(&mapped_result);
+    }
+
+    // 用读回的结果更新 CPU 中的 instance，结果按 id 存放；已经死亡的小球从 instances 中移除
+    fn apply_results(&mut self, mapped_result: &[u8]) {
+        let results = utils::bytes_to_results(mapped_result)
+            .expect("result buffer holds whole Result values");
+        self.unsynced_updates = 0;
+
+        self.instances.retain_mut(|instance| {
+            let result = &results[instance.id as usize];
+            instance.position = glam::Vec3::from_array(result.position);
+            instance.velocity = glam::Vec3::from_array(result.velocity);
+            instance.collision_heat = result.collision_heat;
+            result.dead == 0
+        });
+        self.sleeping = if self.sleep_velocity > 0.0 {
+            self.instances
+                .iter()
+                .filter(|instance| results[instance.id as usize].still_time >= self.sleep_time)
+                .count()
+        } else {
+            0
+        };
+    }
+}
+
+// 窗口程序持有的是 AppSurface，下面是直接接受它的便捷写法；只需要设备的方法直接传入 `&app.device` 即可
+impl ComputeStateBuilder {
+    /// 与 [`build`](Self::build) 相同，使用 `app` 的设备。
+    pub fn build_app(&self, app: &AppSurface) -> anyhow::Result<ComputeState> {
+        self.build(&app.device)
+    }
+}
+
+impl ComputeState {
+    /// 与 [`update`](Self::update) 相同，使用 `app` 的设备和队列。
+    pub fn update_app(&mut self, app: &AppSurface, dt: std::time::Duration) {
+        self.update(&app.device, &app.queue, dt);
+    }
+
+    /// 与 [`reset`](Self::reset) 相同，使用 `app` 的设备和队列。
+    pub fn reset_app(&mut self, app: &AppSurface) -> anyhow::Result<()> {
+        self.reset(&app.device, &app.queue)
+    }
+
+    /// 与 [`pick`](Self::pick) 相同，使用 `app` 的设备和队列。
+    pub fn pick_app(
+        &self,
+        app: &AppSurface,
+        ray_origin: glam::Vec3,
+        ray_dir: glam::Vec3,
+    ) -> Option<(u32, f32)> {
+        self.pick(&app.device, &app.queue, ray_origin, ray_dir)
+    }
+}
+
+/// 测试中使用的设备。
+///
+/// 需要 GPU 的测试都标记为 `#[ignore]`，用 `cargo test -- --ignored` 运行；这时没有可用的适配器，
+/// 或者适配器不支持必需的特性会让测试失败，而不是悄悄地跳过。
+#[cfg(test)]
+pub(crate) fn test_device() -> (wgpu::Device, wgpu::Queue) {
+    let features = REQUIRED_FEATURES;
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter =
+        pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+            .filter(|adapter| adapter.features().contains(features))
+            .expect("没有支持 MAPPABLE_PRIMARY_BUFFERS 的 GPU 适配器");
+    pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: Some("Test Device"),
+            features,
+            limits: adapter.limits(),
+        },
+        None,
+    ))
+    .expect("无法创建测试设备")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_DT: std::time::Duration = std::time::Duration::from_micros(16_667);
+
+    fn test_instances(count: u32) -> Vec<ComputeInstance> {
+        (0..count)
+            .map(|id| ComputeInstance {
+                id,
+                position: glam::Vec3::new(id as f32, 0.5, -0.25),
+                radius: 0.1 + id as f32 * 0.01,
+                velocity: glam::Vec3::new(-1.0, id as f32, 0.0),
+                color: glam::Vec4::new(0.2, 0.4, 0.6, 1.0),
+                collision_heat: id as f32 * 0.5,
+            })
+            .collect()
+    }
+
+    fn test_header(count: u32) -> StateHeader {
+        StateHeader {
+            magic: STATE_MAGIC,
+            version: STATE_VERSION,
+            count,
+            capacity: count,
+            boundary: [2.0, 3.0, 0.5],
+            grid_size: 0.4,
+            gravity: 9.8,
+            gravity_direction: [0.0, -0.6, -0.8],
+            restitution: 0.9,
+            substeps: 4,
+            dimensions: Dimensions::D2 as u32,
+            cell_indexing: CellIndexing::Morton as u32,
+            drag: 0.5,
+            max_speed: 20.0,
+            integrator: Integrator::Verlet as u32,
+            force_model: ForceModel::LennardJones as u32,
+            stiffness: 500.0,
+            equilibrium_distance: 0.3,
+            broad_phase: BroadPhase::Bvh as u32,
+            sleep_velocity: 0.05,
+            sleep_time: 1.0,
+        }
+    }
+
+    #[test]
+    fn workgroup_count_covers_every_instance_exactly() {
+        assert_eq!(workgroup_count(0, 64), 0);
+        assert_eq!(workgroup_count(1, 64), 1);
+        assert_eq!(workgroup_count(64, 64), 1);
+        assert_eq!(workgroup_count(65, 64), 2);
+        assert_eq!(workgroup_count(1000, 256), 4);
+    }
+
+    #[test]
+    fn state_file_round_trips_byte_identical() {
+        // 吸收平面移除过小球、发射器又加入过小球之后，id 不再连续，容量也大于存活的数量
+        let mut instances = test_instances(5);
+        for (instance, id) in instances.iter_mut().zip([0, 2, 3, 6, 7]) {
+            instance.id = id;
+        }
+        let header = StateHeader {
+            capacity: 8,
+            ..test_header(5)
+        };
+        let bytes = encode_state(&header, &instances);
+        let path = std::env::temp_dir().join(format!("cdgs-state-{}.bin", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let read = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let (header, instances) = decode_state(&read).unwrap();
+        assert_eq!(encode_state(&header, &instances), bytes);
+        assert_eq!(
+            Dimensions::from_u32(header.dimensions),
+            Some(Dimensions::D2)
+        );
+        assert_eq!(
+            CellIndexing::from_u32(header.cell_indexing),
+            Some(CellIndexing::Morton)
+        );
+        assert_eq!(
+            BroadPhase::from_u32(header.broad_phase),
+            Some(BroadPhase::Bvh)
+        );
+        assert_eq!(header.capacity, 8);
+        assert_eq!(instances[3].id, 6);
+        assert_eq!(instances[3].collision_heat, 1.5);
+    }
+
+    #[test]
+    #[ignore = "需要 GPU 适配器"]
+    fn saved_state_with_sparse_ids_loads_with_its_capacity_and_parameters() {
+        let (device, queue) = test_device();
+        let builder = ComputeStateBuilder::new(6, 0.8)
+            .drag(0.5)
+            .max_speed(20.0)
+            .integrator(Integrator::Verlet)
+            .force_model(ForceModel::Spring)
+            .stiffness(500.0)
+            .equilibrium_distance(0.3)
+            .broad_phase(BroadPhase::BruteForce)
+            .sleep(0.05, 1.0);
+        let mut world =
+            crate::CollisionWorld::new(&device, &queue, &builder, test_instances(6)).unwrap();
+        // 移除 id 为 1 和 4 的小球，剩下的 id 不连续
+        world
+            .state_mut()
+            .instances
+            .retain(|instance| instance.id % 3 != 1);
+
+        let path = std::env::temp_dir().join(format!("cdgs-sparse-{}.bin", std::process::id()));
+        world.state().save_state(&path).unwrap();
+        let loaded = ComputeState::load_state(&device, &path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(loaded.buffer_len(), 6);
+        let ids = loaded.instances.iter().map(|instance| instance.id);
+        assert_eq!(ids.collect::<Vec<_>>(), [0, 2, 3, 5]);
+        assert_eq!(loaded.drag(), 0.5);
+        assert_eq!(loaded.max_speed(), 20.0);
+        assert_eq!(loaded.integrator, Integrator::Verlet);
+        assert_eq!(loaded.force_model, ForceModel::Spring);
+        assert_eq!(loaded.stiffness, 500.0);
+        assert_eq!(loaded.equilibrium_distance, 0.3);
+        assert_eq!(loaded.broad_phase, BroadPhase::BruteForce);
+        assert_eq!((loaded.sleep_velocity, loaded.sleep_time), (0.05, 1.0));
+    }
+
+    #[test]
+    fn state_file_rejects_invalid_ids() {
+        let mut instances = test_instances(3);
+        instances[2].id = 3;
+        assert!(decode_state(&encode_state(&test_header(3), &instances)).is_err());
+        instances[2].id = 0;
+        assert!(decode_state(&encode_state(&test_header(3), &instances)).is_err());
+    }
+
+    #[test]
+    #[ignore = "需要 GPU 适配器"]
+    fn colliding_pair_conserves_momentum_across_steps() {
+        let (device, queue) = test_device();
+        // 半径不同的两个小球迎面相撞，着色器中质量都是 1，碰撞前后总动量不变
+        let mut instances = test_instances(2);
+        instances[0].position = glam::Vec3::new(-0.5, 0.0, 0.0);
+        instances[0].radius = 0.1;
+        instances[0].velocity = glam::Vec3::new(2.0, 0.0, 0.0);
+        instances[1].position = glam::Vec3::new(0.5, 0.0, 0.0);
+        instances[1].radius = 0.3;
+        instances[1].velocity = glam::Vec3::new(-2.0, 0.0, 0.0);
+        let builder = ComputeStateBuilder::new(2, 0.8)
+            .gravity(0.0)
+            .drag(0.0)
+            .restitution(1.0);
+
+        for mode in [crate::StepMode::Gpu, crate::StepMode::CpuF64] {
+            let mut world =
+                crate::CollisionWorld::new(&device, &queue, &builder, instances.clone()).unwrap();
+            world.set_step_mode(mode);
+            let before = world.state().diagnostics();
+            for _ in 0..20 {
+                world.step(&device, &queue, TEST_DT);
+            }
+            let after = world.state().diagnostics();
+
+            // 两个小球已经相互弹开
+            let [a, b] = [world.instances()[0], world.instances()[1]];
+            assert!(
+                a.velocity.x < 0.0 && b.velocity.x > 0.0,
+                "{mode:?}: {a:?} {b:?}"
+            );
+            let drift = (after.momentum - before.momentum).length();
+            assert!(drift < 1e-4, "{mode:?}: momentum drifted by {drift}");
+        }
+    }
+
+    #[test]
+    #[ignore = "需要 GPU 适配器"]
+    fn kill_plane_removes_falling_spheres_and_frees_their_ids() {
+        let (device, queue) = test_device();
+        // 四个相互离得很远的小球自由下落，id 为 1 和 3 的两个离吸收平面更近，先越过它
+        let mut instances = test_instances(4);
+        for instance in &mut instances {
+            let height = if instance.id % 2 == 0 { 0.0 } else { -0.8 };
+            instance.position = glam::Vec3::new(instance.id as f32 - 1.5, height, 0.0);
+            instance.radius = 0.1;
+            instance.velocity = glam::Vec3::ZERO;
+        }
+        let builder = ComputeStateBuilder::new(4, 0.8).drag(0.0);
+        let mut world = crate::CollisionWorld::new(&device, &queue, &builder, instances).unwrap();
+        world.set_step_mode(crate::StepMode::CpuF64);
+        world
+            .state_mut()
+            .add_kill_plane(&queue, glam::Vec3::new(0.0, -1.0, 0.0), glam::Vec3::Y)
+            .unwrap();
+
+        let mut live_count = world.state().live_count();
+        // 三分之一秒之后下面的两个小球已经越过平面，上面的两个还离它很远
+        for _ in 0..20 {
+            world.step(&device, &queue, TEST_DT);
+            // 小球只会被移除，存活的数量不会增加
+            assert!(world.state().live_count() <= live_count);
+            live_count = world.state().live_count();
+        }
+        assert_eq!(live_count, 2);
+        assert_eq!(world.instances().len(), 2);
+        assert_eq!(world.cpu_solver().unwrap().positions().len(), 2);
+        let ids = world.instances().iter().map(|instance| instance.id);
+        assert_eq!(ids.collect::<Vec<_>>(), [0, 2]);
+
+        // 发射器把新的小球放进空出来的 id 中，数量不超过容量
+        world
+            .state_mut()
+            .set_emitter(
+                Some(Emitter {
+                    position: glam::Vec3::new(0.0, 2.0, 0.0),
+                    direction: glam::Vec3::Y,
+                    spread: 0.0,
+                    rate: 1000.0,
+                    speed: 1.0,
+                    radius: 0.1,
+                }),
+                1,
+            )
+            .unwrap();
+        world.state_mut().emit(TEST_DT);
+        let mut ids = world
+            .instances()
+            .iter()
+            .map(|instance| instance.id)
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        assert_eq!(ids, [0, 1, 2, 3]);
+        assert_eq!(world.state().live_count(), 4);
+    }
+
+    // 暴力检查所有小球对，返回第一对相互重叠的小球
+    fn find_overlap(instances: &[ComputeInstance]) -> Option<(u32, u32)> {
+        instances.iter().enumerate().find_map(|(i, a)| {
+            instances[i + 1..]
+                .iter()
+                .find(|b| a.position.distance(b.position) < a.radius + b.radius)
+                .map(|b| (a.id, b.id))
+        })
+    }
+
+    #[test]
+    fn jittered_lattice_has_no_overlaps() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let radius_of = |id: u32| 0.15 + (id % 4) as f32 * 0.01;
+        let instances = lattice_instances(
+            0.5,
+            [5, 4, 3],
+            0.05,
+            1.0,
+            Dimensions::D3,
+            glam::Vec3::splat(1.5),
+            radius_of,
+            &mut rng,
+        )
+        .unwrap();
+        assert_eq!(instances.len(), 60);
+        assert_eq!(find_overlap(&instances), None);
+        // 扰动之后整个点阵仍然在边界之内
+        for instance in &instances {
+            assert!((instance.position.abs() + instance.radius).max_element() <= 1.5);
+        }
+    }
+
+    #[test]
+    fn lattice_rejects_spacing_that_allows_overlaps() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let result = lattice_instances(
+            0.35,
+            [4, 4, 4],
+            0.05,
+            0.0,
+            Dimensions::D3,
+            glam::Vec3::splat(1.5),
+            |_| 0.15,
+            &mut rng,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn turning_gravity_preserves_its_magnitude() {
+        let gravity = 9.8;
+        let target = glam::Vec3::new(1.0, 0.2, -0.5);
+        let mut direction = glam::Vec3::NEG_Y;
+        for _ in 0..100 {
+            direction = turn_toward(direction, target, 0.01);
+            assert!(((direction * gravity).length() - gravity).abs() < 1e-4);
+        }
+        // 足够多步之后正好停在目标方向上
+        for _ in 0..1000 {
+            direction = turn_toward(direction, target, 0.01);
+        }
+        assert_eq!(direction, target.normalize());
+
+        // 目标与当前方向相反时也能转过去
+        let flipped = turn_toward(glam::Vec3::NEG_Y, glam::Vec3::Y, 0.1);
+        assert!((flipped.length() - 1.0).abs() < 1e-6);
+        assert!((flipped.angle_between(glam::Vec3::NEG_Y) - 0.1).abs() < 1e-4);
+        assert_eq!(
+            turn_toward(glam::Vec3::NEG_Y, glam::Vec3::ZERO, 0.1),
+            glam::Vec3::NEG_Y
+        );
+    }
+
+    #[test]
+    fn state_file_rejects_unknown_dimensions() {
+        let header = StateHeader {
+            dimensions: 4,
+            ..test_header(2)
+        };
+        assert!(decode_state(&encode_state(&header, &test_instances(2))).is_err());
+    }
+
+    fn nearest_brute_force(instances: &[ComputeInstance], point: glam::Vec3) -> Option<(u32, f32)> {
+        instances
+            .iter()
+            .map(|instance| (instance.id, instance.position.distance(point)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    // 逐个比较查询点与 ComputeState::nearest 的结果和暴力查找的结果
+    fn assert_nearest_matches_brute_force(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        state: &ComputeState,
+        rng: &mut rand::rngs::StdRng,
+    ) {
+        for _ in 0..100 {
+            // 一部分查询点在网格之外，覆盖退回到暴力查找的情况
+            let point = glam::Vec3::from_array([(); 3].map(|_| rng.gen_range(-3.0..3.0)));
+            let (id, distance) = state.nearest(device, queue, point).unwrap();
+            let (expected_id, expected_distance) =
+                nearest_brute_force(&state.instances, point).unwrap();
+            assert_eq!(id, expected_id, "nearest to {point}");
+            assert!((distance - expected_distance).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    #[ignore = "需要 GPU 适配器"]
+    fn nearest_matches_brute_force_with_and_without_grid() {
+        let (device, queue) = test_device();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(11);
+        let boundary = glam::Vec3::splat(2.0);
+        let instances = lattice_instances(
+            0.5,
+            [6, 6, 6],
+            0.1,
+            0.5,
+            Dimensions::D3,
+            boundary,
+            |_| 0.1,
+            &mut rng,
+        )
+        .unwrap();
+        let builder = ComputeStateBuilder::new(instances.len() as u32, 0.5)
+            .boundary(boundary)
+            .broad_phase(BroadPhase::Grid);
+        let mut world = crate::CollisionWorld::new(&device, &queue, &builder, instances).unwrap();
+
+        // 第一步之前还没有网格，只能暴力查找
+        assert!(!world.state().grid_ready);
+        assert_nearest_matches_brute_force(&device, &queue, world.state(), &mut rng);
+
+        // 网格宽相位推进一步之后使用网格查找
+        world.step(&device, &queue, TEST_DT);
+        assert!(world.state().grid_ready);
+        assert_nearest_matches_brute_force(&device, &queue, world.state(), &mut rng);
+
+        // 所有小球都越过吸收平面之后场景为空，没有最近的小球
+        world
+            .state_mut()
+            .add_kill_plane(&queue, glam::Vec3::new(0.0, 3.0, 0.0), glam::Vec3::Y)
+            .unwrap();
+        world.step(&device, &queue, TEST_DT);
+        assert_eq!(world.state().live_count(), 0);
+        assert_eq!(
+            world.state().nearest(&device, &queue, glam::Vec3::ZERO),
+            None
+        );
+    }
+
+    // 死亡实例的键，与 header.wgsl 中的 DEAD_CELL 相同
+    const DEAD_CELL: u32 = u32::MAX;
+
+    // 在容量为 buffer_len 的模拟中以随机的顺序放入 live 个位置随机的小球，只运行分配和排序两个阶段，
+    // 读回排序之后的实例 buffer
+    fn run_sort_stage(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        broad_phase: BroadPhase,
+        buffer_len: u32,
+        live: u32,
+    ) -> Vec<ComputeInstanceRaw> {
+        use rand::seq::SliceRandom;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(buffer_len as u64);
+        let mut state = ComputeStateBuilder::new(buffer_len, 0.5)
+            .boundary(glam::Vec3::splat(2.0))
+            .broad_phase(broad_phase)
+            .build(device)
+            .unwrap();
+        let mut ids = (0..buffer_len).collect::<Vec<_>>();
+        ids.shuffle(&mut rng);
+        state.instances = ids[..live as usize]
+            .iter()
+            .map(|&id| ComputeInstance {
+                id,
+                position: glam::Vec3::from_array([(); 3].map(|_| rng.gen_range(-1.9..1.9))),
+                ..test_instances(1)[0]
+            })
+            .collect();
+        state.write_instances_buffer(queue);
+        state.write_params(queue, TEST_DT, 1);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Sort Test Encoder"),
+        });
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            state.dispatch_sort_stage(&mut cpass, state.current);
+        }
+        queue.submit(iter::once(encoder.finish()));
+        bytemuck::pod_collect_to_vec(&read_back(
+            device,
+            queue,
+            &state.instances_buffers[state.current],
+        ))
+    }
+
+    // 键不减，死亡的实例都在最后，每个 id 恰好出现一次
+    fn assert_sorted(sorted: &[ComputeInstanceRaw], live: u32) {
+        for (i, pair) in sorted.windows(2).enumerate() {
+            assert!(
+                pair[0].cell_index <= pair[1].cell_index,
+                "keys out of order at {i}: {} > {}",
+                pair[0].cell_index,
+                pair[1].cell_index
+            );
+        }
+        let (alive, dead) = sorted.split_at(live as usize);
+        assert!(alive
+            .iter()
+            .all(|raw| raw.dead == 0 && raw.cell_index != DEAD_CELL));
+        assert!(dead
+            .iter()
+            .all(|raw| raw.dead != 0 && raw.cell_index == DEAD_CELL));
+        let mut ids = sorted.iter().map(|raw| raw.id).collect::<Vec<_>>();
+        ids.sort_unstable();
+        assert_eq!(ids, (0..sorted.len() as u32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn sort_passes_cover_the_next_power_of_two() {
+        assert!(sort_passes(1).is_empty());
+        // 长度补齐到 128，一共 1 + 2 + ... + 7 遍，最后一个阶段覆盖整个补齐的长度
+        let passes = sort_passes(100);
+        assert_eq!(passes.len(), 28);
+        assert_eq!(passes[0], SortParams { j: 1, k: 2 });
+        assert_eq!(passes[passes.len() - 7], SortParams { j: 64, k: 128 });
+        assert_eq!(passes[passes.len() - 1], SortParams { j: 1, k: 128 });
+    }
+
+    #[test]
+    #[ignore = "需要 GPU 适配器"]
+    fn morton_keys_are_sorted_after_the_sort_stage() {
+        let (device, queue) = test_device();
+        // 100 不是 2 的幂，排序需要补齐之后的最后一个归并阶段才能得到正确的顺序
+        let sorted = run_sort_stage(&device, &queue, BroadPhase::Bvh, 100, 100);
+        assert_sorted(&sorted, 100);
+    }
+
+    #[test]
+    #[ignore = "需要 GPU 适配器"]
+    fn cell_indices_are_sorted_when_the_capacity_is_not_a_multiple_of_the_workgroup_size() {
+        let (device, queue) = test_device();
+        // 150 个位置中有 30 个死亡的占位实例，最后一个工作组只有一部分线程有实例
+        for (buffer_len, live) in [(150, 120), (65, 65), (37, 36)] {
+            let sorted = run_sort_stage(&device, &queue, BroadPhase::Grid, buffer_len, live);
+            assert_sorted(&sorted, live);
+        }
+    }
+
+    // 同一个带随机种子的场景，用给定的宽相位推进 steps 步，按 id 排序返回。
+    // 50 个半径随机的小球从点阵中随机选取，id 和在 buffer 中的顺序也都打乱，
+    // 数量不是 2 的幂，初始顺序也与网格编号无关，排序必须真的起作用
+    fn run_broad_phase(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        broad_phase: BroadPhase,
+        steps: u32,
+    ) -> Vec<ComputeInstance> {
+        use rand::seq::SliceRandom;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+        let boundary = glam::Vec3::splat(1.5);
+        let radii = (0..64)
+            .map(|_| rng.gen_range(0.1..0.2))
+            .collect::<Vec<f32>>();
+        let mut instances = lattice_instances(
+            0.5,
+            [4, 4, 4],
+            0.04,
+            1.0,
+            Dimensions::D3,
+            boundary,
+            |id| radii[id as usize],
+            &mut rng,
+        )
+        .unwrap();
+        instances.shuffle(&mut rng);
+        instances.truncate(50);
+        let mut ids = (0..50).collect::<Vec<u32>>();
+        ids.shuffle(&mut rng);
+        for (instance, id) in instances.iter_mut().zip(ids) {
+            instance.id = id;
+        }
+
+        let builder = ComputeStateBuilder::new(instances.len() as u32, 0.5)
+            .boundary(boundary)
+            .broad_phase(broad_phase);
+        let mut world = crate::CollisionWorld::new(device, queue, &builder, instances).unwrap();
+        for _ in 0..steps {
+            world.step(device, queue, TEST_DT);
+        }
+        let mut instances = world.instances().to_vec();
+        instances.sort_by_key(|instance| instance.id);
+        instances
+    }
+
+    #[test]
+    #[ignore = "需要 GPU 适配器"]
+    fn grid_and_bvh_broad_phases_agree_with_brute_force() {
+        let (device, queue) = test_device();
+        let brute_force = run_broad_phase(&device, &queue, BroadPhase::BruteForce, 20);
+
+        // 各种宽相位找到的是同样的小球对，只有力的累加顺序不同
+        for broad_phase in [BroadPhase::Grid, BroadPhase::Bvh] {
+            let result = run_broad_phase(&device, &queue, broad_phase, 20);
+            assert_eq!(result.len(), brute_force.len());
+            for (a, b) in result.iter().zip(&brute_force) {
+                assert_eq!(a.id, b.id);
+                let position_error = a.position.distance(b.position);
+                assert!(
+                    position_error < 1e-3,
+                    "{broad_phase:?}: instance {} moved apart by {position_error}",
+                    a.id
+                );
+                let velocity_error = a.velocity.distance(b.velocity);
+                assert!(
+                    velocity_error < 1e-2,
+                    "{broad_phase:?}: instance {} velocity differs by {velocity_error}",
+                    a.id
+                );
+            }
         }
     }
 }
@@ -0,0 +1,767 @@
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use crate::{camera, compute, SCENE_SEED};
+
+/// 默认读取的场景文件，位于当前工作目录下。
+pub const DEFAULT_SCENE_FILE: &str = "scene.toml";
+
+/// 默认的小球模型，是一个半径为 1 的球体。
+pub const DEFAULT_MODEL: &str = "sphere.obj";
+
+/// `SceneConfig` 描述初始场景和模拟参数，可以从 TOML 文件中读取，缺少的字段使用默认值。
+///
+/// Properties:
+///
+/// * `points`: 小球的数量。
+/// * `dimensions`: 模拟的维数，`"2d"` 或 `"3d"`；二维时小球都在 z = 0 的平面内，相机换成正对平面的正交视图。
+/// * `boundary`: 边界的半边长，小球在 `[-boundary, boundary]` 内运动；一个数表示立方体，
+///   `[x, y, z]` 表示三个轴上的半边长不同的长方体。
+/// * `radius`: 小球的半径。
+/// * `radii`: 可选的多个半径，非空时小球依次轮流使用其中的半径，忽略 `radius`。
+/// * `grid_size`: 碰撞检测的网格大小，不能小于最大直径；不设置时取最大直径。
+/// * `cell_indexing`: 网格单元的编号方式，`"linear"` 或 `"morton"`；`"morton"` 让排序之后相邻单元中的小球
+///   在内存中靠得更近。
+/// * `broad_phase`: 碰撞阶段查找相邻小球的方法，`"grid"`、`"bvh"` 或 `"brute_force"`；半径相差很大时
+///   `"bvh"` 更合适，`"brute_force"` 用来检查其他方法的结果。运行时可以用 X 键切换。
+/// * `gravity`: 重力加速度的大小，方向沿 -Y。
+/// * `restitution`: 与平面、网格碰撞时的恢复系数，0 表示完全非弹性，1 表示完全弹性。
+/// * `force_model`: 小球之间的作用力模型，`"contact"`、`"spring"` 或 `"lennard_jones"`。
+/// * `stiffness`: 作用力的刚度。
+/// * `equilibrium_distance`: 弹簧和 Lennard-Jones 势的平衡距离，0 表示取两个小球的半径之和；
+///   吸引力只在一个格子的距离内起作用，平衡距离必须小于 `grid_size`。
+/// * `drag`: 与速度成正比的阻尼系数，单位是 1/秒，0 表示没有阻尼。
+/// * `max_speed`: 小球速度大小的上限，防止初始重叠的小球被弹出极大的速度、穿过其他小球。
+/// * `substeps`: 每次更新切分成的碰撞检测轮数。
+/// * `max_move_fraction`: 自适应子步时每一轮中小球最多移动的距离（以半径为单位），小球很快时自动增加轮数；0 表示轮数固定。
+/// * `time_scale`: 时间缩放，小于 1 时是慢动作，大于 1 时快进，0 表示不推进模拟；运行时可以用 `[` 和 `]` 调整。
+/// * `integrator`: 数值积分方法，`"euler"` 或 `"verlet"`。
+/// * `sleep_velocity`: 休眠速度，小球的速度持续低于它时进入休眠，跳过积分和碰撞计算；0 表示不休眠。
+/// * `sleep_time`: 速度低于 `sleep_velocity` 持续多少秒之后进入休眠。
+/// * `workgroup_size`: 碰撞阶段的工作组大小，可以用 `--bench` 找到当前 GPU 上最快的大小。
+/// * `seed`: 生成初始位置和速度的随机种子。
+/// * `lattice`: 设置时小球排成规则的点阵，否则在边界内随机放置。
+/// * `emitter`: 设置时在模拟过程中不断发射新的小球，直到小球的数量达到它的 `max_points`。
+/// * `relax_iterations`: 模拟开始之前推开相互重叠的小球的最多迭代次数，0 表示不处理。
+/// * `pause_on_unfocus`: 窗口失去焦点时停止更新和渲染，回到窗口时继续。
+/// * `aspect_ratio`: 设置时画面锁定为这个宽高比，窗口多出来的部分是黑边；不设置时铺满窗口。
+/// * `sample_count`: MSAA 的采样数，1、2、4 或 8，1 表示关闭抗锯齿；设备不支持时退回到 1。运行时可以用 F2 键切换。
+/// * `model`: 每个小球使用的模型文件，位于 `res` 目录下，`.obj`、`.gltf` 或 `.glb`；模型按小球的半径缩放，
+///   半径为 1 的模型大小正好。每个网格使用自己的材质，`two-materials.obj` 可以用来检查多材质的模型。
+/// * `camera`: 相机的初始位姿和手柄设置，二维时不使用位姿。
+/// * `background`: 背景的颜色。
+/// * `floor`: 地面网格。
+/// * `lighting`: 光源、环境光和曝光。
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneConfig {
+    pub points: u32,
+    pub dimensions: compute::Dimensions,
+    pub boundary: Boundary,
+    pub radius: f32,
+    pub radii: Vec<f32>,
+    pub grid_size: Option<f32>,
+    pub cell_indexing: compute::CellIndexing,
+    pub broad_phase: compute::BroadPhase,
+    pub gravity: f32,
+    pub restitution: f32,
+    pub force_model: compute::ForceModel,
+    pub stiffness: f32,
+    pub equilibrium_distance: f32,
+    pub drag: f32,
+    pub max_speed: f32,
+    pub substeps: u32,
+    pub max_move_fraction: f32,
+    pub time_scale: f32,
+    pub integrator: compute::Integrator,
+    pub sleep_velocity: f32,
+    pub sleep_time: f32,
+    pub workgroup_size: u32,
+    pub seed: u64,
+    pub lattice: Option<LatticeConfig>,
+    pub emitter: Option<EmitterConfig>,
+    pub relax_iterations: u32,
+    pub pause_on_unfocus: bool,
+    pub aspect_ratio: Option<f32>,
+    pub sample_count: u32,
+    pub model: String,
+    pub camera: CameraConfig,
+    pub background: BackgroundConfig,
+    pub floor: FloorConfig,
+    pub lighting: LightingConfig,
+}
+
+/// `LatticeConfig` 让小球从规则的点阵开始，初始状态中没有相互重叠的小球。
+///
+/// Properties:
+///
+/// * `spacing`: 相邻格点的间距，不能小于最大直径加上两倍的 `jitter`。
+/// * `counts`: 三个方向上的格点数量，乘积要等于 `points`。
+/// * `jitter`: 每个坐标的随机扰动的最大值。
+/// * `speed`: 初始速度每个分量的最大值，0 表示静止。
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LatticeConfig {
+    pub spacing: f32,
+    pub counts: [u32; 3],
+    pub jitter: f32,
+    pub speed: f32,
+}
+
+/// `EmitterConfig` 是以固定的速率在一个点上发射新的小球的发射器，见 `compute::Emitter`。
+///
+/// Properties:
+///
+/// * `max_points`: 小球数量的上限，包括一开始的 `points` 个小球，达到上限之后不再发射，不能小于 `points`。
+/// * `position`: 发射点。
+/// * `direction`: 发射方向，不需要归一化。
+/// * `spread`: 初速度方向所在圆锥的半角，以度为单位，0 表示总是沿 `direction` 发射。
+/// * `rate`: 每秒（模拟时间）发射的小球数量。
+/// * `speed`: 初速度的大小。
+/// * `radius`: 新的小球的半径，不设置时使用 `radius`。
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmitterConfig {
+    pub max_points: u32,
+    pub position: [f32; 3],
+    pub direction: [f32; 3],
+    pub spread: f32,
+    pub rate: f32,
+    pub speed: f32,
+    pub radius: Option<f32>,
+}
+
+/// `CameraConfig` 是相机的初始位姿，角度以度为单位，以及手柄控制相机的设置。
+///
+/// Properties:
+///
+/// * `position`: 相机在世界空间中的位置。
+/// * `yaw`: 偏航角。
+/// * `pitch`: 俯仰角。
+/// * `gamepad_deadzone`: 摇杆和扳机的死区，范围 [0, 1)，摇杆回中不准导致相机漂移时调大。
+/// * `gamepad_sensitivity`: 右摇杆推到底时每秒转过的弧度。
+/// * `invert_y`: 反转鼠标和右摇杆的上下方向。
+/// * `smoothing`: 相机移动和转动的平滑时间常数（秒），0 表示立即响应。
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraConfig {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub gamepad_deadzone: f32,
+    pub gamepad_sensitivity: f32,
+    pub invert_y: bool,
+    pub smoothing: f32,
+}
+
+/// `BackgroundConfig` 是背景的颜色，都是线性空间中的 RGB。
+///
+/// Properties:
+///
+/// * `color`: 清屏颜色。
+/// * `gradient`: 设置时从上到下绘制 `[顶部, 底部]` 的渐变，覆盖清屏颜色。
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackgroundConfig {
+    pub color: [f32; 3],
+    pub gradient: Option<[[f32; 3]; 2]>,
+}
+
+impl BackgroundConfig {
+    /// 清屏颜色。
+    pub fn clear_color(&self) -> wgpu::Color {
+        rgb_to_color(self.color)
+    }
+
+    /// 渐变的顶部和底部颜色，没有设置时为 `None`。
+    pub fn gradient(&self) -> Option<(wgpu::Color, wgpu::Color)> {
+        self.gradient
+            .map(|[top, bottom]| (rgb_to_color(top), rgb_to_color(bottom)))
+    }
+}
+
+fn rgb_to_color([r, g, b]: [f32; 3]) -> wgpu::Color {
+    wgpu::Color {
+        r: r as f64,
+        g: g as f64,
+        b: b as f64,
+        a: 1.0,
+    }
+}
+
+/// `FloorConfig` 是自由移动相机时作为参照的地面网格，运行时可以用 H 键显示或隐藏。
+///
+/// Properties:
+///
+/// * `visible`: 开始时是否显示。
+/// * `height`: 地面的高度，不设置时放在边界的底面。
+/// * `spacing`: 相邻网格线的间距。
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FloorConfig {
+    pub visible: bool,
+    pub height: Option<f32>,
+    pub spacing: f32,
+}
+
+/// `LightingConfig` 是场景中的光源以及与单个光源无关的光照参数。
+///
+/// Properties:
+///
+/// * `ambient`: 环境光，线性空间中的 RGB，背光的一面也至少有这么亮。
+/// * `exposure`: 色调映射之前乘上的曝光，越大画面越亮。
+/// * `lights`: 场景中的光源，第一个光源投射阴影；为空时使用默认的一个白色光源。
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LightingConfig {
+    pub ambient: [f32; 3],
+    pub exposure: f32,
+    pub lights: Vec<LightConfig>,
+}
+
+/// `LightConfig` 是场景文件中的一个点光源。
+///
+/// Properties:
+///
+/// * `position`: 光源的位置，开启自动旋转时绕 Y 轴旋转。
+/// * `color`: 光源的颜色，线性空间中的 RGB。
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightConfig {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+}
+
+/// `Boundary` 是场景文件中的边界，可以写成一个数，也可以写成三个数。
+///
+/// Variants:
+///
+/// * `Cube`: 立方体的半边长。
+/// * `Box`: 长方体在 x、y、z 三个轴上的半边长。
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Boundary {
+    Cube(f32),
+    Box([f32; 3]),
+}
+
+impl Boundary {
+    /// 三个轴上的半边长。
+    pub fn half_extents(&self) -> glam::Vec3 {
+        match *self {
+            Boundary::Cube(half_extent) => glam::Vec3::splat(half_extent),
+            Boundary::Box(half_extents) => glam::Vec3::from_array(half_extents),
+        }
+    }
+}
+
+impl Default for SceneConfig {
+    fn default() -> Self {
+        Self {
+            points: 5000,
+            dimensions: compute::Dimensions::default(),
+            boundary: Boundary::Cube(compute::DEFAULT_BOUNDARY),
+            radius: 0.2,
+            radii: Vec::new(),
+            grid_size: None,
+            cell_indexing: compute::CellIndexing::default(),
+            broad_phase: compute::BroadPhase::default(),
+            gravity: compute::DEFAULT_GRAVITY,
+            restitution: compute::DEFAULT_RESTITUTION,
+            force_model: compute::ForceModel::default(),
+            stiffness: compute::DEFAULT_STIFFNESS,
+            equilibrium_distance: compute::DEFAULT_EQUILIBRIUM_DISTANCE,
+            drag: compute::DEFAULT_DRAG,
+            max_speed: compute::DEFAULT_MAX_SPEED,
+            substeps: compute::SIMULATION_ROUNDS,
+            max_move_fraction: compute::DEFAULT_MAX_MOVE_FRACTION,
+            time_scale: compute::DEFAULT_TIME_SCALE,
+            integrator: compute::Integrator::default(),
+            sleep_velocity: compute::DEFAULT_SLEEP_VELOCITY,
+            sleep_time: compute::DEFAULT_SLEEP_TIME,
+            workgroup_size: compute::DEFAULT_WORKGROUP_SIZE,
+            seed: SCENE_SEED,
+            lattice: None,
+            emitter: None,
+            relax_iterations: 0,
+            pause_on_unfocus: false,
+            aspect_ratio: None,
+            sample_count: 4,
+            model: DEFAULT_MODEL.to_string(),
+            camera: CameraConfig::default(),
+            background: BackgroundConfig::default(),
+            floor: FloorConfig::default(),
+            lighting: LightingConfig::default(),
+        }
+    }
+}
+
+impl Default for LatticeConfig {
+    fn default() -> Self {
+        Self {
+            spacing: 0.5,
+            counts: [10, 10, 10],
+            jitter: 0.0,
+            speed: 0.0,
+        }
+    }
+}
+
+impl Default for EmitterConfig {
+    fn default() -> Self {
+        Self {
+            max_points: 10000,
+            position: [0.0, 0.0, 0.0],
+            direction: [0.0, 1.0, 0.0],
+            spread: 15.0,
+            rate: 100.0,
+            speed: 5.0,
+            radius: None,
+        }
+    }
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            position: [0.0, 0.0, 15.0],
+            yaw: -90.0,
+            pitch: -20.0,
+            gamepad_deadzone: camera::DEFAULT_GAMEPAD_DEADZONE,
+            gamepad_sensitivity: camera::DEFAULT_GAMEPAD_SENSITIVITY,
+            invert_y: false,
+            smoothing: 0.0,
+        }
+    }
+}
+
+impl Default for BackgroundConfig {
+    fn default() -> Self {
+        Self {
+            color: [0.1, 0.2, 0.3],
+            gradient: None,
+        }
+    }
+}
+
+impl Default for FloorConfig {
+    fn default() -> Self {
+        Self {
+            visible: false,
+            height: None,
+            spacing: crate::floor::DEFAULT_FLOOR_SPACING,
+        }
+    }
+}
+
+impl Default for LightingConfig {
+    fn default() -> Self {
+        Self {
+            ambient: crate::light::DEFAULT_AMBIENT,
+            exposure: crate::light::DEFAULT_EXPOSURE,
+            lights: Vec::new(),
+        }
+    }
+}
+
+impl SceneConfig {
+    /// 从 TOML 文件中读取场景配置。
+    ///
+    /// Arguments:
+    ///
+    /// * `path`: 场景文件的路径。
+    ///
+    /// Returns:
+    ///
+    /// 文件无法读取或者内容无法解析时返回错误。
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("无法读取场景文件 {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("无法解析场景文件 {}", path.display()))
+    }
+
+    /// 当前目录下存在 `scene.toml` 时读取它，否则使用默认配置。
+    pub fn load_default() -> anyhow::Result<Self> {
+        if Path::new(DEFAULT_SCENE_FILE).exists() {
+            Self::load(DEFAULT_SCENE_FILE)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// 第 `i` 个小球的半径。
+    pub fn radius_of(&self, i: u32) -> f32 {
+        if self.radii.is_empty() {
+            self.radius
+        } else {
+            self.radii[i as usize % self.radii.len()]
+        }
+    }
+
+    /// 所有小球中最大的半径，包括发射器发射的小球，网格大小按它来确定。
+    pub fn max_radius(&self) -> f32 {
+        let max_radius = if self.radii.is_empty() {
+            self.radius
+        } else {
+            self.radii.iter().copied().fold(0.0, f32::max)
+        };
+        match self.emitter() {
+            Some(emitter) => max_radius.max(emitter.radius),
+            None => max_radius,
+        }
+    }
+
+    /// 实例 buffer 的容量：设置了发射器时是它的 `max_points`，否则就是 `points`。
+    pub fn capacity(&self) -> u32 {
+        match &self.emitter {
+            Some(emitter) => emitter.max_points,
+            None => self.points,
+        }
+    }
+
+    /// 场景中的发射器，没有设置时为 `None`；参数没有经过检查，见 `compute::Emitter::validated`。
+    pub fn emitter(&self) -> Option<compute::Emitter> {
+        self.emitter.as_ref().map(|emitter| compute::Emitter {
+            position: glam::Vec3::from_array(emitter.position),
+            direction: glam::Vec3::from_array(emitter.direction),
+            spread: emitter.spread.to_radians(),
+            rate: emitter.rate,
+            speed: emitter.speed,
+            radius: emitter.radius.unwrap_or(self.radius),
+        })
+    }
+
+    /// 碰撞检测的网格大小，没有设置时取能保证不漏掉碰撞的最小值。
+    pub fn grid_size(&self) -> f32 {
+        self.grid_size
+            .unwrap_or_else(|| compute::min_grid_size(self.max_radius()))
+    }
+
+    /// 检查参数组合是否合法，不合法的场景会产生错误的模拟结果。
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.points == 0 {
+            bail!("points 必须大于 0");
+        }
+        let boundary = self.boundary.half_extents();
+        if !(boundary.cmpgt(glam::Vec3::ZERO).all() && boundary.is_finite()) {
+            bail!("boundary 必须是正数，当前为 {}", boundary);
+        }
+        if !(self.radius > 0.0 && self.radius.is_finite()) {
+            bail!("radius 必须是正数，当前为 {}", self.radius);
+        }
+        if let Some(radius) = self
+            .radii
+            .iter()
+            .find(|radius| !(**radius > 0.0 && radius.is_finite()))
+        {
+            bail!("radii 中的半径必须是正数，当前为 {}", radius);
+        }
+        // 二维时小球都在 z = 0 的平面内，z 方向上的半边长只需要放得下一个小球
+        if self.max_radius() >= boundary.min_element() {
+            bail!(
+                "radius ({}) 必须小于 boundary 的每个半边长 ({})，否则小球放不进边界",
+                self.max_radius(),
+                boundary
+            );
+        }
+        if let Some(grid_size) = self.grid_size {
+            let min_grid_size = compute::min_grid_size(self.max_radius());
+            if !(grid_size >= min_grid_size && grid_size.is_finite()) {
+                bail!(
+                    "grid_size ({}) 不能小于最大直径 ({})，否则相隔两个网格的小球之间的碰撞会被漏掉",
+                    grid_size,
+                    min_grid_size
+                );
+            }
+        }
+        let grid_count = compute::grid_count(boundary, self.grid_size())
+            .into_iter()
+            .max()
+            .unwrap_or(1);
+        let max_grid_count = compute::max_morton_grid_count(self.dimensions);
+        if self.cell_indexing == compute::CellIndexing::Morton && grid_count > max_grid_count {
+            bail!(
+                "每个轴上有 {} 个网格，Morton 编号最多支持 {} 个；可以增大 grid_size 或者使用线性编号",
+                grid_count,
+                max_grid_count
+            );
+        }
+        if !(0.0..1.0).contains(&self.camera.gamepad_deadzone) {
+            bail!(
+                "camera.gamepad_deadzone 必须在 [0, 1) 之间，当前为 {}",
+                self.camera.gamepad_deadzone
+            );
+        }
+        let sensitivity = self.camera.gamepad_sensitivity;
+        if !(sensitivity >= 0.0 && sensitivity.is_finite()) {
+            bail!(
+                "camera.gamepad_sensitivity 必须是非负数，当前为 {}",
+                sensitivity
+            );
+        }
+        if !(self.camera.smoothing >= 0.0 && self.camera.smoothing.is_finite()) {
+            bail!(
+                "camera.smoothing 必须是非负数，当前为 {}",
+                self.camera.smoothing
+            );
+        }
+        let colors = std::iter::once(&self.background.color)
+            .chain(self.background.gradient.iter().flatten());
+        for color in colors {
+            if !color.iter().all(|c| (0.0..=1.0).contains(c)) {
+                bail!(
+                    "background 中的颜色分量必须在 [0, 1] 之间，当前为 {:?}",
+                    color
+                );
+            }
+        }
+        if !(self.floor.spacing > 0.0 && self.floor.spacing.is_finite()) {
+            bail!("floor.spacing 必须是正数，当前为 {}", self.floor.spacing);
+        }
+        if let Some(height) = self.floor.height.filter(|height| !height.is_finite()) {
+            bail!("floor.height 必须是有限的数，当前为 {}", height);
+        }
+        if let Some(aspect_ratio) = self
+            .aspect_ratio
+            .filter(|aspect_ratio| !(*aspect_ratio > 0.0 && aspect_ratio.is_finite()))
+        {
+            bail!("aspect_ratio 必须是正数，当前为 {}", aspect_ratio);
+        }
+        if !matches!(self.sample_count, 1 | 2 | 4 | 8) {
+            bail!(
+                "sample_count 只能是 1、2、4 或 8，当前为 {}",
+                self.sample_count
+            );
+        }
+        if self.model.is_empty() {
+            bail!("model 不能为空");
+        }
+        let ambient = self.lighting.ambient;
+        if !ambient.iter().all(|c| *c >= 0.0 && c.is_finite()) {
+            bail!("lighting.ambient 的分量必须是非负数，当前为 {:?}", ambient);
+        }
+        let exposure = self.lighting.exposure;
+        if !(exposure > 0.0 && exposure.is_finite()) {
+            bail!("lighting.exposure 必须是正数，当前为 {}", exposure);
+        }
+        if self.lighting.lights.len() > crate::light::LightState::MAX_LIGHTS {
+            bail!(
+                "lighting.lights 最多只能有 {} 个光源，当前为 {}",
+                crate::light::LightState::MAX_LIGHTS,
+                self.lighting.lights.len()
+            );
+        }
+        for light in &self.lighting.lights {
+            if !light.position.iter().all(|c| c.is_finite()) {
+                bail!(
+                    "lighting.lights 的位置必须是有限的数，当前为 {:?}",
+                    light.position
+                );
+            }
+            if !light.color.iter().all(|c| *c >= 0.0 && c.is_finite()) {
+                bail!(
+                    "lighting.lights 的颜色分量必须是非负数，当前为 {:?}",
+                    light.color
+                );
+            }
+        }
+        if !self.gravity.is_finite() {
+            bail!("gravity 必须是有限的数，当前为 {}", self.gravity);
+        }
+        if !(0.0..=1.0).contains(&self.restitution) {
+            bail!(
+                "restitution 必须在 [0, 1] 之间，当前为 {}",
+                self.restitution
+            );
+        }
+        if let Some(lattice) = &self.lattice {
+            let count = lattice.counts.iter().map(|&n| n as u64).product::<u64>();
+            if count != self.points as u64 {
+                bail!(
+                    "lattice.counts {:?} 一共有 {} 个格点，必须等于 points ({})",
+                    lattice.counts,
+                    count,
+                    self.points
+                );
+            }
+            if self.dimensions == compute::Dimensions::D2 && lattice.counts[2] != 1 {
+                bail!(
+                    "二维场景的 lattice.counts 在 z 方向上只能有一层，当前为 {:?}",
+                    lattice.counts
+                );
+            }
+            if !(lattice.speed >= 0.0 && lattice.speed.is_finite()) {
+                bail!("lattice.speed 必须是非负数，当前为 {}", lattice.speed);
+            }
+            compute::check_lattice(
+                lattice.spacing,
+                lattice.counts,
+                lattice.jitter,
+                self.max_radius(),
+                boundary,
+            )
+            .context("lattice 不合法")?;
+        }
+        if let Some(emitter) = self.emitter() {
+            if self.capacity() < self.points {
+                bail!(
+                    "emitter.max_points ({}) 不能小于 points ({})",
+                    self.capacity(),
+                    self.points
+                );
+            }
+            emitter
+                .validated(self.grid_size())
+                .context("emitter 不合法")?;
+        }
+        if !(self.stiffness > 0.0 && self.stiffness.is_finite()) {
+            bail!("stiffness 必须是正数，当前为 {}", self.stiffness);
+        }
+        if !(self.equilibrium_distance >= 0.0 && self.equilibrium_distance.is_finite()) {
+            bail!(
+                "equilibrium_distance 必须是非负数，当前为 {}",
+                self.equilibrium_distance
+            );
+        }
+        if self.force_model != compute::ForceModel::Contact {
+            let equilibrium_distance = if self.equilibrium_distance > 0.0 {
+                self.equilibrium_distance
+            } else {
+                2.0 * self.max_radius()
+            };
+            if equilibrium_distance >= self.grid_size() {
+                bail!(
+                    "平衡距离 ({}) 必须小于 grid_size ({})，否则吸引力在相邻格子之外才起作用；可以增大 grid_size",
+                    equilibrium_distance,
+                    self.grid_size()
+                );
+            }
+        }
+        if !(self.drag >= 0.0 && self.drag.is_finite()) {
+            bail!("drag 必须是非负数，当前为 {}", self.drag);
+        }
+        if !(self.max_speed > 0.0 && self.max_speed.is_finite()) {
+            bail!("max_speed 必须是正数，当前为 {}", self.max_speed);
+        }
+        if self.substeps == 0 {
+            bail!("substeps 必须大于 0");
+        }
+        if !(self.max_move_fraction >= 0.0 && self.max_move_fraction.is_finite()) {
+            bail!(
+                "max_move_fraction 必须是非负数，当前为 {}",
+                self.max_move_fraction
+            );
+        }
+        if !(0.0..=compute::MAX_TIME_SCALE).contains(&self.time_scale) {
+            bail!(
+                "time_scale 必须在 [0, {}] 之间，当前为 {}",
+                compute::MAX_TIME_SCALE,
+                self.time_scale
+            );
+        }
+        if !(self.sleep_velocity >= 0.0 && self.sleep_velocity.is_finite()) {
+            bail!(
+                "sleep_velocity 必须是非负数，当前为 {}",
+                self.sleep_velocity
+            );
+        }
+        if self.workgroup_size == 0 {
+            bail!("workgroup_size 必须大于 0");
+        }
+        if !(self.sleep_time >= 0.0 && self.sleep_time.is_finite()) {
+            bail!("sleep_time 必须是非负数，当前为 {}", self.sleep_time);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brute_force_broad_phase_parses_and_cycles() {
+        let config: SceneConfig = toml::from_str(r#"broad_phase = "brute_force""#).unwrap();
+        assert_eq!(config.broad_phase, compute::BroadPhase::BruteForce);
+        config.validate().unwrap();
+
+        // X 键依次经过所有的宽相位，再回到网格
+        let mut broad_phase = compute::BroadPhase::Grid;
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            broad_phase = broad_phase.next();
+            seen.push(broad_phase);
+        }
+        assert_eq!(
+            seen,
+            [
+                compute::BroadPhase::Bvh,
+                compute::BroadPhase::BruteForce,
+                compute::BroadPhase::Grid
+            ]
+        );
+    }
+
+    #[test]
+    fn grid_size_must_cover_the_largest_diameter() {
+        let mut config = SceneConfig {
+            radii: vec![0.1, 0.3],
+            ..Default::default()
+        };
+        // 没有设置时取最大直径
+        assert_eq!(config.grid_size(), 0.6);
+        config.validate().unwrap();
+
+        config.grid_size = Some(0.5);
+        assert!(config.validate().is_err());
+        config.grid_size = Some(f32::INFINITY);
+        assert!(config.validate().is_err());
+        config.grid_size = Some(0.6);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn emitter_raises_the_capacity_and_is_validated() {
+        let mut config: SceneConfig = toml::from_str(
+            r#"
+            points = 100
+            radius = 0.2
+            [emitter]
+            max_points = 500
+            direction = [0.0, 2.0, 0.0]
+            spread = 90.0
+            radius = 0.3
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.capacity(), 500);
+        // 网格大小默认也要放得下发射的小球
+        assert_eq!(config.grid_size(), 0.6);
+        let emitter = config.emitter().unwrap();
+        assert!((emitter.spread - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+
+        config.emitter.as_mut().unwrap().max_points = 50;
+        assert!(config.validate().is_err());
+        config.emitter.as_mut().unwrap().max_points = 500;
+        config.emitter.as_mut().unwrap().direction = [0.0; 3];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn sample_count_defaults_to_4x_msaa_and_is_validated() {
+        let mut config = SceneConfig::default();
+        assert_eq!(config.sample_count, 4);
+        for sample_count in [1, 2, 8] {
+            config.sample_count = sample_count;
+            config.validate().unwrap();
+        }
+        config.sample_count = 3;
+        assert!(config.validate().is_err());
+        config.sample_count = 0;
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,516 @@
+use glam::DVec3;
+
+use crate::compute::{
+    self, ComputeInstance, ComputeState, Dimensions, ForceModel, Integrator, HEAT_DECAY_TIME,
+};
+
+// 着色器中的这些常量由 compute.rs 中的同一份定义生成
+const AR: f64 = compute::AR as f64;
+const WAKE_FACTOR: f64 = compute::WAKE_FACTOR as f64;
+const LJ_MIN_DISTANCE_FACTOR: f64 = compute::LJ_MIN_DISTANCE_FACTOR as f64;
+
+// 一个小球在 CPU 上的状态，合并了 GPU 上的 Instance 和按 id 存放的 Result
+#[derive(Debug, Copy, Clone)]
+struct Ball {
+    radius: f64,
+    position: DVec3,
+    velocity: DVec3,
+    // 上一轮的加速度，Verlet 积分用到
+    acceleration: DVec3,
+    // 速度连续低于休眠速度的时间
+    still_time: f64,
+    // 按 HEAT_DECAY_TIME 衰减的接触次数
+    collision_heat: f64,
+}
+
+// 一次 step 中不变的模拟参数，从 ComputeState 读出之后转成 f64
+struct Params {
+    boundary: DVec3,
+    time_step: f64,
+    grid_size: f64,
+    gravity: DVec3,
+    restitution: f64,
+    integrator: Integrator,
+    sleep_velocity: f64,
+    sleep_time: f64,
+    drag: f64,
+    dimensions: Dimensions,
+    force_model: ForceModel,
+    stiffness: f64,
+    equilibrium_distance: f64,
+    max_speed: f64,
+    // (point, normal, is_kill)
+    planes: Vec<(DVec3, DVec3, bool)>,
+}
+
+impl Params {
+    fn new(state: &ComputeState, time_step: f64) -> Self {
+        Self {
+            boundary: state.boundary().as_dvec3(),
+            time_step,
+            grid_size: state.grid_size() as f64,
+            gravity: state.gravity_vector().as_dvec3(),
+            restitution: state.restitution as f64,
+            integrator: state.integrator,
+            sleep_velocity: state.sleep_velocity as f64,
+            sleep_time: state.sleep_time as f64,
+            drag: state.drag() as f64,
+            dimensions: state.dimensions(),
+            force_model: state.force_model,
+            stiffness: state.stiffness as f64,
+            equilibrium_distance: state.equilibrium_distance as f64,
+            max_speed: state.max_speed() as f64,
+            planes: state
+                .planes()
+                .iter()
+                .map(|plane| {
+                    (
+                        glam::Vec3::from_array(plane.point).as_dvec3(),
+                        glam::Vec3::from_array(plane.normal).as_dvec3(),
+                        plane.is_kill(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    // 与 collision.wgsl 中的 pair_force 相同，rel_pos 从另一个小球指向这个小球
+    fn pair_force(&self, rel_pos: DVec3, distance: f64, radius_sum: f64) -> DVec3 {
+        if distance <= 0.0 {
+            return DVec3::ZERO;
+        }
+        let normal = rel_pos / distance;
+        if self.force_model == ForceModel::Contact {
+            return self.stiffness * (radius_sum - distance).max(0.0) * normal;
+        }
+
+        if distance >= self.grid_size {
+            return DVec3::ZERO;
+        }
+        let r0 = if self.equilibrium_distance <= 0.0 {
+            radius_sum
+        } else {
+            self.equilibrium_distance
+        };
+        if self.force_model == ForceModel::Spring {
+            return self.stiffness * (r0 - distance) * normal;
+        }
+
+        let epsilon = self.stiffness * r0 * r0 / 72.0;
+        let r = distance.max(LJ_MIN_DISTANCE_FACTOR * r0);
+        let s2 = (r0 / r) * (r0 / r);
+        let s6 = s2 * s2 * s2;
+        12.0 * epsilon / r * (s6 * s6 - s6) * normal
+    }
+
+    fn clamp_speed(&self, velocity: DVec3) -> DVec3 {
+        let speed = velocity.length();
+        if speed > self.max_speed {
+            velocity * (self.max_speed / speed)
+        } else {
+            velocity
+        }
+    }
+}
+
+/// `CpuSolver` 是碰撞着色器在 CPU 上的参考实现，位置、速度和力的累加全部使用 f64。
+///
+/// GPU 上的结果在不同厂商之间并不完全一致：力按线程调度的顺序累加，各家的 `length`、除法和 FMA
+/// 的精度也不同，这些误差在碰撞中会被迅速放大。`CpuSolver` 单线程地按实例顺序暴力遍历所有小球对，
+/// 只使用 IEEE 754 规定了正确舍入的运算（加减乘除和开方），所以同样的输入在任何平台上都得到逐位相同的结果，
+/// 适合作为回归测试的基准。代价是复杂度为 O(n^2)，只适合几千个以内的小球。
+///
+/// 它和 GPU 的差异：
+///
+/// * 被碰到的休眠小球在这一轮结束之后才被唤醒，GPU 上取决于线程的先后顺序。
+/// * 不处理发射器和静态网格，静态平面和吸收平面照常处理。
+/// * Verlet 积分的上一步加速度只保存在这里，从 GPU 切换过来时从 0 开始。
+/// * `collision_heat` 的衰减用到 `exp`，各平台的结果可能差一个最低位，它只用于着色，不影响位置和速度。
+///
+/// Properties:
+///
+/// * `balls`: 每个存活的小球的 f64 状态，顺序与 `ComputeState::instances` 一致。
+pub struct CpuSolver {
+    balls: Vec<Ball>,
+}
+
+impl CpuSolver {
+    /// 从当前的实例开始，f32 的位置和速度被精确地转成 f64。
+    pub fn new(instances: &[ComputeInstance]) -> Self {
+        let balls = instances
+            .iter()
+            .map(|instance| Ball {
+                radius: instance.radius as f64,
+                position: instance.position.as_dvec3(),
+                velocity: instance.velocity.as_dvec3(),
+                acceleration: DVec3::ZERO,
+                still_time: 0.0,
+                collision_heat: instance.collision_heat as f64,
+            })
+            .collect();
+        Self { balls }
+    }
+
+    /// 把模拟推进 `dt`，时间缩放和轮数与 [`ComputeState::update`] 相同，但不使用 GPU。
+    ///
+    /// 结果舍入成 f32 写回 `state.instances`，越过吸收平面的小球被移除，休眠数量同样更新。
+    ///
+    /// Arguments:
+    ///
+    /// * `state`: 提供模拟参数，接收结果；它的实例必须和创建时一一对应，不能在外部增删。
+    /// * `dt`: 推进的时间。
+    pub fn step(&mut self, state: &mut ComputeState, dt: std::time::Duration) {
+        if state.time_scale() == 0.0 {
+            return;
+        }
+        let dt = dt.mul_f32(state.time_scale());
+        let rounds = state.begin_rounds(dt);
+        let params = Params::new(state, dt.as_secs_f64() / rounds as f64);
+        for _ in 0..rounds {
+            self.round(&params, &mut state.instances);
+        }
+
+        for (instance, ball) in state.instances.iter_mut().zip(&self.balls) {
+            instance.position = ball.position.as_vec3();
+            instance.velocity = ball.velocity.as_vec3();
+            instance.collision_heat = ball.collision_heat as f32;
+        }
+        state.mark_synced();
+        state.sleeping = if params.sleep_velocity > 0.0 {
+            self.balls
+                .iter()
+                .filter(|ball| ball.still_time >= params.sleep_time)
+                .count()
+        } else {
+            0
+        };
+    }
+
+    /// 每个存活的小球的 f64 位置，顺序与 `ComputeState::instances` 一致。
+    pub fn positions(&self) -> Vec<DVec3> {
+        self.balls.iter().map(|ball| ball.position).collect()
+    }
+
+    /// 每个存活的小球的 f64 速度，顺序与 `ComputeState::instances` 一致。
+    pub fn velocities(&self) -> Vec<DVec3> {
+        self.balls.iter().map(|ball| ball.velocity).collect()
+    }
+
+    // 一轮碰撞检测，对应一次 collision.wgsl 的调度；所有小球都读这一轮开始时的状态
+    fn round(&mut self, params: &Params, instances: &mut Vec<ComputeInstance>) {
+        let previous = self.balls.clone();
+        let mut woken = vec![false; previous.len()];
+        let mut dead = vec![false; previous.len()];
+        let time_step = params.time_step;
+        let heat_decay = (-time_step / HEAT_DECAY_TIME as f64).exp();
+
+        for (my_idx, ball) in self.balls.iter_mut().enumerate() {
+            let me = previous[my_idx];
+
+            if params.sleep_velocity > 0.0 && me.still_time >= params.sleep_time {
+                ball.velocity = DVec3::ZERO;
+                ball.acceleration = DVec3::ZERO;
+                ball.collision_heat = me.collision_heat * heat_decay;
+                continue;
+            }
+            let wakes_neighbors = params.sleep_velocity > 0.0
+                && me.velocity.length() > WAKE_FACTOR * params.sleep_velocity;
+
+            let mut total_force = DVec3::ZERO;
+            let mut contact_count = 0;
+            for (other_idx, other) in previous.iter().enumerate() {
+                if other_idx == my_idx {
+                    continue;
+                }
+                let rel_pos = me.position - other.position;
+                let distance = rel_pos.length();
+                let radius_sum = me.radius + other.radius;
+                if distance < radius_sum {
+                    contact_count += 1;
+                    if wakes_neighbors {
+                        woken[other_idx] = true;
+                    }
+                }
+                total_force += params.pair_force(rel_pos, distance, radius_sum);
+            }
+
+            let acceleration = total_force + params.gravity;
+            let mut velocity = match params.integrator {
+                Integrator::Euler => me.velocity + acceleration * time_step,
+                Integrator::Verlet => {
+                    me.velocity + (me.acceleration + acceleration) * 0.5 * time_step
+                }
+            };
+            velocity = params.clamp_speed(velocity);
+
+            // 和边界的碰撞，速度朝向边界外时反向
+            for axis in 0..3 {
+                if me.position[axis] + me.radius - params.boundary[axis] > 0.0 {
+                    velocity[axis] = -velocity[axis].abs();
+                }
+                if me.position[axis] - me.radius + params.boundary[axis] < 0.0 {
+                    velocity[axis] = velocity[axis].abs();
+                }
+            }
+
+            let start_velocity = match params.integrator {
+                Integrator::Euler => me.velocity,
+                Integrator::Verlet => velocity,
+            };
+            let mut position = me.position
+                + start_velocity * time_step
+                + acceleration * time_step * time_step * 0.5;
+
+            for &(point, normal, is_kill) in &params.planes {
+                if is_kill {
+                    if (position - point).dot(normal) < 0.0 {
+                        dead[my_idx] = true;
+                        break;
+                    }
+                    continue;
+                }
+                let penetration = me.radius - (position - point).dot(normal);
+                if penetration > 0.0 {
+                    position += penetration * normal;
+                    let v_n = velocity.dot(normal);
+                    if v_n < 0.0 {
+                        velocity -= (1.0 + params.restitution) * v_n * normal;
+                    }
+                }
+            }
+            if dead[my_idx] {
+                continue;
+            }
+
+            velocity *= (1.0 - params.drag * time_step).max(0.0);
+
+            if params.dimensions == Dimensions::D2 {
+                position.z = 0.0;
+                velocity.z = 0.0;
+            }
+
+            let v_len = velocity.length();
+            velocity =
+                params.clamp_speed(velocity * (1.0 - AR * v_len * v_len * v_len * time_step));
+            ball.still_time = if velocity.length() < params.sleep_velocity {
+                me.still_time + time_step
+            } else {
+                0.0
+            };
+            ball.position = position;
+            ball.velocity = velocity;
+            ball.acceleration = acceleration;
+            ball.collision_heat = me.collision_heat * heat_decay + contact_count as f64 * time_step;
+        }
+
+        for (ball, woken) in self.balls.iter_mut().zip(woken) {
+            if woken {
+                ball.still_time = 0.0;
+            }
+        }
+        if dead.contains(&true) {
+            let mut dead_balls = dead.iter();
+            self.balls.retain(|_| !dead_balls.next().unwrap());
+            let mut dead_instances = dead.iter();
+            instances.retain(|_| !dead_instances.next().unwrap());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 没有重力、阻力、休眠和速度上限，边界足够远的参数，各个测试只修改自己关心的部分
+    fn test_params(time_step: f64) -> Params {
+        Params {
+            boundary: DVec3::splat(100.0),
+            time_step,
+            grid_size: 2.0,
+            gravity: DVec3::ZERO,
+            restitution: 1.0,
+            integrator: Integrator::Euler,
+            sleep_velocity: 0.0,
+            sleep_time: 0.0,
+            drag: 0.0,
+            dimensions: Dimensions::D3,
+            force_model: ForceModel::Contact,
+            stiffness: 100.0,
+            equilibrium_distance: 0.0,
+            max_speed: f64::MAX,
+            planes: Vec::new(),
+        }
+    }
+
+    fn test_instance(id: u32, position: glam::Vec3, radius: f32) -> ComputeInstance {
+        ComputeInstance {
+            id,
+            position,
+            radius,
+            velocity: glam::Vec3::ZERO,
+            color: glam::Vec4::ONE,
+            collision_heat: 0.0,
+        }
+    }
+
+    // 在 x 轴上相距 distance、半径都是 0.5 的两个静止小球
+    fn test_pair(distance: f32) -> Vec<ComputeInstance> {
+        vec![
+            test_instance(0, glam::Vec3::new(-distance / 2.0, 0.0, 0.0), 0.5),
+            test_instance(1, glam::Vec3::new(distance / 2.0, 0.0, 0.0), 0.5),
+        ]
+    }
+
+    fn run(params: &Params, instances: &mut Vec<ComputeInstance>, rounds: usize) -> CpuSolver {
+        let mut solver = CpuSolver::new(instances);
+        for _ in 0..rounds {
+            solver.round(params, instances);
+        }
+        solver
+    }
+
+    // 两个小球之间弹簧的总能量，势能是 stiffness * (distance - r0)^2 / 2
+    fn spring_energy(solver: &CpuSolver, params: &Params) -> f64 {
+        let [a, b] = [solver.balls[0], solver.balls[1]];
+        let stretch = (a.position - b.position).length() - (a.radius + b.radius);
+        let kinetic = (a.velocity.length_squared() + b.velocity.length_squared()) / 2.0;
+        kinetic + params.stiffness * stretch * stretch / 2.0
+    }
+
+    #[test]
+    fn verlet_keeps_spring_energy_where_euler_gains_it() {
+        let mut params = test_params(5e-3);
+        params.force_model = ForceModel::Spring;
+        let initial = spring_energy(&CpuSolver::new(&test_pair(1.2)), &params);
+
+        // 大约 4.5 个周期，Euler 每一步都放大能量，Verlet 只因为 AR 的阻尼损失几个百分点
+        let euler = run(&params, &mut test_pair(1.2), 400);
+        assert!(spring_energy(&euler, &params) > 1.5 * initial);
+
+        params.integrator = Integrator::Verlet;
+        let verlet = run(&params, &mut test_pair(1.2), 400);
+        let drift = (spring_energy(&verlet, &params) - initial).abs() / initial;
+        assert!(drift < 0.1, "energy drifted by {drift}");
+    }
+
+    #[test]
+    fn drag_limits_falling_speed_to_terminal_velocity() {
+        let mut params = test_params(1e-3);
+        params.gravity = DVec3::new(0.0, -10.0, 0.0);
+        params.drag = 5.0;
+
+        // 终端速度是 gravity / drag，AR 和离散化让它略小一点
+        let mut instances = vec![test_instance(0, glam::Vec3::ZERO, 0.5)];
+        let solver = run(&params, &mut instances, 3000);
+        let velocity = solver.velocities()[0];
+        assert!((velocity.y + 2.0).abs() < 0.1, "velocity {velocity}");
+        assert_eq!(velocity.x, 0.0);
+    }
+
+    #[test]
+    fn lennard_jones_changes_sign_at_the_equilibrium_distance() {
+        let mut params = test_params(1e-3);
+        params.force_model = ForceModel::LennardJones;
+        params.equilibrium_distance = 1.0;
+        let force = |distance: f64| params.pair_force(DVec3::X * distance, distance, 0.5).x;
+
+        assert!(force(1.0).abs() < 1e-9);
+        assert!(force(0.9) > 0.0);
+        assert!(force(1.2) < 0.0);
+        // 超过一个格子的小球对之间没有力
+        assert_eq!(force(2.5), 0.0);
+    }
+
+    #[test]
+    fn damped_spring_pair_settles_at_the_equilibrium_distance() {
+        let mut params = test_params(1e-3);
+        params.force_model = ForceModel::Spring;
+        params.equilibrium_distance = 1.5;
+        params.drag = 5.0;
+
+        let solver = run(&params, &mut test_pair(1.2), 5000);
+        let positions = solver.positions();
+        let distance = (positions[1] - positions[0]).length();
+        assert!((distance - 1.5).abs() < 1e-3, "distance {distance}");
+    }
+
+    #[test]
+    fn speed_never_exceeds_the_maximum() {
+        let mut params = test_params(1e-3);
+        params.gravity = DVec3::new(0.0, -100.0, 0.0);
+        params.stiffness = 1e5;
+        params.max_speed = 3.0;
+
+        // 重力和深度重叠产生的斥力都远远超过速度上限
+        let mut instances = test_pair(0.5);
+        let mut solver = CpuSolver::new(&instances);
+        for _ in 0..1000 {
+            solver.round(&params, &mut instances);
+            for velocity in solver.velocities() {
+                assert!(velocity.length() <= 3.0 + 1e-9, "velocity {velocity}");
+            }
+        }
+
+        let clamped = params.clamp_speed(DVec3::new(3.0, 4.0, 0.0));
+        assert!((clamped - DVec3::new(1.8, 2.4, 0.0)).length() < 1e-12);
+    }
+
+    #[test]
+    #[ignore = "需要 GPU 适配器"]
+    fn gpu_and_cpu_solvers_agree() {
+        let (device, queue) = compute::test_device();
+        let base = compute::ComputeStateBuilder::new(2, 2.0)
+            .boundary(glam::Vec3::splat(10.0))
+            .gravity(0.0)
+            .drag(0.0);
+        let cases = [
+            (
+                "verlet spring",
+                base.integrator(Integrator::Verlet)
+                    .force_model(ForceModel::Spring)
+                    .stiffness(100.0)
+                    .equilibrium_distance(1.5),
+            ),
+            ("drag and gravity", base.gravity(10.0).drag(5.0)),
+            (
+                "lennard-jones",
+                base.force_model(ForceModel::LennardJones)
+                    .stiffness(100.0)
+                    .equilibrium_distance(1.0),
+            ),
+            (
+                "speed clamp",
+                base.gravity(100.0).stiffness(1e4).max_speed(3.0),
+            ),
+        ];
+
+        for (name, builder) in cases {
+            let simulate = |mode| {
+                let mut world =
+                    crate::CollisionWorld::new(&device, &queue, &builder, test_pair(0.9)).unwrap();
+                world.set_step_mode(mode);
+                for _ in 0..30 {
+                    world.step(&device, &queue, std::time::Duration::from_micros(16_667));
+                }
+                world.instances().to_vec()
+            };
+            let gpu = simulate(crate::StepMode::Gpu);
+            let cpu = simulate(crate::StepMode::CpuF64);
+
+            // 两边的算法相同，只有 f32 与 f64 的舍入不同
+            assert_eq!(gpu.len(), cpu.len(), "{name}");
+            for (a, b) in gpu.iter().zip(&cpu) {
+                let position_error = a.position.distance(b.position);
+                assert!(
+                    position_error < 1e-3,
+                    "{name}: positions differ by {position_error}"
+                );
+                let velocity_error = a.velocity.distance(b.velocity);
+                assert!(
+                    velocity_error < 1e-2,
+                    "{name}: velocities differ by {velocity_error}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use app_surface::AppSurface;
+
+use crate::{shaders, texture};
+
+/// `DepthResolveState` 把主渲染通道的多重采样深度解析成可以读回的单采样深度。
+///
+/// 多重采样的纹理不能复制到 buffer 中，wgpu 也不支持解析深度附件，
+/// 所以用一个全屏三角形逐像素取第 0 个采样，写入自己持有的单采样深度纹理。
+///
+/// Properties:
+///
+/// * `bind_group_layout`: 读取多重采样深度的绑定组布局。
+/// * `pipeline_layout`: 渲染管线的布局，重新加载着色器时复用。
+/// * `pipeline`: 只写深度的全屏三角形管线。
+/// * `depth`: 解析之后的单采样深度纹理，第一次解析时创建，大小改变时重建。
+pub struct DepthResolveState {
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    depth: Option<texture::Texture>,
+}
+
+impl DepthResolveState {
+    /// 创建解析深度的渲染管线，单采样深度纹理等到第一次解析时再创建。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备。
+    ///
+    /// Returns:
+    ///
+    /// `DepthResolveState` 的一个实例。
+    pub fn new(app: &AppSurface) -> Self {
+        let bind_group_layout =
+            app.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: true,
+                            sample_type: wgpu::TextureSampleType::Depth,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    }],
+                    label: Some("depth_resolve_bind_group_layout"),
+                });
+        let pipeline_layout = app
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Depth Resolve Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = Self::create_pipeline(app, &pipeline_layout);
+
+        Self {
+            bind_group_layout,
+            pipeline_layout,
+            pipeline,
+            depth: None,
+        }
+    }
+
+    fn create_pipeline(app: &AppSurface, layout: &wgpu::PipelineLayout) -> wgpu::RenderPipeline {
+        let shader = app
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Depth Resolve Shader"),
+                source: wgpu::ShaderSource::Wgsl(shaders::shader_source!("depth_resolve.wgsl")),
+            });
+        app.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Depth Resolve Pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                // 每个像素都原样覆盖，不做深度比较
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+    }
+
+    /// 重新读取着色器并重建渲染管线，着色器有错误时保留原来的管线。
+    pub fn reload_shader(&mut self, app: &AppSurface) {
+        if let Some(pipeline) = shaders::try_rebuild(&app.device, "depth_resolve.wgsl", || {
+            Self::create_pipeline(app, &self.pipeline_layout)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    /// 返回与 `source` 内容相同、可以用 [`texture::Texture::read_depth_at`] 读回的单采样深度纹理。
+    ///
+    /// `source` 本身是单采样时直接返回它；否则立即提交一次解析，结果写入自己持有的深度纹理。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和队列。
+    /// * `source`: 主渲染通道的深度纹理，大小与表面一致。
+    pub fn resolve<'a>(
+        &'a mut self,
+        app: &AppSurface,
+        source: &'a texture::Texture,
+    ) -> &'a texture::Texture {
+        if source.texture.sample_count() == 1 {
+            return source;
+        }
+        let stale = self.depth.as_ref().map_or(true, |depth| {
+            depth.texture.width() != source.texture.width()
+                || depth.texture.height() != source.texture.height()
+        });
+        if stale {
+            self.depth = Some(texture::Texture::create_depth_texture(
+                &app.device,
+                &app.config,
+                "resolved_depth_texture",
+                1,
+            ));
+        }
+        let depth = self.depth.as_ref().expect("created above");
+
+        let bind_group = app.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&source.view),
+            }],
+            label: Some("depth_resolve_bind_group"),
+        });
+        let mut encoder = app
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Depth Resolve Encoder"),
+            });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Depth Resolve Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                ..Default::default()
+            });
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        app.queue.submit(std::iter::once(encoder.finish()));
+        depth
+    }
+}
@@ -0,0 +1,208 @@
+use app_surface::AppSurface;
+use wgpu::util::DeviceExt;
+
+use crate::{shaders, texture};
+
+// 相邻网格线的默认间距
+pub const DEFAULT_FLOOR_SPACING: f32 = 1.0;
+
+// 网格完全消失的水平距离，与相机的远平面相当
+const FADE_DISTANCE: f32 = 80.0;
+
+/// 地面着色器使用的 uniform，与 floor.wgsl 中的 `Floor` 结构体布局一致。
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FloorUniform {
+    height: f32,
+    spacing: f32,
+    fade_distance: f32,
+    _padding: f32,
+}
+
+/// `FloorState` 在水平面上画一张跟随相机的网格，自由移动相机时作为参照，随距离淡出。
+///
+/// 网格做深度测试但不写入深度，放在不透明的物体之后绘制，会被落在地面上的小球遮挡。
+///
+/// Properties:
+///
+/// * `uniform_buffer`: 存放高度和间距的缓冲区。
+/// * `bind_group`: `uniform_buffer` 的绑定组，位于 group 1。
+/// * `pipeline_layout`: 渲染管线的布局，重新加载着色器时复用。
+/// * `pipeline`: 带透明混合的渲染管线。
+/// * `visible`: 是否绘制地面。
+pub struct FloorState {
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    pub visible: bool,
+}
+
+impl FloorState {
+    /// 创建地面网格的渲染管线。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和表面格式。
+    /// * `camera_bind_group_layout`: 相机的绑定组布局，位于 group 0。
+    /// * `height`: 地面的高度，通常是边界的底面 `-boundary.y`。
+    /// * `spacing`: 相邻网格线的间距。
+    /// * `sample_count`: MSAA 采样数。
+    ///
+    /// Returns:
+    ///
+    /// `FloorState` 的一个实例。
+    pub fn new(
+        app: &AppSurface,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        height: f32,
+        spacing: f32,
+        sample_count: u32,
+    ) -> Self {
+        let uniform_buffer = app
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Floor Uniform Buffer"),
+                contents: bytemuck::bytes_of(&FloorUniform {
+                    height,
+                    spacing,
+                    fade_distance: FADE_DISTANCE,
+                    _padding: 0.0,
+                }),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+        let bind_group_layout =
+            app.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    }],
+                    label: Some("floor_bind_group_layout"),
+                });
+        let bind_group = app.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("floor_bind_group"),
+        });
+        let pipeline_layout = app
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Floor Pipeline Layout"),
+                bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = Self::create_pipeline(app, &pipeline_layout, sample_count);
+
+        Self {
+            uniform_buffer,
+            bind_group,
+            pipeline_layout,
+            pipeline,
+            visible: false,
+        }
+    }
+
+    fn create_pipeline(
+        app: &AppSurface,
+        layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let shader = app
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Floor Shader"),
+                source: wgpu::ShaderSource::Wgsl(shaders::shader_source!("floor.wgsl")),
+            });
+        app.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Floor Pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: app.config.format.add_srgb_suffix(),
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                // 从地面下方也能看到网格
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+            })
+    }
+
+    /// 重新读取着色器并重建渲染管线，着色器有错误时保留原来的管线。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和表面格式。
+    /// * `sample_count`: MSAA 采样数，需要和当前的渲染附件一致，采样数改变之后用新的值重建。
+    pub fn reload_shader(&mut self, app: &AppSurface, sample_count: u32) {
+        if let Some(pipeline) = shaders::try_rebuild(&app.device, "floor.wgsl", || {
+            Self::create_pipeline(app, &self.pipeline_layout, sample_count)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    /// 修改地面的高度和网格线的间距。
+    pub fn set_floor(&self, app: &AppSurface, height: f32, spacing: f32) {
+        app.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&FloorUniform {
+                height,
+                spacing,
+                fade_distance: FADE_DISTANCE,
+                _padding: 0.0,
+            }),
+        );
+    }
+
+    /// 在给定的渲染通道中绘制地面网格。
+    ///
+    /// Arguments:
+    ///
+    /// * `render_pass`: 当前的渲染通道。
+    /// * `camera_bind_group`: 相机的绑定组。
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if !self.visible {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+}
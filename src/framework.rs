@@ -1,4 +1,7 @@
 use super::State;
+use crate::config::SceneConfig;
+use crate::record::RecordConfig;
+use crate::requirements;
 use winit::{
     dpi::PhysicalSize,
     event::*,
@@ -6,15 +9,52 @@ use winit::{
     window::WindowBuilder,
 };
 
-pub fn run(wh_ratio: Option<f32>) {
+/// 模拟使用的固定时间步长，渲染帧率不同时模拟结果也保持一致。
+pub const FIXED_DT: std::time::Duration = std::time::Duration::from_micros(16_667);
+
+// 一帧最多追赶的模拟时间，避免卡顿之后陷入越追越慢的循环
+const MAX_FRAME_TIME: std::time::Duration = std::time::Duration::from_millis(250);
+
+pub fn run(wh_ratio: Option<f32>, scene: SceneConfig) {
+    // 浏览器中不能阻塞主线程，只能异步创建设备，之后交给浏览器的事件循环
+    #[cfg(target_arch = "wasm32")]
+    {
+        std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+        console_log::init_with_level(log::Level::Warn).expect("Couldn't initialize logger");
+        wasm_bindgen_futures::spawn_local(async move {
+            let (event_loop, instance) = create_action_instance(wh_ratio, &scene).await;
+            start_event_loop(event_loop, instance);
+        });
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    run_native(wh_ratio, scene);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn run_native(wh_ratio: Option<f32>, scene: SceneConfig) {
     env_logger::init();
 
-    let (event_loop, instance) = pollster::block_on(create_action_instance(wh_ratio));
+    let (event_loop, instance) = pollster::block_on(create_action_instance(wh_ratio, &scene));
     start_event_loop(event_loop, instance);
 }
 
+/// 不进入事件循环，按照 `config` 离屏录制一段动画，见 [`State::record`]。
+///
+/// Returns:
+///
+/// 录制失败时返回错误。
+pub fn record(scene: &SceneConfig, config: &RecordConfig) -> anyhow::Result<()> {
+    env_logger::init();
 
-async fn create_action_instance(wh_ratio: Option<f32>) -> (EventLoop<()>, State) {
+    let (_event_loop, mut instance) = pollster::block_on(create_action_instance(None, scene));
+    instance.record(config)
+}
+
+async fn create_action_instance(
+    wh_ratio: Option<f32>,
+    scene: &SceneConfig,
+) -> (EventLoop<()>, State) {
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
 
@@ -32,8 +72,34 @@ async fn create_action_instance(wh_ratio: Option<f32>) -> (EventLoop<()>, State)
     };
     window.set_inner_size(PhysicalSize::new(width, height));
 
+    // 把画布挂到页面中 id 为 collision-detection 的元素下
+    #[cfg(target_arch = "wasm32")]
+    {
+        use winit::platform::web::WindowExtWebSys;
+        web_sys::window()
+            .and_then(|win| win.document())
+            .and_then(|doc| {
+                let dst = doc.get_element_by_id("collision-detection")?;
+                let canvas = web_sys::Element::from(window.canvas());
+                dst.append_child(&canvas).ok()?;
+                Some(())
+            })
+            .expect("Couldn't append canvas to document body.");
+    }
+
+    // AppSurface 创建设备时请求适配器支持的全部特性和限制，其中包括必需的和可选的特性
     let app = app_surface::AppSurface::new(window).await;
-    let instance = State::new(app).await;
+    // 设备不满足要求时在这里给出清楚的错误，而不是在创建管线时才出错
+    if let Err(e) = requirements::check(&app, scene.capacity()) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            eprintln!("{e:#}");
+            std::process::exit(1);
+        }
+        #[cfg(target_arch = "wasm32")]
+        panic!("{e:#}");
+    }
+    let instance = State::from_config(app, scene).await;
 
     let adapter_info = instance.get_adapter_info();
     let gpu_info = format!(
@@ -42,13 +108,13 @@ async fn create_action_instance(wh_ratio: Option<f32>) -> (EventLoop<()>, State)
     );
     println!("{gpu_info}");
 
-
     (event_loop, instance)
 }
 
 fn start_event_loop(event_loop: EventLoop<()>, state: State) {
     let mut state = state;
     let mut last_render_time = instant::Instant::now();
+    let mut accumulator = std::time::Duration::ZERO;
     event_loop.run(move |event, _, control_flow| {
         match event {
             Event::DeviceEvent {
@@ -56,7 +122,10 @@ fn start_event_loop(event_loop: EventLoop<()>, state: State) {
                 ..
             } => {
                 if state.camera_state.mouse_pressed {
-                    state.camera_state.camera_controller.process_mouse(delta.0, delta.1)
+                    state
+                        .camera_state
+                        .camera_controller
+                        .process_mouse(delta.0, delta.1)
                 }
             }
             Event::WindowEvent {
@@ -76,10 +145,10 @@ fn start_event_loop(event_loop: EventLoop<()>, state: State) {
                             ..
                         } => *control_flow = ControlFlow::Exit,
                         WindowEvent::Resized(physical_size) => {
-                            if physical_size.width == 0 || physical_size.height == 0 {
-                                // 处理最小化窗口的事件
-                                println!("Window minimized!");
-                            } else {
+                            // 最小化的窗口面积为 0，没有可以渲染的表面，恢复时会再收到一次 Resized
+                            state.is_minimized =
+                                physical_size.width == 0 || physical_size.height == 0;
+                            if !state.is_minimized {
                                 state.resize(physical_size);
                             }
                         }
@@ -89,6 +158,11 @@ fn start_event_loop(event_loop: EventLoop<()>, state: State) {
                         } => {
                             state.resize(new_inner_size);
                         }
+                        WindowEvent::Focused(focused) => state.set_focused(*focused),
+                        // 光标离开窗口之后可能收不到松开按键的事件，清除按住的移动键
+                        WindowEvent::CursorLeft { .. } => {
+                            state.camera_state.camera_controller.reset_movement()
+                        }
                         _ => {}
                     }
                 }
@@ -97,23 +171,63 @@ fn start_event_loop(event_loop: EventLoop<()>, state: State) {
                 let now = instant::Instant::now();
                 let dt = now - last_render_time;
                 last_render_time = now;
-                state.update(dt);
+                // 最小化期间不更新也不渲染，恢复之后从当前时刻继续，模拟不会追赶最小化的时间
+                if state.is_minimized {
+                    accumulator = std::time::Duration::ZERO;
+                    return;
+                }
+
+                if state.is_unfocus_paused() {
+                    // 失去焦点时只重绘最后的画面，回到窗口时同样不追赶这段时间
+                    accumulator = std::time::Duration::ZERO;
+                } else if state.paused {
+                    state.update(dt);
+                    // 暂停时不累积时间，只在请求单步时推进一次，并直接显示最新的结果
+                    accumulator = std::time::Duration::ZERO;
+                    if std::mem::take(&mut state.single_step) {
+                        state.step(FIXED_DT);
+                    }
+                    state.sync_instances(1.0);
+                } else {
+                    state.update(dt);
+                    // 累积真实时间，按固定步长推进零次或多次模拟
+                    accumulator += dt.min(MAX_FRAME_TIME);
+                    while accumulator >= FIXED_DT {
+                        state.step(FIXED_DT);
+                        accumulator -= FIXED_DT;
+                    }
+                    // 在最近两次模拟结果之间插值渲染
+                    state.sync_instances(accumulator.as_secs_f32() / FIXED_DT.as_secs_f32());
+                }
 
                 match state.render() {
                     Ok(_) => {}
-                    // 当展示平面的上下文丢失，就需重新配置
-                    Err(wgpu::SurfaceError::Lost) => eprintln!("Surface is lost"),
+                    // 当展示平面的上下文丢失或过期，按当前的窗口大小重新配置
+                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                        state.reconfigure_surface()
+                    }
                     // 系统内存不足时，程序应该退出。
                     Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                    // 所有其他错误（过期、超时等）应在下一帧解决
+                    // 所有其他错误（超时等）应在下一帧解决
                     Err(e) => eprintln!("{e:?}"),
                 }
             }
             Event::MainEventsCleared => {
+                // 摇杆是持续的输入，每帧读取一次手柄状态，在下一次更新相机时生效
+                if let Some(gamepad) = state.gamepad.as_mut() {
+                    let input = gamepad.poll();
+                    state.camera_state.camera_controller.process_gamepad(input);
+                }
                 // 除非我们手动请求，RedrawRequested 将只会触发一次。
-                state.request_redraw();
+                // 最小化或者失去焦点暂停时不再请求，等待恢复窗口的事件，避免空转
+                if state.is_minimized || state.is_unfocus_paused() {
+                    *control_flow = ControlFlow::Wait;
+                } else {
+                    *control_flow = ControlFlow::Poll;
+                    state.request_redraw();
+                }
             }
             _ => {}
         }
     });
-}
\ No newline at end of file
+}
@@ -0,0 +1,65 @@
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+
+use crate::camera::GamepadInput;
+
+/// `GamepadState` 在事件循环中读取手柄，把摇杆和扳机转换成相机控制器使用的 [`GamepadInput`]。
+///
+/// 连接了多个手柄时只使用最近有输入的那一个。
+///
+/// Properties:
+///
+/// * `gilrs`: 手柄库的上下文，负责枚举手柄和接收事件。
+/// * `active`: 当前使用的手柄，没有连接手柄时为 `None`。
+pub struct GamepadState {
+    gilrs: Gilrs,
+    active: Option<GamepadId>,
+}
+
+impl GamepadState {
+    /// 初始化手柄库，当前平台不支持手柄时返回 `None`，键盘和鼠标仍然可以使用。
+    pub fn new() -> Option<Self> {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => gilrs,
+            Err(e) => {
+                eprintln!("无法初始化手柄: {e}");
+                return None;
+            }
+        };
+        let active = gilrs.gamepads().next().map(|(id, _)| id);
+        Some(Self { gilrs, active })
+    }
+
+    /// 处理自上次调用以来的手柄事件，返回当前使用的手柄的状态。
+    ///
+    /// Returns:
+    ///
+    /// 手柄的摇杆和扳机的状态，没有连接手柄时全为 0。死区由相机控制器处理，这里返回原始的值。
+    pub fn poll(&mut self) -> GamepadInput {
+        while let Some(gilrs::Event { id, event, .. }) = self.gilrs.next_event() {
+            match event {
+                EventType::Disconnected if self.active == Some(id) => {
+                    self.active = self.gilrs.gamepads().next().map(|(id, _)| id);
+                }
+                EventType::Disconnected => {}
+                _ => self.active = Some(id),
+            }
+        }
+
+        let Some(gamepad) = self.active.and_then(|id| self.gilrs.connected_gamepad(id)) else {
+            return GamepadInput::default();
+        };
+        let trigger = |button| gamepad.button_data(button).map_or(0.0, |data| data.value());
+        GamepadInput {
+            left_stick: glam::Vec2::new(
+                gamepad.value(Axis::LeftStickX),
+                gamepad.value(Axis::LeftStickY),
+            ),
+            right_stick: glam::Vec2::new(
+                gamepad.value(Axis::RightStickX),
+                gamepad.value(Axis::RightStickY),
+            ),
+            left_trigger: trigger(Button::LeftTrigger2),
+            right_trigger: trigger(Button::RightTrigger2),
+        }
+    }
+}
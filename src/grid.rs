@@ -0,0 +1,194 @@
+use app_surface::AppSurface;
+
+use crate::{compute, shaders, texture};
+
+/// `GridState` 把碰撞检测使用的均匀网格画出来：每个非空的网格单元画一个半透明的线框，
+/// 颜色按单元中的小球数量从蓝到红变化，用来检查网格大小相对于半径是否合适。
+///
+/// 单元的占用情况直接在顶点着色器中从 `CellIndex` buffer 读取，不需要读回 CPU。
+///
+/// Properties:
+///
+/// * `cell_count`: 网格单元的数量，每个单元一个实例。
+/// * `bind_group`: 绑定模拟参数和 `CellIndex` buffer 的绑定组。
+/// * `pipeline_layout`: 渲染管线的布局，重新加载着色器时复用。
+/// * `pipeline`: 使用 LineList 拓扑、带透明混合的渲染管线，只做深度测试不写深度。
+/// * `visible`: 是否绘制网格。
+pub struct GridState {
+    pub cell_count: u32,
+    pub bind_group: wgpu::BindGroup,
+    pub pipeline_layout: wgpu::PipelineLayout,
+    pub pipeline: wgpu::RenderPipeline,
+    pub visible: bool,
+}
+
+impl GridState {
+    // 每个单元 12 条棱，每条棱两个顶点
+    const VERTICES_PER_CELL: u32 = 24;
+
+    /// 创建网格视图的绑定组和渲染管线，默认不显示。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和表面格式。
+    /// * `camera_bind_group_layout`: 相机的绑定组布局，位于 group 0。
+    /// * `params_buffer`: 模拟参数的 uniform buffer，用到其中的边界和网格大小。
+    /// * `cell_index_buffer`: 碰撞检测建好的 `CellIndex` buffer。
+    /// * `sample_count`: MSAA 采样数。
+    ///
+    /// Returns:
+    ///
+    /// `GridState` 的一个实例。
+    pub fn new(
+        app: &AppSurface,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        params_buffer: &wgpu::Buffer,
+        cell_index_buffer: &wgpu::Buffer,
+        sample_count: u32,
+    ) -> Self {
+        let bind_group_layout =
+            app.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("Grid Bind Group Layout"),
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::VERTEX,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::VERTEX,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
+                });
+        let bind_group = app.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Grid Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                compute::new_group_entry(0, params_buffer),
+                compute::new_group_entry(1, cell_index_buffer),
+            ],
+        });
+
+        let pipeline_layout = app
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Grid Pipeline Layout"),
+                bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = Self::create_pipeline(app, &pipeline_layout, sample_count);
+
+        Self {
+            cell_count: (cell_index_buffer.size()
+                / std::mem::size_of::<compute::CellIndex>() as u64) as u32,
+            bind_group,
+            pipeline_layout,
+            pipeline,
+            visible: false,
+        }
+    }
+
+    fn create_pipeline(
+        app: &AppSurface,
+        layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let shader = app
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Grid Shader"),
+                // 模拟参数等结构体定义在 header.wgsl 中，与计算着色器共用
+                source: wgpu::ShaderSource::Wgsl(
+                    format!(
+                        "{}\n{}",
+                        compute::shader_header(),
+                        shaders::shader_source!("grid.wgsl")
+                    )
+                    .into(),
+                ),
+            });
+        app.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Grid Pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: app.config.format.add_srgb_suffix(),
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::LineList,
+                    ..Default::default()
+                },
+                // 半透明的线不写深度，避免相邻单元的棱互相遮挡
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+            })
+    }
+
+    /// 重新读取着色器并重建渲染管线，着色器有错误时保留原来的管线。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和表面格式。
+    /// * `sample_count`: MSAA 采样数，需要和当前的渲染附件一致，采样数改变之后用新的值重建。
+    pub fn reload_shader(&mut self, app: &AppSurface, sample_count: u32) {
+        if let Some(pipeline) = shaders::try_rebuild(&app.device, "grid.wgsl", || {
+            Self::create_pipeline(app, &self.pipeline_layout, sample_count)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    /// 在给定的渲染通道中绘制所有非空的网格单元。
+    ///
+    /// Arguments:
+    ///
+    /// * `render_pass`: 当前的渲染通道。
+    /// * `camera_bind_group`: 相机的绑定组。
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if !self.visible {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..Self::VERTICES_PER_CELL, 0..self.cell_count);
+    }
+}
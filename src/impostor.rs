@@ -0,0 +1,142 @@
+use std::ops::Range;
+
+use app_surface::AppSurface;
+
+use crate::{instance, model::Vertex, shaders, texture, utils};
+
+/// `RenderMode` 决定小球的绘制方式，只影响渲染，不影响碰撞计算。
+///
+/// Variants:
+///
+/// * `Mesh`: 每个实例画一个完整的球体网格，带纹理和法线贴图。
+/// * `PointSprite`: 每个实例画一个朝向相机的方块，在片元着色器中按球体着色并写入正确的深度，
+///   适合数十万个小球的场景。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    Mesh,
+    PointSprite,
+}
+
+impl RenderMode {
+    /// 切换到另一种绘制方式。
+    pub fn next(self) -> Self {
+        match self {
+            RenderMode::Mesh => RenderMode::PointSprite,
+            RenderMode::PointSprite => RenderMode::Mesh,
+        }
+    }
+}
+
+/// `ImpostorState` 保存以球体替身绘制小球的渲染管线，和网格绘制共用实例 buffer。
+///
+/// Properties:
+///
+/// * `pipeline_layout`: 渲染管线的布局，重新加载着色器时复用。
+/// * `pipeline`: 绘制球体替身的渲染管线。
+pub struct ImpostorState {
+    pub pipeline_layout: wgpu::PipelineLayout,
+    pub pipeline: wgpu::RenderPipeline,
+}
+
+impl ImpostorState {
+    /// 创建球体替身的渲染管线。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和表面格式。
+    /// * `camera_bind_group_layout`: 相机的绑定组布局，位于 group 0。
+    /// * `light_bind_group_layout`: 光源的绑定组布局，位于 group 1。
+    /// * `shadow_bind_group_layout`: 阴影贴图的绑定组布局，位于 group 2。
+    /// * `sample_count`: MSAA 采样数。
+    ///
+    /// Returns:
+    ///
+    /// `ImpostorState` 的一个实例。
+    pub fn new(
+        app: &AppSurface,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        light_bind_group_layout: &wgpu::BindGroupLayout,
+        shadow_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        let pipeline_layout = app
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Impostor Pipeline Layout"),
+                bind_group_layouts: &[
+                    camera_bind_group_layout,
+                    light_bind_group_layout,
+                    shadow_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+        let pipeline = Self::create_pipeline(app, &pipeline_layout, sample_count);
+
+        Self {
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    fn create_pipeline(
+        app: &AppSurface,
+        layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Impostor Shader"),
+            source: wgpu::ShaderSource::Wgsl(shaders::shader_source!("impostor.wgsl")),
+        };
+        utils::create_render_pipeline(
+            &app.device,
+            layout,
+            app.config.format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[instance::InstanceRaw::desc()],
+            shader,
+            wgpu::PolygonMode::Fill,
+            sample_count,
+        )
+    }
+
+    /// 重新读取着色器并重建渲染管线，着色器有错误时保留原来的管线。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和表面格式。
+    /// * `sample_count`: MSAA 采样数，需要和当前的渲染附件一致，采样数改变之后用新的值重建。
+    pub fn reload_shader(&mut self, app: &AppSurface, sample_count: u32) {
+        if let Some(pipeline) = shaders::try_rebuild(&app.device, "impostor.wgsl", || {
+            Self::create_pipeline(app, &self.pipeline_layout, sample_count)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    /// 在给定的渲染通道中以球体替身绘制实例，每个实例 6 个顶点。
+    ///
+    /// Arguments:
+    ///
+    /// * `render_pass`: 当前的渲染通道。
+    /// * `instance_buffer`: 渲染用的实例 buffer，和网格绘制相同。
+    /// * `instances`: 需要绘制的实例范围。
+    /// * `camera_bind_group`: 相机的绑定组。
+    /// * `light_bind_group`: 光源的绑定组。
+    /// * `shadow_bind_group`: 阴影贴图的绑定组。
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        instance_buffer: &'a wgpu::Buffer,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        shadow_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, light_bind_group, &[]);
+        render_pass.set_bind_group(2, shadow_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, instance_buffer.slice(..));
+        render_pass.draw(0..6, instances);
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use app_surface::AppSurface;
+use wgpu::util::DeviceExt;
+
+use crate::{compute::ComputeNode, model, shaders};
+
+/// `IndirectDrawState` 保存模型每个网格的间接绘制参数，实例数量由计算着色器数出 GPU 上的实例 buffer
+/// 中存活的小球来填写，这样绘制时不需要在 CPU 上知道实例数量。
+///
+/// Properties:
+///
+/// * `indirect_buffer`: 按网格顺序存放的 `DrawIndexedIndirectArgs`。
+/// * `instances_buffers`: 计算用的 A/B 两个实例 buffer。
+/// * `nodes`: 填写实例数量的计算节点，分别读取 A/B 两个实例 buffer。
+pub struct IndirectDrawState {
+    pub indirect_buffer: Arc<wgpu::Buffer>,
+    instances_buffers: [Arc<wgpu::Buffer>; 2],
+    nodes: [ComputeNode; 2],
+}
+
+impl IndirectDrawState {
+    /// 为模型创建间接绘制参数。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备。
+    /// * `model`: 需要绘制的模型，每个网格对应一组参数。
+    /// * `instances_buffers`: 计算用的 A/B 两个实例 buffer，绘制时从保存最新状态的那个中数出存活的实例。
+    pub fn new(
+        app: &AppSurface,
+        model: &model::Model,
+        instances_buffers: [Arc<wgpu::Buffer>; 2],
+    ) -> Self {
+        let args = model
+            .meshes
+            .iter()
+            .map(|mesh| model::DrawIndexedIndirectArgs {
+                index_count: mesh.num_elements,
+                instance_count: 0,
+                first_index: 0,
+                base_vertex: 0,
+                first_instance: 0,
+            })
+            .collect::<Vec<_>>();
+
+        let indirect_buffer = Arc::new(app.device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Indirect Draw Buffer"),
+                contents: bytemuck::cast_slice(&args),
+                usage: wgpu::BufferUsages::INDIRECT
+                    | wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_DST,
+            },
+        ));
+
+        let nodes = Self::create_nodes(app, &instances_buffers, &indirect_buffer);
+
+        Self {
+            indirect_buffer,
+            instances_buffers,
+            nodes,
+        }
+    }
+
+    fn create_nodes(
+        app: &AppSurface,
+        instances_buffers: &[Arc<wgpu::Buffer>; 2],
+        indirect_buffer: &Arc<wgpu::Buffer>,
+    ) -> [ComputeNode; 2] {
+        let source = shaders::shader_source!("indirect.wgsl");
+        [0, 1].map(|i| {
+            ComputeNode::new(
+                &app.device,
+                &source,
+                &[instances_buffers[i].clone(), indirect_buffer.clone()],
+                "Indirect Draw",
+            )
+        })
+    }
+
+    /// 重新读取着色器并重建计算节点，着色器有错误时保留原来的节点。
+    pub fn reload_shader(&mut self, app: &AppSurface) {
+        if let Some(nodes) = shaders::try_rebuild(&app.device, "indirect.wgsl", || {
+            Self::create_nodes(app, &self.instances_buffers, &self.indirect_buffer)
+        }) {
+            self.nodes = nodes;
+        }
+    }
+
+    /// 在绘制之前把填写实例数量的计算通道录制到 `encoder` 中。
+    ///
+    /// Arguments:
+    ///
+    /// * `encoder`: 绘制用的命令编码器。
+    /// * `current`: 保存最新状态的实例 buffer 的下标，见 `ComputeState::current`。
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, current: usize) {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Indirect Draw pass"),
+            ..Default::default()
+        });
+        // 一个工作组内求和，得到存活的实例数量
+        self.nodes[current].dispatch(&mut cpass, 1);
+    }
+}
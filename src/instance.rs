@@ -1,7 +1,11 @@
 use app_surface::AppSurface;
 use wgpu::util::DeviceExt;
 
-use crate::{compute::ComputeInstance, model};
+use crate::{
+    camera,
+    compute::{self, ComputeInstance},
+    model,
+};
 
 /// `InstanceRaw` 类型表示 Rust 中具有模型和普通矩阵的原始实例。
 ///
@@ -9,12 +13,27 @@ use crate::{compute::ComputeInstance, model};
 ///
 /// * `model`: 表示实例模型转换的 4x4 矩阵。该矩阵用于在 3D 空间中定位、旋转和缩放实例。矩阵的每个元素都是一个 32 位浮点数 (f32)。
 /// * `normal`: “normal”属性是“f32”值的 3x3 矩阵。它表示法线矩阵，用于在 3D 空间中变换法线向量。法线向量用于照明计算，以确定光如何与表面相互作用。
+/// * `color`: 实例的 RGBA 颜色，在片元着色器中与漫反射纹理相乘，白色表示保持原样。
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 #[allow(dead_code)]
 pub struct InstanceRaw {
     model: [[f32; 4]; 4],  // model matrix
     normal: [[f32; 3]; 3], // normal matrix
+    color: [f32; 4],       // instance color
+}
+
+/// 由模型矩阵计算法线矩阵，即左上角 3x3 矩阵的逆转置，保证非均匀缩放下法线仍然垂直于表面。
+///
+/// Arguments:
+///
+/// * `model`: 实例的模型矩阵。
+///
+/// Returns:
+///
+/// 用于变换法线的 3x3 矩阵。
+pub fn normal_matrix(model: &glam::Mat4) -> glam::Mat3 {
+    glam::Mat3::from_mat4(*model).inverse().transpose()
 }
 
 impl ComputeInstance {
@@ -24,9 +43,17 @@ impl ComputeInstance {
     ///
     /// `InstanceRaw` 结构的一个实例。
     pub fn to_render_instance_raw(&self) -> InstanceRaw {
-        let model = glam::Mat4::from_translation(self.position).to_cols_array_2d();
-        let normal = glam::Mat3::from_rotation_z(0.0).to_cols_array_2d();
-        InstanceRaw { model, normal }
+        // 模型是单位球体，按半径缩放后再平移到实例的位置
+        let model_matrix = glam::Mat4::from_translation(self.position)
+            * glam::Mat4::from_scale(glam::Vec3::splat(self.radius));
+        let model = model_matrix.to_cols_array_2d();
+        let normal = normal_matrix(&model_matrix).to_cols_array_2d();
+        let color = self.color.to_array();
+        InstanceRaw {
+            model,
+            normal,
+            color,
+        }
     }
 }
 
@@ -79,21 +106,114 @@ impl model::Vertex for InstanceRaw {
                     shader_location: 11,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                // instance color
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
 }
 
+/// `ColorMode` 决定渲染时每个实例的颜色来源，只影响渲染，不影响碰撞计算。
+///
+/// Variants:
+///
+/// * `Uniform`: 使用 `ComputeInstance` 自身携带的颜色。
+/// * `Speed`: 按速度大小映射到色带上，蓝色表示慢，红色表示快。
+/// * `Id`: 按实例 ID 生成互相区分的颜色。
+/// * `CollisionHeat`: 按最近的接触次数映射到色带上，蓝色表示很少接触，红色表示长时间挤在很多小球之间，
+///   用来找出堆积中卡住的区域。接触次数由碰撞阶段累加并随时间衰减，见 `ComputeInstance::collision_heat`。
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorMode {
+    Uniform,
+    Speed,
+    Id,
+    CollisionHeat,
+}
+
+impl ColorMode {
+    /// 返回循环切换时的下一个颜色模式。
+    pub fn next(self) -> Self {
+        match self {
+            ColorMode::Uniform => ColorMode::Speed,
+            ColorMode::Speed => ColorMode::Id,
+            ColorMode::Id => ColorMode::CollisionHeat,
+            ColorMode::CollisionHeat => ColorMode::Uniform,
+        }
+    }
+}
+
+// 两段发生变化的实例之间相隔不超过这么多个实例时合并成一次 write_buffer，避免调用次数过多
+const DIRTY_MERGE_GAP: usize = 64;
+
+/// 用 `instances` 填充 `data`，结果的长度总是 `capacity`，与实例缓冲区的大小相同。
+///
+/// 发射器让实例数量增长，吸收平面让它减少，但实例缓冲区按计算 buffer 的容量分配，写入永远不会越界；
+/// 间接绘制按计算 buffer 中的数量绘制，多出来的部分清零，缩成不可见的点。
+///
+/// Returns:
+///
+/// 实际写入的实例数量，超过 `capacity` 的实例被丢弃。
+fn fill_to_capacity(
+    data: &mut Vec<InstanceRaw>,
+    instances: impl Iterator<Item = InstanceRaw>,
+    capacity: usize,
+) -> usize {
+    data.clear();
+    data.extend(instances.take(capacity));
+    let count = data.len();
+    data.resize(capacity, bytemuck::Zeroable::zeroed());
+    count
+}
+
+/// 将 HSV 颜色转换为 RGB，`hue` 的范围是 [0, 1)。
+fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> glam::Vec3 {
+    let h = hue.rem_euclid(1.0) * 6.0;
+    let c = value * saturation;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    glam::Vec3::new(r, g, b) + glam::Vec3::splat(value - c)
+}
+
 /// `InstanceState` 结构体表示 Rust 程序中实例的状态，包括实例的数量和用于存储实例数据的缓冲区。
 ///
 /// Properties:
 ///
-/// * `instances_number`: 表示实例数量的无符号整数。此属性用于跟踪实例状态中的实例数量。
+/// * `instances_number`: 表示实例数量的无符号整数。此属性用于跟踪实例状态中的实例数量。开启视锥剔除时只计入可见的实例。
+/// * `total_number`: 剔除之前的实例总数。
+/// * `culling`: 是否在上传之前剔除完全位于视锥体外的实例，剔除需要在 CPU 上逐个检查，所以可以关闭。
 /// * `instance_buffer`: `instance_buffer` 是 `wgpu::Buffer` 类型的属性。它是一个存储实例数据的缓冲区。
+/// * `color_mode`: 实例颜色的来源，见 `ColorMode`。
+/// * `min_speed`: `ColorMode::Speed` 下色带最蓝端对应的速度。
+/// * `max_speed`: `ColorMode::Speed` 下色带最红端对应的速度。
+/// * `max_heat`: `ColorMode::CollisionHeat` 下色带最红端对应的 `collision_heat`。
+/// * `incremental`: 是否只上传与 GPU 上现有内容不同的实例范围，关闭时每帧上传全部实例。
+/// * `uploaded_bytes`: 上一次 `update` 实际写入实例缓冲区的字节数，用来衡量增量上传节省的传输量。
+/// * `uploaded`: 实例缓冲区当前内容在 CPU 上的副本，用来找出发生变化的实例。
+/// * `scratch`: 每帧生成 `InstanceRaw` 时复用的暂存数组，和 `uploaded` 交替使用，稳定之后不再分配内存。
 pub struct InstanceState {
     pub instances_number: usize,
-    #[allow(dead_code)]
+    pub total_number: usize,
+    pub culling: bool,
     pub instance_buffer: wgpu::Buffer,
+    pub color_mode: ColorMode,
+    pub min_speed: f32,
+    pub max_speed: f32,
+    pub max_heat: f32,
+    pub incremental: bool,
+    pub uploaded_bytes: usize,
+    uploaded: Vec<InstanceRaw>,
+    scratch: Vec<InstanceRaw>,
 }
 
 impl InstanceState {
@@ -103,15 +223,25 @@ impl InstanceState {
     ///
     /// * `app`: “AppSurface”结构的实例，表示将在其中呈现实例的应用程序表面。
     /// * `compute_instance`: `ComputeInstance` 对象的切片。
+    /// * `capacity`: 实例缓冲区最多容纳的实例数量，应当与计算 buffer 的 `buffer_len` 相同；发射器会让实例数量超过初始的数量。
     ///
     /// Returns:
     ///
     /// `Self` 结构的一个实例。
-    pub fn new(app: &AppSurface, compute_instance: &[ComputeInstance]) -> Self {
-        let instances_data = compute_instance
-            .iter()
-            .map(ComputeInstance::to_render_instance_raw)
-            .collect::<Vec<_>>();
+    pub fn new(app: &AppSurface, compute_instance: &[ComputeInstance], capacity: u32) -> Self {
+        let color_mode = ColorMode::Uniform;
+        let min_speed = 0.0;
+        let max_speed = 5.0;
+        // 一直保持 6 个接触时达到最红端，大约是密堆积中一个小球的一半邻居
+        let max_heat = 6.0 * compute::HEAT_DECAY_TIME;
+        let mut instances_data = Vec::with_capacity(capacity as usize);
+        let instances_number = fill_to_capacity(
+            &mut instances_data,
+            compute_instance.iter().map(|instance| {
+                Self::instance_raw(instance, color_mode, min_speed, max_speed, max_heat)
+            }),
+            capacity as usize,
+        );
         let instance_buffer = app
             .device
             .create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -121,27 +251,215 @@ impl InstanceState {
             });
         Self {
             instance_buffer,
-            instances_number: instances_data.len(),
+            instances_number,
+            total_number: compute_instance.len(),
+            culling: false,
+            color_mode,
+            min_speed,
+            max_speed,
+            max_heat,
+            incremental: true,
+            uploaded_bytes: 0,
+            scratch: Vec::with_capacity(instances_data.len()),
+            uploaded: instances_data,
         }
     }
 
+    /// 按照颜色模式计算实例的颜色，并生成用于渲染的 `InstanceRaw`。
+    ///
+    /// Arguments:
+    ///
+    /// * `instance`: 需要渲染的计算实例。
+    /// * `color_mode`: 颜色模式。
+    /// * `min_speed`: 速度色带的下限。
+    /// * `max_speed`: 速度色带的上限。
+    /// * `max_heat`: 接触次数色带的上限。
+    ///
+    /// Returns:
+    ///
+    /// 带有对应颜色的 `InstanceRaw`。
+    fn instance_raw(
+        instance: &ComputeInstance,
+        color_mode: ColorMode,
+        min_speed: f32,
+        max_speed: f32,
+        max_heat: f32,
+    ) -> InstanceRaw {
+        let mut raw = instance.to_render_instance_raw();
+        let color = match color_mode {
+            ColorMode::Uniform => instance.color,
+            ColorMode::Speed => {
+                let range = (max_speed - min_speed).max(f32::EPSILON);
+                let t = ((instance.velocity.length() - min_speed) / range).clamp(0.0, 1.0);
+                // 色相从 240°（蓝）到 0°（红）
+                hsv_to_rgb((1.0 - t) * 2.0 / 3.0, 1.0, 1.0).extend(1.0)
+            }
+            ColorMode::Id => {
+                // 用黄金分割比打散相邻 ID 的色相
+                let hue = (instance.id as f32 * 0.618_034).fract();
+                hsv_to_rgb(hue, 0.6, 1.0).extend(1.0)
+            }
+            ColorMode::CollisionHeat => {
+                let t = (instance.collision_heat / max_heat.max(f32::EPSILON)).clamp(0.0, 1.0);
+                hsv_to_rgb((1.0 - t) * 2.0 / 3.0, 1.0, 1.0).extend(1.0)
+            }
+        };
+        raw.color = color.to_array();
+        raw
+    }
+
     /// “update”函数使用来自“compute_instance”向量的数据更新实例缓冲区。
     ///
+    /// 开启 `incremental` 时与上一次上传的内容逐个比较，只写入发生变化的范围；休眠的小球和没有变化的颜色不会重复上传。
+    ///
     /// Arguments:
     ///
     /// * `app`: “AppSurface”结构的实例，表示将发生渲染的应用程序表面或窗口。
     /// * `compute_instance`: “compute_instance”是“ComputeInstance”对象的一部分。
-    pub fn update(&mut self, app: &AppSurface, compute_instance: &[ComputeInstance]) {
-        self.instances_number = compute_instance.len();
-        let instances_data = compute_instance
-            .iter()
-            .map(ComputeInstance::to_render_instance_raw)
-            .collect::<Vec<_>>();
+    /// * `frustum`: 相机的视锥体，开启剔除时只上传与它相交的实例。
+    pub fn update(
+        &mut self,
+        app: &AppSurface,
+        compute_instance: &[ComputeInstance],
+        frustum: &camera::Frustum,
+    ) {
+        self.total_number = compute_instance.len();
+        let mut instances_data = std::mem::take(&mut self.scratch);
+        // uploaded 的长度就是实例缓冲区的容量
+        self.instances_number = fill_to_capacity(
+            &mut instances_data,
+            compute_instance
+                .iter()
+                .filter(|instance| {
+                    !self.culling || frustum.intersects_sphere(instance.position, instance.radius)
+                })
+                .map(|instance| {
+                    Self::instance_raw(
+                        instance,
+                        self.color_mode,
+                        self.min_speed,
+                        self.max_speed,
+                        self.max_heat,
+                    )
+                }),
+            self.uploaded.len(),
+        );
         // Update the instance buffer
-        app.queue.write_buffer(
-            &self.instance_buffer,
-            0,
-            bytemuck::cast_slice(&instances_data),
+        self.uploaded_bytes = if self.incremental {
+            self.write_dirty_ranges(app, &instances_data)
+        } else {
+            app.queue.write_buffer(
+                &self.instance_buffer,
+                0,
+                bytemuck::cast_slice(&instances_data),
+            );
+            std::mem::size_of_val(instances_data.as_slice())
+        };
+        // 这一帧的数据成为 GPU 上的内容，上一帧的数组留作下一帧的暂存
+        self.scratch = std::mem::replace(&mut self.uploaded, instances_data);
+    }
+
+    /// 把 `instances_data` 中与 `uploaded` 不同的实例写入实例缓冲区，相隔不超过 `DIRTY_MERGE_GAP` 的变化合并成一次写入。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 提供写入缓冲区的队列。
+    /// * `instances_data`: 这一帧的实例数据，长度不小于 `uploaded`。
+    ///
+    /// Returns:
+    ///
+    /// 实际写入的字节数。
+    fn write_dirty_ranges(&self, app: &AppSurface, instances_data: &[InstanceRaw]) -> usize {
+        let mut written = 0;
+        let mut write = |range: std::ops::Range<usize>| {
+            let bytes: &[u8] = bytemuck::cast_slice(&instances_data[range.clone()]);
+            let offset = range.start * std::mem::size_of::<InstanceRaw>();
+            app.queue
+                .write_buffer(&self.instance_buffer, offset as wgpu::BufferAddress, bytes);
+            written += bytes.len();
+        };
+
+        let mut dirty: Option<std::ops::Range<usize>> = None;
+        for (i, raw) in instances_data.iter().enumerate() {
+            let changed = self.uploaded.get(i).map_or(true, |old| {
+                bytemuck::bytes_of(old) != bytemuck::bytes_of(raw)
+            });
+            if !changed {
+                continue;
+            }
+            dirty = match dirty {
+                Some(range) if i - range.end <= DIRTY_MERGE_GAP => Some(range.start..i + 1),
+                Some(range) => {
+                    write(range);
+                    Some(i..i + 1)
+                }
+                None => Some(i..i + 1),
+            };
+        }
+        if let Some(range) = dirty {
+            write(range);
+        }
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normal_matrix_keeps_normals_perpendicular_under_non_uniform_scale() {
+        let model = glam::Mat4::from_scale_rotation_translation(
+            glam::Vec3::new(3.0, 0.5, 1.5),
+            glam::Quat::from_rotation_y(0.7),
+            glam::Vec3::new(1.0, 2.0, 3.0),
         );
+        let normal = glam::Vec3::new(1.0, 1.0, 0.0).normalize();
+        let tangent = glam::Vec3::new(1.0, -1.0, 0.0).normalize();
+        let transformed_tangent = model.transform_vector3(tangent);
+
+        let transformed_normal = normal_matrix(&model) * normal;
+        assert!(transformed_normal.dot(transformed_tangent).abs() < 1e-5);
+        // 直接用模型矩阵变换法线时不再垂直
+        let naive_normal = glam::Mat3::from_mat4(model) * normal;
+        assert!(naive_normal.dot(transformed_tangent).abs() > 0.1);
+    }
+
+    fn test_raw(index: usize) -> InstanceRaw {
+        ComputeInstance {
+            id: index as u32,
+            position: glam::Vec3::new(index as f32, 0.0, 0.0),
+            radius: 0.5,
+            velocity: glam::Vec3::ZERO,
+            color: glam::Vec4::ONE,
+            collision_heat: 0.0,
+        }
+        .to_render_instance_raw()
+    }
+
+    #[test]
+    fn instance_data_always_fills_the_buffer_capacity() {
+        let zero: InstanceRaw = bytemuck::Zeroable::zeroed();
+        let mut data = Vec::new();
+        // 创建时只有 points 个实例，缓冲区按 max_points 分配
+        assert_eq!(fill_to_capacity(&mut data, (0..5).map(test_raw), 10), 5);
+        assert_eq!(data.len(), 10);
+
+        // 发射器让实例数量超过 points 之后写入的数据仍然正好填满缓冲区
+        assert_eq!(fill_to_capacity(&mut data, (0..8).map(test_raw), 10), 8);
+        assert_eq!(data.len(), 10);
+        assert_eq!(
+            bytemuck::bytes_of(&data[7]),
+            bytemuck::bytes_of(&test_raw(7))
+        );
+        assert_eq!(bytemuck::bytes_of(&data[8]), bytemuck::bytes_of(&zero));
+
+        // 超过容量的实例被丢弃，而不是写到缓冲区之外
+        assert_eq!(fill_to_capacity(&mut data, (0..12).map(test_raw), 10), 10);
+        assert_eq!(data.len(), 10);
+
+        // 实例减少之后上次多写的部分清零
+        assert_eq!(fill_to_capacity(&mut data, (0..3).map(test_raw), 10), 3);
+        assert_eq!(bytemuck::bytes_of(&data[5]), bytemuck::bytes_of(&zero));
     }
 }
@@ -0,0 +1,24 @@
+//! 在 GPU 上模拟大量小球之间的碰撞。
+//!
+//! 模拟只需要 `wgpu::Device` 和 `wgpu::Queue`，不依赖窗口和事件循环，可以通过 [`CollisionWorld`]
+//! 在其他程序中使用；需要更细的控制时直接使用 [`compute::ComputeState`]。
+//! `my-collision-detect` 可执行文件是建立在这个库之上的可视化示例。
+//!
+//! GPU 上的模拟不保证在不同的机器上得到相同的结果：力的累加顺序取决于线程调度，各家 GPU 的
+//! `length`、除法和 FMA 的精度也不一样，微小的差别会在碰撞中被放大。需要逐位一致的结果时，
+//! 例如跨机器比较的回归测试，用 [`CollisionWorld::set_step_mode`] 切换到 [`StepMode::CpuF64`]，
+//! 在 CPU 上以 f64 推进，见 [`cpu::CpuSolver`]。
+
+pub mod camera;
+pub mod compute;
+pub mod cpu;
+pub mod instance;
+pub mod model;
+mod readback;
+pub mod resources;
+pub mod shaders;
+pub mod texture;
+pub mod utils;
+mod world;
+
+pub use world::{CollisionWorld, StepMode};
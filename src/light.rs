@@ -13,6 +13,7 @@ use wgpu::util::DeviceExt;
 /// * `color`: “color”属性是一个由“f32”值组成的 3 元素数组，表示灯光的 RGB 颜色分量。每个分量的范围从 0.0 到 1.0，其中 0.0 表示无强度，1.0 表示全强度。
 /// * `_padding2`: `_padding2` 字段用于填充，以确保 `color` 字段在内存中正确对齐。着色器中的 Uniform 通常要求元素之间有 16 字节（4
 /// 个浮点）间距，因此添加填充字段以确保正确对齐。
+/// * `view_proj`: 光源空间的视图投影矩阵，用于渲染和采样阴影贴图。
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct LightUniform {
@@ -21,81 +22,333 @@ pub struct LightUniform {
     _padding: u32,
     color: [f32; 3],
     _padding2: u32,
+    view_proj: [[f32; 4]; 4],
+}
+
+impl LightUniform {
+    /// 创建一个给定位置和颜色的光源。
+    ///
+    /// Arguments:
+    ///
+    /// * `position`: 光源的位置。
+    /// * `color`: 光源的 RGB 颜色。
+    ///
+    /// Returns:
+    ///
+    /// `LightUniform` 的一个实例，光源空间矩阵需要调用 `update_view_proj` 计算。
+    pub fn new(position: [f32; 3], color: [f32; 3]) -> Self {
+        Self {
+            position,
+            _padding: 0,
+            color,
+            _padding2: 0,
+            view_proj: glam::Mat4::IDENTITY.to_cols_array_2d(),
+        }
+    }
+
+    /// 根据光源位置计算光源空间的视图投影矩阵。
+    ///
+    /// 点光源的阴影用沿光源方向的正交投影来近似：阴影相机放在光源方向上、场景包围球之外，
+    /// 正对原点，投影范围覆盖整个包围球。
+    ///
+    /// Arguments:
+    ///
+    /// * `scene_radius`: 场景包围球的半径。
+    pub fn update_view_proj(&mut self, scene_radius: f32) {
+        let position = glam::Vec3::from_array(self.position);
+        let direction = position.try_normalize().unwrap_or(glam::Vec3::Y);
+        // 光源方向和 Y 轴平行时换一个上方向，避免 look_at 退化
+        let up = if direction.cross(glam::Vec3::Y).length_squared() < 1e-6 {
+            glam::Vec3::Z
+        } else {
+            glam::Vec3::Y
+        };
+        let eye = direction * scene_radius * 2.0;
+        let view = glam::Mat4::look_at_rh(eye, glam::Vec3::ZERO, up);
+        let proj = glam::Mat4::orthographic_rh(
+            -scene_radius,
+            scene_radius,
+            -scene_radius,
+            scene_radius,
+            scene_radius * 0.5,
+            scene_radius * 3.5,
+        );
+        self.view_proj = (proj * view).to_cols_array_2d();
+    }
+}
+
+/// 默认的环境光，照亮背光的一面。
+pub const DEFAULT_AMBIENT: [f32; 3] = [0.1, 0.1, 0.1];
+
+/// 默认的曝光，色调映射为 `1 - exp(-color * exposure)`。
+pub const DEFAULT_EXPOSURE: f32 = 1.0;
+
+/// `LightGlobals` 是与单个光源无关的光照参数，与着色器中的 `LightGlobals` 结构体布局一致。
+///
+/// Properties:
+///
+/// * `count`: 光源的数量。
+/// * `exposure`: 色调映射之前乘上的曝光。
+/// * `ambient`: 环境光的 RGB 颜色，和光源的数量无关。
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightGlobals {
+    count: u32,
+    exposure: f32,
+    _padding: [u32; 2],
+    ambient: [f32; 3],
+    _padding2: u32,
 }
 
 /// `LightState` 结构表示 Rust 程序中光源的状态。
 ///
 /// Properties:
 ///
-/// * `light_uniform`: “light_uniform”属性是“LightUniform”类型的结构体或对象。它可能包含与光源属性相关的数据，例如其位置、颜色、强度等。
-/// * `light_buffer`: “light_buffer”属性是存储灯光数据的缓冲区。它的类型为`wgpu::Buffer`，是wgpu库提供的缓冲区对象。缓冲区用于在 GPU
-/// 上存储和操作数据。在这种情况下，`light_buffer
+/// * `lights`: 所有光源，第一个光源会投射阴影。
+/// * `light_buffer`: 存储所有光源的只读 storage buffer，容量为 `MAX_LIGHTS`。
+/// * `light_globals_buffer`: 存储光源数量、环境光和曝光的 uniform buffer。
 /// * `light_bind_group_layout`: “light_bind_group_layout”是一个布局，描述了将绑定到着色器的资源的绑定槽和类型。它定义着色器将使用的资源的结构和组织。
 /// * `light_bind_group`:
 /// “light_bind_group”是一个绑定组，表示可以绑定在一起以在着色器中使用的资源集合。它用于将“light_buffer”和其他资源绑定到着色器管道。
+/// * `scene_radius`: 场景包围球的半径，决定阴影贴图覆盖的范围。
+/// * `auto_rotate`: 是否让光源每帧绕 Y 轴自动旋转。
+/// * `angular_speed`: 自动旋转的角速度，单位是弧度每秒。
+/// * `ambient`: 环境光的 RGB 颜色。
+/// * `exposure`: 色调映射的曝光。
+/// * `dirty`: 光源数据是否被修改过、需要重新写入 GPU。
 pub struct LightState {
-    pub light_uniform: LightUniform,
+    pub lights: Vec<LightUniform>,
     pub light_buffer: wgpu::Buffer,
+    pub light_globals_buffer: wgpu::Buffer,
     pub light_bind_group_layout: wgpu::BindGroupLayout,
     pub light_bind_group: wgpu::BindGroup,
+    pub scene_radius: f32,
+    pub auto_rotate: bool,
+    pub angular_speed: f32,
+    ambient: glam::Vec3,
+    exposure: f32,
+    dirty: bool,
 }
 
 impl LightState {
-    pub fn new(app: &AppSurface) -> Self {
-        let light_uniform = LightUniform {
-            position: [2.0, 2.0, 2.0],
-            _padding: 0,
-            color: [1.0, 1.0, 1.0],
-            _padding2: 0,
-        };
+    // 光源数量的上限，决定 storage buffer 的容量
+    pub const MAX_LIGHTS: usize = 8;
 
-        let light_buffer = app
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Light Vertex Buffer"),
-                contents: bytemuck::cast_slice(&[light_uniform]),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            });
+    pub fn new(app: &AppSurface, scene_radius: f32) -> Self {
+        let mut light_uniform = LightUniform::new([2.0, 2.0, 2.0], [1.0, 1.0, 1.0]);
+        light_uniform.update_view_proj(scene_radius);
+        let lights = vec![light_uniform];
+
+        let light_buffer = app.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Storage Buffer"),
+            size: (std::mem::size_of::<LightUniform>() * Self::MAX_LIGHTS) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        app.queue
+            .write_buffer(&light_buffer, 0, bytemuck::cast_slice(&lights));
+
+        let light_globals_buffer =
+            app.device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Light Globals Buffer"),
+                    contents: bytemuck::cast_slice(&[LightGlobals {
+                        count: lights.len() as u32,
+                        exposure: DEFAULT_EXPOSURE,
+                        _padding: [0; 2],
+                        ambient: DEFAULT_AMBIENT,
+                        _padding2: 0,
+                    }]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
         let light_bind_group_layout =
             app.device
                 .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    entries: &[wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
                         },
-                        count: None,
-                    }],
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
                     label: None,
                 });
 
         let light_bind_group = app.device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: light_globals_buffer.as_entire_binding(),
+                },
+            ],
             label: None,
         });
 
         Self {
-            light_uniform,
+            lights,
             light_buffer,
+            light_globals_buffer,
             light_bind_group_layout,
             light_bind_group,
+            scene_radius,
+            auto_rotate: true,
+            angular_speed: consts::FRAC_PI_3,
+            ambient: glam::Vec3::from_array(DEFAULT_AMBIENT),
+            exposure: DEFAULT_EXPOSURE,
+            dirty: false,
+        }
+    }
+
+    /// 替换全部光源，超过 `MAX_LIGHTS` 的部分会被丢弃。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于写入缓冲区。
+    /// * `lights`: 新的光源列表，第一个光源会投射阴影。
+    pub fn set_lights(&mut self, app: &AppSurface, lights: &[LightUniform]) {
+        let count = lights.len().min(Self::MAX_LIGHTS);
+        if count < lights.len() {
+            println!(
+                "光源数量 {} 超过上限 {}，多余的光源将被忽略。",
+                lights.len(),
+                Self::MAX_LIGHTS
+            );
+        }
+        self.lights = lights[..count].to_vec();
+        for light in &mut self.lights {
+            light.update_view_proj(self.scene_radius);
+        }
+        self.write_lights(app);
+    }
+
+    /// 设置某个光源的位置，下一次 `update` 时写入 GPU。
+    ///
+    /// Arguments:
+    ///
+    /// * `index`: 光源的下标。
+    /// * `position`: 新的位置。
+    pub fn set_position(&mut self, index: usize, position: glam::Vec3) {
+        if let Some(light) = self.lights.get_mut(index) {
+            light.position = position.to_array();
+            light.update_view_proj(self.scene_radius);
+            self.dirty = true;
+        }
+    }
+
+    /// 设置某个光源的颜色，下一次 `update` 时写入 GPU。
+    ///
+    /// Arguments:
+    ///
+    /// * `index`: 光源的下标。
+    /// * `color`: 新的 RGB 颜色。
+    pub fn set_color(&mut self, index: usize, color: glam::Vec3) {
+        if let Some(light) = self.lights.get_mut(index) {
+            light.color = color.to_array();
+            self.dirty = true;
         }
     }
-    pub fn update(&mut self, app: &AppSurface) {
-        let old_position = glam::Vec3::from_array(self.light_uniform.position);
-        self.light_uniform.position =
-            (glam::Quat::from_axis_angle(glam::Vec3::Y, consts::PI / 180.) * old_position).into();
+
+    /// 某个光源的颜色，没有这个光源时返回 `None`。
+    pub fn color(&self, index: usize) -> Option<glam::Vec3> {
+        self.lights
+            .get(index)
+            .map(|light| glam::Vec3::from_array(light.color))
+    }
+
+    /// 设置环境光，下一次 `update` 时写入 GPU。
+    ///
+    /// Arguments:
+    ///
+    /// * `ambient`: 环境光的 RGB 颜色，背光的一面也至少有这么亮。
+    pub fn set_ambient(&mut self, ambient: glam::Vec3) {
+        self.ambient = ambient;
+        self.dirty = true;
+    }
+
+    /// 环境光的 RGB 颜色。
+    pub fn ambient(&self) -> glam::Vec3 {
+        self.ambient
+    }
+
+    /// 设置曝光，下一次 `update` 时写入 GPU。
+    ///
+    /// Arguments:
+    ///
+    /// * `exposure`: 色调映射之前乘上的系数，越大画面越亮。
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure;
+        self.dirty = true;
+    }
+
+    /// 色调映射的曝光。
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// 光源的数量。
+    pub fn light_count(&self) -> u32 {
+        self.lights.len() as u32
+    }
+
+    // 把光源、光源数量、环境光和曝光写入 GPU
+    fn write_lights(&self, app: &AppSurface) {
+        app.queue
+            .write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&self.lights));
         app.queue.write_buffer(
-            &self.light_buffer,
+            &self.light_globals_buffer,
             0,
-            bytemuck::cast_slice(&[self.light_uniform]),
+            bytemuck::cast_slice(&[LightGlobals {
+                count: self.light_count(),
+                exposure: self.exposure,
+                _padding: [0; 2],
+                ambient: self.ambient.to_array(),
+                _padding2: 0,
+            }]),
         );
     }
+
+    /// 更新光源：开启自动旋转时让光源绕 Y 轴旋转，只有光源数据改变时才写入 GPU。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于写入缓冲区。
+    /// * `dt`: 距离上一帧的时间。
+    pub fn update(&mut self, app: &AppSurface, dt: std::time::Duration) {
+        if self.auto_rotate {
+            let angle = self.angular_speed * dt.as_secs_f32();
+            let rotation = glam::Quat::from_axis_angle(glam::Vec3::Y, angle);
+            let positions = self
+                .lights
+                .iter()
+                .map(|light| rotation * glam::Vec3::from_array(light.position))
+                .collect::<Vec<_>>();
+            for (index, position) in positions.into_iter().enumerate() {
+                self.set_position(index, position);
+            }
+        }
+        if self.dirty {
+            self.write_lights(app);
+            self.dirty = false;
+        }
+    }
 }
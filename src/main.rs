@@ -1,48 +1,517 @@
 use std::iter;
 
 use app_surface::{AppSurface, SurfaceFrame};
-use rand::Rng;
+use rand::{Rng, SeedableRng};
 use winit::{event::*, window::WindowId};
 
+mod args;
+mod background;
+mod bench;
+mod boundary;
+mod depth_resolve;
+mod floor;
 mod framework;
+mod gamepad;
+mod grid;
+mod impostor;
+mod indirect;
 mod light;
+mod normals;
+mod outline;
 use framework::run;
-mod camera;
-mod compute;
-mod instance;
-mod model;
-mod resources;
-mod texture;
-mod utils;
+mod config;
+mod overlay;
+mod prepass;
+mod record;
+mod requirements;
+mod shadow;
+mod ssao;
+mod trail;
+
+// 模拟和渲染用到的公共部分在库中，这里的模块仍然通过 crate::compute 等路径使用它们
+use collision_detection_gpu::{
+    camera, compute, instance, model, resources, shaders, texture, utils,
+};
 
 use model::{DrawLight, DrawModel, Vertex};
 
+// 初始场景的随机种子，固定种子保证每次运行（以及录制）的初始状态一致
+const SCENE_SEED: u64 = 42;
+
+// 视图空间法线渲染目标的格式
+const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+// F2 键依次切换的 MSAA 采样数
+const SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+// 用到 MSAA 采样数的管线的着色器，采样数改变时都要重建
+const MULTISAMPLED_SHADERS: [&str; 10] = [
+    "background.wgsl",
+    "floor.wgsl",
+    "outline.wgsl",
+    "boundary.wgsl",
+    "impostor.wgsl",
+    "grid.wgsl",
+    "trail.wgsl",
+    "prepass.wgsl",
+    "light.wgsl",
+    "draw.wgsl",
+];
+
+// 用 [ 键减小时间缩放时的最小非零值，再减小就停在 0
+const MIN_KEY_TIME_SCALE: f32 = 1.0 / 16.0;
+
+// 用数字键调节模拟参数时每次的步长和范围
+const GRAVITY_KEY_STEP: f32 = 1.0;
+const MAX_KEY_GRAVITY: f32 = 50.0;
+const RESTITUTION_KEY_STEP: f32 = 0.05;
+const MAX_KEY_SUBSTEPS: u32 = 64;
+
+// F5 保存、F9 恢复模拟状态时使用的文件，位于当前目录
+const STATE_FILE: &str = "state.cdgs";
+
+// 用小键盘旋转重力时每次转过的角度，以及实际的重力方向转向目标的角速度（弧度每秒）
+const GRAVITY_KEY_ANGLE: f32 = std::f32::consts::PI / 12.0;
+const GRAVITY_TURN_RATE: f32 = std::f32::consts::FRAC_PI_2;
+
+// J 键依次切换的第一个光源的颜色：白色、暖色和冷色
+const LIGHT_COLORS: [glam::Vec3; 3] = [
+    glam::Vec3::ONE,
+    glam::Vec3::new(1.0, 0.8, 0.6),
+    glam::Vec3::new(0.6, 0.8, 1.0),
+];
+
+// U 键依次切换的清屏颜色：默认的深蓝色、黑色和白色
+const CLEAR_COLORS: [wgpu::Color; 3] = [
+    wgpu::Color {
+        r: 0.1,
+        g: 0.2,
+        b: 0.3,
+        a: 1.0,
+    },
+    wgpu::Color::BLACK,
+    wgpu::Color::WHITE,
+];
+
 struct State {
     app: AppSurface,
     // pipelines
     render_pipeline: wgpu::RenderPipeline,
+    // 线框模式的管线，设备不支持 POLYGON_MODE_LINE 时为 None
+    wireframe_render_pipeline: Option<wgpu::RenderPipeline>,
+    // 深度预渲染之后使用的管线，只比较深度而不写入
+    prepass_render_pipeline: wgpu::RenderPipeline,
     light_render_pipeline: wgpu::RenderPipeline,
+    // 重新加载着色器时复用的管线布局
+    render_pipeline_layout: wgpu::PipelineLayout,
+    light_pipeline_layout: wgpu::PipelineLayout,
+    // debug 构建下监听着色器文件，修改后重建对应的管线
+    shader_watcher: Option<shaders::ShaderWatcher>,
+    // 是否以线框模式绘制小球
+    wireframe: bool,
+    // 以网格还是球体替身绘制小球
+    render_mode: impostor::RenderMode,
+    // sphere impostor pipeline, used by RenderMode::PointSprite
+    impostor_state: impostor::ImpostorState,
     // model for drawing object
     obj_model: model::Model,
     depth_texture: texture::Texture,
+    // MSAA related, msaa_texture is None when sample_count is 1
+    sample_count: u32,
+    msaa_texture: Option<texture::Texture>,
+    // single-sample copy of depth_texture for reading back the depth under the cursor
+    depth_resolve_state: depth_resolve::DepthResolveState,
+    // view-space normals of the sphere meshes for post effects, None when disabled
+    normal_texture: Option<texture::Texture>,
+    // multisampled normals resolved into normal_texture, None when sample_count is 1
+    msaa_normal_texture: Option<texture::Texture>,
+    // full-screen debug view of normal_texture, toggled together with the normals output
+    normal_view_state: normals::NormalViewState,
     // camera related
     camera_state: camera::CameraState,
     // light related
     light_state: light::LightState,
+    // shadow map related
+    shadow_state: shadow::ShadowState,
+    // depth-only prepass for the sphere meshes
+    prepass_state: prepass::DepthPrepassState,
+    // screen-space ambient occlusion for the sphere meshes
+    ssao_state: ssao::SsaoState,
     // Instances related
     instance_state: instance::InstanceState,
     // compute instances
     compute_state: compute::ComputeState,
-    // fps related, last time we update fps
-    last_fps_update: std::time::Instant,
+    // direction the gravity turns toward, rotated with the numpad
+    gravity_target: glam::Vec3,
+    // indirect draw arguments, instance count filled on the GPU
+    indirect_state: indirect::IndirectDrawState,
+    // boundary wireframe
+    boundary_state: boundary::BoundaryState,
+    // reference grid on the floor
+    floor_state: floor::FloorState,
+    // floor settings from the scene, the default height follows the boundary of a loaded state
+    floor: config::FloorConfig,
+    // outline around the selected instances
+    outline_state: outline::OutlineState,
+    // color the frame is cleared to before anything is drawn
+    clear_color: wgpu::Color,
+    // optional full-screen gradient drawn in place of clear_color
+    background_state: background::BackgroundState,
+    // motion trails of tracked instances
+    trail_state: trail::TrailState,
+    // debug view of the occupied grid cells
+    grid_state: grid::GridState,
+    // cursor position in physical pixels, used for picking
+    cursor_position: winit::dpi::PhysicalPosition<f64>,
+    // simulation pause, camera and rendering keep running while paused
+    paused: bool,
+    // advance exactly one simulation step on the next frame while paused
+    single_step: bool,
+    // the window has zero area, update and render are skipped until it is restored
+    is_minimized: bool,
+    // target width / height of the rendered image, None fills the window
+    aspect_ratio: Option<f32>,
+    // render area [x, y, width, height] in pixels, smaller than the surface when letterboxed
+    viewport: [f32; 4],
+    // whether the window has keyboard focus
+    focused: bool,
+    // stop updating while the window is unfocused
+    pause_on_unfocus: bool,
+    // show total kinetic energy and momentum in the overlay
+    show_diagnostics: bool,
+    // positions before the last simulation step, used to interpolate rendering
+    previous_positions: Vec<glam::Vec3>,
+    // on-screen text with fps and simulation stats
+    overlay: overlay::TextOverlay,
+    // exponentially smoothed fps shown in the overlay
+    fps: f32,
+    // gamepad camera input, None when the platform has no gamepad support
+    gamepad: Option<gamepad::GamepadState>,
+}
+
+/// 按照场景参数创建计算状态，并用固定的随机种子生成小球的初始位置和速度。
+fn create_compute_state(app: &AppSurface, scene: &config::SceneConfig) -> compute::ComputeState {
+    let boundary = scene.boundary.half_extents();
+
+    // 网格大小默认取最大直径，保证相互碰撞的小球一定在相邻的格子中
+    // 有发射器时实例 buffer 按发射器的上限分配，一开始只放 points 个小球
+    let mut compute_state = compute::ComputeState::builder(scene.capacity(), scene.grid_size())
+        .boundary(boundary)
+        .dimensions(scene.dimensions)
+        .cell_indexing(scene.cell_indexing)
+        .broad_phase(scene.broad_phase)
+        .force_model(scene.force_model)
+        .stiffness(scene.stiffness)
+        .equilibrium_distance(scene.equilibrium_distance)
+        .gravity(scene.gravity)
+        .restitution(scene.restitution)
+        .drag(scene.drag)
+        .max_speed(scene.max_speed)
+        .integrator(scene.integrator)
+        .sleep(scene.sleep_velocity, scene.sleep_time)
+        .substeps(scene.substeps)
+        .max_move_fraction(scene.max_move_fraction)
+        .time_scale(scene.time_scale)
+        .build_app(app)
+        .expect("scene parameters are checked when the scene is validated");
+    // 工作组大小还取决于设备的限制，不可用时退回到默认值，而不是让创建失败
+    if let Err(e) = compute_state.set_workgroup_size(&app.device, scene.workgroup_size) {
+        eprintln!(
+            "无法使用工作组大小 {}，继续使用 {}: {e:#}",
+            scene.workgroup_size,
+            compute_state.workgroup_size()
+        );
+    }
+    // set points
+    // 所有小球共用一个带种子的随机数生成器，相同的种子总是得到相同的初始状态
+    let mut rng = rand::rngs::StdRng::seed_from_u64(scene.seed);
+
+    if let Some(lattice) = &scene.lattice {
+        compute_state
+            .seed_lattice(
+                lattice.spacing,
+                lattice.counts,
+                lattice.jitter,
+                lattice.speed,
+                |i| scene.radius_of(i),
+                &mut rng,
+            )
+            .expect("lattice is checked when the scene is validated");
+    } else {
+        for i in 0..scene.points {
+            let x = rng.gen_range(-boundary.x..boundary.x);
+            let y = rng.gen_range(-boundary.y..boundary.y);
+            let mut z = rng.gen_range(-boundary.z..boundary.z);
+
+            let vx = rng.gen_range(-1.0..1.0);
+            let vy = rng.gen_range(-1.0..1.0);
+            let mut vz = rng.gen_range(-1.0..1.0);
+
+            // 二维时照常生成 z 分量再丢掉，同一个种子在二维和三维中得到相同的 x 和 y
+            if scene.dimensions == compute::Dimensions::D2 {
+                z = 0.0;
+                vz = 0.0;
+            }
+
+            compute_state.instances.push(compute::ComputeInstance {
+                id: i,
+                position: glam::Vec3::new(x, y, z),
+                radius: scene.radius_of(i),
+                velocity: glam::Vec3::new(vx, vy, vz),
+                color: glam::Vec4::ONE,
+                collision_heat: 0.0,
+            })
+        }
+    }
+    compute_state
+        .set_emitter(scene.emitter(), scene.seed)
+        .expect("emitter is checked when the scene is validated");
+    // 场景配置已经检查过网格大小，这里再按实际的实例检查一次
+    if let Err(e) = compute_state.check_grid_size() {
+        eprintln!("{e:#}");
+    }
+
+    // 一开始就相互重叠的小球会在第一步被猛烈地弹开
+    let overlaps = if scene.relax_iterations > 0 {
+        compute_state.relax_overlaps(scene.relax_iterations)
+    } else {
+        compute_state.count_initial_overlaps()
+    };
+    if overlaps as f32 > compute::OVERLAP_WARNING_FRACTION * scene.points as f32 {
+        eprintln!(
+            "初始状态中有 {overlaps} 对小球相互重叠，第一步会把它们猛烈地弹开；\
+             可以设置 relax_iterations 先把它们推开，或者使用 lattice 排列小球"
+        );
+    }
+    // R 键恢复到这里
+    compute_state.save_initial_state();
+
+    compute_state
+}
+
+/// 返回 `presets` 中排在 `current` 后面的一项，`current` 不在其中时从第一项开始。
+fn next_preset<T: Copy + PartialEq>(presets: &[T], current: T) -> T {
+    let next = presets
+        .iter()
+        .position(|preset| *preset == current)
+        .map_or(0, |index| (index + 1) % presets.len());
+    presets[next]
+}
+
+/// 主渲染通道中所有多重采样附件的格式：颜色、深度和法线。
+fn render_target_formats(app: &AppSurface) -> [wgpu::TextureFormat; 3] {
+    [
+        app.config.format.add_srgb_suffix(),
+        texture::Texture::DEPTH_FORMAT,
+        NORMAL_FORMAT,
+    ]
+}
+
+/// 按表面当前的大小和格式创建主渲染通道的深度附件和多重采样颜色附件。
+///
+/// 所有附件的采样数都是 `sample_count`，与用同一个采样数创建的管线一致；创建和改变大小时都通过这里，
+/// 附件之间不会出现大小或采样数不一致的情况。
+///
+/// Returns:
+///
+/// 深度纹理，以及 `sample_count` 大于 1 时的多重采样颜色纹理。
+fn create_render_targets(
+    app: &AppSurface,
+    sample_count: u32,
+) -> (texture::Texture, Option<texture::Texture>) {
+    let depth_texture = texture::Texture::create_depth_texture(
+        &app.device,
+        &app.config,
+        "depth_texture",
+        sample_count,
+    );
+    let msaa_texture = (sample_count > 1).then(|| {
+        texture::Texture::create_msaa_texture(
+            &app.device,
+            &app.config,
+            "msaa_texture",
+            sample_count,
+        )
+    });
+    (depth_texture, msaa_texture)
+}
+
+/// 创建绘制光源的渲染管线。
+fn create_light_render_pipeline(
+    app: &AppSurface,
+    layout: &wgpu::PipelineLayout,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let shader = wgpu::ShaderModuleDescriptor {
+        label: Some("Light Shader"),
+        source: wgpu::ShaderSource::Wgsl(shaders::shader_source!("light.wgsl")),
+    };
+    utils::create_render_pipeline(
+        &app.device,
+        layout,
+        app.config.format,
+        Some(texture::Texture::DEPTH_FORMAT),
+        &[model::ModelVertex::desc()],
+        shader,
+        wgpu::PolygonMode::Fill,
+        sample_count,
+    )
+}
+
+/// 创建绘制小球的渲染管线，`polygon_mode` 为 `Line` 时是线框模式。
+///
+/// `depth_prepass` 为 `true` 时，深度已经由深度预渲染写好，管线只用 `LessEqual` 比较深度而不再写入。
+/// `normals` 为 `true` 时，管线同时把视图空间的法线写入第二个颜色目标。
+fn create_sphere_pipeline(
+    app: &AppSurface,
+    layout: &wgpu::PipelineLayout,
+    sample_count: u32,
+    polygon_mode: wgpu::PolygonMode,
+    depth_prepass: bool,
+    normals: bool,
+) -> wgpu::RenderPipeline {
+    let shader = wgpu::ShaderModuleDescriptor {
+        label: Some("Normal Shader"),
+        source: wgpu::ShaderSource::Wgsl(shaders::shader_source!("draw.wgsl")),
+    };
+    let (depth_write_enabled, depth_compare) = if depth_prepass {
+        (false, wgpu::CompareFunction::LessEqual)
+    } else {
+        (true, wgpu::CompareFunction::Less)
+    };
+    let color_target = utils::color_target(app.config.format);
+    let (targets, fs_entry_point) = if normals {
+        let normal_target = Some(wgpu::ColorTargetState {
+            format: NORMAL_FORMAT,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+        });
+        (vec![color_target, normal_target], "fs_normals")
+    } else {
+        (vec![color_target], "fs_main")
+    };
+    utils::create_custom_render_pipeline(
+        &app.device,
+        layout,
+        &targets,
+        Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled,
+            depth_compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        &[model::ModelVertex::desc(), instance::InstanceRaw::desc()],
+        shader,
+        fs_entry_point,
+        polygon_mode,
+        sample_count,
+    )
+}
+
+/// 创建绘制小球需要的所有管线：填充模式、线框模式（设备不支持时为 `None`）和深度预渲染之后使用的管线。
+fn create_sphere_pipelines(
+    app: &AppSurface,
+    layout: &wgpu::PipelineLayout,
+    sample_count: u32,
+    normals: bool,
+) -> (
+    wgpu::RenderPipeline,
+    Option<wgpu::RenderPipeline>,
+    wgpu::RenderPipeline,
+) {
+    let create = |polygon_mode, depth_prepass| {
+        create_sphere_pipeline(
+            app,
+            layout,
+            sample_count,
+            polygon_mode,
+            depth_prepass,
+            normals,
+        )
+    };
+    // 适配器支持时 AppSurface 创建设备时已经请求了线框模式需要的特性
+    let wireframe_render_pipeline = if app
+        .device
+        .features()
+        .contains(wgpu::Features::POLYGON_MODE_LINE)
+    {
+        Some(create(wgpu::PolygonMode::Line, false))
+    } else {
+        // 缺少这个特性的提示由 requirements::check 打印
+        None
+    };
+    (
+        create(wgpu::PolygonMode::Fill, false),
+        wireframe_render_pipeline,
+        create(wgpu::PolygonMode::Fill, true),
+    )
 }
 
 impl State {
-    async fn new(app: AppSurface) -> Self {
-        // Camera
-        let camera_state = camera::CameraState::new(&app);
-        // Light
-        let light_state = light::LightState::new(&app);
+    /// 按照场景配置创建初始场景。
+    async fn from_config(app: AppSurface, scene: &config::SceneConfig) -> Self {
+        let boundary = scene.boundary.half_extents();
+
+        // Camera, 二维时从 +Z 方向正对 z = 0 的平面，使用能看到整个边界的正交投影
+        let mut camera_state = match scene.dimensions {
+            compute::Dimensions::D2 => {
+                let mut camera_state = camera::CameraState::new(
+                    &app,
+                    camera::Camera::new([0.0, 0.0, boundary.z + 1.0], -90.0, 0.0),
+                );
+                camera_state
+                    .projection
+                    .set_orthographic(Some(boundary.x.max(boundary.y) * 1.1));
+                // 跟随小球时仍然正对 z = 0 的平面
+                camera_state.set_follow_offset(glam::Vec3::new(0.0, 0.0, boundary.z + 1.0));
+                camera_state
+            }
+            compute::Dimensions::D3 => camera::CameraState::new(
+                &app,
+                camera::Camera::new(scene.camera.position, scene.camera.yaw, scene.camera.pitch),
+            ),
+        };
+        camera_state.camera_controller.set_gamepad_settings(
+            scene.camera.gamepad_deadzone,
+            scene.camera.gamepad_sensitivity,
+        );
+        camera_state
+            .camera_controller
+            .set_invert_y(scene.camera.invert_y);
+        camera_state
+            .camera_controller
+            .set_smoothing(scene.camera.smoothing);
+        // 远平面随相机到边界的距离调整，边界很大时远处的小球不会被裁掉
+        camera_state.set_scene_bounds(Some(boundary));
+        // 锁定宽高比时投影使用视口的宽高比，视口之外是黑边
+        let viewport =
+            utils::letterbox_viewport(app.config.width, app.config.height, scene.aspect_ratio);
+        camera_state
+            .projection
+            .resize(viewport[2] as u32, viewport[3] as u32);
+        // Light, 阴影贴图需要覆盖整个边界盒子
+        let mut light_state = light::LightState::new(&app, boundary.length());
+        light_state.set_ambient(glam::Vec3::from_array(scene.lighting.ambient));
+        light_state.set_exposure(scene.lighting.exposure);
+        if !scene.lighting.lights.is_empty() {
+            let lights = scene
+                .lighting
+                .lights
+                .iter()
+                .map(|light| light::LightUniform::new(light.position, light.color))
+                .collect::<Vec<_>>();
+            light_state.set_lights(&app, &lights);
+        }
+        // SSAO, 采样半径取小球的最大直径
+        let mut ssao_state = ssao::SsaoState::new(&app, scene.grid_size());
+        ssao_state.set_viewport(viewport);
+        // Shadow
+        let shadow_state = shadow::ShadowState::new(
+            &app,
+            &light_state.light_bind_group_layout,
+            ssao_state.ao_view(),
+        );
 
         let texture_bind_group_layout =
             app.device
@@ -81,16 +550,30 @@ impl State {
                             ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                             count: None,
                         },
+                        // specular parameters of the material
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 4,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
                     ],
                     label: Some("texture_bind_group_layout"),
                 });
 
-        let depth_texture =
-            texture::Texture::create_depth_texture(&app.device, &app.config, "depth_texture");
+        let sample_count = utils::supported_sample_count(
+            &app.adapter,
+            &render_target_formats(&app),
+            scene.sample_count,
+        );
+        let (depth_texture, msaa_texture) = create_render_targets(&app, sample_count);
 
-        let light_render_pipeline = {
-            let layout = app
-                .device
+        let light_pipeline_layout =
+            app.device
                 .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                     label: Some("Light Pipeline Layout"),
                     bind_group_layouts: &[
@@ -99,19 +582,8 @@ impl State {
                     ],
                     push_constant_ranges: &[],
                 });
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Light Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/light.wgsl").into()),
-            };
-            utils::create_render_pipeline(
-                &app.device,
-                &layout,
-                app.config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc()],
-                shader,
-            )
-        };
+        let light_render_pipeline =
+            create_light_render_pipeline(&app, &light_pipeline_layout, sample_count);
 
         let render_pipeline_layout =
             app.device
@@ -121,74 +593,153 @@ impl State {
                         &texture_bind_group_layout,
                         &camera_state.camera_bind_group_layout,
                         &light_state.light_bind_group_layout,
+                        &shadow_state.shadow_bind_group_layout,
                     ],
                     push_constant_ranges: &[],
                 });
 
-        let render_pipeline = {
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Normal Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/draw.wgsl").into()),
-            };
-            utils::create_render_pipeline(
-                &app.device,
-                &render_pipeline_layout,
-                app.config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc(), instance::InstanceRaw::desc()],
-                shader,
-            )
-        };
+        let (render_pipeline, wireframe_render_pipeline, prepass_render_pipeline) =
+            create_sphere_pipelines(&app, &render_pipeline_layout, sample_count, false);
 
-        let boundary = 10.0;
-        let points_cnt = 5000;
-        let radius = 0.2f32;
-
-        // 统一的用来画的模型（目前是一个球体）
+        // 统一的用来画的模型（默认是一个单位球体），每个实例按自己的半径缩放
         let obj_model = resources::load_model(
-            "sphere.obj",
+            &scene.model,
             &app.device,
             &app.queue,
             &texture_bind_group_layout,
-            radius,
         )
         .await
         .unwrap();
 
-        let mut compute_state = compute::ComputeState::new(&app, points_cnt as u32, boundary, 2.0 * radius);
-        // set points
+        let compute_state = create_compute_state(&app, scene);
 
-        for i in 0..points_cnt {
-            let x = rand::rngs::ThreadRng::default().gen_range(-boundary..boundary);
-            let y = rand::rngs::ThreadRng::default().gen_range(-boundary..boundary);
-            let z = rand::rngs::ThreadRng::default().gen_range(-boundary..boundary);
+        // 绘制小球时的实例数量直接取自 GPU 上的实例 buffer
+        let indirect_state = indirect::IndirectDrawState::new(
+            &app,
+            &obj_model,
+            compute_state.instances_buffers.clone(),
+        );
 
-            let vx = rand::rngs::ThreadRng::default().gen_range(-1.0..1.0);
-            let vy = rand::rngs::ThreadRng::default().gen_range(-1.0..1.0);
-            let vz = rand::rngs::ThreadRng::default().gen_range(-1.0..1.0);
+        // instance_state for rendering
+        let instance_state = instance::InstanceState::new(
+            &app,
+            &compute_state.instances,
+            compute_state.buffer_len(),
+        );
 
-            compute_state.instances.push(compute::ComputeInstance {
-                id: i as u32,
-                position: glam::Vec3::new(x, y, z),
-                radius,
-                velocity: glam::Vec3::new(vx, vy, vz),
-            })
-        }
+        // 模拟边界的线框
+        let boundary_state = boundary::BoundaryState::new(
+            &app,
+            &camera_state.camera_bind_group_layout,
+            boundary,
+            sample_count,
+        );
 
-        // instance_state for rendering
-        let instance_state = instance::InstanceState::new(&app, &compute_state.instances);
+        // 地面网格，默认在边界的底面
+        let mut floor_state = floor::FloorState::new(
+            &app,
+            &camera_state.camera_bind_group_layout,
+            scene.floor.height.unwrap_or(-boundary.y),
+            scene.floor.spacing,
+            sample_count,
+        );
+        floor_state.visible = scene.floor.visible;
+
+        // 选中小球的轮廓
+        let outline_state =
+            outline::OutlineState::new(&app, &camera_state.camera_bind_group_layout, sample_count);
+
+        // 碰撞检测网格的调试视图
+        let grid_state = grid::GridState::new(
+            &app,
+            &camera_state.camera_bind_group_layout,
+            &compute_state.params_buffer,
+            &compute_state.cell_index_buffer,
+            sample_count,
+        );
+
+        // 背景渐变，没有设置时只用清屏颜色
+        let mut background_state =
+            background::BackgroundState::new(&app, scene.background.clear_color(), sample_count);
+        background_state.set_gradient(&app, scene.background.gradient());
+
+        // 被跟踪小球的轨迹
+        let trail_state =
+            trail::TrailState::new(&app, &camera_state.camera_bind_group_layout, sample_count);
+
+        // 小球很多时可以换成球体替身绘制
+        let impostor_state = impostor::ImpostorState::new(
+            &app,
+            &camera_state.camera_bind_group_layout,
+            &light_state.light_bind_group_layout,
+            &shadow_state.shadow_bind_group_layout,
+            sample_count,
+        );
+
+        // 小球互相遮挡很多时，可以先只渲染深度，减少颜色阶段的重复着色
+        let prepass_state = prepass::DepthPrepassState::new(
+            &app,
+            &camera_state.camera_bind_group_layout,
+            sample_count,
+        );
+
+        // 帧率和模拟统计的文字
+        let overlay = overlay::TextOverlay::new(&app);
+
+        // 法线输出的调试视图，开启法线输出之后才显示
+        let normal_view_state = normals::NormalViewState::new(&app);
+        let depth_resolve_state = depth_resolve::DepthResolveState::new(&app);
 
         Self {
             app,
             render_pipeline,
+            wireframe_render_pipeline,
+            prepass_render_pipeline,
             light_render_pipeline,
+            render_pipeline_layout,
+            light_pipeline_layout,
+            shader_watcher: shaders::ShaderWatcher::new(),
+            wireframe: false,
+            render_mode: impostor::RenderMode::Mesh,
+            impostor_state,
             obj_model,
             camera_state,
             light_state,
+            shadow_state,
+            prepass_state,
+            ssao_state,
             compute_state,
+            gravity_target: glam::Vec3::NEG_Y,
             instance_state,
+            indirect_state,
+            boundary_state,
+            floor_state,
+            floor: scene.floor.clone(),
+            outline_state,
+            clear_color: scene.background.clear_color(),
+            background_state,
+            trail_state,
+            grid_state,
             depth_texture,
-            last_fps_update: std::time::Instant::now(),
+            sample_count,
+            msaa_texture,
+            normal_texture: None,
+            msaa_normal_texture: None,
+            normal_view_state,
+            depth_resolve_state,
+            cursor_position: winit::dpi::PhysicalPosition::new(0.0, 0.0),
+            paused: false,
+            single_step: false,
+            is_minimized: false,
+            aspect_ratio: scene.aspect_ratio,
+            viewport,
+            focused: true,
+            pause_on_unfocus: scene.pause_on_unfocus,
+            show_diagnostics: false,
+            previous_positions: Vec::new(),
+            overlay,
+            fps: 0.0,
+            gamepad: gamepad::GamepadState::new(),
         }
     }
 
@@ -214,16 +765,181 @@ impl State {
     /// new size in pixels.
     fn resize(&mut self, new_size: &winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
+            self.viewport =
+                utils::letterbox_viewport(new_size.width, new_size.height, self.aspect_ratio);
             self.camera_state
                 .projection
-                .resize(new_size.width, new_size.height);
+                .resize(self.viewport[2] as u32, self.viewport[3] as u32);
+            self.ssao_state.set_viewport(self.viewport);
             self.app.resize_surface();
-            self.depth_texture = texture::Texture::create_depth_texture(
+            self.recreate_render_targets();
+            self.ssao_state.resize(&self.app);
+            self.shadow_state
+                .set_ao_view(&self.app, self.ssao_state.ao_view());
+            self.overlay.resize(&self.app);
+        }
+    }
+
+    /// 按当前的表面配置和采样数重建所有与表面同样大小的附件，包括开启时的法线附件。
+    fn recreate_render_targets(&mut self) {
+        (self.depth_texture, self.msaa_texture) =
+            create_render_targets(&self.app, self.sample_count);
+        if self.normal_texture.is_some() {
+            self.create_normal_textures();
+        }
+    }
+
+    /// 记录窗口是否有焦点。失去焦点时收不到松开按键的事件，清除按住的移动键，避免相机继续漂移。
+    fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+        if !focused {
+            self.camera_state.camera_controller.reset_movement();
+        }
+    }
+
+    /// 开启了 `pause_on_unfocus` 并且窗口失去焦点时不推进模拟和相机，只在系统要求时重绘。
+    fn is_unfocus_paused(&self) -> bool {
+        self.pause_on_unfocus && !self.focused
+    }
+
+    /// 表面丢失或过期时按窗口当前的大小重新配置表面，和窗口大小改变时走同样的路径。
+    fn reconfigure_surface(&mut self) {
+        let size = self.app.view.inner_size();
+        self.resize(&size);
+    }
+
+    // 按数字键调节模拟参数，限制在各自的范围内，下一次 update 写入参数时生效
+    fn adjust_parameter(&mut self, key: VirtualKeyCode) {
+        let compute_state = &mut self.compute_state;
+        match key {
+            VirtualKeyCode::Key1 | VirtualKeyCode::Key2 => {
+                let step = if key == VirtualKeyCode::Key1 {
+                    -GRAVITY_KEY_STEP
+                } else {
+                    GRAVITY_KEY_STEP
+                };
+                compute_state.gravity =
+                    (compute_state.gravity + step).clamp(-MAX_KEY_GRAVITY, MAX_KEY_GRAVITY);
+            }
+            VirtualKeyCode::Key3 | VirtualKeyCode::Key4 => {
+                let step = if key == VirtualKeyCode::Key3 {
+                    -RESTITUTION_KEY_STEP
+                } else {
+                    RESTITUTION_KEY_STEP
+                };
+                // 按步长取整，避免多次加减之后累积浮点误差
+                let restitution = ((compute_state.restitution + step) / RESTITUTION_KEY_STEP)
+                    .round()
+                    * RESTITUTION_KEY_STEP;
+                compute_state.restitution = restitution.clamp(0.0, 1.0);
+            }
+            VirtualKeyCode::Key5 => {
+                compute_state.substeps = compute_state.substeps.saturating_sub(1).max(1);
+            }
+            // 场景中给出的子步数可以超过按键的上限，此时只能减少
+            VirtualKeyCode::Key6 if compute_state.substeps < MAX_KEY_SUBSTEPS => {
+                compute_state.substeps += 1;
+            }
+            _ => {}
+        }
+    }
+
+    // 按小键盘旋转重力的目标方向：8/2 绕 X 轴，4/6 绕 Z 轴，5 恢复向下；实际的方向在 step 中逐渐转过去
+    fn rotate_gravity(&mut self, key: VirtualKeyCode) {
+        let rotation = match key {
+            VirtualKeyCode::Numpad8 => glam::Quat::from_rotation_x(GRAVITY_KEY_ANGLE),
+            VirtualKeyCode::Numpad2 => glam::Quat::from_rotation_x(-GRAVITY_KEY_ANGLE),
+            VirtualKeyCode::Numpad4 => glam::Quat::from_rotation_z(-GRAVITY_KEY_ANGLE),
+            VirtualKeyCode::Numpad6 => glam::Quat::from_rotation_z(GRAVITY_KEY_ANGLE),
+            _ => {
+                self.gravity_target = glam::Vec3::NEG_Y;
+                return;
+            }
+        };
+        self.gravity_target = (rotation * self.gravity_target).normalize();
+    }
+
+    // 按表面大小和 MSAA 采样数创建法线渲染目标
+    fn create_normal_textures(&mut self) {
+        self.normal_texture = Some(texture::Texture::create_render_target(
+            &self.app.device,
+            &self.app.config,
+            NORMAL_FORMAT,
+            "normal_texture",
+            1,
+        ));
+        self.msaa_normal_texture = (self.sample_count > 1).then(|| {
+            texture::Texture::create_render_target(
                 &self.app.device,
                 &self.app.config,
-                "depth_texture",
-            );
+                NORMAL_FORMAT,
+                "msaa_normal_texture",
+                self.sample_count,
+            )
+        });
+        let normal_view = self.normal_texture.as_ref().map(|texture| &texture.view);
+        self.normal_view_state
+            .set_normal_view(&self.app, normal_view);
+    }
+
+    /// 改变 MSAA 采样数，重建与表面同样大小的附件和所有用到采样数的管线。
+    ///
+    /// Arguments:
+    ///
+    /// * `sample_count`: 新的采样数，只能是 1、2、4 或 8，设备不支持时退回到 1。
+    fn set_sample_count(&mut self, sample_count: u32) {
+        let sample_count = utils::supported_sample_count(
+            &self.app.adapter,
+            &render_target_formats(&self.app),
+            sample_count,
+        );
+        if sample_count == self.sample_count {
+            return;
         }
+        self.sample_count = sample_count;
+        self.recreate_render_targets();
+        // 管线的采样数必须和附件一致，按热重载的方式用新的采样数重建
+        self.reload_shaders(&MULTISAMPLED_SHADERS.map(String::from));
+        println!("MSAA 采样数: {sample_count}");
+    }
+
+    /// 按 `SAMPLE_COUNTS` 的顺序找到当前采样数之后第一个设备支持的采样数，都不支持时返回 1。
+    fn next_sample_count(&self) -> u32 {
+        let formats = render_target_formats(&self.app);
+        let current = SAMPLE_COUNTS
+            .iter()
+            .position(|n| *n == self.sample_count)
+            .unwrap_or(0);
+        (1..=SAMPLE_COUNTS.len())
+            .map(|i| SAMPLE_COUNTS[(current + i) % SAMPLE_COUNTS.len()])
+            .find(|n| utils::sample_count_supported(&self.app.adapter, &formats, *n))
+            .unwrap_or(1)
+    }
+
+    /// 开启或关闭视图空间法线的输出。开启后绘制网格小球时同时把法线写入 `normal_texture`，
+    /// 背景和球体替身所在的像素为 0，有小球的像素 w 为 1，画面换成显示法线的调试视图。
+    fn set_normals_enabled(&mut self, enabled: bool) {
+        if enabled == self.normal_texture.is_some() {
+            return;
+        }
+        if enabled {
+            self.create_normal_textures();
+        } else {
+            self.normal_texture = None;
+            self.msaa_normal_texture = None;
+            self.normal_view_state.set_normal_view(&self.app, None);
+        }
+        // 颜色目标的数量变了，需要重建绘制小球的管线
+        (
+            self.render_pipeline,
+            self.wireframe_render_pipeline,
+            self.prepass_render_pipeline,
+        ) = create_sphere_pipelines(
+            &self.app,
+            &self.render_pipeline_layout,
+            self.sample_count,
+            enabled,
+        );
     }
 
     /// This function handles various input events such as keyboard input, mouse wheel scrolling, and mouse
@@ -238,7 +954,660 @@ impl State {
     ///
     /// a boolean value.
     fn input(&mut self, event: &WindowEvent) -> bool {
-        return self.camera_state.input(event);
+        match event {
+            // C 键切换实例的颜色模式
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::C),
+                        ..
+                    },
+                ..
+            } => {
+                self.instance_state.color_mode = self.instance_state.color_mode.next();
+                true
+            }
+            // P 键切换小球的线框/填充模式
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::P),
+                        ..
+                    },
+                ..
+            } => {
+                if self.wireframe_render_pipeline.is_some() {
+                    self.wireframe = !self.wireframe;
+                } else {
+                    println!("当前设备不支持 POLYGON_MODE_LINE，线框模式不可用");
+                }
+                true
+            }
+            // L 键开启/关闭光源的自动旋转
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::L),
+                        ..
+                    },
+                ..
+            } => {
+                self.light_state.auto_rotate = !self.light_state.auto_rotate;
+                true
+            }
+            // B 键显示/隐藏边界线框
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::B),
+                        ..
+                    },
+                ..
+            } => {
+                self.boundary_state.visible = !self.boundary_state.visible;
+                true
+            }
+            // G 键显示/隐藏碰撞检测网格中被占用的单元
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::G),
+                        ..
+                    },
+                ..
+            } => {
+                self.grid_state.visible = !self.grid_state.visible;
+                true
+            }
+            // H 键显示/隐藏地面网格
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::H),
+                        ..
+                    },
+                ..
+            } => {
+                self.floor_state.visible = !self.floor_state.visible;
+                true
+            }
+            // J 键切换第一个光源的颜色
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::J),
+                        ..
+                    },
+                ..
+            } => {
+                let color = self.light_state.color(0).unwrap_or(glam::Vec3::ONE);
+                self.light_state
+                    .set_color(0, next_preset(&LIGHT_COLORS, color));
+                true
+            }
+            // U 键切换清屏颜色，设置了背景渐变时被渐变覆盖
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::U),
+                        ..
+                    },
+                ..
+            } => {
+                self.set_clear_color(next_preset(&CLEAR_COLORS, self.clear_color));
+                true
+            }
+            // K 键暂停/继续模拟；空格已经用于相机上升，P 键用于线框模式
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::K),
+                        ..
+                    },
+                ..
+            } => {
+                self.paused = !self.paused;
+                true
+            }
+            // F 键开启/关闭视锥剔除
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F),
+                        ..
+                    },
+                ..
+            } => {
+                self.instance_state.culling = !self.instance_state.culling;
+                true
+            }
+            // E 键显示/隐藏总动能和总动量
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::E),
+                        ..
+                    },
+                ..
+            } => {
+                self.show_diagnostics = !self.show_diagnostics;
+                true
+            }
+            // M 键在网格和球体替身两种绘制方式之间切换
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::M),
+                        ..
+                    },
+                ..
+            } => {
+                self.render_mode = self.render_mode.next();
+                true
+            }
+            // T 键显示/隐藏叠加文字
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::T),
+                        ..
+                    },
+                ..
+            } => {
+                self.overlay.visible = !self.overlay.visible;
+                true
+            }
+            // X 键循环切换碰撞阶段的宽相位，暴力查找可以用来检查网格和 LBVH 是否漏掉了碰撞
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::X),
+                        ..
+                    },
+                ..
+            } => {
+                self.compute_state.broad_phase = self.compute_state.broad_phase.next();
+                true
+            }
+            // R 键把模拟恢复到初始状态，不重新创建 buffer 和管线
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::R),
+                        ..
+                    },
+                ..
+            } => {
+                self.reset();
+                true
+            }
+            // [ 和 ] 键把时间缩放减半或加倍，减到最小值以下时停在 0，不推进模拟
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode:
+                            Some(key @ (VirtualKeyCode::LBracket | VirtualKeyCode::RBracket)),
+                        ..
+                    },
+                ..
+            } => {
+                let time_scale = self.compute_state.time_scale();
+                let time_scale = if *key == VirtualKeyCode::LBracket {
+                    if time_scale > MIN_KEY_TIME_SCALE {
+                        time_scale / 2.0
+                    } else {
+                        0.0
+                    }
+                } else {
+                    (time_scale * 2.0).clamp(MIN_KEY_TIME_SCALE, compute::MAX_TIME_SCALE)
+                };
+                if let Err(e) = self.compute_state.set_time_scale(time_scale) {
+                    eprintln!("{e:#}");
+                }
+                true
+            }
+            // 1/2 键减小/增大重力，3/4 键减小/增大恢复系数，5/6 键减少/增加子步数
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode:
+                            Some(
+                                key @ (VirtualKeyCode::Key1
+                                | VirtualKeyCode::Key2
+                                | VirtualKeyCode::Key3
+                                | VirtualKeyCode::Key4
+                                | VirtualKeyCode::Key5
+                                | VirtualKeyCode::Key6),
+                            ),
+                        ..
+                    },
+                ..
+            } => {
+                self.adjust_parameter(*key);
+                true
+            }
+            // 小键盘 8/2/4/6 键旋转重力的方向，5 键恢复向下，小球会流向新的低处
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode:
+                            Some(
+                                key @ (VirtualKeyCode::Numpad8
+                                | VirtualKeyCode::Numpad2
+                                | VirtualKeyCode::Numpad4
+                                | VirtualKeyCode::Numpad6
+                                | VirtualKeyCode::Numpad5),
+                            ),
+                        ..
+                    },
+                ..
+            } => {
+                self.rotate_gravity(*key);
+                true
+            }
+            // 7/8/9 键切换到俯视、正视、侧视的正交视图，0 键回到原来的相机
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode:
+                            Some(
+                                key @ (VirtualKeyCode::Key7
+                                | VirtualKeyCode::Key8
+                                | VirtualKeyCode::Key9
+                                | VirtualKeyCode::Key0),
+                            ),
+                        ..
+                    },
+                ..
+            } => {
+                let view = match key {
+                    VirtualKeyCode::Key7 => camera::View::Top,
+                    VirtualKeyCode::Key8 => camera::View::Front,
+                    VirtualKeyCode::Key9 => camera::View::Side,
+                    _ => camera::View::Default,
+                };
+                self.camera_state
+                    .snap_view(view, self.compute_state.boundary());
+                true
+            }
+            // Home 键移动相机，对准所有的小球
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::Home),
+                        ..
+                    },
+                ..
+            } => {
+                let (min, max) = self.instance_bounds();
+                self.camera_state.frame_bounds(min, max);
+                true
+            }
+            // Z 键开启/关闭小球的深度预渲染
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::Z),
+                        ..
+                    },
+                ..
+            } => {
+                self.prepass_state.enabled = !self.prepass_state.enabled;
+                true
+            }
+            // O 键开启/关闭屏幕空间环境光遮蔽
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::O),
+                        ..
+                    },
+                ..
+            } => {
+                let enabled = !self.ssao_state.enabled();
+                self.ssao_state.set_enabled(&self.app, enabled);
+                true
+            }
+            // 暂停时 N 键单步推进一次模拟
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::N),
+                        ..
+                    },
+                ..
+            } => {
+                self.single_step = self.paused;
+                true
+            }
+            // F2 键依次切换 MSAA 采样数，跳过设备不支持的采样数
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F2),
+                        ..
+                    },
+                ..
+            } => {
+                self.set_sample_count(self.next_sample_count());
+                true
+            }
+            // F3 键开启/关闭法线输出，开启时显示法线的调试视图
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F3),
+                        ..
+                    },
+                ..
+            } => {
+                self.set_normals_enabled(self.normal_texture.is_none());
+                true
+            }
+            // F5 键把模拟状态保存到文件，F9 键从文件恢复
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F5),
+                        ..
+                    },
+                ..
+            } => {
+                self.save_state();
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F9),
+                        ..
+                    },
+                ..
+            } => {
+                self.load_state();
+                true
+            }
+            // V 键让相机跟随光标下的小球，正在跟随时停止跟随
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::V),
+                        ..
+                    },
+                ..
+            } => {
+                if self.camera_state.following().is_some() {
+                    self.camera_state.follow(None);
+                } else if let Some(id) = self.pick() {
+                    self.camera_state.follow(Some(id));
+                }
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = *position;
+                false
+            }
+            // 右键按下时开始或停止跟踪光标下小球的轨迹
+            WindowEvent::MouseInput {
+                button: MouseButton::Right,
+                state: ElementState::Pressed,
+                ..
+            } => {
+                if let Some(id) = self.pick() {
+                    if !self.untrack_instance(id) {
+                        self.track_instance(id);
+                    }
+                }
+                true
+            }
+            // 中键按下时读回光标处的深度，打印对应的世界空间位置
+            WindowEvent::MouseInput {
+                button: MouseButton::Middle,
+                state: ElementState::Pressed,
+                ..
+            } => {
+                if let Some(position) = self.cursor_world_position() {
+                    println!("光标处的位置 {position:.2}");
+                }
+                true
+            }
+            // 左键按下时拾取并选中光标下的小球，同时仍交给相机处理拖拽
+            WindowEvent::MouseInput {
+                button: MouseButton::Left,
+                state: ElementState::Pressed,
+                ..
+            } => {
+                let picked = self.pick();
+                self.set_selected(picked.as_slice());
+                self.camera_state.input(event)
+            }
+            _ => self.camera_state.input(event),
+        }
+    }
+
+    /// 最近一次读回的所有小球（包括半径）的包围盒，没有小球时返回边界。
+    fn instance_bounds(&self) -> (glam::Vec3, glam::Vec3) {
+        let instances = &self.compute_state.instances;
+        if instances.is_empty() {
+            let boundary = self.compute_state.boundary();
+            return (-boundary, boundary);
+        }
+        instances.iter().fold(
+            (glam::Vec3::splat(f32::MAX), glam::Vec3::splat(f32::MIN)),
+            |(min, max), instance| {
+                (
+                    min.min(instance.position - instance.radius),
+                    max.max(instance.position + instance.radius),
+                )
+            },
+        )
+    }
+
+    /// 拾取光标下的小球并打印其 id，没有选中时返回 `None`。
+    fn pick(&self) -> Option<u32> {
+        // 拾取需要阻塞地读回结果，浏览器中不支持
+        if cfg!(target_arch = "wasm32") {
+            return None;
+        }
+        let (cursor, width, height) = self.viewport_cursor();
+        let (origin, direction) = self.camera_state.screen_ray(cursor, width, height);
+        match self.compute_state.pick_app(&self.app, origin, direction) {
+            Some((id, distance)) => {
+                println!("选中了小球 {id}，距离 {distance:.2}");
+                Some(id)
+            }
+            None => {
+                println!("没有选中任何小球");
+                None
+            }
+        }
+    }
+
+    /// 替换选中的小球，选中的小球画上轮廓，轮廓跟随小球移动，传入空的切片时取消选中。
+    ///
+    /// Arguments:
+    ///
+    /// * `ids`: 选中的小球的 id，最多 `outline::MAX_SELECTED` 个，不存在的 id 被忽略。
+    fn set_selected(&mut self, ids: &[u32]) {
+        if !self.outline_state.set_selected(ids) {
+            eprintln!(
+                "最多只能选中 {} 个小球，其余的被忽略",
+                outline::MAX_SELECTED
+            );
+        }
+    }
+
+    /// 读回光标处的深度并还原成世界空间中的位置，光标处没有绘制任何东西时返回 `None`。
+    fn cursor_world_position(&mut self) -> Option<glam::Vec3> {
+        // 读回需要阻塞，浏览器中不支持
+        if cfg!(target_arch = "wasm32") {
+            return None;
+        }
+        // 多采样的深度纹理不能读回，开启 MSAA 时先解析成单采样的深度
+        let depth_texture = self
+            .depth_resolve_state
+            .resolve(&self.app, &self.depth_texture);
+        let x = self.cursor_position.x as u32;
+        let y = self.cursor_position.y as u32;
+        let depth = match depth_texture.read_depth_at(&self.app.device, &self.app.queue, x, y) {
+            Ok(depth) => depth,
+            Err(e) => {
+                eprintln!("无法读回光标处的深度: {e:#}");
+                return None;
+            }
+        };
+        if depth >= 1.0 {
+            eprintln!("光标处没有绘制任何东西");
+            return None;
+        }
+        let (cursor, width, height) = self.viewport_cursor();
+        Some(self.camera_state.unproject(cursor, width, height, depth))
+    }
+
+    /// 光标相对于视口左上角的位置，以及视口的宽和高；锁定宽高比时去掉了黑边。
+    fn viewport_cursor(&self) -> (winit::dpi::PhysicalPosition<f64>, u32, u32) {
+        let [x, y, width, height] = self.viewport;
+        let cursor = winit::dpi::PhysicalPosition::new(
+            self.cursor_position.x - x as f64,
+            self.cursor_position.y - y as f64,
+        );
+        (cursor, width as u32, height as u32)
+    }
+
+    /// 开始记录并绘制小球 `id` 的轨迹。跟踪的数量达到上限或者已经在跟踪时返回 `false`。
+    fn track_instance(&mut self, id: u32) -> bool {
+        let tracked = self.trail_state.track(id);
+        if !tracked && !self.trail_state.is_tracked(id) {
+            println!("最多同时跟踪 {} 个小球", trail::MAX_TRACKED);
+        }
+        tracked
+    }
+
+    /// 停止跟踪小球 `id` 并丢弃它的轨迹。没有在跟踪时返回 `false`。
+    fn untrack_instance(&mut self, id: u32) -> bool {
+        self.trail_state.untrack(id)
+    }
+
+    /// 设置清屏颜色（线性空间），设置了背景渐变时被渐变覆盖。
+    fn set_clear_color(&mut self, clear_color: wgpu::Color) {
+        self.clear_color = clear_color;
+        self.background_state
+            .set_clear_color(&self.app, clear_color);
+    }
+
+    /// 把模拟状态保存到 `STATE_FILE`，读回间隔大于 1 时先同步最新的结果。
+    fn save_state(&mut self) {
+        // 文件读写和阻塞的读回在浏览器中都不支持
+        if cfg!(target_arch = "wasm32") {
+            return;
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if !self.compute_state.is_synced() {
+            self.compute_state.read_results(&self.app.device);
+        }
+        match self.compute_state.save_state(STATE_FILE) {
+            Ok(()) => println!("模拟状态已保存到 {STATE_FILE}"),
+            Err(e) => eprintln!("无法保存模拟状态: {e:#}"),
+        }
+    }
+
+    /// 从 `STATE_FILE` 恢复模拟状态，重新创建引用了计算 buffer 的渲染状态，之后 R 键恢复到读入的状态。
+    ///
+    /// 状态文件不包含宽相位、工作组大小、静态平面和发射器，它们保持当前的设置或者被清空。
+    fn load_state(&mut self) {
+        if cfg!(target_arch = "wasm32") {
+            return;
+        }
+        let device = &self.app.device;
+        let mut compute_state = match compute::ComputeState::load_state(device, STATE_FILE) {
+            Ok(compute_state) => compute_state,
+            Err(e) => {
+                eprintln!("无法读取模拟状态 {STATE_FILE}: {e:#}");
+                return;
+            }
+        };
+        if let Err(e) =
+            compute_state.set_workgroup_size(device, self.compute_state.workgroup_size())
+        {
+            eprintln!("{e:#}");
+        }
+        compute_state.write_instances_buffer(&self.app.queue);
+        compute_state.save_initial_state();
+
+        // 实例数量和 buffer 都可能变了，读取它们的渲染状态按新的计算状态重新创建
+        self.indirect_state = indirect::IndirectDrawState::new(
+            &self.app,
+            &self.obj_model,
+            compute_state.instances_buffers.clone(),
+        );
+        let mut instance_state = instance::InstanceState::new(
+            &self.app,
+            &compute_state.instances,
+            compute_state.buffer_len(),
+        );
+        instance_state.color_mode = self.instance_state.color_mode;
+        instance_state.culling = self.instance_state.culling;
+        self.instance_state = instance_state;
+        let mut grid_state = grid::GridState::new(
+            &self.app,
+            &self.camera_state.camera_bind_group_layout,
+            &compute_state.params_buffer,
+            &compute_state.cell_index_buffer,
+            self.sample_count,
+        );
+        grid_state.visible = self.grid_state.visible;
+        self.grid_state = grid_state;
+
+        // 没有设置地面高度时地面跟着新的边界的底面
+        let floor_height = self.floor.height.unwrap_or(-compute_state.boundary().y);
+        self.floor_state
+            .set_floor(&self.app, floor_height, self.floor.spacing);
+
+        self.gravity_target = compute_state.gravity_direction();
+        self.compute_state = compute_state;
+        self.previous_positions.clear();
+        self.trail_state.clear();
+        self.set_selected(&[]);
+        self.camera_state.follow(None);
+        println!("已从 {STATE_FILE} 恢复模拟状态");
+    }
+
+    /// 恢复到初始状态，轨迹和插值用的上一步位置也一起丢掉。
+    fn reset(&mut self) {
+        if let Err(e) = self.compute_state.reset_app(&self.app) {
+            eprintln!("{e:#}");
+            return;
+        }
+        self.previous_positions.clear();
+        self.trail_state.clear();
     }
 
     /// This function updates the camera and light based on the controller and writes the updated data to
@@ -251,31 +1620,281 @@ impl State {
     /// with nanosecond precision. In this code snippet, `dt` is used to update the camera and light based
     /// on the controller.
     fn update(&mut self, dt: std::time::Duration) {
-        // Update the FPS to the title
-        let now = std::time::Instant::now();
-        let is_fps_update = now - self.last_fps_update >= std::time::Duration::from_secs_f32(0.1);
-        if is_fps_update {
-            self.app
-                .view
-                .set_title(&format!("FPS: {:.2}", 1.0 / dt.as_secs_f32()));
-            self.last_fps_update = now;
-        }
+        // 帧率做指数平滑，避免叠加文字每帧跳动
+        let fps = 1.0 / dt.as_secs_f32().max(f32::EPSILON);
+        self.fps = if self.fps > 0.0 {
+            self.fps * 0.9 + fps * 0.1
+        } else {
+            fps
+        };
+        let stats = self.stats_text();
+        self.overlay.update(&self.app, &stats);
 
         // Update the camera based on the controller
-        self.camera_state.update(&self.app, dt);
+        self.camera_state
+            .update(&self.app, dt, &self.compute_state.instances);
+        self.ssao_state.update(&self.app, &self.camera_state);
         // Update the light position
-        self.light_state.update(&self.app);
+        self.light_state.update(&self.app, dt);
+
+        // 着色器文件被修改时重建对应的管线
+        let changed = self
+            .shader_watcher
+            .as_ref()
+            .map(|watcher| watcher.changed())
+            .unwrap_or_default();
+        if !changed.is_empty() {
+            self.reload_shaders(&changed);
+        }
+    }
+
+    /// 叠加文字显示的统计信息：帧率、小球数量、子步数以及可选的守恒量。
+    fn stats_text(&self) -> String {
+        let mut lines = vec![format!("FPS: {:.1}", self.fps)];
+        // 开启视锥剔除时同时显示实际绘制的实例数量
+        if self.instance_state.culling {
+            lines.push(format!(
+                "particles: {} (drawn {})",
+                self.instance_state.total_number, self.instance_state.instances_number
+            ));
+        } else {
+            lines.push(format!("particles: {}", self.compute_state.instances.len()));
+        }
+        // 显示最近一次更新实际使用的轮数，自适应子步加密时标出来
+        let substeps = self.compute_state.substeps;
+        let rounds = self.compute_state.last_rounds();
+        let time_scale = self.compute_state.time_scale();
+        if rounds == 0 {
+            lines.push(format!("substeps: {}", substeps));
+        } else {
+            let adaptive = if rounds as f32 > (substeps as f32 * time_scale).ceil() {
+                " (adaptive)"
+            } else {
+                ""
+            };
+            lines.push(format!(
+                "substeps: {} x {:.3} ms{}",
+                rounds,
+                framework::FIXED_DT.as_secs_f64() * 1000.0 * time_scale as f64 / rounds as f64,
+                adaptive
+            ));
+        }
+        lines.push(format!(
+            "gravity: {:.1}  restitution: {:.2}",
+            self.compute_state.gravity, self.compute_state.restitution
+        ));
+        let gravity_direction = self.compute_state.gravity_direction();
+        if gravity_direction != glam::Vec3::NEG_Y {
+            lines.push(format!(
+                "gravity direction: ({:.2}, {:.2}, {:.2})",
+                gravity_direction.x, gravity_direction.y, gravity_direction.z
+            ));
+        }
+        lines.push(format!("broad phase: {:?}", self.compute_state.broad_phase));
+        if time_scale != compute::DEFAULT_TIME_SCALE {
+            lines.push(format!("time scale: {}x", time_scale));
+        }
+        let (ambient, exposure) = (self.light_state.ambient(), self.light_state.exposure());
+        if ambient != glam::Vec3::from_array(light::DEFAULT_AMBIENT)
+            || exposure != light::DEFAULT_EXPOSURE
+        {
+            lines.push(format!(
+                "ambient: ({:.2}, {:.2}, {:.2})  exposure: {:.2}",
+                ambient.x, ambient.y, ambient.z, exposure
+            ));
+        }
+        if self.prepass_state.enabled {
+            lines.push("depth prepass: on".to_string());
+        }
+        if self.ssao_state.enabled() {
+            lines.push("SSAO: on".to_string());
+        }
+        let (live, capacity) = (
+            self.compute_state.live_count(),
+            self.compute_state.buffer_len(),
+        );
+        if live < capacity as usize {
+            lines.push(format!("live: {}/{}", live, capacity));
+        }
+        if self.compute_state.sleep_velocity > 0.0 {
+            lines.push(format!("sleeping: {}", self.compute_state.sleeping));
+        }
+        if self.show_diagnostics {
+            let diagnostics = self.compute_state.diagnostics();
+            lines.push(format!("E: {:.4}", diagnostics.kinetic_energy));
+            lines.push(format!(
+                "p: ({:.4}, {:.4}, {:.4})",
+                diagnostics.momentum.x, diagnostics.momentum.y, diagnostics.momentum.z
+            ));
+            lines.push(format!("overlaps: {}", diagnostics.overlaps));
+        }
+        if self.paused {
+            lines.push("[paused] K 继续  N 单步".to_string());
+        }
+        lines.join("\n")
+    }
+
+    /// 按照被修改的着色器文件重建对应的管线和计算节点。`header.wgsl` 会被拼接到所有计算着色器和网格视图的
+    /// 着色器前面，修改它会重建全部计算节点。
+    ///
+    /// Arguments:
+    ///
+    /// * `changed`: 被修改的着色器文件名。
+    fn reload_shaders(&mut self, changed: &[String]) {
+        let is_changed = |name: &str| changed.iter().any(|n| n == name);
+        let app = &self.app;
+
+        if [
+            "header.wgsl",
+            "assign.wgsl",
+            "sort.wgsl",
+            "memset.wgsl",
+            "build_grid.wgsl",
+            "collision.wgsl",
+            "bvh_assign.wgsl",
+            "bvh_build.wgsl",
+            "pick.wgsl",
+            "nearest.wgsl",
+        ]
+        .into_iter()
+        .any(is_changed)
+        {
+            self.compute_state.reload_shaders(&app.device);
+        }
+        if is_changed("header.wgsl") || is_changed("indirect.wgsl") {
+            self.indirect_state.reload_shader(app);
+        }
+        if is_changed("shadow.wgsl") {
+            self.shadow_state.reload_shader(app);
+        }
+        if is_changed("background.wgsl") {
+            self.background_state.reload_shader(app, self.sample_count);
+        }
+        if is_changed("floor.wgsl") {
+            self.floor_state.reload_shader(app, self.sample_count);
+        }
+        if is_changed("outline.wgsl") {
+            self.outline_state.reload_shader(app, self.sample_count);
+        }
+        if is_changed("boundary.wgsl") {
+            self.boundary_state.reload_shader(app, self.sample_count);
+        }
+        if is_changed("impostor.wgsl") {
+            self.impostor_state.reload_shader(app, self.sample_count);
+        }
+        if is_changed("header.wgsl") || is_changed("grid.wgsl") {
+            self.grid_state.reload_shader(app, self.sample_count);
+        }
+        if is_changed("trail.wgsl") {
+            self.trail_state.reload_shader(app, self.sample_count);
+        }
+        if is_changed("ssao.wgsl") {
+            self.ssao_state.reload_shader(app);
+        }
+        if is_changed("ssao_blur.wgsl") {
+            self.ssao_state.reload_blur_shader(app);
+        }
+        if is_changed("normals.wgsl") {
+            self.normal_view_state.reload_shader(app);
+        }
+        if is_changed("depth_resolve.wgsl") {
+            self.depth_resolve_state.reload_shader(app);
+        }
+        if is_changed("prepass.wgsl") {
+            self.prepass_state.reload_shader(app, self.sample_count);
+        }
+        if is_changed("light.wgsl") {
+            if let Some(pipeline) = shaders::try_rebuild(&app.device, "light.wgsl", || {
+                create_light_render_pipeline(app, &self.light_pipeline_layout, self.sample_count)
+            }) {
+                self.light_render_pipeline = pipeline;
+            }
+        }
+        if is_changed("draw.wgsl") {
+            let pipelines = shaders::try_rebuild(&app.device, "draw.wgsl", || {
+                create_sphere_pipelines(
+                    app,
+                    &self.render_pipeline_layout,
+                    self.sample_count,
+                    self.normal_texture.is_some(),
+                )
+            });
+            if let Some((render_pipeline, wireframe_render_pipeline, prepass_render_pipeline)) =
+                pipelines
+            {
+                self.render_pipeline = render_pipeline;
+                self.wireframe_render_pipeline = wireframe_render_pipeline;
+                self.prepass_render_pipeline = prepass_render_pipeline;
+            }
+        }
+    }
+
+    /// 以固定的时间步长推进一次模拟，与渲染帧率无关。
+    fn step(&mut self, dt: std::time::Duration) {
+        self.previous_positions = self
+            .compute_state
+            .instances
+            .iter()
+            .map(|instance| instance.position)
+            .collect();
+
+        // 重力的方向每一步转过一点，小球的堆会平滑地流向新的低处
+        self.compute_state
+            .turn_gravity_toward(self.gravity_target, GRAVITY_TURN_RATE * dt.as_secs_f32());
 
         // Do collision detection and update back the compute_state instaces
-        self.compute_state.update(&self.app, dt);
+        self.compute_state.update_app(&self.app, dt);
+        self.trail_state
+            .update(&self.app, &self.compute_state.instances);
+    }
 
-        // Update the instance buffer for rendering
+    /// 用最近两次模拟结果之间的插值更新渲染用的 instance buffer。
+    ///
+    /// Arguments:
+    ///
+    /// * `alpha`: 插值系数，0 表示上一步的位置，1 表示最新的位置。
+    fn sync_instances(&mut self, alpha: f32) {
+        let instances = &self.compute_state.instances;
+        let frustum = self.camera_state.camera_uniform.frustum();
+        if self.previous_positions.len() != instances.len() {
+            self.instance_state.update(&self.app, instances, &frustum);
+            self.outline_state.update(&self.app, instances);
+            return;
+        }
+
+        let interpolated = instances
+            .iter()
+            .zip(&self.previous_positions)
+            .map(|(instance, previous)| compute::ComputeInstance {
+                position: previous.lerp(instance.position, alpha),
+                ..*instance
+            })
+            .collect::<Vec<_>>();
         self.instance_state
-            .update(&self.app, &self.compute_state.instances);
+            .update(&self.app, &interpolated, &frustum);
+        // 轮廓使用和小球相同的插值位置，不会落后于小球
+        self.outline_state.update(&self.app, &interpolated);
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let (output, view) = self.app.get_current_frame_view(None);
+        // 直接获取交换链的纹理，表面丢失或过期时把错误交给事件循环重新配置
+        let output = self.app.surface.get_current_texture()?;
+        let view = output.texture.create_view(&wgpu::TextureViewDescriptor {
+            format: Some(self.app.config.format.add_srgb_suffix()),
+            ..Default::default()
+        });
+        self.render_to(&view);
+        output.present();
+
+        Ok(())
+    }
+
+    /// 将场景渲染到给定的颜色视图上，视图的大小和格式需要与表面的配置一致。
+    ///
+    /// Arguments:
+    ///
+    /// * `view`: 渲染目标，可以是交换链的视图，也可以是离屏纹理的视图。
+    fn render_to(&self, view: &wgpu::TextureView) {
         let mut encoder = self
             .app
             .device
@@ -283,57 +1902,247 @@ impl State {
                 label: Some("Render Encoder"),
             });
 
+        // 填写间接绘制的实例数量
+        self.indirect_state
+            .encode(&mut encoder, self.compute_state.current());
+
+        // 先从光源视角渲染阴影贴图，开启视锥剔除时视野外的小球不会投下阴影
+        self.shadow_state.render(
+            &mut encoder,
+            &self.obj_model,
+            &self.instance_state.instance_buffer,
+            0..self.instance_state.instances_number as u32,
+            &self.light_state.light_bind_group,
+        );
+
+        // 剔除后的实例数量只有 CPU 知道，此时不能使用 GPU 填写的间接绘制参数
+        let indirect_buffer =
+            (!self.instance_state.culling).then_some(&self.indirect_state.indirect_buffer);
+
+        // 深度预渲染只用于填充的网格小球，线框和球体替身仍然按原来的方式绘制
+        let depth_prepass = self.prepass_state.enabled
+            && self.render_mode == impostor::RenderMode::Mesh
+            && !(self.wireframe && self.wireframe_render_pipeline.is_some());
+        if depth_prepass {
+            self.prepass_state.render(
+                &mut encoder,
+                &self.depth_texture.view,
+                &self.obj_model,
+                &self.instance_state.instance_buffer,
+                0..self.instance_state.instances_number as u32,
+                indirect_buffer,
+                &self.camera_state.camera_bind_group,
+                self.viewport,
+            );
+        }
+
+        // 球体替身不读取环境光遮蔽
+        if self.render_mode == impostor::RenderMode::Mesh {
+            self.ssao_state.render(
+                &mut encoder,
+                &self.obj_model,
+                &self.instance_state.instance_buffer,
+                0..self.instance_state.instances_number as u32,
+                indirect_buffer,
+            );
+        }
+
+        // 开启 MSAA 时先渲染到多重采样纹理，再解析到交换链的视图上
+        let (color_view, resolve_target) = match &self.msaa_texture {
+            Some(msaa_texture) => (&msaa_texture.view, Some(view)),
+            None => (view, None),
+        };
+
+        let color_attachment = |load| {
+            Some(wgpu::RenderPassColorAttachment {
+                view: color_view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })
+        };
+        let depth_attachment = |load| {
+            Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            })
+        };
+        // 开启法线输出时多一个颜色附件，同样先渲染到多重采样纹理再解析
+        let normal_attachment = self.normal_texture.as_ref().map(|normal_texture| {
+            let (view, resolve_target) = match &self.msaa_normal_texture {
+                Some(msaa_normal_texture) => {
+                    (&msaa_normal_texture.view, Some(&normal_texture.view))
+                }
+                None => (&normal_texture.view, None),
+            };
+            wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            }
+        });
+        // 画了背景渐变或者锁定宽高比时保留画好的背景，否则用清屏颜色清空
+        let letterbox = self.aspect_ratio.map(|_| self.viewport);
+        let color_load = if self
+            .background_state
+            .render(&mut encoder, color_view, letterbox)
+        {
+            wgpu::LoadOp::Load
+        } else {
+            wgpu::LoadOp::Clear(self.clear_color)
+        };
+        let color_attachments = [color_attachment(color_load), normal_attachment.clone()];
+        let color_attachment_count = if normal_attachment.is_some() { 2 } else { 1 };
+
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
+                color_attachments: &color_attachments[..color_attachment_count],
+                // 保留深度预渲染写入的深度，光源标记和后面的物体照常与之比较
+                depth_stencil_attachment: depth_attachment(if depth_prepass {
+                    wgpu::LoadOp::Load
+                } else {
+                    wgpu::LoadOp::Clear(1.0)
                 }),
                 ..Default::default()
             });
+            utils::set_viewport(&mut render_pass, self.viewport);
+
+            if self.render_mode == impostor::RenderMode::Mesh {
+                // 不支持线框模式时退回到填充模式
+                let sphere_pipeline = match &self.wireframe_render_pipeline {
+                    Some(pipeline) if self.wireframe => pipeline,
+                    _ if depth_prepass => &self.prepass_render_pipeline,
+                    _ => &self.render_pipeline,
+                };
+                let instance_buffer = self.instance_state.instance_buffer.slice(..);
+                render_pass.set_pipeline(sphere_pipeline);
+                render_pass.set_bind_group(3, &self.shadow_state.shadow_bind_group, &[]);
+                match indirect_buffer {
+                    Some(indirect_buffer) => render_pass.draw_model_instanced_indirect(
+                        &self.obj_model,
+                        instance_buffer,
+                        indirect_buffer,
+                        &self.camera_state.camera_bind_group,
+                        &self.light_state.light_bind_group,
+                    ),
+                    None => render_pass.draw_model_instanced(
+                        &self.obj_model,
+                        instance_buffer,
+                        0..self.instance_state.instances_number as u32,
+                        &self.camera_state.camera_bind_group,
+                        &self.light_state.light_bind_group,
+                    ),
+                }
+            }
+
+            // 其它管线只有一个颜色目标，写完法线之后换一个不带法线附件的渲染通道继续绘制
+            if normal_attachment.is_some() {
+                drop(render_pass);
+                render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Render Pass"),
+                    color_attachments: &[color_attachment(wgpu::LoadOp::Load)],
+                    depth_stencil_attachment: depth_attachment(wgpu::LoadOp::Load),
+                    ..Default::default()
+                });
+                utils::set_viewport(&mut render_pass, self.viewport);
+            }
 
-            render_pass.set_vertex_buffer(1, self.instance_state.instance_buffer.slice(..));
             render_pass.set_pipeline(&self.light_render_pipeline);
-            render_pass.draw_light_model(
+            // 每个光源画一个标记
+            render_pass.draw_light_model_instanced(
                 &self.obj_model,
+                0..self.light_state.light_count(),
                 &self.camera_state.camera_bind_group,
                 &self.light_state.light_bind_group,
             );
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.draw_model_instanced(
+            // 球体替身不写入法线
+            if self.render_mode == impostor::RenderMode::PointSprite {
+                self.impostor_state.draw(
+                    &mut render_pass,
+                    &self.instance_state.instance_buffer,
+                    0..self.instance_state.instances_number as u32,
+                    &self.camera_state.camera_bind_group,
+                    &self.light_state.light_bind_group,
+                    &self.shadow_state.shadow_bind_group,
+                );
+            }
+
+            // 小球已经写好了深度，轮廓只露出小球外面的一圈
+            self.outline_state.draw(
+                &mut render_pass,
                 &self.obj_model,
-                0..self.instance_state.instances_number as u32,
                 &self.camera_state.camera_bind_group,
-                &self.light_state.light_bind_group,
             );
+
+            self.boundary_state
+                .draw(&mut render_pass, &self.camera_state.camera_bind_group);
+            // 地面、网格和轨迹都是半透明的，放在不透明的物体之后绘制
+            self.floor_state
+                .draw(&mut render_pass, &self.camera_state.camera_bind_group);
+            self.grid_state
+                .draw(&mut render_pass, &self.camera_state.camera_bind_group);
+            self.trail_state
+                .draw(&mut render_pass, &self.camera_state.camera_bind_group);
         }
 
-        self.app.queue.submit(iter::once(encoder.finish()));
-        output.present();
+        // 法线在第一个渲染通道结束时已经解析好，调试视图盖住正常的画面，文字仍然画在最上面
+        self.normal_view_state
+            .render(&mut encoder, view, self.viewport);
 
-        Ok(())
+        // 文字画在解析之后的画面上，不参与 MSAA 和深度测试
+        self.overlay.render(&mut encoder, view);
+
+        self.app.queue.submit(iter::once(encoder.finish()));
     }
 }
 
 fn main() {
-    run(None);
+    let args = match args::SceneArgs::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e:#}\n{}", args::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let scene = match args.scene_config() {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("{e:#}");
+            std::process::exit(2);
+        }
+    };
+    if let Some(steps) = args.bench {
+        if let Err(e) = bench::run(&scene, steps) {
+            eprintln!("基准测试失败: {e:?}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    let record = match args.record_config() {
+        Ok(record) => record,
+        Err(e) => {
+            eprintln!("{e:#}");
+            std::process::exit(2);
+        }
+    };
+    // 需要录制时只做离屏录制，不进入事件循环
+    if let Some(config) = record {
+        if let Err(e) = framework::record(&scene, &config) {
+            eprintln!("录制失败: {e:?}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    run(None, scene);
 }
@@ -1,7 +1,15 @@
 use std::ops::Range;
 
+use wgpu::util::DeviceExt;
+
 use crate::texture;
 
+/// 材质没有指定高光指数时使用的默认值。
+pub const DEFAULT_SHININESS: f32 = 32.0;
+
+/// 材质没有指定高光强度时使用的默认值。
+pub const DEFAULT_SPECULAR_STRENGTH: f32 = 1.0;
+
 pub trait Vertex {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a>;
 }
@@ -64,6 +72,15 @@ impl Vertex for ModelVertex {
     }
 }
 
+/// 材质的高光参数，与 draw.wgsl 中的 `Material` 结构体布局一致。
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    shininess: f32,
+    specular_strength: f32,
+    _padding: [f32; 2],
+}
+
 /// “Material”结构表示具有名称、漫反射和法线纹理以及用于 GPU 绑定的绑定组的材质。
 ///
 /// Properties:
@@ -74,11 +91,17 @@ impl Vertex for ModelVertex {
 /// `texture::Texture`。它表示用于材质中法线贴图的纹理。法线贴图是计算机图形学中使用的一种技术，通过模拟小凹凸和缝隙来向表面添加细节。
 /// * `bind_group`: `bind_group` 是 `wgpu::BindGroup` 类型的属性。它在 WebGPU API 的上下文中使用，WebGPU API 是用于 Web
 /// 的低级图形和计算 API。 “BindGroup”表示绑定在一起并由着色器使用的资源集合
+/// * `shininess`: Blinn-Phong 高光的指数，越大高光越小越集中。
+/// * `specular_strength`: 高光的强度，0 表示没有高光。
+/// * `uniform_buffer`: 存放 `shininess` 和 `specular_strength` 的缓冲区，和纹理在同一个绑定组中。
 pub struct Material {
     pub name: String,
     pub diffuse_texture: texture::Texture,
     pub normal_texture: texture::Texture,
     pub bind_group: wgpu::BindGroup,
+    pub shininess: f32,
+    pub specular_strength: f32,
+    pub uniform_buffer: wgpu::Buffer,
 }
 
 impl Material {
@@ -87,8 +110,19 @@ impl Material {
         name: &str,
         diffuse_texture: texture::Texture,
         normal_texture: texture::Texture,
+        shininess: f32,
+        specular_strength: f32,
         layout: &wgpu::BindGroupLayout,
     ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(name),
+            contents: bytemuck::bytes_of(&MaterialUniform {
+                shininess,
+                specular_strength,
+                _padding: [0.0; 2],
+            }),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout,
             entries: &[
@@ -108,6 +142,10 @@ impl Material {
                     binding: 3,
                     resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
             ],
             label: Some(name),
         });
@@ -117,8 +155,26 @@ impl Material {
             diffuse_texture,
             normal_texture,
             bind_group,
+            shininess,
+            specular_strength,
+            uniform_buffer,
         }
     }
+
+    /// 修改高光参数，在下一次绘制时生效。
+    pub fn set_specular(&mut self, queue: &wgpu::Queue, shininess: f32, specular_strength: f32) {
+        self.shininess = shininess;
+        self.specular_strength = specular_strength;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&MaterialUniform {
+                shininess,
+                specular_strength,
+                _padding: [0.0; 2],
+            }),
+        );
+    }
 }
 
 /// “Mesh”结构表示具有名称、顶点和索引缓冲区、元素数量和材质索引的 3D 网格。
@@ -150,11 +206,23 @@ pub struct Model {
     pub materials: Vec<Material>,
 }
 
+/// 与 `draw_indexed_indirect` 读取的参数布局一致，`instance_count` 由计算着色器填写。
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DrawIndexedIndirectArgs {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub first_instance: u32,
+}
+
 pub trait DrawModel<'a> {
     fn draw_mesh(
         &mut self,
         mesh: &'a Mesh,
         material: &'a Material,
+        instance_buffer: wgpu::BufferSlice<'a>,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
@@ -162,6 +230,7 @@ pub trait DrawModel<'a> {
         &mut self,
         mesh: &'a Mesh,
         material: &'a Material,
+        instance_buffer: wgpu::BufferSlice<'a>,
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
@@ -170,12 +239,16 @@ pub trait DrawModel<'a> {
     fn draw_model(
         &mut self,
         model: &'a Model,
+        instance_buffer: wgpu::BufferSlice<'a>,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
+    /// 用 `instance_buffer` 中的 `instances` 绘制模型的所有网格，实例 buffer 绑定在第 1 个顶点 buffer 上，
+    /// 同一个渲染通道中可以用各自的实例 buffer 依次绘制不同的模型。
     fn draw_model_instanced(
         &mut self,
         model: &'a Model,
+        instance_buffer: wgpu::BufferSlice<'a>,
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
@@ -184,10 +257,31 @@ pub trait DrawModel<'a> {
         &mut self,
         model: &'a Model,
         material: &'a Material,
+        instance_buffer: wgpu::BufferSlice<'a>,
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
+
+    fn draw_mesh_instanced_indirect(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        instance_buffer: wgpu::BufferSlice<'a>,
+        indirect_buffer: &'a wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+    /// 实例数量由 GPU 上的 `indirect_buffer` 决定，其中按网格的顺序存放每个网格的绘制参数。
+    fn draw_model_instanced_indirect(
+        &mut self,
+        model: &'a Model,
+        instance_buffer: wgpu::BufferSlice<'a>,
+        indirect_buffer: &'a wgpu::Buffer,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
 }
 
 impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
@@ -198,21 +292,31 @@ where
         &mut self,
         mesh: &'b Mesh,
         material: &'b Material,
+        instance_buffer: wgpu::BufferSlice<'b>,
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'b wgpu::BindGroup,
     ) {
-        self.draw_mesh_instanced(mesh, material, 0..1, camera_bind_group, light_bind_group);
+        self.draw_mesh_instanced(
+            mesh,
+            material,
+            instance_buffer,
+            0..1,
+            camera_bind_group,
+            light_bind_group,
+        );
     }
 
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'b Mesh,
         material: &'b Material,
+        instance_buffer: wgpu::BufferSlice<'b>,
         instances: Range<u32>,
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'b wgpu::BindGroup,
     ) {
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_vertex_buffer(1, instance_buffer);
         self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
         self.set_bind_group(0, &material.bind_group, &[]);
         self.set_bind_group(1, camera_bind_group, &[]);
@@ -223,15 +327,23 @@ where
     fn draw_model(
         &mut self,
         model: &'b Model,
+        instance_buffer: wgpu::BufferSlice<'b>,
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'b wgpu::BindGroup,
     ) {
-        self.draw_model_instanced(model, 0..1, camera_bind_group, light_bind_group);
+        self.draw_model_instanced(
+            model,
+            instance_buffer,
+            0..1,
+            camera_bind_group,
+            light_bind_group,
+        );
     }
 
     fn draw_model_instanced(
         &mut self,
         model: &'b Model,
+        instance_buffer: wgpu::BufferSlice<'b>,
         instances: Range<u32>,
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'b wgpu::BindGroup,
@@ -241,6 +353,7 @@ where
             self.draw_mesh_instanced(
                 mesh,
                 material,
+                instance_buffer,
                 instances.clone(),
                 camera_bind_group,
                 light_bind_group,
@@ -252,6 +365,7 @@ where
         &mut self,
         model: &'b Model,
         material: &'b Material,
+        instance_buffer: wgpu::BufferSlice<'b>,
         instances: Range<u32>,
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'b wgpu::BindGroup,
@@ -260,12 +374,54 @@ where
             self.draw_mesh_instanced(
                 mesh,
                 material,
+                instance_buffer,
                 instances.clone(),
                 camera_bind_group,
                 light_bind_group,
             );
         }
     }
+
+    fn draw_mesh_instanced_indirect(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        instance_buffer: wgpu::BufferSlice<'b>,
+        indirect_buffer: &'b wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_vertex_buffer(1, instance_buffer);
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.draw_indexed_indirect(indirect_buffer, indirect_offset);
+    }
+
+    fn draw_model_instanced_indirect(
+        &mut self,
+        model: &'b Model,
+        instance_buffer: wgpu::BufferSlice<'b>,
+        indirect_buffer: &'b wgpu::Buffer,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for (i, mesh) in model.meshes.iter().enumerate() {
+            let material = &model.materials[mesh.material];
+            self.draw_mesh_instanced_indirect(
+                mesh,
+                material,
+                instance_buffer,
+                indirect_buffer,
+                (i * std::mem::size_of::<DrawIndexedIndirectArgs>()) as wgpu::BufferAddress,
+                camera_bind_group,
+                light_bind_group,
+            );
+        }
+    }
 }
 
 pub trait DrawLight<'a> {
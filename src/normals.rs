@@ -0,0 +1,149 @@
+use app_surface::AppSurface;
+
+use crate::{shaders, utils};
+
+/// `NormalViewState` 是视图空间法线的调试视图，把法线渲染目标画满整个视口，代替正常的画面。
+///
+/// 法线只由网格小球写入，背景、光源标记和球体替身所在的像素都显示为黑色。
+///
+/// Properties:
+///
+/// * `bind_group_layout`: 读取法线纹理的绑定组布局。
+/// * `pipeline_layout`: 渲染管线的布局，重新加载着色器时复用。
+/// * `pipeline`: 画全屏三角形的渲染管线。
+/// * `bind_group`: 读取法线纹理的绑定组，没有法线纹理时为 `None`，此时不显示调试视图。
+pub struct NormalViewState {
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    bind_group: Option<wgpu::BindGroup>,
+}
+
+impl NormalViewState {
+    /// 创建调试视图的渲染管线，开始时没有法线纹理。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和表面格式。
+    ///
+    /// Returns:
+    ///
+    /// `NormalViewState` 的一个实例。
+    pub fn new(app: &AppSurface) -> Self {
+        let bind_group_layout =
+            app.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    }],
+                    label: Some("normal_view_bind_group_layout"),
+                });
+        let pipeline_layout = app
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Normal View Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = Self::create_pipeline(app, &pipeline_layout);
+
+        Self {
+            bind_group_layout,
+            pipeline_layout,
+            pipeline,
+            bind_group: None,
+        }
+    }
+
+    fn create_pipeline(app: &AppSurface, layout: &wgpu::PipelineLayout) -> wgpu::RenderPipeline {
+        let shader = app
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Normal View Shader"),
+                source: wgpu::ShaderSource::Wgsl(shaders::shader_source!("normals.wgsl")),
+            });
+        app.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Normal View Pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[utils::color_target(app.config.format)],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+    }
+
+    /// 重新读取着色器并重建渲染管线，着色器有错误时保留原来的管线。
+    pub fn reload_shader(&mut self, app: &AppSurface) {
+        if let Some(pipeline) = shaders::try_rebuild(&app.device, "normals.wgsl", || {
+            Self::create_pipeline(app, &self.pipeline_layout)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    /// 绑定新的法线纹理，法线纹理重建之后需要重新调用。`None` 表示关闭调试视图。
+    pub fn set_normal_view(&mut self, app: &AppSurface, normal_view: Option<&wgpu::TextureView>) {
+        self.bind_group = normal_view.map(|view| {
+            app.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                }],
+                label: Some("normal_view_bind_group"),
+            })
+        });
+    }
+
+    /// 把法线画到解析之后的画面上，覆盖视口内已经画好的内容；没有绑定法线纹理时什么都不做。
+    ///
+    /// Arguments:
+    ///
+    /// * `encoder`: 命令编码器，应该在法线写完之后调用。
+    /// * `view`: 单采样的输出视图，与表面同样大小。
+    /// * `viewport`: 渲染的视口 `[x, y, 宽, 高]`，视口之外的黑边保持不变。
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        viewport: [f32; 4],
+    ) {
+        let Some(bind_group) = &self.bind_group else {
+            return;
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Normal View Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        utils::set_viewport(&mut render_pass, viewport);
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
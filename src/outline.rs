@@ -0,0 +1,207 @@
+use app_surface::AppSurface;
+
+use crate::{compute::ComputeInstance, instance, model, model::Vertex, shaders, texture};
+
+/// 最多同时高亮的小球数量，实例缓冲区按这个数量一次性分配。
+pub const MAX_SELECTED: usize = 64;
+
+// 轮廓球体相对于小球的放大倍数，决定轮廓的粗细
+const OUTLINE_SCALE: f32 = 1.15;
+
+// 轮廓的颜色
+const OUTLINE_COLOR: glam::Vec4 = glam::Vec4::new(1.0, 0.8, 0.1, 1.0);
+
+/// `OutlineState` 给选中的小球画上纯色的轮廓。
+///
+/// 把每个选中的小球放大一点之后只画背面，小球本身会挡住中间的部分，只露出外面的一圈。
+/// 轮廓每帧按渲染时的位置重新生成，会跟着小球移动。
+///
+/// Properties:
+///
+/// * `selected`: 选中的小球的 id。
+/// * `instance_buffer`: 放大之后的轮廓实例，容量为 `MAX_SELECTED`。
+/// * `instance_count`: 这一帧找到的选中小球的数量，已经被移除的小球不画轮廓。
+/// * `pipeline_layout`: 渲染管线的布局，重新加载着色器时复用。
+/// * `pipeline`: 剔除正面、写入深度的渲染管线。
+pub struct OutlineState {
+    selected: Vec<u32>,
+    instance_buffer: wgpu::Buffer,
+    instance_count: u32,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl OutlineState {
+    /// 创建轮廓的实例缓冲区和渲染管线，开始时没有选中的小球。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和表面格式。
+    /// * `camera_bind_group_layout`: 相机的绑定组布局，位于 group 0。
+    /// * `sample_count`: MSAA 采样数。
+    ///
+    /// Returns:
+    ///
+    /// `OutlineState` 的一个实例。
+    pub fn new(
+        app: &AppSurface,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        let instance_buffer = app.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Outline Instance Buffer"),
+            size: (MAX_SELECTED * std::mem::size_of::<instance::InstanceRaw>())
+                as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let layout = app
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Outline Pipeline Layout"),
+                bind_group_layouts: &[camera_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = Self::create_pipeline(app, &layout, sample_count);
+
+        Self {
+            selected: Vec::new(),
+            instance_buffer,
+            instance_count: 0,
+            pipeline_layout: layout,
+            pipeline,
+        }
+    }
+
+    fn create_pipeline(
+        app: &AppSurface,
+        layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let shader = app
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Outline Shader"),
+                source: wgpu::ShaderSource::Wgsl(shaders::shader_source!("outline.wgsl")),
+            });
+        app.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Outline Pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[model::ModelVertex::desc(), instance::InstanceRaw::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: app.config.format.add_srgb_suffix(),
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                // 只画背面，正对相机的一半被小球本身挡住
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: Some(wgpu::Face::Front),
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+            })
+    }
+
+    /// 重新读取着色器并重建渲染管线，着色器有错误时保留原来的管线。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和表面格式。
+    /// * `sample_count`: MSAA 采样数，需要和当前的渲染附件一致，采样数改变之后用新的值重建。
+    pub fn reload_shader(&mut self, app: &AppSurface, sample_count: u32) {
+        if let Some(pipeline) = shaders::try_rebuild(&app.device, "outline.wgsl", || {
+            Self::create_pipeline(app, &self.pipeline_layout, sample_count)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    /// 替换选中的小球，超过 `MAX_SELECTED` 的部分被忽略，下一次 `update` 之后生效。
+    ///
+    /// Returns:
+    ///
+    /// 是否所有的小球都被选中了。
+    pub fn set_selected(&mut self, ids: &[u32]) -> bool {
+        self.selected.clear();
+        self.selected.extend(ids.iter().copied().take(MAX_SELECTED));
+        ids.len() <= MAX_SELECTED
+    }
+
+    /// 按渲染时的位置重新生成轮廓实例。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于写入实例缓冲区。
+    /// * `instances`: 这一帧渲染的小球，可以是插值之后的位置。
+    pub fn update(&mut self, app: &AppSurface, instances: &[ComputeInstance]) {
+        if self.selected.is_empty() {
+            self.instance_count = 0;
+            return;
+        }
+        let raws = instances
+            .iter()
+            .filter(|instance| self.selected.contains(&instance.id))
+            .map(|instance| {
+                ComputeInstance {
+                    radius: instance.radius * OUTLINE_SCALE,
+                    color: OUTLINE_COLOR,
+                    ..*instance
+                }
+                .to_render_instance_raw()
+            })
+            .collect::<Vec<_>>();
+        self.instance_count = raws.len() as u32;
+        if !raws.is_empty() {
+            app.queue
+                .write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&raws));
+        }
+    }
+
+    /// 在给定的渲染通道中绘制轮廓，应该在小球之后绘制，小球已经写好了深度。
+    ///
+    /// Arguments:
+    ///
+    /// * `render_pass`: 当前的渲染通道，只有一个颜色附件。
+    /// * `model`: 小球的模型。
+    /// * `camera_bind_group`: 相机的绑定组。
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a model::Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.instance_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        for mesh in &model.meshes {
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..mesh.num_elements, 0, 0..self.instance_count);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use app_surface::AppSurface;
+use wgpu_text::{
+    glyph_brush::{ab_glyph::FontRef, Section, Text},
+    BrushBuilder, TextBrush,
+};
+
+// 叠加文字使用的字体，编译时嵌入，wasm 上也不需要额外下载
+const FONT: &[u8] = include_bytes!("../res/fonts/DejaVuSansMono.ttf");
+
+/// `TextOverlay` 在画面左上角绘制帧率和模拟统计信息，全屏和录制时也能看到。
+///
+/// Properties:
+///
+/// * `brush`: 排版并绘制文字的画刷。
+/// * `visible`: 是否绘制叠加文字。
+pub struct TextOverlay {
+    brush: TextBrush<FontRef<'static>>,
+    pub visible: bool,
+}
+
+impl TextOverlay {
+    // 文字大小，单位是像素
+    const FONT_SIZE: f32 = 18.0;
+    // 文字离画面左上角的距离，单位是像素
+    const MARGIN: f32 = 10.0;
+
+    /// 创建叠加文字的画刷。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和表面配置。
+    pub fn new(app: &AppSurface) -> Self {
+        let brush = BrushBuilder::using_font_bytes(FONT)
+            .expect("embedded font is valid")
+            .build(
+                &app.device,
+                app.config.width,
+                app.config.height,
+                app.config.format.add_srgb_suffix(),
+            );
+        Self {
+            brush,
+            visible: true,
+        }
+    }
+
+    /// 表面大小改变之后更新文字的投影。
+    pub fn resize(&self, app: &AppSurface) {
+        self.brush.resize_view(
+            app.config.width as f32,
+            app.config.height as f32,
+            &app.queue,
+        );
+    }
+
+    /// 排版这一帧要显示的文字，每行一条统计信息。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和队列。
+    /// * `text`: 要显示的文字。
+    pub fn update(&mut self, app: &AppSurface, text: &str) {
+        if !self.visible {
+            return;
+        }
+        let section = Section::default()
+            .add_text(
+                Text::new(text)
+                    .with_scale(Self::FONT_SIZE)
+                    .with_color([1.0, 1.0, 1.0, 1.0]),
+            )
+            .with_screen_position((Self::MARGIN, Self::MARGIN));
+        if let Err(e) = self.brush.queue(&app.device, &app.queue, vec![&section]) {
+            eprintln!("无法排版叠加文字: {e:?}");
+        }
+    }
+
+    /// 在场景画完之后，把文字叠加到 `view` 上。`view` 需要是单采样的最终画面。
+    ///
+    /// Arguments:
+    ///
+    /// * `encoder`: 命令编码器。
+    /// * `view`: 渲染目标。
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        if !self.visible {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Text Overlay Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        self.brush.draw(&mut render_pass);
+    }
+}
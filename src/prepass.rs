@@ -0,0 +1,173 @@
+use std::ops::Range;
+
+use app_surface::AppSurface;
+
+use crate::{instance, model, model::Vertex, shaders, texture, utils};
+
+/// `DepthPrepassState` 负责在颜色阶段之前只渲染小球的深度。
+///
+/// 小球很多且相互遮挡时，颜色阶段的片元着色器会在同一个像素上执行很多次。
+/// 先写好深度之后，颜色阶段只比较深度而不再写入，每个像素只有最前面的小球会执行光照计算。
+///
+/// Properties:
+///
+/// * `pipeline_layout`: 深度预渲染管线的布局，重新加载着色器时复用。
+/// * `pipeline`: 只有顶点着色器的深度预渲染管线。
+/// * `enabled`: 是否开启深度预渲染。
+pub struct DepthPrepassState {
+    pub pipeline_layout: wgpu::PipelineLayout,
+    pub pipeline: wgpu::RenderPipeline,
+    pub enabled: bool,
+}
+
+impl DepthPrepassState {
+    /// 创建深度预渲染管线，默认不开启。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备。
+    /// * `camera_bind_group_layout`: 相机的绑定组布局。
+    /// * `sample_count`: MSAA 采样数，必须与深度纹理的采样数一致。
+    ///
+    /// Returns:
+    ///
+    /// `DepthPrepassState` 的一个实例。
+    pub fn new(
+        app: &AppSurface,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        let layout = app
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Depth Prepass Pipeline Layout"),
+                bind_group_layouts: &[camera_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = Self::create_pipeline(app, &layout, sample_count);
+
+        Self {
+            pipeline_layout: layout,
+            pipeline,
+            enabled: false,
+        }
+    }
+
+    fn create_pipeline(
+        app: &AppSurface,
+        layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let shader = app
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Depth Prepass Shader"),
+                source: wgpu::ShaderSource::Wgsl(shaders::shader_source!("prepass.wgsl")),
+            });
+        app.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Depth Prepass Pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[model::ModelVertex::desc(), instance::InstanceRaw::desc()],
+                },
+                // 只需要写深度
+                fragment: None,
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                // 深度状态与绘制小球的颜色管线一致，但不能有深度偏移，否则颜色阶段的比较会失败
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+            })
+    }
+
+    /// 重新读取着色器并重建深度预渲染管线，着色器有错误时保留原来的管线。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备。
+    /// * `sample_count`: MSAA 采样数，需要和当前的渲染附件一致，采样数改变之后用新的值重建。
+    pub fn reload_shader(&mut self, app: &AppSurface, sample_count: u32) {
+        if let Some(pipeline) = shaders::try_rebuild(&app.device, "prepass.wgsl", || {
+            Self::create_pipeline(app, &self.pipeline_layout, sample_count)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    /// 清空深度纹理，并把小球的深度渲染进去。
+    ///
+    /// 颜色阶段需要保留这里写入的深度，并且必须以同样的方式绘制同样的实例，
+    /// 所以也接受间接绘制的参数。
+    ///
+    /// Arguments:
+    ///
+    /// * `encoder`: 命令编码器。
+    /// * `depth_view`: 颜色阶段使用的深度纹理视图。
+    /// * `model`: 需要绘制的模型。
+    /// * `instance_buffer`: 实例缓冲区。
+    /// * `instances`: 需要绘制的实例范围，使用间接绘制时忽略。
+    /// * `indirect_buffer`: 按网格顺序存放的间接绘制参数，为 `None` 时直接绘制 `instances`。
+    /// * `camera_bind_group`: 相机的绑定组。
+    /// * `viewport`: 渲染的视口 `[x, y, 宽, 高]`，需要与颜色阶段一致。
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        depth_view: &wgpu::TextureView,
+        model: &model::Model,
+        instance_buffer: &wgpu::Buffer,
+        instances: Range<u32>,
+        indirect_buffer: Option<&wgpu::Buffer>,
+        camera_bind_group: &wgpu::BindGroup,
+        viewport: [f32; 4],
+    ) {
+        let mut prepass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth Prepass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            ..Default::default()
+        });
+
+        utils::set_viewport(&mut prepass, viewport);
+        prepass.set_pipeline(&self.pipeline);
+        prepass.set_bind_group(0, camera_bind_group, &[]);
+        prepass.set_vertex_buffer(1, instance_buffer.slice(..));
+        for (i, mesh) in model.meshes.iter().enumerate() {
+            prepass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            prepass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            match indirect_buffer {
+                Some(indirect_buffer) => prepass.draw_indexed_indirect(
+                    indirect_buffer,
+                    (i * std::mem::size_of::<model::DrawIndexedIndirectArgs>())
+                        as wgpu::BufferAddress,
+                ),
+                None => prepass.draw_indexed(0..mesh.num_elements, 0, instances.clone()),
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+// map_async 回调和等待方之间共享的状态
+#[derive(Default)]
+struct MapState {
+    result: Option<Result<(), wgpu::BufferAsyncError>>,
+    waker: Option<Waker>,
+}
+
+/// `Readback` 表示一次已经开始、但还不一定完成的 buffer 映射。
+///
+/// 创建时就调用 `map_async`，之后可以阻塞等待（`wait`），可以每帧非阻塞地查询（`try_take`），
+/// 也可以作为 future 等待（`into_future`），在此期间 CPU 可以继续做其他工作。
+///
+/// Properties:
+///
+/// * `buffer`: 需要读回的 buffer，必须带有 `MAP_READ`。
+/// * `state`: 映射的结果，由 `map_async` 的回调填写。
+pub struct Readback {
+    buffer: Arc<wgpu::Buffer>,
+    state: Arc<Mutex<MapState>>,
+}
+
+impl Readback {
+    /// 开始映射整个 buffer。之前提交的写入这个 buffer 的命令完成后映射才会完成。
+    pub fn new(buffer: Arc<wgpu::Buffer>) -> Self {
+        let state = Arc::new(Mutex::new(MapState::default()));
+        let callback_state = state.clone();
+        buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let mut state = callback_state.lock().unwrap();
+                state.result = Some(result);
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            });
+
+        Self { buffer, state }
+    }
+
+    /// 阻塞直到映射完成，然后返回 buffer 的内容。
+    pub fn wait(self, device: &wgpu::Device) -> Vec<u8> {
+        while !self.is_mapped() {
+            device.poll(wgpu::MaintainBase::Wait);
+        }
+        self.take()
+    }
+
+    /// 不阻塞地检查映射是否完成，完成时返回 buffer 的内容，否则返回 `None`，可以在下一帧再次查询。
+    pub fn try_take(&mut self, device: &wgpu::Device) -> Option<Vec<u8>> {
+        device.poll(wgpu::MaintainBase::Poll);
+        self.is_mapped().then(|| self.take())
+    }
+
+    /// 把这次读回转换为 future，完成时得到 buffer 的内容。
+    pub fn into_future(self, device: &wgpu::Device) -> ReadbackFuture<'_> {
+        ReadbackFuture {
+            readback: Some(self),
+            device,
+        }
+    }
+
+    fn is_mapped(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        if let Some(Err(e)) = &state.result {
+            panic!("failed to map storage buffer: {e:?}");
+        }
+        state.result.is_some()
+    }
+
+    // 映射完成之后复制出内容并解除映射
+    fn take(&self) -> Vec<u8> {
+        let results = self.buffer.slice(..).get_mapped_range().to_vec();
+        self.buffer.unmap();
+        results
+    }
+}
+
+/// 等待 `Readback` 完成的 future。
+///
+/// 原生平台上 `map_async` 的回调只会在 `device.poll` 中触发，所以每次被轮询时都会非阻塞地 poll 一次设备，
+/// 并让执行器稍后再次轮询；浏览器中回调由事件循环触发，会直接唤醒这个 future。
+pub struct ReadbackFuture<'a> {
+    readback: Option<Readback>,
+    device: &'a wgpu::Device,
+}
+
+impl Future for ReadbackFuture<'_> {
+    type Output = Vec<u8>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.device.poll(wgpu::MaintainBase::Poll);
+
+        let readback = self
+            .readback
+            .as_ref()
+            .expect("ReadbackFuture polled after completion");
+        if readback.is_mapped() {
+            let readback = self.readback.take().unwrap();
+            return Poll::Ready(readback.take());
+        }
+
+        readback.state.lock().unwrap().waker = Some(cx.waker().clone());
+        if !cfg!(target_arch = "wasm32") {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
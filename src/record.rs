@@ -0,0 +1,190 @@
+use std::{iter, path::PathBuf, sync::Arc};
+
+use anyhow::{bail, Context};
+
+use crate::{compute, State};
+
+/// `RecordConfig` 描述一次离屏录制：以固定的时间步长推进模拟，并把每一帧保存为 PNG。
+///
+/// Properties:
+///
+/// * `frames`: 需要录制的帧数。
+/// * `fixed_dt`: 每一帧推进的模拟时间，与实际帧率无关。
+/// * `output_dir`: 保存 `frame_0000.png` 等文件的目录。
+#[derive(Debug, Clone)]
+pub struct RecordConfig {
+    pub frames: u32,
+    pub fixed_dt: std::time::Duration,
+    pub output_dir: PathBuf,
+}
+
+// 没有设置 RECORD_DT 或者 --record-dt 时每帧的秒数
+pub const DEFAULT_RECORD_DT: f32 = 1.0 / 60.0;
+
+// 没有设置 RECORD_DIR 或者 --record-dir 时的输出目录
+pub const DEFAULT_RECORD_DIR: &str = "frames";
+
+impl RecordConfig {
+    /// 检查每帧的时间并创建录制配置。
+    ///
+    /// Arguments:
+    ///
+    /// * `frames`: 需要录制的帧数。
+    /// * `fixed_dt`: 每一帧推进的模拟时间，单位是秒。
+    /// * `output_dir`: 保存帧的目录。
+    ///
+    /// Returns:
+    ///
+    /// `fixed_dt` 不是有限的正数时返回错误。
+    pub fn new(frames: u32, fixed_dt: f32, output_dir: PathBuf) -> anyhow::Result<Self> {
+        if !(fixed_dt > 0.0 && fixed_dt.is_finite()) {
+            bail!("每帧的时间必须是有限的正数，得到 {fixed_dt}");
+        }
+        Ok(Self {
+            frames,
+            fixed_dt: std::time::Duration::from_secs_f32(fixed_dt),
+            output_dir,
+        })
+    }
+
+    /// 从环境变量中读取录制配置：`RECORD_FRAMES` 为帧数（必需），`RECORD_DT` 为每帧的秒数（默认 1/60），
+    /// `RECORD_DIR` 为输出目录（默认 `frames`）。
+    ///
+    /// Returns:
+    ///
+    /// 设置了 `RECORD_FRAMES` 时返回 `Some(RecordConfig)`，否则返回 `None`；
+    /// `RECORD_FRAMES` 或者 `RECORD_DT` 无法解析，或者每帧的时间不合法时返回错误。
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(frames) = std::env::var("RECORD_FRAMES") else {
+            return Ok(None);
+        };
+        let frames = frames
+            .parse()
+            .with_context(|| format!("RECORD_FRAMES 的取值 {frames} 无法解析"))?;
+        let fixed_dt = match std::env::var("RECORD_DT") {
+            Ok(dt) => dt
+                .parse()
+                .with_context(|| format!("RECORD_DT 的取值 {dt} 无法解析"))?,
+            Err(_) => DEFAULT_RECORD_DT,
+        };
+        let output_dir =
+            std::env::var("RECORD_DIR").unwrap_or_else(|_| DEFAULT_RECORD_DIR.to_string());
+        Self::new(frames, fixed_dt, PathBuf::from(output_dir)).map(Some)
+    }
+}
+
+impl State {
+    /// 按照配置录制一段动画：每一帧以固定的时间步长推进模拟，渲染到离屏纹理，再读回并保存为 PNG。
+    ///
+    /// 初始场景使用固定的随机种子，因此同样的配置总能得到同样的帧序列。
+    ///
+    /// Arguments:
+    ///
+    /// * `config`: 录制配置。
+    ///
+    /// Returns:
+    ///
+    /// 写文件失败时返回错误。
+    pub fn record(&mut self, config: &RecordConfig) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&config.output_dir)?;
+
+        let width = self.app.config.width;
+        let height = self.app.config.height;
+        let format = self.app.config.format.add_srgb_suffix();
+
+        let target = self.app.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Record Target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // 每行字节数需要按 COPY_BYTES_PER_ROW_ALIGNMENT 对齐
+        let unpadded_bytes_per_row = width * 4;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+        let readback_buffer = Arc::new(self.app.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Record Readback Buffer"),
+            size: padded_bytes_per_row as u64 * height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        }));
+
+        // 表面格式可能是 BGRA，保存前需要交换红蓝通道
+        let is_bgra = matches!(
+            format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+
+        for frame in 0..config.frames {
+            self.update(config.fixed_dt);
+            self.step(config.fixed_dt);
+            self.sync_instances(1.0);
+            self.render_to(&target_view);
+
+            let mut encoder =
+                self.app
+                    .device
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                        label: Some("Record Copy Encoder"),
+                    });
+            encoder.copy_texture_to_buffer(
+                target.as_image_copy(),
+                wgpu::ImageCopyBuffer {
+                    buffer: &readback_buffer,
+                    layout: wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(padded_bytes_per_row),
+                        rows_per_image: Some(height),
+                    },
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+            self.app.queue.submit(iter::once(encoder.finish()));
+
+            let padded = compute::read_buffer_bytes(&self.app.device, readback_buffer.clone());
+            let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+            for row in padded.chunks(padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+            if is_bgra {
+                for pixel in pixels.chunks_mut(4) {
+                    pixel.swap(0, 2);
+                }
+            }
+
+            let path = config.output_dir.join(format!("frame_{frame:04}.png"));
+            image::save_buffer(&path, &pixels, width, height, image::ColorType::Rgba8)?;
+            println!("已保存 {}", path.display());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_time_must_be_positive_and_finite() {
+        for dt in [0.0, -1.0 / 60.0, f32::NAN, f32::INFINITY] {
+            assert!(RecordConfig::new(10, dt, PathBuf::from("frames")).is_err());
+        }
+        let config = RecordConfig::new(10, 0.5, PathBuf::from("frames")).unwrap();
+        assert_eq!(config.fixed_dt, std::time::Duration::from_millis(500));
+    }
+}
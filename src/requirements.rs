@@ -0,0 +1,105 @@
+//! 模拟和渲染对 GPU 的要求。
+//!
+//! `AppSurface` 创建设备时会请求适配器支持的全部特性和限制，所以这里的要求在创建设备时就已经传入，
+//! 不需要事后替换设备；[`check`] 在创建之后立即检查设备是否满足要求，不满足时一次列出所有缺少的能力，
+//! 而不是在创建管线或者 buffer 时才出错。
+
+use anyhow::bail;
+use app_surface::AppSurface;
+
+use crate::compute;
+
+/// 可以没有的特性，以及缺少时不可用的功能。
+pub const OPTIONAL_FEATURES: [(wgpu::Features, &str); 2] = [
+    (wgpu::Features::TIMESTAMP_QUERY, "基准测试中的 GPU 计时"),
+    (wgpu::Features::POLYGON_MODE_LINE, "线框模式"),
+];
+
+/// 必需的特性，即 [`compute::REQUIRED_FEATURES`]：结果 buffer 需要同时作为 storage buffer 和映射读取。
+pub fn required_features() -> wgpu::Features {
+    compute::REQUIRED_FEATURES
+}
+
+/// 必需的 downlevel 能力：计算着色器、在顶点着色器中读取 storage buffer（网格视图）以及间接绘制。
+pub fn required_downlevel_flags() -> wgpu::DownlevelFlags {
+    wgpu::DownlevelFlags::COMPUTE_SHADERS
+        | wgpu::DownlevelFlags::VERTEX_STORAGE
+        | wgpu::DownlevelFlags::INDIRECT_EXECUTION
+}
+
+/// 模拟 `particle_count` 个小球所需的限制。
+///
+/// 碰撞阶段每个 buffer 占一个 bind group，一共 8 个，其中 7 个是 storage buffer；实例和结果 buffer
+/// 的大小与小球数量成正比；清空网格的着色器使用 256 大小的工作组。
+pub fn required_limits(particle_count: u32) -> wgpu::Limits {
+    let per_particle = std::mem::size_of::<compute::ComputeInstanceRaw>()
+        .max(std::mem::size_of::<compute::Result>()) as u64;
+    let buffer_size = per_particle * particle_count as u64;
+    wgpu::Limits {
+        max_bind_groups: 8,
+        max_storage_buffers_per_shader_stage: 7,
+        max_storage_buffer_binding_size: u32::try_from(buffer_size).unwrap_or(u32::MAX),
+        max_buffer_size: buffer_size,
+        max_compute_workgroup_size_x: 256,
+        max_compute_invocations_per_workgroup: 256,
+        max_compute_workgroups_per_dimension: compute::workgroup_count(
+            particle_count,
+            compute::SHADER_WORKGROUP_SIZE,
+        ),
+        ..wgpu::Limits::downlevel_defaults()
+    }
+}
+
+/// 检查设备是否满足模拟 `particle_count` 个小球的要求，并打印缺少的可选特性。
+///
+/// Arguments:
+///
+/// * `app`: 应用程序表面，用于访问适配器和设备。
+/// * `particle_count`: 小球的数量。
+///
+/// Returns:
+///
+/// 缺少任何必需的特性、downlevel 能力或者限制时返回错误，错误信息中列出所有缺少的项。
+pub fn check(app: &AppSurface, particle_count: u32) -> anyhow::Result<()> {
+    let mut missing = Vec::new();
+
+    let features = app.device.features();
+    let missing_features = required_features() - features;
+    if !missing_features.is_empty() {
+        missing.push(format!("特性 {:?}", missing_features));
+    }
+
+    let downlevel = app.adapter.get_downlevel_capabilities();
+    let missing_flags = required_downlevel_flags() - downlevel.flags;
+    if !missing_flags.is_empty() {
+        missing.push(format!("downlevel 能力 {:?}", missing_flags));
+    }
+
+    required_limits(particle_count).check_limits_with_fail_fn(
+        &app.device.limits(),
+        false,
+        |name, required, allowed| {
+            missing.push(format!(
+                "限制 {name}：需要 {required}，设备只支持 {allowed}"
+            ));
+        },
+    );
+
+    if !missing.is_empty() {
+        let adapter = app.adapter.get_info();
+        bail!(
+            "{}（{:?}）不满足模拟 {} 个小球的要求，缺少：\n  {}",
+            adapter.name,
+            adapter.backend,
+            particle_count,
+            missing.join("\n  ")
+        );
+    }
+
+    for (feature, usage) in OPTIONAL_FEATURES {
+        if !features.contains(feature) {
+            println!("当前设备不支持 {feature:?}，{usage}不可用。");
+        }
+    }
+    Ok(())
+}
@@ -1,9 +1,36 @@
 use std::io::{BufReader, Cursor};
 
+use cfg_if::cfg_if;
+
 use wgpu::util::DeviceExt;
 
 use crate::{model, texture};
 
+/// `res_path` 返回构建脚本复制到 `OUT_DIR/res` 中的资源文件的路径。
+///
+/// Arguments:
+///
+/// * `file_name`: 资源文件的名称。
+///
+/// Returns:
+///
+/// 资源文件的完整路径。
+#[cfg(not(target_arch = "wasm32"))]
+pub fn res_path(file_name: &str) -> std::path::PathBuf {
+    std::path::Path::new(env!("OUT_DIR"))
+        .join("res")
+        .join(file_name)
+}
+
+/// 在浏览器中，资源文件放在页面所在目录的 `res/` 下，通过 HTTP 获取。
+#[cfg(target_arch = "wasm32")]
+fn format_url(file_name: &str) -> reqwest::Url {
+    let window = web_sys::window().unwrap();
+    let location = window.location();
+    let base = reqwest::Url::parse(&location.href().unwrap()).unwrap();
+    base.join("res/").unwrap().join(file_name).unwrap()
+}
+
 /// `load_string` 函数将文件内容作为 Rust 中的字符串加载。
 ///
 /// Arguments:
@@ -14,10 +41,15 @@ use crate::{model, texture};
 ///
 /// 函数“load_string”返回“Result”类型，成功情况包含“String”，错误情况包含“anyhow::Error”。
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
-    let path = std::path::Path::new(env!("OUT_DIR"))
-        .join("res")
-        .join(file_name);
-    let txt = std::fs::read_to_string(path)?;
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            let url = format_url(file_name);
+            let txt = reqwest::get(url).await?.error_for_status()?.text().await?;
+        } else {
+            let path = res_path(file_name);
+            let txt = std::fs::read_to_string(path)?;
+        }
+    }
 
     Ok(txt)
 }
@@ -32,10 +64,20 @@ pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
 ///
 /// 函数“load_binary”返回“Result”类型，成功情况包含“Vec<u8>”（字节向量），错误情况包含“anyhow::Error”。
 pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
-    let path = std::path::Path::new(env!("OUT_DIR"))
-        .join("res")
-        .join(file_name);
-    let data = std::fs::read(path)?;
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            let url = format_url(file_name);
+            let data = reqwest::get(url)
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?
+                .to_vec();
+        } else {
+            let path = res_path(file_name);
+            let data = std::fs::read(path)?;
+        }
+    }
 
     Ok(data)
 }
@@ -63,7 +105,346 @@ pub async fn load_texture(
     texture::Texture::from_bytes(device, queue, &data, file_name, is_normal_map)
 }
 
-/// Rust 中的“load_model”函数从文件加载 3D 模型，包括其材质和纹理，并使用网格和材质创建模型对象。
+/// 用相邻三角形的面法线（按面积加权）计算每个顶点的法线，用于模型没有提供法线的情况。
+///
+/// Arguments:
+///
+/// * `vertices`: 顶点数组，会直接修改其中的 `normal`。
+/// * `indices`: 三角形的索引，每 3 个为一组。
+fn compute_normals(vertices: &mut [model::ModelVertex], indices: &[u32]) {
+    let mut normals = vec![glam::Vec3::ZERO; vertices.len()];
+    for c in indices.chunks(3) {
+        let pos0 = glam::Vec3::from_array(vertices[c[0] as usize].position);
+        let pos1 = glam::Vec3::from_array(vertices[c[1] as usize].position);
+        let pos2 = glam::Vec3::from_array(vertices[c[2] as usize].position);
+        // 叉积的长度是三角形面积的两倍，直接累加即为按面积加权
+        let face_normal = (pos1 - pos0).cross(pos2 - pos0);
+        for &i in c {
+            normals[i as usize] += face_normal;
+        }
+    }
+    for (v, n) in vertices.iter_mut().zip(normals) {
+        v.normal = n.normalize_or_zero().into();
+    }
+}
+
+// 纹理坐标组成的 2x2 矩阵的行列式小于这个值时认为三角形的纹理坐标退化（重合或者共线），无法求出切线
+const MIN_UV_DETERMINANT: f32 = 1e-12;
+
+/// 取任意一组与法线正交的切线和副切线，法线无效时按 +Y 处理，保证着色器中的切线空间有效。
+///
+/// Arguments:
+///
+/// * `v`: 需要切线的顶点，会直接修改其中的 `tangent` 和 `bitangent`。
+fn fallback_tangent(v: &mut model::ModelVertex) {
+    let normal = glam::Vec3::from_array(v.normal)
+        .try_normalize()
+        .unwrap_or(glam::Vec3::Y);
+    let (tangent, bitangent) = normal.any_orthonormal_pair();
+    v.tangent = tangent.into();
+    v.bitangent = bitangent.into();
+}
+
+/// 没有纹理坐标时无法计算切线，每个顶点都使用 `fallback_tangent`。
+///
+/// Arguments:
+///
+/// * `vertices`: 顶点数组，会直接修改其中的 `tangent` 和 `bitangent`。
+fn fallback_tangents(vertices: &mut [model::ModelVertex]) {
+    vertices.iter_mut().for_each(fallback_tangent);
+}
+
+/// 根据三角形的纹理坐标计算每个顶点的切线和副切线，结果是所有相邻三角形的平均值。
+///
+/// 纹理坐标退化的三角形不参与平均；没有任何有效三角形、或者平均之后不是有限的非零向量的顶点
+/// 改用 `fallback_tangent`，不会产生 NaN 破坏法线贴图的光照。
+///
+/// Arguments:
+///
+/// * `vertices`: 顶点数组，会直接修改其中的 `tangent` 和 `bitangent`。
+/// * `indices`: 三角形的索引，每 3 个为一组。
+fn compute_tangents(vertices: &mut [model::ModelVertex], indices: &[u32]) {
+    let mut triangles_included = vec![0; vertices.len()];
+
+    // Calculate tangents and bitangets. We're going to
+    // use the triangles, so we need to loop through the
+    // indices in chunks of 3
+    for c in indices.chunks(3) {
+        let v0 = vertices[c[0] as usize];
+        let v1 = vertices[c[1] as usize];
+        let v2 = vertices[c[2] as usize];
+
+        let pos0: glam::Vec3 = v0.position.into();
+        let pos1: glam::Vec3 = v1.position.into();
+        let pos2: glam::Vec3 = v2.position.into();
+
+        let uv0: glam::Vec2 = v0.tex_coords.into();
+        let uv1: glam::Vec2 = v1.tex_coords.into();
+        let uv2: glam::Vec2 = v2.tex_coords.into();
+
+        // Calculate the edges of the triangle
+        let delta_pos1 = pos1 - pos0;
+        let delta_pos2 = pos2 - pos0;
+
+        // This will give us a direction to calculate the
+        // tangent and bitangent
+        let delta_uv1 = uv1 - uv0;
+        let delta_uv2 = uv2 - uv0;
+
+        // Solving the following system of equations will
+        // give us the tangent and bitangent.
+        //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
+        //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
+        // Luckily, the place I found this equation provided
+        // the solution!
+        let determinant = delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x;
+        // 纹理坐标退化时方程组没有唯一解，这个三角形不提供切线
+        if !(determinant.abs() >= MIN_UV_DETERMINANT) {
+            continue;
+        }
+        let r = 1.0 / determinant;
+        let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+        // We flip the bitangent to enable right-handed normal
+        // maps with wgpu texture coordinate system
+        let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+
+        // We'll use the same tangent/bitangent for each vertex in the triangle
+        vertices[c[0] as usize].tangent =
+            (tangent + glam::Vec3::from_array(vertices[c[0] as usize].tangent)).into();
+        vertices[c[1] as usize].tangent =
+            (tangent + glam::Vec3::from_array(vertices[c[1] as usize].tangent)).into();
+        vertices[c[2] as usize].tangent =
+            (tangent + glam::Vec3::from_array(vertices[c[2] as usize].tangent)).into();
+        vertices[c[0] as usize].bitangent =
+            (bitangent + glam::Vec3::from_array(vertices[c[0] as usize].bitangent)).into();
+        vertices[c[1] as usize].bitangent =
+            (bitangent + glam::Vec3::from_array(vertices[c[1] as usize].bitangent)).into();
+        vertices[c[2] as usize].bitangent =
+            (bitangent + glam::Vec3::from_array(vertices[c[2] as usize].bitangent)).into();
+
+        // Used to average the tangents/bitangents
+        triangles_included[c[0] as usize] += 1;
+        triangles_included[c[1] as usize] += 1;
+        triangles_included[c[2] as usize] += 1;
+    }
+
+    // Average the tangents/bitangents
+    for (i, n) in triangles_included.into_iter().enumerate() {
+        let v = &mut vertices[i];
+        if n == 0 {
+            fallback_tangent(v);
+            continue;
+        }
+        let denom = 1.0 / n as f32;
+        let tangent = glam::Vec3::from_array(v.tangent) * denom;
+        let bitangent = glam::Vec3::from_array(v.bitangent) * denom;
+        // 相邻三角形的切线方向相反时平均值可能接近 0，极小的面积也可能让结果溢出
+        let valid = |t: glam::Vec3| t.is_finite() && t.length_squared() > 0.0;
+        if valid(tangent) && valid(bitangent) {
+            v.tangent = tangent.into();
+            v.bitangent = bitangent.into();
+        } else {
+            fallback_tangent(v);
+        }
+    }
+}
+
+/// 纹理加载失败时打印警告，并退回到默认纹理（漫反射为品红棋盘格，法线贴图为平坦法线）。
+///
+/// Arguments:
+///
+/// * `texture`: 纹理的加载结果。
+/// * `name`: 纹理的名称，用于打印警告。
+/// * `is_normal_map`: 是否是法线贴图，决定使用哪种默认纹理。
+/// * `device`: 对 wgpu::Device 的引用。
+/// * `queue`: 用于提交 GPU 命令的命令队列。
+///
+/// Returns:
+///
+/// 加载成功的纹理或默认纹理。
+fn texture_or_default(
+    texture: anyhow::Result<texture::Texture>,
+    name: &str,
+    is_normal_map: bool,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    match texture {
+        Ok(texture) => Ok(texture),
+        Err(e) => {
+            println!("警告：无法加载纹理 {name:?}，使用默认纹理代替: {e}");
+            if is_normal_map {
+                texture::Texture::default_normal(device, queue)
+            } else {
+                texture::Texture::default_diffuse(device, queue)
+            }
+        }
+    }
+}
+
+/// 创建使用默认纹理的材质，用于模型没有任何材质的情况。
+fn default_material(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Material> {
+    Ok(model::Material::new(
+        device,
+        "default_material",
+        texture::Texture::default_diffuse(device, queue)?,
+        texture::Texture::default_normal(device, queue)?,
+        model::DEFAULT_SHININESS,
+        model::DEFAULT_SPECULAR_STRENGTH,
+        layout,
+    ))
+}
+
+/// 把每个网格的材质编号对应到材质的下标，没有指定材质或者编号越界的网格使用放在最后的默认材质。
+///
+/// Arguments:
+///
+/// * `material_ids`: 文件中每个网格的材质编号，顺序与网格一致。
+/// * `material_count`: 从文件中读出的材质数量，默认材质的下标就是它。
+///
+/// Returns:
+///
+/// 每个网格的材质下标，以及是否有网格使用默认材质。
+fn material_slots(material_ids: &[Option<usize>], material_count: usize) -> (Vec<usize>, bool) {
+    let indices = material_ids
+        .iter()
+        .map(|id| match *id {
+            Some(id) if id < material_count => id,
+            _ => material_count,
+        })
+        .collect::<Vec<_>>();
+    let needs_default = indices.contains(&material_count);
+    (indices, needs_default)
+}
+
+/// 把每个网格的材质编号对应到 `materials` 中的下标，规则见 `material_slots`。
+///
+/// 默认材质只在需要时添加一次，放在最后，不影响其他网格的编号，
+/// 其余的网格仍然使用各自的材质，不会退回到第一个材质。
+///
+/// Arguments:
+///
+/// * `material_ids`: 文件中每个网格的材质编号，顺序与网格一致。
+/// * `materials`: 从文件中读出的材质，需要时在末尾加上默认材质。
+/// * `device`: 用于创建默认材质的设备。
+/// * `queue`: 用于上传默认纹理的队列。
+/// * `layout`: 材质绑定组的布局。
+///
+/// Returns:
+///
+/// 每个网格在 `materials` 中的材质下标。
+fn resolve_material_indices(
+    material_ids: &[Option<usize>],
+    materials: &mut Vec<model::Material>,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<Vec<usize>> {
+    let (indices, needs_default) = material_slots(material_ids, materials.len());
+    if needs_default {
+        materials.push(default_material(device, queue, layout)?);
+    }
+    Ok(indices)
+}
+
+/// 从 MTL 的 `Ns` 和 `Ks` 得到高光指数和高光强度，高光强度取 `Ks` 中最大的分量。
+///
+/// tobj 把没有写出的 `Ns` 和 `Ks` 读成 0，两者都是 0 时认为材质没有指定高光，使用默认值。
+fn mtl_specular(m: &tobj::Material) -> (f32, f32) {
+    if m.shininess <= 0.0 && m.specular == [0.0; 3] {
+        return (model::DEFAULT_SHININESS, model::DEFAULT_SPECULAR_STRENGTH);
+    }
+    // 指数小于 1 时高光会铺满整个半球
+    let shininess = m.shininess.max(1.0);
+    let specular_strength = m.specular.into_iter().fold(0.0, f32::max);
+    (shininess, specular_strength)
+}
+
+/// 把 OBJ 网格转换成顶点，补全缺少的法线、纹理坐标和切线。
+///
+/// OBJ 中可能没有 vt 或 vn，此时分别使用零纹理坐标和计算出的法线；没有纹理坐标时无法求切线，使用 `fallback_tangents`。
+///
+/// Arguments:
+///
+/// * `mesh`: 以 `single_index` 和 `triangulate` 读入的网格。
+///
+/// Returns:
+///
+/// 与 `mesh.indices` 对应的顶点。
+fn obj_mesh_vertices(mesh: &tobj::Mesh) -> Vec<model::ModelVertex> {
+    let has_tex_coords = !mesh.texcoords.is_empty();
+    let has_normals = !mesh.normals.is_empty();
+    let mut vertices = (0..mesh.positions.len() / 3)
+        .map(|i| model::ModelVertex {
+            position: [
+                mesh.positions[i * 3],
+                mesh.positions[i * 3 + 1],
+                mesh.positions[i * 3 + 2],
+            ],
+            tex_coords: if has_tex_coords {
+                [mesh.texcoords[i * 2], mesh.texcoords[i * 2 + 1]]
+            } else {
+                [0.0; 2]
+            },
+            normal: if has_normals {
+                [
+                    mesh.normals[i * 3],
+                    mesh.normals[i * 3 + 1],
+                    mesh.normals[i * 3 + 2],
+                ]
+            } else {
+                [0.0; 3]
+            },
+            // We'll calculate these later
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+        })
+        .collect::<Vec<_>>();
+
+    if !has_normals {
+        compute_normals(&mut vertices, &mesh.indices);
+    }
+    if has_tex_coords {
+        compute_tangents(&mut vertices, &mesh.indices);
+    } else {
+        fallback_tangents(&mut vertices);
+    }
+    vertices
+}
+
+/// 按照文件扩展名选择加载器加载模型：`.gltf` 和 `.glb` 使用 glTF 加载器，其余按 OBJ 加载。
+///
+/// Arguments:
+///
+/// * `file_name`: 包含模型数据的文件的名称。
+/// * `device`: 对 wgpu::Device 的引用，表示用于渲染的 GPU 设备。
+/// * `queue`: 用于提交 GPU 命令的命令队列。
+/// * `layout`: 材质绑定组的布局。
+///
+/// Returns:
+///
+/// 如果加载过程成功，返回一个包含“model::Model”对象的“Result”。
+pub async fn load_model(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Model> {
+    let extension = std::path::Path::new(file_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("gltf") | Some("glb") => load_gltf(file_name, device, queue, layout).await,
+        _ => load_obj(file_name, device, queue, layout).await,
+    }
+}
+
+/// Rust 中的“load_obj”函数从 OBJ 文件加载 3D 模型，包括其材质和纹理，并使用网格和材质创建模型对象。
 ///
 /// Arguments:
 ///
@@ -71,21 +452,100 @@ pub async fn load_texture(
 /// * `device`: 对 wgpu::Device 的引用，表示用于渲染的 GPU 设备。
 /// * `queue`: `queue` 参数是 `wgpu::Queue` 的实例，它代表用于提交 GPU 命令的命令队列。它用于将命令提交给GPU进行处理。
 /// * `layout`: `layout` 参数是对 `wgpu::BindGroupLayout` 对象的引用。该对象定义用于将资源（例如纹理）绑定到着色器管道的绑定组的布局。它用于为模型创建材料。
-/// * `scale_factor`: “scale_factor”参数是一个浮点值，用于确定应用于模型顶点的缩放因子。它用于将模型调整到所需的尺寸。
 ///
 /// Returns:
 ///
-/// 如果加载过程成功，函数“load_model”将返回一个包含“model::Model”对象的“Result”。
-pub async fn load_model(
+/// 如果加载过程成功，函数“load_obj”将返回一个包含“model::Model”对象的“Result”。
+pub async fn load_obj(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
-    scale_factor: f32,
 ) -> anyhow::Result<model::Model> {
-    let obj_text = load_string(file_name).await?;
-    let obj_cursor = Cursor::new(obj_text);
-    let mut obj_reader = BufReader::new(obj_cursor);
+    let obj_bytes = load_binary(file_name).await?;
+
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            // 浏览器中无法同步读取文件，先把 OBJ 引用的 MTL 和纹理都下载下来
+            let files = prefetch_obj_dependencies(&obj_bytes).await;
+            let resolve = move |name: &str| {
+                files
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("failed to fetch {name}"))
+            };
+        } else {
+            let resolve = |name: &str| -> anyhow::Result<Vec<u8>> {
+                Ok(std::fs::read(res_path(name))?)
+            };
+        }
+    }
+
+    load_model_from_bytes(&obj_bytes, file_name, resolve, device, queue, layout).await
+}
+
+/// 找出 OBJ 中 `mtllib` 引用的 MTL 文件以及 MTL 中引用的纹理，并全部下载下来。
+/// 下载失败的文件不放入结果中，加载时会退回到默认的材质或纹理。
+#[cfg(target_arch = "wasm32")]
+async fn prefetch_obj_dependencies(obj_bytes: &[u8]) -> std::collections::HashMap<String, Vec<u8>> {
+    // 每行第一个词是关键字，最后一个词是文件名
+    fn referenced_files<'a>(text: &'a str, keywords: &[&str]) -> Vec<&'a str> {
+        text.lines()
+            .filter_map(|line| {
+                let mut words = line.split_whitespace();
+                let keyword = words.next()?;
+                keywords.contains(&keyword).then(|| words.last()).flatten()
+            })
+            .collect()
+    }
+
+    let mut files = std::collections::HashMap::new();
+    let obj_text = String::from_utf8_lossy(obj_bytes);
+    for mtl_name in referenced_files(&obj_text, &["mtllib"]) {
+        let Ok(mtl_bytes) = load_binary(mtl_name).await else {
+            continue;
+        };
+        let mtl_text = String::from_utf8_lossy(&mtl_bytes).into_owned();
+        for texture_name in referenced_files(
+            &mtl_text,
+            &["map_Kd", "map_Bump", "map_bump", "bump", "norm"],
+        ) {
+            if let Ok(bytes) = load_binary(texture_name).await {
+                files.insert(texture_name.to_string(), bytes);
+            }
+        }
+        files.insert(mtl_name.to_string(), mtl_bytes);
+    }
+    files
+}
+
+/// 从内存中的 OBJ 数据加载模型，OBJ 引用的 MTL 文件和纹理通过 `resolve` 闭包获取，
+/// 因此资源可以来自 `include_bytes!`、虚拟文件系统或任意路径，而不局限于 `OUT_DIR`。
+///
+/// Arguments:
+///
+/// * `obj_bytes`: OBJ 文件的内容。
+/// * `label`: 模型的名称，用于网格名称和缓冲区标签。
+/// * `resolve`: 根据 OBJ/MTL 中引用的文件名返回对应文件内容的闭包。
+/// * `device`: 对 wgpu::Device 的引用，表示用于渲染的 GPU 设备。
+/// * `queue`: 用于提交 GPU 命令的命令队列。
+/// * `layout`: 材质绑定组的布局。
+///
+/// Returns:
+///
+/// 如果加载过程成功，返回一个包含“model::Model”对象的“Result”。
+pub async fn load_model_from_bytes<R>(
+    obj_bytes: &[u8],
+    label: &str,
+    resolve: R,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Model>
+where
+    R: Fn(&str) -> anyhow::Result<Vec<u8>>,
+{
+    let mut obj_reader = BufReader::new(Cursor::new(obj_bytes));
 
     let (models, obj_materials) = tobj::load_obj_buf_async(
         &mut obj_reader,
@@ -94,137 +554,438 @@ pub async fn load_model(
             single_index: true,
             ..Default::default()
         },
-        |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+        |p| {
+            let mat_bytes = resolve(&p);
+            async move {
+                match mat_bytes {
+                    Ok(mat_bytes) => {
+                        tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_bytes)))
+                    }
+                    Err(e) => {
+                        eprintln!("无法加载材质 {p:?}: {e:?}");
+                        Err(tobj::LoadError::OpenFileFailed)
+                    }
+                }
+            }
         },
     )
     .await?;
 
     let mut materials = Vec::new();
     for m in obj_materials? {
-        let diffuse_texture = load_texture(&m.diffuse_texture, false, device, queue).await?;
-        let normal_texture = load_texture(&m.normal_texture, true, device, queue).await?;
+        let diffuse_texture = resolve(&m.diffuse_texture).and_then(|bytes| {
+            texture::Texture::from_bytes(device, queue, &bytes, &m.diffuse_texture, false)
+        });
+        let diffuse_texture =
+            texture_or_default(diffuse_texture, &m.diffuse_texture, false, device, queue)?;
+        let normal_texture = resolve(&m.normal_texture).and_then(|bytes| {
+            texture::Texture::from_bytes(device, queue, &bytes, &m.normal_texture, true)
+        });
+        let normal_texture =
+            texture_or_default(normal_texture, &m.normal_texture, true, device, queue)?;
+        let (shininess, specular_strength) = mtl_specular(&m);
 
         materials.push(model::Material::new(
             device,
             &m.name,
             diffuse_texture,
             normal_texture,
+            shininess,
+            specular_strength,
             layout,
         ));
     }
+    let material_ids = models
+        .iter()
+        .map(|m| m.mesh.material_id)
+        .collect::<Vec<_>>();
+    let material_indices =
+        resolve_material_indices(&material_ids, &mut materials, device, queue, layout)?;
 
     let meshes = models
         .into_iter()
-        .map(|m| {
-            let mut vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| model::ModelVertex {
-                    position: [
-                        m.mesh.positions[i * 3] * scale_factor,
-                        m.mesh.positions[i * 3 + 1] * scale_factor,
-                        m.mesh.positions[i * 3 + 2] * scale_factor,
-                    ],
-                    tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
+        .zip(material_indices)
+        .map(|(m, material)| {
+            let vertices = obj_mesh_vertices(&m.mesh);
+
+            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{label:?} Vertex Buffer")),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
+            });
+            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{label:?} Index Buffer")),
+                contents: bytemuck::cast_slice(&m.mesh.indices),
+                usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC,
+            });
+
+            model::Mesh {
+                name: label.to_string(),
+                vertex_buffer,
+                index_buffer,
+                num_elements: m.mesh.indices.len() as u32,
+                material,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Ok(model::Model { meshes, materials })
+}
+
+/// 读取 glTF 图片的原始字节，图片可以存放在 buffer view 中，也可以是外部文件。
+///
+/// Arguments:
+///
+/// * `image`: glTF 中的图片。
+/// * `buffer_data`: 已经加载好的所有 buffer 的数据。
+///
+/// Returns:
+///
+/// 编码后的图片字节（例如 PNG、JPEG）。
+async fn load_gltf_image(
+    image: gltf::Image<'_>,
+    buffer_data: &[Vec<u8>],
+) -> anyhow::Result<Vec<u8>> {
+    match image.source() {
+        gltf::image::Source::View { view, .. } => {
+            let start = view.offset();
+            let end = start + view.length();
+            Ok(buffer_data[view.buffer().index()][start..end].to_vec())
+        }
+        gltf::image::Source::Uri { uri, .. } => load_binary(uri).await,
+    }
+}
+
+/// 从 glTF 纹理创建 `texture::Texture`。
+///
+/// Arguments:
+///
+/// * `texture`: glTF 中的纹理。
+/// * `buffer_data`: 已经加载好的所有 buffer 的数据。
+/// * `is_normal_map`: 是否是法线贴图。
+/// * `device`: 对 wgpu::Device 的引用。
+/// * `queue`: 用于提交 GPU 命令的命令队列。
+///
+/// Returns:
+///
+/// 创建好的纹理。
+async fn load_gltf_texture(
+    texture: gltf::Texture<'_>,
+    buffer_data: &[Vec<u8>],
+    is_normal_map: bool,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    let image = texture.source();
+    let label = image
+        .name()
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("gltf image {}", image.index()));
+    let data = load_gltf_image(image, buffer_data).await?;
+    texture::Texture::from_bytes(device, queue, &data, &label, is_normal_map)
+}
+
+/// `load_gltf` 函数从 glTF（`.gltf` 或 `.glb`）文件加载 3D 模型，返回和 OBJ 相同的 `model::Model`。
+///
+/// 每个 primitive 对应一个 `model::Mesh`，材质的 base color 纹理和法线纹理映射到 `model::Material`。
+/// 节点的变换不会被应用，顶点使用 mesh 自身的坐标。
+///
+/// Arguments:
+///
+/// * `file_name`: 包含模型数据的文件的名称。
+/// * `device`: 对 wgpu::Device 的引用，表示用于渲染的 GPU 设备。
+/// * `queue`: 用于提交 GPU 命令的命令队列。
+/// * `layout`: 材质绑定组的布局。
+///
+/// Returns:
+///
+/// 如果加载过程成功，返回一个包含“model::Model”对象的“Result”。
+pub async fn load_gltf(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Model> {
+    let gltf_bytes = load_binary(file_name).await?;
+    let gltf = gltf::Gltf::from_slice(&gltf_bytes)?;
+
+    // 加载所有 buffer，.glb 的第一个 buffer 存放在二进制块中
+    let mut buffer_data = Vec::new();
+    for buffer in gltf.buffers() {
+        match buffer.source() {
+            gltf::buffer::Source::Bin => {
+                let blob = gltf
+                    .blob
+                    .as_deref()
+                    .ok_or_else(|| anyhow::anyhow!("{file_name}: missing GLB binary chunk"))?;
+                buffer_data.push(blob.to_vec());
+            }
+            gltf::buffer::Source::Uri(uri) => {
+                if uri.starts_with("data:") {
+                    anyhow::bail!("{file_name}: embedded data URIs are not supported");
+                }
+                buffer_data.push(load_binary(uri).await?);
+            }
+        }
+    }
+
+    let mut materials = Vec::new();
+    for (i, m) in gltf.materials().enumerate() {
+        let name = m
+            .name()
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{file_name} material {i}"));
+        let diffuse_texture = match m.pbr_metallic_roughness().base_color_texture() {
+            Some(info) => {
+                load_gltf_texture(info.texture(), &buffer_data, false, device, queue).await
+            }
+            None => Err(anyhow::anyhow!("missing base color texture")),
+        };
+        let diffuse_texture = texture_or_default(diffuse_texture, &name, false, device, queue)?;
+        let normal_texture = match m.normal_texture() {
+            Some(info) => {
+                load_gltf_texture(info.texture(), &buffer_data, true, device, queue).await
+            }
+            None => Err(anyhow::anyhow!("missing normal texture")),
+        };
+        let normal_texture = texture_or_default(normal_texture, &name, true, device, queue)?;
+
+        // glTF 的材质是基于物理的，没有对应的高光参数，使用默认值
+        materials.push(model::Material::new(
+            device,
+            &name,
+            diffuse_texture,
+            normal_texture,
+            model::DEFAULT_SHININESS,
+            model::DEFAULT_SPECULAR_STRENGTH,
+            layout,
+        ));
+    }
+    // 与下面的循环跳过同样的 primitive，材质下标与网格一一对应
+    let material_ids = gltf
+        .meshes()
+        .flat_map(|mesh| mesh.primitives())
+        .filter(|primitive| primitive.mode() == gltf::mesh::Mode::Triangles)
+        .map(|primitive| primitive.material().index())
+        .collect::<Vec<_>>();
+    let mut material_indices =
+        resolve_material_indices(&material_ids, &mut materials, device, queue, layout)?.into_iter();
+
+    let mut meshes = Vec::new();
+    for mesh in gltf.meshes() {
+        let mesh_name = mesh.name().unwrap_or(file_name);
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                println!("跳过 {mesh_name} 中非三角形的 primitive");
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(buffer_data[buffer.index()].as_slice()));
+
+            let positions = reader
+                .read_positions()
+                .ok_or_else(|| anyhow::anyhow!("{mesh_name}: primitive without positions"))?
+                .collect::<Vec<_>>();
+            let normals = reader
+                .read_normals()
+                .map(|normals| normals.collect::<Vec<_>>());
+            let has_normals = normals.is_some();
+            let normals = normals.unwrap_or_else(|| vec![[0.0; 3]; positions.len()]);
+            let tex_coords = reader
+                .read_tex_coords(0)
+                .map(|tex_coords| tex_coords.into_f32().collect::<Vec<_>>());
+            let has_tex_coords = tex_coords.is_some();
+            let tex_coords = tex_coords.unwrap_or_else(|| vec![[0.0; 2]; positions.len()]);
+            let indices = reader
+                .read_indices()
+                .map(|indices| indices.into_u32().collect::<Vec<_>>())
+                .unwrap_or_else(|| (0..positions.len() as u32).collect());
+
+            let mut vertices = positions
+                .iter()
+                .zip(normals.iter())
+                .zip(tex_coords.iter())
+                .map(|((position, normal), tex_coords)| model::ModelVertex {
+                    position: *position,
+                    tex_coords: *tex_coords,
+                    normal: *normal,
                     // We'll calculate these later
                     tangent: [0.0; 3],
                     bitangent: [0.0; 3],
                 })
                 .collect::<Vec<_>>();
 
-            let indices = &m.mesh.indices;
-            let mut triangles_included = vec![0; vertices.len()];
-
-            // Calculate tangents and bitangets. We're going to
-            // use the triangles, so we need to loop through the
-            // indices in chunks of 3
-            for c in indices.chunks(3) {
-                let v0 = vertices[c[0] as usize];
-                let v1 = vertices[c[1] as usize];
-                let v2 = vertices[c[2] as usize];
-
-                let pos0: glam::Vec3 = v0.position.into();
-                let pos1: glam::Vec3 = v1.position.into();
-                let pos2: glam::Vec3 = v2.position.into();
-
-                let uv0: glam::Vec2 = v0.tex_coords.into();
-                let uv1: glam::Vec2 = v1.tex_coords.into();
-                let uv2: glam::Vec2 = v2.tex_coords.into();
-
-                // Calculate the edges of the triangle
-                let delta_pos1 = pos1 - pos0;
-                let delta_pos2 = pos2 - pos0;
-
-                // This will give us a direction to calculate the
-                // tangent and bitangent
-                let delta_uv1 = uv1 - uv0;
-                let delta_uv2 = uv2 - uv0;
-
-                // Solving the following system of equations will
-                // give us the tangent and bitangent.
-                //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
-                //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
-                // Luckily, the place I found this equation provided
-                // the solution!
-                let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
-                let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
-                // We flip the bitangent to enable right-handed normal
-                // maps with wgpu texture coordinate system
-                let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
-
-                // We'll use the same tangent/bitangent for each vertex in the triangle
-                vertices[c[0] as usize].tangent =
-                    (tangent + glam::Vec3::from_array(vertices[c[0] as usize].tangent)).into();
-                vertices[c[1] as usize].tangent =
-                    (tangent + glam::Vec3::from_array(vertices[c[1] as usize].tangent)).into();
-                vertices[c[2] as usize].tangent =
-                    (tangent + glam::Vec3::from_array(vertices[c[2] as usize].tangent)).into();
-                vertices[c[0] as usize].bitangent =
-                    (bitangent + glam::Vec3::from_array(vertices[c[0] as usize].bitangent)).into();
-                vertices[c[1] as usize].bitangent =
-                    (bitangent + glam::Vec3::from_array(vertices[c[1] as usize].bitangent)).into();
-                vertices[c[2] as usize].bitangent =
-                    (bitangent + glam::Vec3::from_array(vertices[c[2] as usize].bitangent)).into();
-
-                // Used to average the tangents/bitangents
-                triangles_included[c[0] as usize] += 1;
-                triangles_included[c[1] as usize] += 1;
-                triangles_included[c[2] as usize] += 1;
+            if !has_normals {
+                compute_normals(&mut vertices, &indices);
             }
-
-            // Average the tangents/bitangents
-            for (i, n) in triangles_included.into_iter().enumerate() {
-                let denom = 1.0 / n as f32;
-                let v = &mut vertices[i];
-                v.tangent = (glam::Vec3::from_array(v.tangent) * denom).into();
-                v.bitangent = (glam::Vec3::from_array(v.bitangent) * denom).into();
+            if has_tex_coords {
+                compute_tangents(&mut vertices, &indices);
+            } else {
+                fallback_tangents(&mut vertices);
             }
 
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{file_name:?} Vertex Buffer")),
+                label: Some(&format!("{mesh_name:?} Vertex Buffer")),
                 contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
             });
             let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{file_name:?} Index Buffer")),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
+                label: Some(&format!("{mesh_name:?} Index Buffer")),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC,
             });
 
-            model::Mesh {
-                name: file_name.to_string(),
+            meshes.push(model::Mesh {
+                name: mesh_name.to_string(),
                 vertex_buffer,
                 index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
-            }
-        })
-        .collect::<Vec<_>>();
+                num_elements: indices.len() as u32,
+                material: material_indices
+                    .next()
+                    .expect("one material index per triangle primitive"),
+            });
+        }
+    }
 
     Ok(model::Model { meshes, materials })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(position: [f32; 3], tex_coords: [f32; 2]) -> model::ModelVertex {
+        model::ModelVertex {
+            position,
+            tex_coords,
+            normal: [0.0, 0.0, 1.0],
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+        }
+    }
+
+    // 每个顶点的切线和副切线都是有限的非零向量，并且与法线垂直
+    fn assert_valid_tangent_space(vertices: &[model::ModelVertex]) {
+        for v in vertices {
+            let normal = glam::Vec3::from_array(v.normal);
+            for t in [v.tangent, v.bitangent].map(glam::Vec3::from_array) {
+                assert!(t.is_finite(), "{t} is not finite");
+                assert!(t.length_squared() > 0.0);
+                assert!(t.dot(normal).abs() < 1e-5);
+            }
+        }
+    }
+
+    // 只有 v 和 f 的立方体，没有纹理坐标和法线
+    const BARE_CUBE_OBJ: &str = "\
+v -1 -1 -1
+v 1 -1 -1
+v 1 1 -1
+v -1 1 -1
+v -1 -1 1
+v 1 -1 1
+v 1 1 1
+v -1 1 1
+f 1 4 3 2
+f 5 6 7 8
+f 1 2 6 5
+f 2 3 7 6
+f 3 4 8 7
+f 4 1 5 8
+";
+
+    fn load_obj_str(obj: &str) -> Vec<tobj::Model> {
+        let (models, _) = tobj::load_obj_buf(
+            &mut BufReader::new(Cursor::new(obj.as_bytes())),
+            &tobj::LoadOptions {
+                triangulate: true,
+                single_index: true,
+                ..Default::default()
+            },
+            |_| Err(tobj::LoadError::OpenFileFailed),
+        )
+        .unwrap();
+        models
+    }
+
+    #[test]
+    fn obj_without_tex_coords_or_normals_loads() {
+        let models = load_obj_str(BARE_CUBE_OBJ);
+        assert_eq!(models.len(), 1);
+        let mesh = &models[0].mesh;
+        assert!(mesh.texcoords.is_empty() && mesh.normals.is_empty());
+        assert_eq!(mesh.indices.len(), 36);
+
+        let vertices = obj_mesh_vertices(mesh);
+        assert_eq!(vertices.len(), mesh.positions.len() / 3);
+        for v in &vertices {
+            assert_eq!(v.tex_coords, [0.0; 2]);
+            // 计算出的法线从立方体中心指向外面
+            let normal = glam::Vec3::from_array(v.normal);
+            assert!((normal.length() - 1.0).abs() < 1e-5);
+            assert!(normal.dot(glam::Vec3::from_array(v.position)) > 0.0);
+        }
+        assert_valid_tangent_space(&vertices);
+    }
+
+    #[test]
+    fn meshes_without_a_valid_material_share_one_default() {
+        let (indices, needs_default) = material_slots(&[Some(1), None, Some(7), Some(0)], 2);
+        assert_eq!(indices, [1, 2, 2, 0]);
+        assert!(needs_default);
+        assert_eq!(material_slots(&[Some(0), Some(1)], 2), (vec![0, 1], false));
+    }
+
+    #[test]
+    fn two_material_model_uses_both_materials() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/res/two-materials.obj");
+        let (models, materials) = tobj::load_obj(
+            path,
+            &tobj::LoadOptions {
+                triangulate: true,
+                single_index: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let materials = materials.unwrap();
+        assert_eq!(materials.len(), 2);
+        assert_ne!(materials[0].diffuse_texture, materials[1].diffuse_texture);
+
+        // 每个材质各有一个网格，绘制时每个网格绑定自己的材质
+        let material_ids = models
+            .iter()
+            .map(|m| m.mesh.material_id)
+            .collect::<Vec<_>>();
+        let (mut indices, needs_default) = material_slots(&material_ids, materials.len());
+        assert!(!needs_default);
+        indices.sort_unstable();
+        assert_eq!(indices, [0, 1]);
+    }
+
+    #[test]
+    fn tangents_of_collinear_uvs_fall_back_to_the_normal() {
+        let mut vertices = vec![
+            vertex([0.0, 0.0, 0.0], [0.0, 0.0]),
+            vertex([1.0, 0.0, 0.0], [0.5, 0.5]),
+            vertex([0.0, 1.0, 0.0], [1.0, 1.0]),
+        ];
+        compute_tangents(&mut vertices, &[0, 1, 2]);
+        assert_valid_tangent_space(&vertices);
+    }
+
+    #[test]
+    fn tangents_of_unreferenced_vertices_fall_back_to_the_normal() {
+        let mut vertices = vec![
+            vertex([0.0, 0.0, 0.0], [0.0, 0.0]),
+            vertex([1.0, 0.0, 0.0], [1.0, 0.0]),
+            vertex([0.0, 1.0, 0.0], [0.0, 1.0]),
+            // 没有任何三角形引用这个顶点
+            vertex([5.0, 5.0, 0.0], [0.0, 0.0]),
+        ];
+        compute_tangents(&mut vertices, &[0, 1, 2]);
+        assert_valid_tangent_space(&vertices);
+        // 有效的三角形仍然得到沿 u 方向的切线
+        assert_eq!(vertices[0].tangent, [1.0, 0.0, 0.0]);
+        assert_eq!(vertices[0].bitangent, [0.0, -1.0, 0.0]);
+    }
+}
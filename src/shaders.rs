@@ -0,0 +1,138 @@
+//! 着色器源码的加载与热重载。
+//!
+//! debug 构建（非 wasm）下着色器在运行时从 `shaders/` 目录读取，并由 [`ShaderWatcher`] 监听文件变化，
+//! 保存后重新创建受影响的管线；release 构建以及 wasm 上直接使用编译时通过 `include_str!` 嵌入的源码。
+
+use std::borrow::Cow;
+
+/// 按文件名取得 `shaders/` 目录下着色器的源码，返回 `Cow<'static, str>`。
+///
+/// 例如 `shader_source!("draw.wgsl")`。
+#[macro_export]
+macro_rules! shader_source {
+    ($name:literal) => {
+        $crate::shaders::load(
+            $name,
+            include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/", $name)),
+        )
+    };
+}
+pub use crate::shader_source;
+
+cfg_if::cfg_if! {
+    if #[cfg(all(debug_assertions, not(target_arch = "wasm32")))] {
+        use notify::Watcher;
+
+        fn shader_dir() -> std::path::PathBuf {
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("shaders")
+        }
+
+        /// 从磁盘读取着色器，读取失败时退回到嵌入的版本。
+        pub fn load(name: &str, embedded: &'static str) -> Cow<'static, str> {
+            match std::fs::read_to_string(shader_dir().join(name)) {
+                Ok(source) => Cow::Owned(source),
+                Err(e) => {
+                    println!("读取着色器 {} 失败，使用嵌入的版本：{}", name, e);
+                    Cow::Borrowed(embedded)
+                }
+            }
+        }
+
+        /// `ShaderWatcher` 监听 `shaders/` 目录，记录被修改的着色器文件。
+        pub struct ShaderWatcher {
+            _watcher: notify::RecommendedWatcher,
+            receiver: std::sync::mpsc::Receiver<notify::Result<notify::Event>>,
+        }
+
+        impl ShaderWatcher {
+            /// 开始监听着色器目录，失败时打印原因并返回 `None`。
+            pub fn new() -> Option<Self> {
+                let (sender, receiver) = std::sync::mpsc::channel();
+                let watcher = notify::recommended_watcher(sender).and_then(|mut watcher| {
+                    watcher.watch(&shader_dir(), notify::RecursiveMode::NonRecursive)?;
+                    Ok(watcher)
+                });
+                match watcher {
+                    Ok(watcher) => Some(Self {
+                        _watcher: watcher,
+                        receiver,
+                    }),
+                    Err(e) => {
+                        println!("无法监听着色器目录，热重载不可用：{}", e);
+                        None
+                    }
+                }
+            }
+
+            /// 返回自上次调用以来被修改过的着色器文件名（不含目录），没有修改时为空。
+            pub fn changed(&self) -> Vec<String> {
+                let mut names = Vec::new();
+                for event in self.receiver.try_iter() {
+                    let event = match event {
+                        Ok(event) => event,
+                        Err(e) => {
+                            println!("监听着色器目录出错：{}", e);
+                            continue;
+                        }
+                    };
+                    if !matches!(
+                        event.kind,
+                        notify::EventKind::Create(_) | notify::EventKind::Modify(_)
+                    ) {
+                        continue;
+                    }
+                    for path in event.paths {
+                        if path.extension().is_some_and(|ext| ext == "wgsl") {
+                            if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+                                if !names.iter().any(|n| n == name) {
+                                    names.push(name.to_string());
+                                }
+                            }
+                        }
+                    }
+                }
+                names
+            }
+        }
+    } else {
+        /// 直接使用嵌入的着色器。
+        pub fn load(_name: &str, embedded: &'static str) -> Cow<'static, str> {
+            Cow::Borrowed(embedded)
+        }
+
+        /// release 构建和 wasm 上没有热重载，`new` 总是返回 `None`。
+        pub struct ShaderWatcher;
+
+        impl ShaderWatcher {
+            pub fn new() -> Option<Self> {
+                None
+            }
+
+            pub fn changed(&self) -> Vec<String> {
+                Vec::new()
+            }
+        }
+    }
+}
+
+/// 在验证错误作用域中创建管线。着色器有错误时打印错误并返回 `None`，调用方继续使用旧的管线。
+///
+/// Arguments:
+///
+/// * `device`: 创建管线用的设备。
+/// * `label`: 出错时打印的名称。
+/// * `create`: 创建管线的闭包。
+pub fn try_rebuild<T>(device: &wgpu::Device, label: &str, create: impl FnOnce() -> T) -> Option<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(e) => {
+            println!("重新加载 {} 失败，继续使用旧的管线：{}", label, e);
+            None
+        }
+        None => {
+            println!("已重新加载 {}", label);
+            Some(value)
+        }
+    }
+}
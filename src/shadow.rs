@@ -0,0 +1,233 @@
+use std::ops::Range;
+
+use app_surface::AppSurface;
+
+use crate::{instance, model, model::Vertex, shaders, texture};
+
+/// `ShadowState` 负责从光源视角渲染阴影贴图，并提供片元着色器采样阴影贴图所需的绑定组。
+///
+/// 默认最多只能有 4 个绑定组，绘制小球的管线已经全部用完，所以屏幕空间环境光遮蔽的结果也放在这个绑定组中。
+///
+/// Properties:
+///
+/// * `shadow_texture`: 阴影贴图，是一张深度纹理。
+/// * `pipeline_layout`: 阴影渲染管线的布局，重新加载着色器时复用。
+/// * `pipeline`: 只有顶点着色器的阴影渲染管线。
+/// * `shadow_bind_group_layout`: 阴影贴图、比较采样器与环境光遮蔽纹理的绑定组布局，供绘制小球的管线使用。
+/// * `shadow_bind_group`: 阴影贴图、比较采样器与环境光遮蔽纹理的绑定组。
+pub struct ShadowState {
+    pub shadow_texture: texture::Texture,
+    pub pipeline_layout: wgpu::PipelineLayout,
+    pub pipeline: wgpu::RenderPipeline,
+    pub shadow_bind_group_layout: wgpu::BindGroupLayout,
+    pub shadow_bind_group: wgpu::BindGroup,
+}
+
+impl ShadowState {
+    // 阴影贴图的边长
+    pub const SHADOW_MAP_SIZE: u32 = 2048;
+
+    /// 创建阴影贴图、阴影渲染管线和采样阴影贴图用的绑定组。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备。
+    /// * `light_bind_group_layout`: 光源的绑定组布局，阴影管线从中读取光源空间矩阵。
+    /// * `ao_view`: 屏幕空间环境光遮蔽的结果纹理。
+    ///
+    /// Returns:
+    ///
+    /// `ShadowState` 的一个实例。
+    pub fn new(
+        app: &AppSurface,
+        light_bind_group_layout: &wgpu::BindGroupLayout,
+        ao_view: &wgpu::TextureView,
+    ) -> Self {
+        let shadow_texture = texture::Texture::create_shadow_texture(
+            &app.device,
+            Self::SHADOW_MAP_SIZE,
+            "shadow_texture",
+        );
+
+        let shadow_bind_group_layout =
+            app.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                multisampled: false,
+                                sample_type: wgpu::TextureSampleType::Depth,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                            count: None,
+                        },
+                        // 环境光遮蔽按像素用 textureLoad 读取
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                multisampled: false,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                            },
+                            count: None,
+                        },
+                    ],
+                    label: Some("shadow_bind_group_layout"),
+                });
+        let shadow_bind_group =
+            Self::create_bind_group(app, &shadow_bind_group_layout, &shadow_texture, ao_view);
+
+        let layout = app
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Shadow Pipeline Layout"),
+                bind_group_layouts: &[light_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = Self::create_pipeline(app, &layout);
+
+        Self {
+            shadow_texture,
+            pipeline_layout: layout,
+            pipeline,
+            shadow_bind_group_layout,
+            shadow_bind_group,
+        }
+    }
+
+    fn create_bind_group(
+        app: &AppSurface,
+        layout: &wgpu::BindGroupLayout,
+        shadow_texture: &texture::Texture,
+        ao_view: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        app.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&shadow_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&shadow_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(ao_view),
+                },
+            ],
+            label: Some("shadow_bind_group"),
+        })
+    }
+
+    /// 环境光遮蔽纹理随窗口大小重建之后，重新创建绑定组。
+    pub fn set_ao_view(&mut self, app: &AppSurface, ao_view: &wgpu::TextureView) {
+        self.shadow_bind_group = Self::create_bind_group(
+            app,
+            &self.shadow_bind_group_layout,
+            &self.shadow_texture,
+            ao_view,
+        );
+    }
+
+    fn create_pipeline(app: &AppSurface, layout: &wgpu::PipelineLayout) -> wgpu::RenderPipeline {
+        let shader = app
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Shadow Shader"),
+                source: wgpu::ShaderSource::Wgsl(shaders::shader_source!("shadow.wgsl")),
+            });
+        app.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Shadow Pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[model::ModelVertex::desc(), instance::InstanceRaw::desc()],
+                },
+                // 只需要写深度
+                fragment: None,
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: wgpu::StencilState::default(),
+                    // 深度偏移，减轻阴影痤疮（shadow acne）
+                    bias: wgpu::DepthBiasState {
+                        constant: 2,
+                        slope_scale: 2.0,
+                        clamp: 0.0,
+                    },
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+    }
+
+    /// 重新读取着色器并重建阴影渲染管线，着色器有错误时保留原来的管线。
+    pub fn reload_shader(&mut self, app: &AppSurface) {
+        if let Some(pipeline) = shaders::try_rebuild(&app.device, "shadow.wgsl", || {
+            Self::create_pipeline(app, &self.pipeline_layout)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    /// 从光源视角渲染所有实例的深度到阴影贴图中。
+    ///
+    /// Arguments:
+    ///
+    /// * `encoder`: 命令编码器。
+    /// * `model`: 需要绘制的模型。
+    /// * `instance_buffer`: 实例缓冲区。
+    /// * `instances`: 需要绘制的实例范围。
+    /// * `light_bind_group`: 光源的绑定组。
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &model::Model,
+        instance_buffer: &wgpu::Buffer,
+        instances: Range<u32>,
+        light_bind_group: &wgpu::BindGroup,
+    ) {
+        let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.shadow_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            ..Default::default()
+        });
+
+        shadow_pass.set_pipeline(&self.pipeline);
+        shadow_pass.set_bind_group(0, light_bind_group, &[]);
+        shadow_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        for mesh in &model.meshes {
+            shadow_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            shadow_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            shadow_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+        }
+    }
+}
@@ -0,0 +1,612 @@
+use std::ops::Range;
+
+use app_surface::AppSurface;
+use rand::{Rng, SeedableRng};
+
+use crate::{camera, instance, model, model::Vertex, shaders, texture, utils};
+
+// 半球采样核的大小，与 ssao.wgsl 中的 KERNEL_SIZE 一致
+const KERNEL_SIZE: usize = 16;
+// G-buffer 中视图空间位置和法线的格式
+const GBUFFER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+// 环境光遮蔽只有一个通道
+const AO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+// 采样点与表面的深度差小于这个值时不算遮挡，避免表面自己遮挡自己
+const DEFAULT_BIAS: f32 = 0.025;
+// 遮蔽程度的指数
+const DEFAULT_POWER: f32 = 2.0;
+
+/// SSAO 着色器使用的 uniform，与 ssao.wgsl 中的 `Ssao` 结构体布局一致。
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsaoUniform {
+    view: [[f32; 4]; 4],
+    proj: [[f32; 4]; 4],
+    kernel: [[f32; 4]; KERNEL_SIZE],
+    radius: f32,
+    bias: f32,
+    power: f32,
+    _padding: f32,
+    viewport: [f32; 4],
+}
+
+impl SsaoUniform {
+    fn new(radius: f32) -> Self {
+        Self {
+            view: glam::Mat4::IDENTITY.to_cols_array_2d(),
+            proj: glam::Mat4::IDENTITY.to_cols_array_2d(),
+            kernel: Self::kernel(),
+            radius,
+            bias: DEFAULT_BIAS,
+            power: DEFAULT_POWER,
+            _padding: 0.0,
+            viewport: [0.0; 4],
+        }
+    }
+
+    // 用固定的种子在切线空间的 +z 半球内生成采样点，靠近中心的采样点更多，近处的遮挡权重更大
+    fn kernel() -> [[f32; 4]; KERNEL_SIZE] {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        std::array::from_fn(|i| {
+            let direction = glam::Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(0.0..1.0),
+            )
+            .normalize_or_zero();
+            let t = i as f32 / KERNEL_SIZE as f32;
+            let scale = 0.1 + 0.9 * t * t;
+            (direction * rng.gen_range(0.0..1.0f32) * scale)
+                .extend(0.0)
+                .to_array()
+        })
+    }
+}
+
+/// 屏幕空间环境光遮蔽用到的所有纹理，窗口大小改变时一起重建。
+///
+/// Properties:
+///
+/// * `position`: 视图空间位置，w 为 1 表示这个像素上有小球。
+/// * `normal`: 视图空间法线。
+/// * `depth`: 渲染 G-buffer 时使用的单采样深度纹理。
+/// * `raw_ao`: 模糊之前的环境光遮蔽。
+/// * `ao`: 模糊之后的环境光遮蔽，绘制小球时用来调制环境光。
+struct SsaoTargets {
+    position: texture::Texture,
+    normal: texture::Texture,
+    depth: texture::Texture,
+    raw_ao: texture::Texture,
+    ao: texture::Texture,
+}
+
+impl SsaoTargets {
+    fn new(app: &AppSurface) -> Self {
+        let create = |format, label| {
+            texture::Texture::create_render_target(&app.device, &app.config, format, label, 1)
+        };
+        Self {
+            position: create(GBUFFER_FORMAT, "ssao_position_texture"),
+            normal: create(GBUFFER_FORMAT, "ssao_normal_texture"),
+            depth: texture::Texture::create_depth_texture(
+                &app.device,
+                &app.config,
+                "ssao_depth_texture",
+                1,
+            ),
+            raw_ao: create(AO_FORMAT, "ssao_raw_ao_texture"),
+            ao: create(AO_FORMAT, "ssao_ao_texture"),
+        }
+    }
+}
+
+/// `SsaoState` 负责计算屏幕空间环境光遮蔽（SSAO），让密集的小球之间的缝隙更暗，更容易看出前后关系。
+///
+/// 先把小球的视图空间位置和法线渲染到 G-buffer 中，再在每个像素法线方向的半球内采样估计遮蔽程度，
+/// 最后模糊掉噪声。结果纹理与阴影贴图一起绑定在绘制小球的第 3 组中，只调制环境光。
+/// 计算量较大，默认关闭，关闭时结果纹理被清空为 1，即没有遮蔽。
+///
+/// Properties:
+///
+/// * `enabled`: 是否开启 SSAO，修改时使用 `set_enabled`。
+/// * `uniform`: 相机矩阵、采样核和采样参数。
+/// * `uniform_buffer`: 存放 `uniform` 的缓冲区。
+/// * `uniform_bind_group`: `uniform_buffer` 的绑定组。
+/// * `gbuffer_pipeline_layout`: G-buffer 管线的布局，重新加载着色器时复用。
+/// * `gbuffer_pipeline`: 把小球的位置和法线写入 G-buffer 的管线。
+/// * `ao_pipeline_layout`: 计算遮蔽的管线的布局。
+/// * `ao_pipeline`: 计算遮蔽的全屏管线。
+/// * `blur_pipeline_layout`: 模糊管线的布局。
+/// * `blur_pipeline`: 模糊遮蔽的全屏管线。
+/// * `gbuffer_bind_group_layout`: 读取 G-buffer 的绑定组布局。
+/// * `blur_bind_group_layout`: 读取模糊之前的遮蔽的绑定组布局。
+/// * `targets`: 所有的渲染目标。
+/// * `gbuffer_bind_group`: 读取 G-buffer 的绑定组，随渲染目标重建。
+/// * `blur_bind_group`: 读取模糊之前的遮蔽的绑定组，随渲染目标重建。
+pub struct SsaoState {
+    enabled: bool,
+    uniform: SsaoUniform,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    gbuffer_pipeline_layout: wgpu::PipelineLayout,
+    gbuffer_pipeline: wgpu::RenderPipeline,
+    ao_pipeline_layout: wgpu::PipelineLayout,
+    ao_pipeline: wgpu::RenderPipeline,
+    blur_pipeline_layout: wgpu::PipelineLayout,
+    blur_pipeline: wgpu::RenderPipeline,
+    gbuffer_bind_group_layout: wgpu::BindGroupLayout,
+    blur_bind_group_layout: wgpu::BindGroupLayout,
+    targets: SsaoTargets,
+    gbuffer_bind_group: wgpu::BindGroup,
+    blur_bind_group: wgpu::BindGroup,
+}
+
+impl SsaoState {
+    /// 创建 SSAO 的渲染目标和管线，默认不开启。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备，渲染目标与表面同样大小。
+    /// * `radius`: 世界空间中的采样半径，取小球的最大直径比较合适。
+    ///
+    /// Returns:
+    ///
+    /// `SsaoState` 的一个实例。
+    pub fn new(app: &AppSurface, radius: f32) -> Self {
+        let mut uniform = SsaoUniform::new(radius);
+        uniform.viewport = utils::letterbox_viewport(app.config.width, app.config.height, None);
+        let uniform_buffer = app.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("SSAO Uniform Buffer"),
+            size: std::mem::size_of::<SsaoUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        app.queue
+            .write_buffer(&uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let uniform_bind_group_layout =
+            app.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    }],
+                    label: Some("ssao_uniform_bind_group_layout"),
+                });
+        let uniform_bind_group = app.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &uniform_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("ssao_uniform_bind_group"),
+        });
+
+        // 全屏通道都用 textureLoad 按像素读取，不需要采样器
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let gbuffer_bind_group_layout =
+            app.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[texture_entry(0), texture_entry(1)],
+                    label: Some("ssao_gbuffer_bind_group_layout"),
+                });
+        let blur_bind_group_layout =
+            app.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[texture_entry(0)],
+                    label: Some("ssao_blur_bind_group_layout"),
+                });
+
+        let create_layout = |label, bind_group_layouts: &[&wgpu::BindGroupLayout]| {
+            app.device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some(label),
+                    bind_group_layouts,
+                    push_constant_ranges: &[],
+                })
+        };
+        let gbuffer_pipeline_layout = create_layout(
+            "SSAO G-buffer Pipeline Layout",
+            &[&uniform_bind_group_layout],
+        );
+        let ao_pipeline_layout = create_layout(
+            "SSAO Pipeline Layout",
+            &[&uniform_bind_group_layout, &gbuffer_bind_group_layout],
+        );
+        let blur_pipeline_layout =
+            create_layout("SSAO Blur Pipeline Layout", &[&blur_bind_group_layout]);
+
+        let (gbuffer_pipeline, ao_pipeline) =
+            Self::create_ssao_pipelines(app, &gbuffer_pipeline_layout, &ao_pipeline_layout);
+        let blur_pipeline = Self::create_blur_pipeline(app, &blur_pipeline_layout);
+
+        let targets = SsaoTargets::new(app);
+        let (gbuffer_bind_group, blur_bind_group) = Self::create_bind_groups(
+            app,
+            &targets,
+            &gbuffer_bind_group_layout,
+            &blur_bind_group_layout,
+        );
+
+        let ssao_state = Self {
+            enabled: false,
+            uniform,
+            uniform_buffer,
+            uniform_bind_group,
+            gbuffer_pipeline_layout,
+            gbuffer_pipeline,
+            ao_pipeline_layout,
+            ao_pipeline,
+            blur_pipeline_layout,
+            blur_pipeline,
+            gbuffer_bind_group_layout,
+            blur_bind_group_layout,
+            targets,
+            gbuffer_bind_group,
+            blur_bind_group,
+        };
+        ssao_state.clear(app);
+        ssao_state
+    }
+
+    fn create_bind_groups(
+        app: &AppSurface,
+        targets: &SsaoTargets,
+        gbuffer_bind_group_layout: &wgpu::BindGroupLayout,
+        blur_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        let gbuffer_bind_group = app.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: gbuffer_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&targets.position.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&targets.normal.view),
+                },
+            ],
+            label: Some("ssao_gbuffer_bind_group"),
+        });
+        let blur_bind_group = app.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: blur_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&targets.raw_ao.view),
+            }],
+            label: Some("ssao_blur_bind_group"),
+        });
+        (gbuffer_bind_group, blur_bind_group)
+    }
+
+    fn create_ssao_pipelines(
+        app: &AppSurface,
+        gbuffer_pipeline_layout: &wgpu::PipelineLayout,
+        ao_pipeline_layout: &wgpu::PipelineLayout,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let shader = app
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("SSAO Shader"),
+                source: wgpu::ShaderSource::Wgsl(shaders::shader_source!("ssao.wgsl")),
+            });
+        let gbuffer_target = Some(wgpu::ColorTargetState {
+            format: GBUFFER_FORMAT,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+        });
+        let gbuffer_pipeline = app
+            .device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("SSAO G-buffer Pipeline"),
+                layout: Some(gbuffer_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_gbuffer",
+                    buffers: &[model::ModelVertex::desc(), instance::InstanceRaw::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_gbuffer",
+                    targets: &[gbuffer_target.clone(), gbuffer_target],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            });
+        let ao_pipeline = Self::create_fullscreen_pipeline(
+            app,
+            "SSAO Pipeline",
+            ao_pipeline_layout,
+            &shader,
+            "vs_fullscreen",
+            "fs_ao",
+        );
+        (gbuffer_pipeline, ao_pipeline)
+    }
+
+    fn create_blur_pipeline(
+        app: &AppSurface,
+        layout: &wgpu::PipelineLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = app
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("SSAO Blur Shader"),
+                source: wgpu::ShaderSource::Wgsl(shaders::shader_source!("ssao_blur.wgsl")),
+            });
+        Self::create_fullscreen_pipeline(
+            app,
+            "SSAO Blur Pipeline",
+            layout,
+            &shader,
+            "vs_main",
+            "fs_main",
+        )
+    }
+
+    // 画一个覆盖整个屏幕的三角形，输出到单通道的遮蔽纹理
+    fn create_fullscreen_pipeline(
+        app: &AppSurface,
+        label: &str,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        vs_entry_point: &str,
+        fs_entry_point: &str,
+    ) -> wgpu::RenderPipeline {
+        app.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: vs_entry_point,
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: fs_entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: AO_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+    }
+
+    /// 模糊之后的环境光遮蔽纹理，绘制小球时按像素读取。
+    pub fn ao_view(&self) -> &wgpu::TextureView {
+        &self.targets.ao.view
+    }
+
+    /// 是否开启了 SSAO。
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 开启或关闭 SSAO，关闭时把结果清空为没有遮蔽。
+    pub fn set_enabled(&mut self, app: &AppSurface, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.clear(app);
+        }
+    }
+
+    /// 按新的表面大小重建渲染目标。重建之后需要重新绑定 `ao_view`。
+    pub fn resize(&mut self, app: &AppSurface) {
+        self.targets = SsaoTargets::new(app);
+        (self.gbuffer_bind_group, self.blur_bind_group) = Self::create_bind_groups(
+            app,
+            &self.targets,
+            &self.gbuffer_bind_group_layout,
+            &self.blur_bind_group_layout,
+        );
+        if !self.enabled {
+            self.clear(app);
+        }
+    }
+
+    // 新建的纹理内容为 0，即完全遮蔽，关闭 SSAO 时需要清空为 1
+    fn clear(&self, app: &AppSurface) {
+        let mut encoder = app
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("SSAO Clear Encoder"),
+            });
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("SSAO Clear Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.targets.ao.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        app.queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    /// 设置渲染的视口 `[x, y, 宽, 高]`，需要与颜色阶段一致，下一次 `update` 时写入 GPU。
+    pub fn set_viewport(&mut self, viewport: [f32; 4]) {
+        self.uniform.viewport = viewport;
+    }
+
+    /// 按相机当前的视图矩阵和投影矩阵更新 uniform，每帧在相机更新之后调用。
+    pub fn update(&mut self, app: &AppSurface, camera_state: &camera::CameraState) {
+        self.uniform.view = camera_state.camera.calc_matrix().to_cols_array_2d();
+        self.uniform.proj = camera_state.projection.calc_matrix().to_cols_array_2d();
+        app.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+
+    /// 重新读取 ssao.wgsl 并重建 G-buffer 和遮蔽管线，着色器有错误时保留原来的管线。
+    pub fn reload_shader(&mut self, app: &AppSurface) {
+        if let Some((gbuffer_pipeline, ao_pipeline)) =
+            shaders::try_rebuild(&app.device, "ssao.wgsl", || {
+                Self::create_ssao_pipelines(
+                    app,
+                    &self.gbuffer_pipeline_layout,
+                    &self.ao_pipeline_layout,
+                )
+            })
+        {
+            self.gbuffer_pipeline = gbuffer_pipeline;
+            self.ao_pipeline = ao_pipeline;
+        }
+    }
+
+    /// 重新读取 ssao_blur.wgsl 并重建模糊管线，着色器有错误时保留原来的管线。
+    pub fn reload_blur_shader(&mut self, app: &AppSurface) {
+        if let Some(pipeline) = shaders::try_rebuild(&app.device, "ssao_blur.wgsl", || {
+            Self::create_blur_pipeline(app, &self.blur_pipeline_layout)
+        }) {
+            self.blur_pipeline = pipeline;
+        }
+    }
+
+    /// 渲染 G-buffer，计算并模糊环境光遮蔽。没有开启时什么也不做。
+    ///
+    /// Arguments:
+    ///
+    /// * `encoder`: 命令编码器。
+    /// * `model`: 需要绘制的模型。
+    /// * `instance_buffer`: 实例缓冲区。
+    /// * `instances`: 需要绘制的实例范围，使用间接绘制时忽略。
+    /// * `indirect_buffer`: 按网格顺序存放的间接绘制参数，为 `None` 时直接绘制 `instances`。
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &model::Model,
+        instance_buffer: &wgpu::Buffer,
+        instances: Range<u32>,
+        indirect_buffer: Option<&wgpu::Buffer>,
+    ) {
+        if !self.enabled {
+            return;
+        }
+
+        {
+            let color_attachments =
+                [&self.targets.position.view, &self.targets.normal.view].map(|view| {
+                    Some(wgpu::RenderPassColorAttachment {
+                        view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })
+                });
+            let mut gbuffer_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("SSAO G-buffer Pass"),
+                color_attachments: &color_attachments,
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.targets.depth.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Discard,
+                    }),
+                    stencil_ops: None,
+                }),
+                ..Default::default()
+            });
+            utils::set_viewport(&mut gbuffer_pass, self.uniform.viewport);
+            gbuffer_pass.set_pipeline(&self.gbuffer_pipeline);
+            gbuffer_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+            gbuffer_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            for (i, mesh) in model.meshes.iter().enumerate() {
+                gbuffer_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                gbuffer_pass
+                    .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                match indirect_buffer {
+                    Some(indirect_buffer) => gbuffer_pass.draw_indexed_indirect(
+                        indirect_buffer,
+                        (i * std::mem::size_of::<model::DrawIndexedIndirectArgs>())
+                            as wgpu::BufferAddress,
+                    ),
+                    None => gbuffer_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone()),
+                }
+            }
+        }
+
+        Self::fullscreen_pass(
+            encoder,
+            "SSAO Pass",
+            &self.targets.raw_ao.view,
+            &self.ao_pipeline,
+            &[&self.uniform_bind_group, &self.gbuffer_bind_group],
+        );
+        Self::fullscreen_pass(
+            encoder,
+            "SSAO Blur Pass",
+            &self.targets.ao.view,
+            &self.blur_pipeline,
+            &[&self.blur_bind_group],
+        );
+    }
+
+    // 在一个单独的通道中画一个全屏三角形，输出到 `view`
+    fn fullscreen_pass(
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        view: &wgpu::TextureView,
+        pipeline: &wgpu::RenderPipeline,
+        bind_groups: &[&wgpu::BindGroup],
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        pass.set_pipeline(pipeline);
+        for (index, bind_group) in bind_groups.iter().enumerate() {
+            pass.set_bind_group(index as u32, bind_group, &[]);
+        }
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -25,6 +25,7 @@ impl Texture {
     /// * `device`: 对 wgpu::Device 对象的引用，它表示用于渲染的 GPU 设备。
     /// * `config`: `config` 参数的类型为 `&wgpu::SurfaceConfiguration`，表示将渲染深度纹理的表面的配置。它包含表面的宽度和高度等信息。
     /// * `label`: 为深度纹理提供标签的字符串。该标签用于在 GPU 调试器和分析器工具中调试和识别纹理。
+    /// * `sample_count`: 深度纹理的采样数，必须与颜色附件的采样数一致。
     ///
     /// Returns:
     ///
@@ -33,6 +34,7 @@ impl Texture {
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
         label: &str,
+        sample_count: u32,
     ) -> Self {
         let size = wgpu::Extent3d {
             width: config.width,
@@ -43,10 +45,13 @@ impl Texture {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            // COPY_SRC 用于 read_depth_at 读回单个像素的深度
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         };
         let texture = device.create_texture(&desc);
@@ -71,6 +76,265 @@ impl Texture {
         }
     }
 
+    /// 读回深度纹理中一个像素的深度，阻塞直到 GPU 完成之前提交的渲染。
+    ///
+    /// 只支持由 `create_depth_texture` 创建的单采样纹理，多采样的纹理不能复制到 buffer 中。
+    /// 配合 [`CameraState::unproject`](crate::camera::CameraState::unproject) 可以得到点击位置在世界空间中的坐标。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 用于创建临时 buffer 和等待读回的设备。
+    /// * `queue`: 用于提交复制命令的队列。
+    /// * `x`: 像素的横坐标，原点在左上角。
+    /// * `y`: 像素的纵坐标。
+    ///
+    /// Returns:
+    ///
+    /// [0, 1] 范围内的深度，1 表示这个像素上没有绘制任何东西。纹理是多采样的、格式不是 `DEPTH_FORMAT`
+    /// 或者坐标超出纹理范围时返回错误。
+    pub fn read_depth_at(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        x: u32,
+        y: u32,
+    ) -> Result<f32> {
+        ensure!(
+            self.texture.sample_count() == 1,
+            "cannot read back a multisampled depth texture"
+        );
+        ensure!(
+            self.texture.format() == Self::DEPTH_FORMAT
+                && self.texture.usage().contains(wgpu::TextureUsages::COPY_SRC),
+            "texture is not a readable depth texture"
+        );
+        ensure!(
+            x < self.texture.width() && y < self.texture.height(),
+            "pixel ({x}, {y}) is outside the {}x{} depth texture",
+            self.texture.width(),
+            self.texture.height()
+        );
+
+        // 纹理复制到 buffer 时每行的字节数必须按 256 字节对齐，即使只复制一个像素
+        let row_bytes = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let staging_buffer = std::sync::Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Depth Readback Buffer"),
+            size: row_bytes as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        }));
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Depth Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::DepthOnly,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &staging_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(row_bytes),
+                    rows_per_image: Some(1),
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let bytes = crate::compute::read_buffer_bytes(device, staging_buffer);
+        Ok(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// 创建用于阴影贴图的深度纹理，附带比较采样器。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 对 wgpu::Device 对象的引用。
+    /// * `size`: 阴影贴图的边长（像素）。
+    /// * `label`: 纹理的标签。
+    ///
+    /// Returns:
+    ///
+    /// 包含阴影贴图、其视图和比较采样器的结构体实例。
+    pub fn create_shadow_texture(device: &wgpu::Device, size: u32, label: &str) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// 创建多重采样的颜色纹理，渲染后再解析（resolve）到交换链的视图上。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 对 wgpu::Device 对象的引用。
+    /// * `config`: 表面的配置，纹理的大小和格式与之相同。
+    /// * `label`: 纹理的标签。
+    /// * `sample_count`: 采样数，应大于 1。
+    ///
+    /// Returns:
+    ///
+    /// 包含多重采样纹理、其视图和采样器的结构体实例。
+    pub fn create_msaa_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        label: &str,
+        sample_count: u32,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            // 与渲染管线的颜色目标格式保持一致
+            format: config.format.add_srgb_suffix(),
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// 创建与表面同样大小的渲染目标，之后的通道可以用 `textureLoad` 读取它。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 对 wgpu::Device 对象的引用。
+    /// * `config`: 表面的配置，渲染目标的宽高与之相同。
+    /// * `format`: 渲染目标的格式。
+    /// * `label`: 纹理的标签。
+    /// * `sample_count`: 采样数，与 MSAA 的颜色附件放在同一个渲染通道中时必须与之一致。
+    ///
+    /// Returns:
+    ///
+    /// 包含渲染目标、其视图和最近点采样器的结构体实例。
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        label: &str,
+        sample_count: u32,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// 创建品红色与黑色相间的棋盘格纹理，用作缺失漫反射纹理时的占位。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 对 wgpu::Device 对象的引用。
+    /// * `queue`: 用于提交 GPU 命令的命令队列。
+    ///
+    /// Returns:
+    ///
+    /// 占位用的漫反射纹理。
+    pub fn default_diffuse(device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Self> {
+        let img = image::RgbaImage::from_fn(8, 8, |x, y| {
+            if (x + y) % 2 == 0 {
+                image::Rgba([255, 0, 255, 255])
+            } else {
+                image::Rgba([0, 0, 0, 255])
+            }
+        });
+        Self::from_image(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(img),
+            Some("default_diffuse"),
+            false,
+        )
+    }
+
+    /// 创建平坦的法线贴图，所有像素都是 (0.5, 0.5, 1.0)，即切线空间中朝外的法线。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 对 wgpu::Device 对象的引用。
+    /// * `queue`: 用于提交 GPU 命令的命令队列。
+    ///
+    /// Returns:
+    ///
+    /// 占位用的法线贴图。
+    pub fn default_normal(device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Self> {
+        let img = image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 255, 255]));
+        Self::from_image(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(img),
+            Some("default_normal"),
+            true,
+        )
+    }
+
     /// Rust 中的“from_bytes”函数接受设备、队列、字节、标签和指示它是否是法线映射的布尔标志，并返回结果。
     ///
     /// Arguments:
@@ -84,7 +348,6 @@ impl Texture {
     /// Returns:
     ///
     /// 一个 `Result<Self>`。
-    #[allow(dead_code)]
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
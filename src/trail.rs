@@ -0,0 +1,278 @@
+use std::collections::VecDeque;
+
+use app_surface::AppSurface;
+
+use crate::{compute::ComputeInstance, model, shaders, texture};
+
+/// 最多同时跟踪的小球数量，顶点缓冲区按这个数量一次性分配。
+pub const MAX_TRACKED: usize = 16;
+
+/// 每条轨迹保存的位置数量，每次模拟步进记录一个位置。
+pub const TRAIL_LENGTH: usize = 256;
+
+/// `TrailVertex` 是轨迹线的顶点。
+///
+/// Properties:
+///
+/// * `position`: 顶点在世界空间中的位置。
+/// * `alpha`: 不透明度，最新的位置为 1，越旧越透明。
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TrailVertex {
+    position: [f32; 3],
+    alpha: f32,
+}
+
+impl model::Vertex for TrailVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TrailVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32,
+                },
+            ],
+        }
+    }
+}
+
+/// `Trail` 是一个被跟踪的小球最近的位置。
+///
+/// Properties:
+///
+/// * `id`: 小球的 ID。
+/// * `slot`: 轨迹在顶点缓冲区中占用的段，停止跟踪其他小球时不会改变。
+/// * `positions`: 长度不超过 `TRAIL_LENGTH` 的环形缓冲区，从旧到新排列。
+struct Trail {
+    id: u32,
+    slot: usize,
+    positions: VecDeque<glam::Vec3>,
+}
+
+/// `TrailState` 记录被跟踪小球的轨迹，并用 LineStrip 画出逐渐变淡的轨迹线，只影响渲染。
+///
+/// Properties:
+///
+/// * `trails`: 被跟踪的小球，最多 `MAX_TRACKED` 个。
+/// * `vertex_buffer`: 所有轨迹的顶点，大小固定为 `MAX_TRACKED * TRAIL_LENGTH` 个顶点。
+/// * `pipeline_layout`: 渲染管线的布局，重新加载着色器时复用。
+/// * `pipeline`: 带透明混合的 LineStrip 渲染管线，只做深度测试不写深度。
+pub struct TrailState {
+    trails: Vec<Trail>,
+    pub vertex_buffer: wgpu::Buffer,
+    pub pipeline_layout: wgpu::PipelineLayout,
+    pub pipeline: wgpu::RenderPipeline,
+}
+
+impl TrailState {
+    /// 创建轨迹的顶点缓冲区和渲染管线，一开始不跟踪任何小球。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和表面格式。
+    /// * `camera_bind_group_layout`: 相机的绑定组布局，位于 group 0。
+    /// * `sample_count`: MSAA 采样数。
+    ///
+    /// Returns:
+    ///
+    /// `TrailState` 的一个实例。
+    pub fn new(
+        app: &AppSurface,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        let vertex_buffer = app.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Trail Vertex Buffer"),
+            size: (std::mem::size_of::<TrailVertex>() * MAX_TRACKED * TRAIL_LENGTH) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let pipeline_layout = app
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Trail Pipeline Layout"),
+                bind_group_layouts: &[camera_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = Self::create_pipeline(app, &pipeline_layout, sample_count);
+
+        Self {
+            trails: Vec::new(),
+            vertex_buffer,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    fn create_pipeline(
+        app: &AppSurface,
+        layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let shader = app
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Trail Shader"),
+                source: wgpu::ShaderSource::Wgsl(shaders::shader_source!("trail.wgsl")),
+            });
+        app.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Trail Pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[<TrailVertex as model::Vertex>::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: app.config.format.add_srgb_suffix(),
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::LineStrip,
+                    ..Default::default()
+                },
+                // 半透明的线不写深度，避免遮住后面的轨迹
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+            })
+    }
+
+    /// 重新读取着色器并重建渲染管线，着色器有错误时保留原来的管线。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问设备和表面格式。
+    /// * `sample_count`: MSAA 采样数，需要和当前的渲染附件一致，采样数改变之后用新的值重建。
+    pub fn reload_shader(&mut self, app: &AppSurface, sample_count: u32) {
+        if let Some(pipeline) = shaders::try_rebuild(&app.device, "trail.wgsl", || {
+            Self::create_pipeline(app, &self.pipeline_layout, sample_count)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    /// 开始跟踪一个小球。已经在跟踪或者跟踪数量达到 `MAX_TRACKED` 时返回 `false`。
+    pub fn track(&mut self, id: u32) -> bool {
+        if self.is_tracked(id) {
+            return false;
+        }
+        // 取第一个空闲的段
+        let Some(slot) =
+            (0..MAX_TRACKED).find(|slot| self.trails.iter().all(|trail| trail.slot != *slot))
+        else {
+            return false;
+        };
+        self.trails.push(Trail {
+            id,
+            slot,
+            positions: VecDeque::with_capacity(TRAIL_LENGTH),
+        });
+        true
+    }
+
+    /// 停止跟踪一个小球并丢弃它的轨迹。没有在跟踪这个小球时返回 `false`。
+    pub fn untrack(&mut self, id: u32) -> bool {
+        let len = self.trails.len();
+        self.trails.retain(|trail| trail.id != id);
+        self.trails.len() != len
+    }
+
+    /// 丢弃所有轨迹中已经记录的位置，继续跟踪同样的小球。模拟重新开始时调用，避免轨迹连到原来的位置。
+    pub fn clear(&mut self) {
+        for trail in self.trails.iter_mut() {
+            trail.positions.clear();
+        }
+    }
+
+    /// 是否正在跟踪这个小球。
+    pub fn is_tracked(&self, id: u32) -> bool {
+        self.trails.iter().any(|trail| trail.id == id)
+    }
+
+    /// 记录被跟踪小球的最新位置，并把轨迹写到顶点缓冲区中。每次模拟步进之后调用一次。
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: 应用程序表面，用于访问队列。
+    /// * `instances`: 读回的实例，按 ID 排列。
+    pub fn update(&mut self, app: &AppSurface, instances: &[ComputeInstance]) {
+        for trail in self.trails.iter_mut() {
+            // 有小球被移除之后实例不再按 id 连续存放
+            let Some(instance) = instances
+                .get(trail.id as usize)
+                .filter(|instance| instance.id == trail.id)
+                .or_else(|| instances.iter().find(|instance| instance.id == trail.id))
+            else {
+                continue;
+            };
+            if trail.positions.len() == TRAIL_LENGTH {
+                trail.positions.pop_front();
+            }
+            trail.positions.push_back(instance.position);
+
+            let len = trail.positions.len();
+            let vertices = trail
+                .positions
+                .iter()
+                .enumerate()
+                .map(|(k, position)| TrailVertex {
+                    position: position.to_array(),
+                    alpha: (k + 1) as f32 / len as f32,
+                })
+                .collect::<Vec<_>>();
+            app.queue.write_buffer(
+                &self.vertex_buffer,
+                (std::mem::size_of::<TrailVertex>() * TRAIL_LENGTH * trail.slot) as u64,
+                bytemuck::cast_slice(&vertices),
+            );
+        }
+    }
+
+    /// 在给定的渲染通道中绘制所有轨迹，每条轨迹是一次 LineStrip 绘制。
+    ///
+    /// Arguments:
+    ///
+    /// * `render_pass`: 当前的渲染通道。
+    /// * `camera_bind_group`: 相机的绑定组。
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.trails.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        for trail in &self.trails {
+            let start = (TRAIL_LENGTH * trail.slot) as u32;
+            render_pass.draw(start..start + trail.positions.len() as u32, 0..1);
+        }
+    }
+}
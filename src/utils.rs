@@ -1,3 +1,5 @@
+use crate::compute;
+
 /// 该代码提供了辅助函数，用于在 Rust 中创建渲染管道并将字节转换为 u32 和 f32 值。
 ///
 /// Arguments:
@@ -11,6 +13,8 @@
 /// 结构的数组。每个结构体都描述渲染管道中使用的顶点缓冲区的布局。它指定步幅（每个顶点的大小，以字节为单位）、步长模式（顶点缓冲区是逐顶点还是
 /// * `shader`:
 /// “shader”参数是一个“wgpu::ShaderModuleDescriptor”，它描述渲染管道中使用的着色器模块。它包含诸如着色器代码以及顶点和片段着色器的入口点等信息。
+/// * `polygon_mode`: 多边形的光栅化方式，`Line` 需要设备支持 `wgpu::Features::POLYGON_MODE_LINE`。
+/// * `sample_count`: MSAA 采样数，必须和颜色、深度附件的采样数一致。
 pub fn create_render_pipeline(
     device: &wgpu::Device,
     layout: &wgpu::PipelineLayout,
@@ -18,6 +22,95 @@ pub fn create_render_pipeline(
     depth_format: Option<wgpu::TextureFormat>,
     vertex_layouts: &[wgpu::VertexBufferLayout],
     shader: wgpu::ShaderModuleDescriptor,
+    polygon_mode: wgpu::PolygonMode,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    create_custom_render_pipeline(
+        device,
+        layout,
+        &[color_target(color_format)],
+        depth_format.map(|format| wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        vertex_layouts,
+        shader,
+        "fs_main",
+        polygon_mode,
+        sample_count,
+    )
+}
+
+/// 在 `width` x `height` 的表面中居中放置宽高比为 `aspect` 的最大矩形，多出来的部分留作黑边。
+///
+/// Arguments:
+///
+/// * `width`: 表面的宽度（像素）。
+/// * `height`: 表面的高度（像素）。
+/// * `aspect`: 目标宽高比，`None` 时铺满整个表面。
+///
+/// Returns:
+///
+/// 视口 `[x, y, 宽, 高]`，单位是像素，落在表面之内。
+pub fn letterbox_viewport(width: u32, height: u32, aspect: Option<f32>) -> [f32; 4] {
+    let (width, height) = (width as f32, height as f32);
+    let Some(aspect) = aspect else {
+        return [0.0, 0.0, width, height];
+    };
+    if width > height * aspect {
+        // 窗口更宽，左右留黑边
+        let viewport_width = (height * aspect).round().max(1.0);
+        let x = ((width - viewport_width) * 0.5).floor();
+        [x, 0.0, viewport_width, height]
+    } else {
+        // 窗口更高，上下留黑边
+        let viewport_height = (width / aspect).round().max(1.0);
+        let y = ((height - viewport_height) * 0.5).floor();
+        [0.0, y, width, viewport_height]
+    }
+}
+
+/// 把渲染通道的视口设置为 `letterbox_viewport` 返回的 `[x, y, 宽, 高]`，深度范围为 [0, 1]。
+pub fn set_viewport(render_pass: &mut wgpu::RenderPass, [x, y, width, height]: [f32; 4]) {
+    render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+}
+
+/// 以不混合的方式写入 `format` 的 sRGB 版本的颜色目标，即交换链使用的颜色目标。
+pub fn color_target(format: wgpu::TextureFormat) -> Option<wgpu::ColorTargetState> {
+    Some(wgpu::ColorTargetState {
+        format: format.add_srgb_suffix(),
+        blend: Some(wgpu::BlendState {
+            alpha: wgpu::BlendComponent::REPLACE,
+            color: wgpu::BlendComponent::REPLACE,
+        }),
+        write_mask: wgpu::ColorWrites::ALL,
+    })
+}
+
+/// 与 `create_render_pipeline` 相同，但由调用者给出所有的颜色目标、片元着色器入口和完整的深度模板状态，
+/// 例如深度预渲染之后只做深度比较、不再写深度的管线，或者同时输出多个渲染目标的管线。
+///
+/// Arguments:
+///
+/// * `targets`: 所有的颜色目标，数量和格式必须与片元着色器的输出以及渲染通道的颜色附件一致。
+/// * `depth_stencil`: 深度模板状态，为 `None` 时管线不使用深度附件。
+/// * `fs_entry_point`: 片元着色器的入口函数名。
+///
+/// 其余参数与 `create_render_pipeline` 相同。
+#[allow(clippy::too_many_arguments)]
+pub fn create_custom_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    targets: &[Option<wgpu::ColorTargetState>],
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: wgpu::ShaderModuleDescriptor,
+    fs_entry_point: &str,
+    polygon_mode: wgpu::PolygonMode,
+    sample_count: u32,
 ) -> wgpu::RenderPipeline {
     let shader = device.create_shader_module(shader);
 
@@ -31,37 +124,25 @@ pub fn create_render_pipeline(
         },
         fragment: Some(wgpu::FragmentState {
             module: &shader,
-            entry_point: "fs_main",
-            targets: &[Some(wgpu::ColorTargetState {
-                format: color_format.add_srgb_suffix(),
-                blend: Some(wgpu::BlendState {
-                    alpha: wgpu::BlendComponent::REPLACE,
-                    color: wgpu::BlendComponent::REPLACE,
-                }),
-                write_mask: wgpu::ColorWrites::ALL,
-            })],
+            entry_point: fs_entry_point,
+            targets,
         }),
         primitive: wgpu::PrimitiveState {
             topology: wgpu::PrimitiveTopology::TriangleList,
             strip_index_format: None,
             front_face: wgpu::FrontFace::Ccw,
             cull_mode: Some(wgpu::Face::Back),
-            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-            polygon_mode: wgpu::PolygonMode::Fill,
+            // Setting this to anything other than Fill requires Features::POLYGON_MODE_LINE
+            // or Features::POLYGON_MODE_POINT
+            polygon_mode,
             // Requires Features::DEPTH_CLIP_CONTROL
             unclipped_depth: false,
             // Requires Features::CONSERVATIVE_RASTERIZATION
             conservative: false,
         },
-        depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
-            format,
-            depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::Less,
-            stencil: wgpu::StencilState::default(),
-            bias: wgpu::DepthBiasState::default(),
-        }),
+        depth_stencil,
         multisample: wgpu::MultisampleState {
-            count: 1,
+            count: sample_count,
             mask: !0,
             alpha_to_coverage_enabled: false,
         },
@@ -71,6 +152,75 @@ pub fn create_render_pipeline(
     })
 }
 
+/// 检查适配器是否支持给定的 MSAA 采样数，不支持时退回到 1。
+///
+/// Arguments:
+///
+/// * `adapter`: 当前使用的适配器。
+/// * `formats`: 需要使用该采样数的所有附件格式，例如颜色格式和深度格式。
+/// * `sample_count`: 期望的采样数，只能是 1、2、4 或 8。
+///
+/// Returns:
+///
+/// 所有格式都支持时返回 `sample_count`，否则返回 1。
+pub fn supported_sample_count(
+    adapter: &wgpu::Adapter,
+    formats: &[wgpu::TextureFormat],
+    sample_count: u32,
+) -> u32 {
+    if !matches!(sample_count, 1 | 2 | 4 | 8) {
+        println!("无效的 MSAA 采样数 {sample_count}，只能是 1、2、4 或 8。");
+        return 1;
+    }
+    if sample_count_supported(adapter, formats, sample_count) {
+        sample_count
+    } else {
+        println!("当前设备不支持 {sample_count}x MSAA，已关闭抗锯齿。");
+        1
+    }
+}
+
+/// 检查给定的所有附件格式是否都支持 `sample_count` 个采样，不打印任何信息。
+pub fn sample_count_supported(
+    adapter: &wgpu::Adapter,
+    formats: &[wgpu::TextureFormat],
+    sample_count: u32,
+) -> bool {
+    formats.iter().all(|format| {
+        adapter
+            .get_texture_format_features(*format)
+            .flags
+            .sample_count_supported(sample_count)
+    })
+}
+
+/// 将字节切片按照 `T` 的内存布局转换为 `T` 的向量，字节切片不需要按 `T` 对齐。
+///
+/// Arguments:
+///
+/// * `bytes`: 从 GPU 读回的原始字节。
+///
+/// Returns:
+///
+/// 字节数不是 `size_of::<T>()` 的整数倍时返回错误，否则返回转换后的向量。
+pub fn bytes_to_vec<T: bytemuck::Pod>(bytes: &[u8]) -> anyhow::Result<Vec<T>> {
+    let size = std::mem::size_of::<T>();
+    if size == 0 || bytes.len() % size != 0 {
+        anyhow::bail!(
+            "byte length {} is not a multiple of element size {}",
+            bytes.len(),
+            size
+        );
+    }
+    Ok(bytemuck::pod_collect_to_vec(bytes))
+}
+
+// 丢弃末尾不足一个元素的字节之后再转换
+fn bytes_to_vec_truncated<T: bytemuck::Pod>(bytes: &[u8]) -> Vec<T> {
+    let whole = bytes.len() - bytes.len() % std::mem::size_of::<T>();
+    bytes_to_vec(&bytes[..whole]).expect("length is a multiple of the element size")
+}
+
 /// 该代码提供了将字节切片转换为 u32 或 f32 值向量的函数，还包括使用标签打印转换后的值的函数。
 ///
 /// Arguments:
@@ -81,14 +231,7 @@ pub fn create_render_pipeline(
 ///
 /// `bytes_to_u32` 函数返回一个 `Vec<u32>`，其中包含输入 `bytes` 切片的转换值。
 pub fn bytes_to_u32(bytes: &[u8]) -> Vec<u32> {
-    let mut results: Vec<u32> = Vec::new();
-
-    for i in 0..bytes.len() / 4 {
-        let value = u32::from_ne_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap());
-        results.push(value);
-    }
-
-    results
+    bytes_to_vec_truncated(bytes)
 }
 
 /// 该代码提供了将字节数组转换为 f32 值向量的函数，并将字节数组打印为带标签的 u32 或 f32 值。
@@ -100,24 +243,86 @@ pub fn bytes_to_u32(bytes: &[u8]) -> Vec<u32> {
 /// Returns:
 ///
 /// `bytes_to_f32` 函数返回一个 `Vec<f32>`，它是 32 位浮点数的向量。
-#[allow(dead_code)]
 pub fn bytes_to_f32(bytes: &[u8]) -> Vec<f32> {
-    let mut results: Vec<f32> = Vec::new();
-
-    for i in 0..bytes.len() / 4 {
-        let value = f32::from_ne_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap());
-        results.push(value);
-    }
+    bytes_to_vec_truncated(bytes)
+}
 
-    results
+/// 将结果 buffer 读回的字节转换为 `compute::Result` 的向量，第 `i` 个结果属于 `id` 为 `i` 的实例。
+///
+/// Arguments:
+///
+/// * `bytes`: 从结果 buffer 读回的原始字节。
+///
+/// Returns:
+///
+/// 字节数不是 `compute::Result` 大小的整数倍时返回错误。
+pub fn bytes_to_results(bytes: &[u8]) -> anyhow::Result<Vec<compute::Result>> {
+    bytes_to_vec(bytes)
 }
 
-#[allow(dead_code)]
 pub fn output_bytes_as_u32(bytes: &[u8], label: &str) {
     println!("Label: {:?} Output: {:?}", label, bytes_to_u32(bytes));
 }
 
-#[allow(dead_code)]
 pub fn output_bytes_as_f32(bytes: &[u8], label: &str) {
     println!("Label: {:?} Output: {:?}", label, bytes_to_f32(bytes));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_to_vec_accepts_empty_input() {
+        assert!(bytes_to_vec::<u32>(&[]).unwrap().is_empty());
+        assert!(bytes_to_u32(&[]).is_empty());
+    }
+
+    #[test]
+    fn bytes_to_vec_rejects_partial_elements() {
+        assert!(bytes_to_vec::<u32>(&[1, 2, 3]).is_err());
+        assert!(bytes_to_vec::<f32>(&[0; 9]).is_err());
+        // 包装函数丢弃末尾不足一个元素的字节
+        assert_eq!(bytes_to_u32(&[1, 0, 0, 0, 2, 0]), vec![1]);
+    }
+
+    #[test]
+    fn bytes_to_vec_reads_misaligned_input() {
+        let values = [1u32, 0xdead_beef, u32::MAX];
+        let mut bytes = vec![0u8];
+        bytes.extend_from_slice(bytemuck::cast_slice(&values));
+        // 从第 1 个字节开始的切片一定不按 4 字节对齐
+        assert_eq!(bytes_to_vec::<u32>(&bytes[1..]).unwrap(), values);
+    }
+
+    #[test]
+    fn bytes_to_results_decodes_every_field() {
+        let mut bytes = Vec::new();
+        for id in 0..2u32 {
+            let base = id as f32 * 10.0;
+            for value in [base + 1.0, base + 2.0, base + 3.0, 0.5] {
+                bytes.extend_from_slice(&f32::to_ne_bytes(value));
+            }
+            for value in [base + 4.0, base + 5.0, base + 6.0] {
+                bytes.extend_from_slice(&f32::to_ne_bytes(value));
+            }
+            bytes.extend_from_slice(&id.to_ne_bytes());
+            for value in [base + 7.0, base + 8.0, base + 9.0, 0.25] {
+                bytes.extend_from_slice(&f32::to_ne_bytes(value));
+            }
+        }
+        assert_eq!(bytes.len(), 2 * std::mem::size_of::<compute::Result>());
+
+        let results = bytes_to_results(&bytes).unwrap();
+        assert_eq!(results.len(), 2);
+        let second = results[1];
+        assert_eq!(second.position, [11.0, 12.0, 13.0]);
+        assert_eq!(second.still_time, 0.5);
+        assert_eq!(second.velocity, [14.0, 15.0, 16.0]);
+        assert_eq!(second.dead, 1);
+        assert_eq!(second.acceleration, [17.0, 18.0, 19.0]);
+        assert_eq!(second.collision_heat, 0.25);
+        assert_eq!(results[0].dead, 0);
+        assert!(bytes_to_results(&bytes[..bytes.len() - 4]).is_err());
+    }
+}
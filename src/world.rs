@@ -0,0 +1,180 @@
+use std::ops::ControlFlow;
+
+use crate::{
+    compute::{ComputeInstance, ComputeState, ComputeStateBuilder, Diagnostics},
+    cpu::CpuSolver,
+};
+
+/// `StepMode` 决定 [`CollisionWorld::step`] 在哪里推进模拟。
+///
+/// Variants:
+///
+/// * `Gpu`: 在 GPU 上推进，速度快，但结果随 GPU 厂商和驱动有细微的差别。
+/// * `CpuF64`: 用 [`CpuSolver`] 在 CPU 上以 f64 推进，在任何平台上都逐位一致，但复杂度是 O(n^2)。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StepMode {
+    #[default]
+    Gpu,
+    CpuF64,
+}
+
+/// `CollisionWorld` 是只关心物理、自己负责渲染时使用的最小接口：放好小球，按时间步推进，取出位置。
+///
+/// 它只持有一个 [`ComputeState`]，需要拾取、静态平面等功能时可以用 [`state_mut`](Self::state_mut) 访问。
+///
+/// Properties:
+///
+/// * `state`: 模拟的全部 GPU 资源和 CPU 中的实例。
+/// * `cpu_solver`: `StepMode::CpuF64` 时的 f64 状态，`StepMode::Gpu` 时为 `None`。
+pub struct CollisionWorld {
+    state: ComputeState,
+    cpu_solver: Option<CpuSolver>,
+}
+
+impl CollisionWorld {
+    /// 按 `builder` 的参数创建模拟，放入初始的小球并上传到 GPU。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 用于创建 buffer 和管线的设备。
+    /// * `queue`: 用于上传初始实例的队列。
+    /// * `builder`: 模拟的参数，小球数量必须与 `instances` 一致。
+    /// * `instances`: 初始的小球，`id` 应该依次为 0 到 n - 1。
+    ///
+    /// Returns:
+    ///
+    /// 参数不合法、小球数量与 `builder` 不一致，或者网格小于最大直径时返回错误。
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        builder: &ComputeStateBuilder,
+        instances: Vec<ComputeInstance>,
+    ) -> anyhow::Result<Self> {
+        let mut state = builder.build(device)?;
+        if instances.len() != state.buffer_len() as usize {
+            anyhow::bail!(
+                "expected {} instances, got {}",
+                state.buffer_len(),
+                instances.len()
+            );
+        }
+        state.instances = instances;
+        state.check_grid_size()?;
+        // 第一步之前也可以用 instances_buffer 渲染，reset 也能恢复到这里
+        state.write_instances_buffer(queue);
+        state.save_initial_state();
+
+        Ok(Self {
+            state,
+            cpu_solver: None,
+        })
+    }
+
+    /// 当前的推进方式。
+    pub fn step_mode(&self) -> StepMode {
+        if self.cpu_solver.is_some() {
+            StepMode::CpuF64
+        } else {
+            StepMode::Gpu
+        }
+    }
+
+    /// 切换推进方式，从当前的实例继续。
+    ///
+    /// 切换到 `StepMode::CpuF64` 时 f64 的状态从 f32 的实例开始，之后一直以 f64 累加，
+    /// 需要逐位一致的回归测试应该在第一步之前切换。切换回 `StepMode::Gpu` 时丢弃 f64 的状态。
+    /// 读回间隔大于 1 时，切换之前应该先用 `ComputeState::read_results` 同步实例。
+    pub fn set_step_mode(&mut self, mode: StepMode) {
+        match mode {
+            StepMode::Gpu => self.cpu_solver = None,
+            StepMode::CpuF64 => {
+                if self.cpu_solver.is_none() {
+                    self.cpu_solver = Some(CpuSolver::new(&self.state.instances));
+                }
+            }
+        }
+    }
+
+    /// `StepMode::CpuF64` 时的求解器，可以取出没有舍入的 f64 位置和速度。
+    pub fn cpu_solver(&self) -> Option<&CpuSolver> {
+        self.cpu_solver.as_ref()
+    }
+
+    /// 把模拟推进 `dt`，实际模拟的时间还要乘以时间缩放，并被切成若干个子步。
+    ///
+    /// 非 wasm 平台上会等待这一步完成并读回结果；wasm 上结果在之后的某次调用中才会读回。
+    /// `StepMode::CpuF64` 时不使用 GPU，结果立即可用。读回间隔见 [`ComputeState::set_readback_interval`]。
+    pub fn step(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, dt: std::time::Duration) {
+        match self.cpu_solver.as_mut() {
+            Some(cpu_solver) => cpu_solver.step(&mut self.state, dt),
+            None => self.state.update(device, queue, dt),
+        }
+    }
+
+    /// 不打开窗口地连续推进 `steps` 步，每 `every` 步以及最后一步调用一次 `progress`。
+    ///
+    /// `progress` 收到已经完成的步数（从 1 开始）和当时的 [`Diagnostics`]，返回 `ControlFlow::Break`
+    /// 时立即停止，例如动能已经低于某个阈值、可以认为堆积已经收敛的时候。统计重叠需要遍历所有小球，
+    /// 小球很多时 `every` 不宜太小。
+    ///
+    /// Arguments:
+    ///
+    /// * `device`: 用于提交计算和等待读回的设备。
+    /// * `queue`: 用于写入 buffer 的队列。
+    /// * `dt`: 每一步推进的时间。
+    /// * `steps`: 最多推进的步数。
+    /// * `every`: 两次调用 `progress` 之间的步数，为 0 时按 1 处理。
+    /// * `progress`: 进度回调。
+    ///
+    /// Returns:
+    ///
+    /// 实际推进的步数，提前停止时小于 `steps`。
+    pub fn run(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        dt: std::time::Duration,
+        steps: u32,
+        every: u32,
+        mut progress: impl FnMut(u32, &Diagnostics) -> ControlFlow<()>,
+    ) -> u32 {
+        let every = every.max(1);
+        for step in 1..=steps {
+            self.step(device, queue, dt);
+            if step % every == 0 || step == steps {
+                // 读回间隔大于 1 时 instances 可能是过时的，统计之前先同步
+                #[cfg(not(target_arch = "wasm32"))]
+                if !self.state.is_synced() {
+                    self.state.read_results(device);
+                }
+                let diagnostics = self.state.diagnostics();
+                if progress(step, &diagnostics).is_break() {
+                    return step;
+                }
+            }
+        }
+        steps
+    }
+
+    /// 最近一次读回的每个存活的小球的位置，顺序与 [`instances`](Self::instances) 一致。
+    pub fn positions(&self) -> Vec<glam::Vec3> {
+        self.state
+            .instances
+            .iter()
+            .map(|instance| instance.position)
+            .collect()
+    }
+
+    /// 最近一次读回的所有小球。
+    pub fn instances(&self) -> &[ComputeInstance] {
+        &self.state.instances
+    }
+
+    pub fn state(&self) -> &ComputeState {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut ComputeState {
+        &mut self.state
+    }
+}